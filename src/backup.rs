@@ -0,0 +1,633 @@
+use crate::db::DB;
+use crate::env::{Env, WritableFile};
+use crate::filename::{parse_file_name, FileType};
+use crate::util::crc32;
+use crate::util::error::TinyError;
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+// The layout of the backup directory:
+//
+//   meta/1                                    the files of backup 1
+//   private/1/CURRENT                         the files owned by backup 1
+//   private/1/MANIFEST-000004
+//   private/1/000008.log
+//   shared_checksum/000005_1234567_4096.sst   the immutable files shared by the backups
+//
+// The shared files are named by the file number, the crc32c and the size of the content,
+// so the backups share a file instead of keeping their own copies.
+const META_DIR: &str = "meta";
+const PRIVATE_DIR: &str = "private";
+const SHARED_DIR: &str = "shared_checksum";
+const TEMP_SUFFIX: &str = ".tmp";
+
+const COPY_BUFFER_SIZE: usize = 64 << 10;
+
+// A file of a backup
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct BackupFile {
+    // the name of the file in the DB directory
+    db_name: String,
+    // the path relative to the backup directory
+    path: String,
+    size: u64,
+    checksum: u32,
+}
+
+struct BackupMeta {
+    // the seconds since the Unix epoch when the backup is created
+    timestamp: u64,
+    files: Vec<BackupFile>,
+}
+
+impl BackupMeta {
+    // The meta file is in the format of
+    //
+    //   <timestamp>
+    //   <number of files>
+    //   <db name> <path> <size> <crc32c>
+    //   ...
+    fn encode(&self) -> String {
+        let mut s = format!("{}\n{}\n", self.timestamp, self.files.len());
+        for f in self.files.iter() {
+            s.push_str(&format!("{} {} {} {}\n", f.db_name, f.path, f.size, f.checksum));
+        }
+        s
+    }
+
+    fn decode(s: &str) -> Result<BackupMeta, TinyError> {
+        let corruption = || TinyError::Corruption("[backup] bad meta file".to_owned());
+        let mut lines = s.lines();
+        let mut next_number = || -> Result<u64, TinyError> {
+            lines.next().and_then(|l| l.parse().ok()).ok_or_else(corruption)
+        };
+        let timestamp = next_number()?;
+        let num_files = next_number()?;
+        let mut files = vec![];
+        for line in lines.by_ref() {
+            let fields: Vec<&str> = line.split(' ').collect();
+            if fields.len() != 4 {
+                return Err(corruption());
+            }
+            files.push(BackupFile {
+                db_name: fields[0].to_owned(),
+                path: fields[1].to_owned(),
+                size: fields[2].parse().map_err(|_| corruption())?,
+                checksum: fields[3].parse().map_err(|_| corruption())?,
+            });
+        }
+        if files.len() as u64 != num_files {
+            return Err(corruption());
+        }
+        Ok(BackupMeta { timestamp, files })
+    }
+
+    fn size(&self) -> u64 {
+        self.files.iter().map(|f| f.size).sum()
+    }
+}
+
+/// The summary of a backup
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub backup_id: u32,
+    /// The seconds since the Unix epoch when the backup is created
+    pub timestamp: u64,
+    /// The total size of the files of the backup, including the shared ones
+    pub size: u64,
+    pub num_files: usize,
+}
+
+// Reads `src`, or only the first `limit` bytes of it if given, and writes the data to
+// `target` if given. Returns the size and the crc32c of the data.
+fn transfer(
+    env: &dyn Env,
+    src: &str,
+    mut target: Option<&mut dyn WritableFile>,
+    limit: Option<u64>,
+) -> Result<(u64, u32), TinyError> {
+    let mut file = env.new_sequential_file(src)?.take(limit.unwrap_or(u64::MAX));
+    let mut buf = vec![0; COPY_BUFFER_SIZE];
+    let mut size = 0;
+    let mut checksum = 0;
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        };
+        checksum = crc32::extend(checksum, &buf[..n]);
+        if let Some(target) = target.as_mut() {
+            target.write_all(&buf[..n])?;
+        }
+        size += n as u64;
+    }
+    if let Some(limit) = limit {
+        if size < limit {
+            return Err(TinyError::IOError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("[backup] {} is shorter than {} bytes", src, limit),
+            )));
+        }
+    }
+    if let Some(target) = target {
+        target.sync()?;
+    }
+    Ok((size, checksum))
+}
+
+// Copies `src` in `src_env`, or only the first `limit` bytes of it if given, to `target`
+// in `target_env`. Returns the size and the crc32c of the data.
+fn copy_file(
+    src_env: &dyn Env,
+    src: &str,
+    target_env: &dyn Env,
+    target: &str,
+    limit: Option<u64>,
+) -> Result<(u64, u32), TinyError> {
+    let mut file = target_env.new_writable_file(target)?;
+    transfer(src_env, src, Some(file.as_mut()), limit)
+}
+
+fn file_name(path: &str) -> &str {
+    &path[path.rfind('/').map_or(0, |i| i + 1)..]
+}
+
+/// BackupEngine keeps the backups of the DBs in a directory. A backup only copies the
+/// table and value log files not in the directory yet, which are shared with the other
+/// backups, so backing up a DB periodically costs about the size of the new files.
+///
+/// A BackupEngine must be the only one working on its directory.
+pub struct BackupEngine {
+    env: Arc<dyn Env>,
+    backup_dir: String,
+    backups: BTreeMap<u32, BackupMeta>,
+}
+
+impl BackupEngine {
+    /// Opens the backups in `backup_dir` of `env`, which is created if missing.
+    /// The DBs are restored to `env` as well.
+    pub fn open(env: Arc<dyn Env>, backup_dir: &str) -> Result<BackupEngine, TinyError> {
+        let backup_dir = backup_dir.trim_end_matches('/').to_owned();
+        for dir in [META_DIR, PRIVATE_DIR, SHARED_DIR].iter() {
+            env.create_dir(&format!("{}/{}", backup_dir, dir))?;
+        }
+        let mut engine = BackupEngine {
+            env,
+            backup_dir,
+            backups: BTreeMap::new(),
+        };
+        for name in engine.env.get_children(&engine.path(META_DIR))? {
+            if let Ok(id) = name.parse::<u32>() {
+                let mut meta = String::new();
+                engine
+                    .env
+                    .new_sequential_file(&engine.meta_path(id))?
+                    .read_to_string(&mut meta)?;
+                engine.backups.insert(id, BackupMeta::decode(&meta)?);
+            }
+        }
+        // clean up the backups failed before
+        engine.garbage_collect()?;
+        Ok(engine)
+    }
+
+    /// Backs up the live files and the WAL files of `db`. Returns the id of the new backup.
+    pub fn create_new_backup(&mut self, db: &DB) -> Result<u32, TinyError> {
+        let id = self.backups.keys().next_back().map_or(1, |id| id + 1);
+        db.disable_file_deletions();
+        let files = self.backup_files(db, id);
+        db.enable_file_deletions();
+        let meta = BackupMeta {
+            timestamp: self.env.now_micros() / 1_000_000,
+            files: match files {
+                Ok(files) => files,
+                Err(e) => {
+                    let _ = self.garbage_collect();
+                    return Err(e);
+                }
+            },
+        };
+        if let Err(e) = self.write_meta(id, &meta) {
+            let _ = self.garbage_collect();
+            return Err(e);
+        }
+        self.backups.insert(id, meta);
+        Ok(id)
+    }
+
+    /// Returns the backups ordered by id
+    pub fn get_backup_info(&self) -> Vec<BackupInfo> {
+        self.backups
+            .iter()
+            .map(|(id, meta)| BackupInfo {
+                backup_id: *id,
+                timestamp: meta.timestamp,
+                size: meta.size(),
+                num_files: meta.files.len(),
+            })
+            .collect()
+    }
+
+    /// Deletes the backup and the shared files not used by the other backups
+    pub fn delete_backup(&mut self, backup_id: u32) -> Result<(), TinyError> {
+        self.backup(backup_id)?;
+        self.env.remove_file(&self.meta_path(backup_id))?;
+        self.backups.remove(&backup_id);
+        self.garbage_collect()
+    }
+
+    /// Deletes the oldest backups so that at most `num_backups_to_keep` are left
+    pub fn purge_old_backups(&mut self, num_backups_to_keep: usize) -> Result<(), TinyError> {
+        while self.backups.len() > num_backups_to_keep {
+            let id = *self.backups.keys().next().unwrap();
+            self.env.remove_file(&self.meta_path(id))?;
+            self.backups.remove(&id);
+        }
+        self.garbage_collect()
+    }
+
+    /// Checks that all the files of the backup exist with the recorded sizes and checksums.
+    /// Returns `Corruption` otherwise.
+    pub fn verify_backup(&self, backup_id: u32) -> Result<(), TinyError> {
+        for f in self.backup(backup_id)?.files.iter() {
+            let path = self.path(&f.path);
+            if !self.env.file_exists(&path) {
+                return Err(TinyError::Corruption(format!("[backup] {} is missing", path)));
+            }
+            let (size, checksum) = transfer(self.env.as_ref(), &path, None, None)?;
+            Self::check_file(f, &path, size, checksum)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the backup into `db_dir`, which must be empty or missing. The value log
+    /// files are restored into `db_dir` as well. The files are verified while copied.
+    pub fn restore_db_from_backup(&self, backup_id: u32, db_dir: &str) -> Result<(), TinyError> {
+        let meta = self.backup(backup_id)?;
+        let db_dir = db_dir.trim_end_matches('/');
+        if self.env.file_exists(db_dir) && !self.env.get_children(db_dir)?.is_empty() {
+            return Err(TinyError::InvalidArgument(format!(
+                "[backup] can not restore into {} which is not empty",
+                db_dir
+            )));
+        }
+        self.env.create_dir(db_dir)?;
+        for f in meta.files.iter() {
+            let path = self.path(&f.path);
+            let (size, checksum) = copy_file(
+                self.env.as_ref(),
+                &path,
+                self.env.as_ref(),
+                &format!("{}/{}", db_dir, f.db_name),
+                None,
+            )?;
+            Self::check_file(f, &path, size, checksum)?;
+        }
+        Ok(())
+    }
+
+    pub fn restore_db_from_latest_backup(&self, db_dir: &str) -> Result<(), TinyError> {
+        match self.backups.keys().next_back() {
+            Some(id) => self.restore_db_from_backup(*id, db_dir),
+            None => Err(TinyError::InvalidArgument(
+                "[backup] there is no backup".to_owned(),
+            )),
+        }
+    }
+
+    // Writes the meta file, whose existence makes the backup complete
+    fn write_meta(&self, id: u32, meta: &BackupMeta) -> Result<(), TinyError> {
+        let tmp_path = format!("{}{}", self.meta_path(id), TEMP_SUFFIX);
+        let mut file = self.env.new_writable_file(&tmp_path)?;
+        file.write_all(meta.encode().as_bytes())?;
+        file.sync()?;
+        drop(file);
+        self.env.rename_file(&tmp_path, &self.meta_path(id))
+    }
+
+    fn backup(&self, backup_id: u32) -> Result<&BackupMeta, TinyError> {
+        self.backups.get(&backup_id).ok_or_else(|| {
+            TinyError::InvalidArgument(format!("[backup] backup {} not found", backup_id))
+        })
+    }
+
+    fn check_file(f: &BackupFile, path: &str, size: u64, checksum: u32) -> Result<(), TinyError> {
+        if size != f.size || checksum != f.checksum {
+            return Err(TinyError::Corruption(format!(
+                "[backup] {} has size {} and checksum {} but expected {} and {}",
+                path, size, checksum, f.size, f.checksum
+            )));
+        }
+        Ok(())
+    }
+
+    // Copies the files of `db` into the backup directory
+    fn backup_files(&self, db: &DB, id: u32) -> Result<Vec<BackupFile>, TinyError> {
+        let db_env = db.config().env.as_ref();
+        let private_dir = format!("{}/{}", PRIVATE_DIR, id);
+        self.env.create_dir(&self.path(&private_dir))?;
+        let (live_files, manifest_size) = db.get_live_files()?;
+        let wal_files = db.get_sorted_wal_files()?;
+        let mut files = vec![];
+        for src in live_files.iter().chain(wal_files.iter()) {
+            let db_name = file_name(src);
+            let (path, size, checksum) = match parse_file_name(db_name) {
+                Some((FileType::Table, _)) | Some((FileType::ValueLog, _)) => {
+                    self.backup_shared_file(db_env, src, db_name)?
+                }
+                file_type => {
+                    // the edits appended after the live files are taken are left out
+                    let limit = match file_type {
+                        Some((FileType::Manifest, _)) => Some(manifest_size),
+                        _ => None,
+                    };
+                    let path = format!("{}/{}", private_dir, db_name);
+                    let (size, checksum) =
+                        copy_file(db_env, src, self.env.as_ref(), &self.path(&path), limit)?;
+                    (path, size, checksum)
+                }
+            };
+            files.push(BackupFile {
+                db_name: db_name.to_owned(),
+                path,
+                size,
+                checksum,
+            });
+        }
+        Ok(files)
+    }
+
+    // Copies the immutable file into a temporary file, which is named by the checksum
+    // and the size computed while copied unless the same file is in the directory.
+    // Returns the path, the size and the checksum of the file.
+    fn backup_shared_file(
+        &self,
+        db_env: &dyn Env,
+        src: &str,
+        db_name: &str,
+    ) -> Result<(String, u64, u32), TinyError> {
+        let tmp_target = self.path(&format!("{}/{}{}", SHARED_DIR, db_name, TEMP_SUFFIX));
+        let (size, checksum) = copy_file(db_env, src, self.env.as_ref(), &tmp_target, None)?;
+        let (stem, ext) = db_name.split_at(db_name.find('.').unwrap_or(db_name.len()));
+        let path = format!("{}/{}_{}_{}{}", SHARED_DIR, stem, checksum, size, ext);
+        let target = self.path(&path);
+        if self.env.file_exists(&target) {
+            self.env.remove_file(&tmp_target)?;
+        } else {
+            self.env.rename_file(&tmp_target, &target)?;
+        }
+        Ok((path, size, checksum))
+    }
+
+    // Removes the files not belonging to any backup
+    fn garbage_collect(&self) -> Result<(), TinyError> {
+        let env = self.env.as_ref();
+        let shared: HashSet<&str> = self
+            .backups
+            .values()
+            .flat_map(|meta| meta.files.iter().map(|f| f.path.as_str()))
+            .collect();
+        for name in env.get_children(&self.path(SHARED_DIR))? {
+            let path = format!("{}/{}", SHARED_DIR, name);
+            if !shared.contains(path.as_str()) {
+                env.remove_file(&self.path(&path))?;
+            }
+        }
+        for name in env.get_children(&self.path(PRIVATE_DIR))? {
+            let id = name.parse::<u32>().ok();
+            if id.is_some_and(|id| self.backups.contains_key(&id)) {
+                continue;
+            }
+            let dir = self.path(&format!("{}/{}", PRIVATE_DIR, name));
+            for file in env.get_children(&dir)? {
+                env.remove_file(&format!("{}/{}", dir, file))?;
+            }
+            env.remove_dir(&dir)?;
+        }
+        for name in env.get_children(&self.path(META_DIR))? {
+            if name.ends_with(TEMP_SUFFIX) {
+                env.remove_file(&self.path(&format!("{}/{}", META_DIR, name)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn path(&self, relative: &str) -> String {
+        format!("{}/{}", self.backup_dir, relative)
+    }
+
+    fn meta_path(&self, id: u32) -> String {
+        self.path(&format!("{}/{}", META_DIR, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::job::CompactRangeOptions;
+    use crate::config::Config;
+    use crate::db::open_db;
+    use crate::env::fault_injection::FaultInjectionEnv;
+    use crate::env::mem::MemEnv;
+    use crate::filename::value_log_file_name;
+    use crate::util::slice::Slice;
+
+    fn write_file(env: &dyn Env, path: &str, data: &[u8]) {
+        let mut file = env.new_writable_file(path).unwrap();
+        file.write_all(data).unwrap();
+        file.sync().unwrap();
+    }
+
+    // Returns the names and the contents of the files in `dir`
+    fn dir_contents(env: &dyn Env, dir: &str) -> BTreeMap<String, Vec<u8>> {
+        env.get_children(dir)
+            .unwrap()
+            .into_iter()
+            .map(|name| {
+                let mut data = vec![];
+                env.new_sequential_file(&format!("{}/{}", dir, name))
+                    .unwrap()
+                    .read_to_end(&mut data)
+                    .unwrap();
+                (name, data)
+            })
+            .collect()
+    }
+
+    // Opens a DB with a table file, a value log file and a WAL file
+    fn open_test_db(env: &Arc<dyn Env>) -> DB {
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        db.write(Slice::from("b"), Slice::from("2")).unwrap();
+        write_file(env.as_ref(), &value_log_file_name("/db", 100), b"value log 100");
+        db
+    }
+
+    fn table_names(env: &dyn Env, dir: &str) -> Vec<String> {
+        let mut names = env.get_children(dir).unwrap();
+        names.retain(|name| name.ends_with(".sst"));
+        names
+    }
+
+    // Returns the contents of the DB directory as restored, i.e. without LOCK
+    fn db_contents(env: &dyn Env) -> BTreeMap<String, Vec<u8>> {
+        let mut contents = dir_contents(env, "/db");
+        contents.remove("LOCK");
+        contents
+    }
+
+    #[test]
+    fn test_incremental_backups() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_test_db(&env);
+        let mut engine = BackupEngine::open(env.clone(), "/backup").unwrap();
+        assert!(engine.get_backup_info().is_empty());
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 1);
+        let first = db_contents(env.as_ref());
+
+        // compacted into a new table file with a new WAL
+        let tables = table_names(env.as_ref(), "/db");
+        db.write(Slice::from("a"), Slice::from("3")).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        db.write(Slice::from("c"), Slice::from("4")).unwrap();
+        assert_eq!(tables.len(), 1);
+        assert!(!table_names(env.as_ref(), "/db").contains(&tables[0]));
+        assert_eq!(engine.create_new_backup(&db).unwrap(), 2);
+        let second = db_contents(env.as_ref());
+        assert!(db.file_deletions_enabled());
+
+        // the value log is copied only once
+        let shared = dir_contents(env.as_ref(), "/backup/shared_checksum");
+        assert_eq!(shared.len(), 3);
+        let infos = engine.get_backup_info();
+        assert_eq!(infos.iter().map(|i| i.backup_id).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(infos[0].num_files, 5);
+        assert_eq!(infos[1].size, second.values().map(|d| d.len() as u64).sum());
+
+        // reopened from the meta files
+        drop(engine);
+        let mut engine = BackupEngine::open(env.clone(), "/backup/").unwrap();
+        assert_eq!(engine.get_backup_info(), infos);
+        engine.verify_backup(1).unwrap();
+        engine.verify_backup(2).unwrap();
+
+        engine.restore_db_from_backup(1, "/restore1").unwrap();
+        assert_eq!(dir_contents(env.as_ref(), "/restore1"), first);
+        engine.restore_db_from_latest_backup("/restore2").unwrap();
+        assert_eq!(dir_contents(env.as_ref(), "/restore2"), second);
+        assert!(matches!(
+            engine.restore_db_from_backup(1, "/restore2"),
+            Err(TinyError::InvalidArgument(_))
+        ));
+
+        // the first table file is only used by backup 1
+        engine.delete_backup(1).unwrap();
+        assert!(matches!(engine.verify_backup(1), Err(TinyError::InvalidArgument(_))));
+        assert_eq!(dir_contents(env.as_ref(), "/backup/shared_checksum").len(), 2);
+        assert_eq!(env.get_children("/backup/private").unwrap(), vec!["2".to_owned()]);
+        engine.purge_old_backups(0).unwrap();
+        assert!(engine.get_backup_info().is_empty());
+        assert!(env.get_children("/backup/shared_checksum").unwrap().is_empty());
+        assert!(env.get_children("/backup/private").unwrap().is_empty());
+        assert!(env.get_children("/backup/meta").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_verify_backup() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_test_db(&env);
+        let mut engine = BackupEngine::open(env.clone(), "/backup").unwrap();
+        let id = engine.create_new_backup(&db).unwrap();
+        let shared = env.get_children("/backup/shared_checksum").unwrap();
+        let table = shared.iter().find(|name| name.ends_with(".sst")).unwrap();
+        // the same size with a different content
+        let path = format!("/backup/shared_checksum/{}", table);
+        let mut data = dir_contents(env.as_ref(), "/backup/shared_checksum").remove(table).unwrap();
+        data[0] ^= 1;
+        write_file(env.as_ref(), &path, &data);
+        assert!(matches!(engine.verify_backup(id), Err(TinyError::Corruption(_))));
+        assert!(matches!(
+            engine.restore_db_from_backup(id, "/restore"),
+            Err(TinyError::Corruption(_))
+        ));
+        env.remove_file("/backup/private/1/CURRENT").unwrap();
+        assert!(matches!(engine.verify_backup(id), Err(TinyError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_flush_during_backup() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        let db = Arc::new(
+            open_db(Config {
+                dir: "/db".to_owned(),
+                env: env.clone(),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        // the edit appended to the MANIFEST while backed up refers to a table file
+        // not in the backup
+        let flushed = db.clone();
+        env.before_read(
+            |path| path.starts_with("/db/MANIFEST"),
+            move || {
+                flushed.write(Slice::from("b"), Slice::from("2")).unwrap();
+                flushed.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+            },
+        );
+        let mut engine = BackupEngine::open(env.clone(), "/backup").unwrap();
+        let id = engine.create_new_backup(&db).unwrap();
+        assert_eq!(table_names(env.as_ref(), "/backup/shared_checksum").len(), 0);
+        engine.verify_backup(id).unwrap();
+        engine.restore_db_from_backup(id, "/restore").unwrap();
+        let restored = open_db(Config {
+            dir: "/restore".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        assert_eq!(restored.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(restored.get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_failed_backup() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_test_db(&env);
+        let backup_env = Arc::new(FaultInjectionEnv::new(env.clone()));
+        let mut engine = BackupEngine::open(backup_env.clone(), "/backup").unwrap();
+        let mut failures = 0;
+        for n in 0..100 {
+            backup_env.fail_after_operations(n);
+            if engine.create_new_backup(&db).is_ok() {
+                break;
+            }
+            failures += 1;
+            assert!(db.file_deletions_enabled());
+            backup_env.clear_failures();
+            // the failed backup is cleaned up by reopening if not by itself
+            engine = BackupEngine::open(backup_env.clone(), "/backup").unwrap();
+            assert!(engine.get_backup_info().is_empty());
+            assert!(env.get_children("/backup/shared_checksum").unwrap().is_empty());
+            assert!(env.get_children("/backup/private").unwrap().is_empty());
+            assert!(env.get_children("/backup/meta").unwrap().is_empty());
+        }
+        assert!(failures > 0);
+        backup_env.clear_failures();
+        let id = engine.get_backup_info()[0].backup_id;
+        engine.verify_backup(id).unwrap();
+        engine.restore_db_from_backup(id, "/restore").unwrap();
+        assert_eq!(dir_contents(env.as_ref(), "/restore"), db_contents(env.as_ref()));
+    }
+}
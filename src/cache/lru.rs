@@ -0,0 +1,159 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+struct LRUEntry<V> {
+    value: Arc<V>,
+    charge: usize,
+    // the last time the entry is used
+    tick: u64,
+}
+
+struct LRUInner<K, V> {
+    usage: usize,
+    // increased on every access
+    tick: u64,
+    table: HashMap<K, LRUEntry<V>>,
+    // tick -> key ordered from the least recently used
+    lru: BTreeMap<u64, K>,
+}
+
+impl<K: Hash + Eq + Clone, V> LRUInner<K, V> {
+    fn remove(&mut self, key: &K) -> Option<LRUEntry<V>> {
+        let entry = self.table.remove(key)?;
+        self.lru.remove(&entry.tick);
+        self.usage -= entry.charge;
+        Some(entry)
+    }
+}
+
+/// LRUCache is a thread-safe cache evicting the least recently used entries once the total
+/// charge of the entries exceeds the capacity.
+///
+/// The values are handed out as `Arc`s so an evicted entry stays alive as long as
+/// someone is still using it.
+pub struct LRUCache<K, V> {
+    capacity: usize,
+    inner: Mutex<LRUInner<K, V>>,
+}
+
+impl<K: Hash + Eq + Clone, V> LRUCache<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        LRUCache {
+            capacity,
+            inner: Mutex::new(LRUInner {
+                usage: 0,
+                tick: 0,
+                table: HashMap::new(),
+                lru: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Inserts a mapping from key to value into the cache against the specified charge
+    /// and returns the cached value. The existing mapping of the key is replaced.
+    pub fn insert(&self, key: K, value: V, charge: usize) -> Arc<V> {
+        let value = Arc::new(value);
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(&key);
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.lru.insert(tick, key.clone());
+        inner.usage += charge;
+        inner.table.insert(
+            key,
+            LRUEntry {
+                value: value.clone(),
+                charge,
+                tick,
+            },
+        );
+        while inner.usage > self.capacity {
+            let oldest = match inner.lru.iter().next() {
+                Some((_, k)) => k.clone(),
+                None => break,
+            };
+            inner.remove(&oldest);
+        }
+        value
+    }
+
+    /// Returns the value mapped by the key and marks it as the most recently used
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tick += 1;
+        let tick = inner.tick;
+        let (value, old_tick) = match inner.table.get_mut(key) {
+            Some(entry) => {
+                let old_tick = entry.tick;
+                entry.tick = tick;
+                (entry.value.clone(), old_tick)
+            }
+            None => return None,
+        };
+        inner.lru.remove(&old_tick);
+        inner.lru.insert(tick, key.clone());
+        Some(value)
+    }
+
+    /// Removes the mapping of the key if it exists
+    pub fn erase(&self, key: &K) {
+        self.inner.lock().unwrap().remove(key);
+    }
+
+    /// Returns the sum of the charges of all the entries in the cache
+    pub fn total_charge(&self) -> usize {
+        self.inner.lock().unwrap().usage
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_get() {
+        let cache = LRUCache::new(100);
+        assert!(cache.get(&1).is_none());
+        cache.insert(1, "a", 1);
+        cache.insert(2, "b", 1);
+        assert_eq!(*cache.get(&1).unwrap(), "a");
+        assert_eq!(*cache.get(&2).unwrap(), "b");
+        cache.insert(1, "c", 1);
+        assert_eq!(*cache.get(&1).unwrap(), "c");
+        assert_eq!(cache.total_charge(), 2);
+        cache.erase(&1);
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.total_charge(), 1);
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let cache = LRUCache::new(3);
+        for i in 0..3 {
+            cache.insert(i, i, 1);
+        }
+        // 0 becomes the most recently used
+        cache.get(&0);
+        cache.insert(3, 3, 1);
+        assert!(cache.get(&1).is_none());
+        assert!(cache.get(&0).is_some());
+        assert!(cache.get(&2).is_some());
+        assert!(cache.get(&3).is_some());
+
+        let big = cache.insert(4, 4, 3);
+        assert_eq!(cache.total_charge(), 3);
+        assert!(cache.get(&0).is_none());
+        assert!(cache.get(&3).is_none());
+        assert_eq!(*big, 4);
+        // the entry is still alive after being evicted
+        cache.insert(5, 5, 10);
+        assert_eq!(cache.total_charge(), 0);
+        assert_eq!(*big, 4);
+    }
+}
@@ -0,0 +1,277 @@
+use crate::db::DB;
+use crate::env::{copy_file, Env};
+use crate::filename::{parse_file_name, FileType};
+use crate::level::version::set_current_file;
+use crate::util::error::TinyError;
+
+/// Checkpoint creates an openable snapshot of a live DB in another directory without
+/// stopping the writes.
+///
+/// The immutable files (the table and the value log files) are hard linked when the
+/// directory is on the same file system, so a checkpoint costs little space until the
+/// files are compacted away in the DB. The MANIFEST is copied up to the edits of the
+/// table files linked and CURRENT is written for the copy, while the WAL files are
+/// copied up to their current sizes and replayed when the checkpoint is opened. The value log files are placed in the checkpoint directory too,
+/// so the checkpoint is opened with the same `Config::dir` and `Config::value_dir`.
+pub struct Checkpoint {}
+
+impl Checkpoint {
+    /// Creates the checkpoint of `db` in `checkpoint_dir`, which must not exist.
+    /// The directory is removed if the checkpoint fails.
+    pub fn create(db: &DB, checkpoint_dir: &str) -> Result<(), TinyError> {
+        let env = db.config().env.as_ref();
+        let checkpoint_dir = checkpoint_dir.trim_end_matches('/');
+        if checkpoint_dir.is_empty() {
+            return Err(TinyError::InvalidArgument(
+                "[checkpoint] the checkpoint directory is empty".to_owned(),
+            ));
+        }
+        if env.file_exists(checkpoint_dir) {
+            return Err(TinyError::InvalidArgument(format!(
+                "[checkpoint] {} exists",
+                checkpoint_dir
+            )));
+        }
+        // the live files must not be deleted before linked or copied
+        db.disable_file_deletions();
+        let result = Self::create_files(db, env, checkpoint_dir);
+        db.enable_file_deletions();
+        if result.is_err() {
+            Self::remove_dir(env, checkpoint_dir);
+        }
+        result
+    }
+
+    fn create_files(db: &DB, env: &dyn Env, checkpoint_dir: &str) -> Result<(), TinyError> {
+        env.create_dir(checkpoint_dir)?;
+        let (live_files, manifest_size) = db.get_live_files()?;
+        let wal_files = db.get_sorted_wal_files()?;
+        for path in live_files.iter().chain(wal_files.iter()) {
+            let name = &path[path.rfind('/').map_or(0, |i| i + 1)..];
+            let target = format!("{}/{}", checkpoint_dir, name);
+            match parse_file_name(name) {
+                Some((FileType::Table, _)) | Some((FileType::ValueLog, _)) => {
+                    // fall back to copying, e.g. across file systems
+                    if env.link_file(path, &target).is_err() {
+                        copy_file(env, path, &target, None)?;
+                    }
+                }
+                // the edits appended after the live files are taken are left out
+                Some((FileType::Manifest, number)) => {
+                    copy_file(env, path, &target, Some(manifest_size))?;
+                    set_current_file(env, checkpoint_dir, number)?;
+                }
+                // written for the MANIFEST copied
+                Some((FileType::Current, _)) => {}
+                _ => {
+                    copy_file(env, path, &target, None)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Removes the partial checkpoint
+    fn remove_dir(env: &dyn Env, dir: &str) {
+        if let Ok(children) = env.get_children(dir) {
+            for name in children {
+                let _ = env.remove_file(&format!("{}/{}", dir, name));
+            }
+        }
+        let _ = env.remove_dir(dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::job::CompactRangeOptions;
+    use crate::config::Config;
+    use crate::db::{open_db, open_db_read_only};
+    use crate::env::fault_injection::FaultInjectionEnv;
+    use crate::env::mem::MemEnv;
+    use crate::env::posix::PosixEnv;
+    use crate::filename::{
+        log_file_name, manifest_file_name, table_file_name, value_log_file_name,
+    };
+    use crate::util::slice::Slice;
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+
+    fn write_file(env: &dyn Env, path: &str, data: &[u8]) {
+        let mut file = env.new_writable_file(path).unwrap();
+        file.write_all(data).unwrap();
+        file.sync().unwrap();
+    }
+
+    fn read_file(env: &dyn Env, path: &str) -> Vec<u8> {
+        let mut data = vec![];
+        env.new_sequential_file(path).unwrap().read_to_end(&mut data).unwrap();
+        data
+    }
+
+    fn sorted_children(env: &dyn Env, dir: &str) -> Vec<String> {
+        let mut children = env.get_children(dir).unwrap();
+        children.sort();
+        children
+    }
+
+    // Writes a table file and a WAL file through the DB in `dir`, and creates the files
+    // which are not live: an obsolete MANIFEST, a table file left by a crash and an
+    // unknown file. The value log files are all live.
+    fn create_db_files(db: &DB, env: &dyn Env, dir: &str) {
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        db.write(Slice::from("b"), Slice::from("2")).unwrap();
+        write_file(env, &manifest_file_name(dir, 100), b"old manifest");
+        write_file(env, &table_file_name(dir, 101), b"table 101");
+        write_file(env, &value_log_file_name(dir, 102), b"value log 102");
+        write_file(env, &format!("{}/unknown", dir), b"unknown");
+    }
+
+    #[test]
+    fn test_create_checkpoint() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        };
+        let db = open_db(config).unwrap();
+        create_db_files(&db, env.as_ref(), "/db");
+
+        Checkpoint::create(&db, "/checkpoint/").unwrap();
+        assert!(db.file_deletions_enabled());
+        assert_eq!(
+            sorted_children(env.as_ref(), "/checkpoint"),
+            vec![
+                "000003.log",
+                "000005.sst",
+                "000102.vlog",
+                "CURRENT",
+                "MANIFEST-000001",
+            ]
+        );
+        for name in sorted_children(env.as_ref(), "/checkpoint") {
+            assert_eq!(
+                read_file(env.as_ref(), &format!("/checkpoint/{}", name)),
+                read_file(env.as_ref(), &format!("/db/{}", name))
+            );
+        }
+        // the table files are linked while the WAL files are copied
+        let wal = read_file(env.as_ref(), "/db/000003.log");
+        env.new_appendable_file("/db/000005.sst").unwrap().write_all(b"!").unwrap();
+        env.new_appendable_file("/db/000003.log").unwrap().write_all(b"!").unwrap();
+        assert_eq!(
+            read_file(env.as_ref(), "/checkpoint/000005.sst"),
+            read_file(env.as_ref(), "/db/000005.sst")
+        );
+        assert_eq!(read_file(env.as_ref(), "/checkpoint/000003.log"), wal);
+
+        assert!(matches!(
+            Checkpoint::create(&db, "/checkpoint"),
+            Err(TinyError::InvalidArgument(_))
+        ));
+        // the checkpoint is a DB by itself
+        let checkpoint = open_db_read_only(Config {
+            dir: "/checkpoint".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        assert_eq!(checkpoint.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(checkpoint.get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_flush_during_checkpoint() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        let db = Arc::new(
+            open_db(Config {
+                dir: "/db".to_owned(),
+                env: env.clone(),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        // the memtable is flushed after the live files are taken, whose edit in the
+        // MANIFEST refers to a table file not in the checkpoint and to the next log
+        let flushed = db.clone();
+        env.before_read(
+            |path| path.starts_with("/db/MANIFEST"),
+            move || {
+                flushed.write(Slice::from("b"), Slice::from("2")).unwrap();
+                flushed.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+            },
+        );
+        Checkpoint::create(&db, "/checkpoint").unwrap();
+        let checkpoint = open_db_read_only(Config {
+            dir: "/checkpoint".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        assert_eq!(checkpoint.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(checkpoint.get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_checkpoint_with_value_dir() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        env.create_dir("/values").unwrap();
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            value_dir: "/values".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        write_file(env.as_ref(), &value_log_file_name("/values", 100), b"value log");
+
+        Checkpoint::create(&db, "/checkpoint").unwrap();
+        assert_eq!(
+            sorted_children(env.as_ref(), "/checkpoint"),
+            vec!["000003.log", "000005.sst", "000100.vlog", "CURRENT", "MANIFEST-000001"]
+        );
+    }
+
+    #[test]
+    fn test_checkpoint_on_posix_env() {
+        use std::os::unix::fs::MetadataExt;
+
+        let root = std::env::temp_dir().join(format!("tinydb_checkpoint_{}", std::process::id()));
+        let dir = root.join("db").to_str().unwrap().to_owned();
+        let checkpoint_dir = root.join("checkpoint").to_str().unwrap().to_owned();
+        let env: Arc<dyn Env> = Arc::new(PosixEnv::new());
+        let db = open_db_read_only(Config {
+            dir: dir.clone(),
+            env: env.clone(),
+            ..Config::default()
+        });
+        assert!(db.is_err());
+        let config = Config {
+            dir: dir.clone(),
+            env: env.clone(),
+            ..Config::default()
+        };
+        let db = open_db(config).unwrap();
+        create_db_files(&db, env.as_ref(), &dir);
+        drop(db);
+        let db = open_db_read_only(Config {
+            dir: dir.clone(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+
+        Checkpoint::create(&db, &checkpoint_dir).unwrap();
+        let table = table_file_name(&checkpoint_dir, 5);
+        assert_eq!(std::fs::metadata(&table).unwrap().nlink(), 2);
+        assert_eq!(read_file(env.as_ref(), &table), read_file(env.as_ref(), &table_file_name(&dir, 5)));
+        assert_eq!(std::fs::metadata(log_file_name(&checkpoint_dir, 3)).unwrap().nlink(), 1);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
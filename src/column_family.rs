@@ -0,0 +1,291 @@
+use crate::compaction::filter::CompactionFilterSource;
+use crate::mem::memtable::MemTable;
+use crate::util::comparator::{BytewiseComparator, Comparator};
+use crate::util::error::TinyError;
+use crate::util::merge_operator::MergeOperator;
+use crate::util::slice::Slice;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+/// The name of the column family every DB has and which can not be dropped.
+pub const DEFAULT_COLUMN_FAMILY_NAME: &str = "default";
+
+/// The id of the default column family.
+pub const DEFAULT_COLUMN_FAMILY_ID: u32 = 0;
+
+/// The options which can be tuned separately for every column family.
+#[derive(Clone)]
+pub struct ColumnFamilyOptions {
+    /// The comparator defining the order of the keys in the column family.
+    pub comparator: Arc<dyn Comparator<Slice> + Send + Sync>,
+    /// The merge operator used by `DB::merge` on the column family.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The filter invoked by the compactions of the column family.
+    pub compaction_filter: Option<CompactionFilterSource>,
+}
+
+impl Default for ColumnFamilyOptions {
+    fn default() -> Self {
+        ColumnFamilyOptions {
+            comparator: Arc::new(BytewiseComparator::new()),
+            merge_operator: None,
+            compaction_filter: None,
+        }
+    }
+}
+
+/// A ColumnFamilyHandle identifies a column family in the `*_cf` APIs of `DB`.
+/// The handle becomes invalid once the column family is dropped.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ColumnFamilyHandle {
+    id: u32,
+    name: String,
+}
+
+impl ColumnFamilyHandle {
+    #[inline]
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// ColumnFamilyData holds the states owned by a single column family.
+/// Every column family has its own memtable and levels while the WAL and
+/// the MANIFEST are shared by the whole DB.
+pub struct ColumnFamilyData {
+    handle: ColumnFamilyHandle,
+    options: ColumnFamilyOptions,
+    // the writes of the column family logged in the WAL files since the last flush
+    mem: RwLock<MemTable>,
+    // the largest sequence of the keys dropped entirely by the compactions since opened
+    dropped_seq: AtomicU64,
+}
+
+impl ColumnFamilyData {
+    #[inline]
+    pub fn handle(&self) -> &ColumnFamilyHandle {
+        &self.handle
+    }
+
+    #[inline]
+    pub fn options(&self) -> &ColumnFamilyOptions {
+        &self.options
+    }
+
+    #[inline]
+    pub(crate) fn mem(&self) -> &RwLock<MemTable> {
+        &self.mem
+    }
+
+    /// Returns the largest sequence of the keys whose entries are all dropped by the
+    /// compactions since the DB is opened, or 0 if none is dropped
+    #[inline]
+    pub(crate) fn dropped_seq(&self) -> u64 {
+        self.dropped_seq.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn record_dropped_seq(&self, seq: u64) {
+        self.dropped_seq.fetch_max(seq, Ordering::SeqCst);
+    }
+}
+
+/// ColumnFamilySet tracks all the live column families of a DB.
+pub struct ColumnFamilySet {
+    // the id assigned to the next created column family
+    next_id: u32,
+    // name -> id
+    names: HashMap<String, u32>,
+    families: HashMap<u32, Arc<ColumnFamilyData>>,
+}
+
+impl ColumnFamilySet {
+    /// Create a ColumnFamilySet containing only the default column family
+    pub fn new(default_options: ColumnFamilyOptions) -> Self {
+        let mut set = ColumnFamilySet {
+            next_id: DEFAULT_COLUMN_FAMILY_ID,
+            names: HashMap::new(),
+            families: HashMap::new(),
+        };
+        set.create(DEFAULT_COLUMN_FAMILY_NAME, default_options)
+            .expect("the default column family must be created in an empty set");
+        set
+    }
+
+    /// Returns the handle of the default column family
+    pub fn default_handle(&self) -> ColumnFamilyHandle {
+        self.default_family().handle.clone()
+    }
+
+    /// Returns the default column family
+    pub fn default_family(&self) -> &Arc<ColumnFamilyData> {
+        &self.families[&DEFAULT_COLUMN_FAMILY_ID]
+    }
+
+    pub fn create(
+        &mut self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamilyHandle, TinyError> {
+        if self.names.contains_key(name) {
+            return Err(TinyError::InvalidArgument(format!(
+                "column family [{}] already exists",
+                name
+            )));
+        }
+        let id = self.next_id;
+        self.insert(id, name, options)
+    }
+
+    /// Adds the column family recovered with its `id`, which is never given to a
+    /// created one afterwards
+    pub fn recover(
+        &mut self,
+        id: u32,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamilyHandle, TinyError> {
+        if self.names.contains_key(name) || self.families.contains_key(&id) {
+            return Err(TinyError::InvalidArgument(format!(
+                "column family [{}] already exists",
+                name
+            )));
+        }
+        self.insert(id, name, options)
+    }
+
+    /// Makes sure the ids up to `id` are never given to a created column family, e.g.
+    /// the ids of the dropped column families
+    pub fn mark_id_used(&mut self, id: u32) {
+        self.next_id = self.next_id.max(id + 1);
+    }
+
+    fn insert(
+        &mut self,
+        id: u32,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamilyHandle, TinyError> {
+        let handle = ColumnFamilyHandle {
+            id,
+            name: name.to_owned(),
+        };
+        self.mark_id_used(id);
+        self.names.insert(name.to_owned(), id);
+        let mem = RwLock::new(MemTable::new(options.comparator.clone()));
+        self.families.insert(
+            id,
+            Arc::new(ColumnFamilyData {
+                handle: handle.clone(),
+                options,
+                mem,
+                dropped_seq: AtomicU64::new(0),
+            }),
+        );
+        Ok(handle)
+    }
+
+    pub fn remove(&mut self, handle: &ColumnFamilyHandle) -> Result<(), TinyError> {
+        if handle.id == DEFAULT_COLUMN_FAMILY_ID {
+            return Err(TinyError::InvalidArgument(
+                "the default column family can not be dropped".to_owned(),
+            ));
+        }
+        self.get(handle)?;
+        self.families.remove(&handle.id);
+        self.names.remove(&handle.name);
+        Ok(())
+    }
+
+    /// Returns the column family identified by `handle` or an error if it has been dropped
+    pub fn get(&self, handle: &ColumnFamilyHandle) -> Result<&Arc<ColumnFamilyData>, TinyError> {
+        match self.families.get(&handle.id) {
+            Some(cfd) if cfd.handle.name == handle.name => Ok(cfd),
+            _ => Err(TinyError::InvalidArgument(format!(
+                "column family [{}] does not exist",
+                handle.name
+            ))),
+        }
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<&Arc<ColumnFamilyData>> {
+        self.names.get(name).and_then(|id| self.families.get(id))
+    }
+
+    pub fn get_by_id(&self, id: u32) -> Option<&Arc<ColumnFamilyData>> {
+        self.families.get(&id)
+    }
+
+    /// Returns all the live column families in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<ColumnFamilyData>> {
+        self.families.values()
+    }
+
+    /// Returns the number of the live column families including the default one
+    pub fn len(&self) -> usize {
+        self.families.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.families.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_column_family() {
+        let mut set = ColumnFamilySet::new(ColumnFamilyOptions::default());
+        let default = set.default_handle();
+        assert_eq!(default.id(), DEFAULT_COLUMN_FAMILY_ID);
+        assert_eq!(default.name(), DEFAULT_COLUMN_FAMILY_NAME);
+        assert_eq!(set.len(), 1);
+        assert!(set.remove(&default).is_err());
+        assert!(set
+            .create(DEFAULT_COLUMN_FAMILY_NAME, ColumnFamilyOptions::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_create_and_drop_column_family() {
+        let mut set = ColumnFamilySet::new(ColumnFamilyOptions::default());
+        let users = set.create("users", ColumnFamilyOptions::default()).unwrap();
+        let orders = set.create("orders", ColumnFamilyOptions::default()).unwrap();
+        assert_ne!(users.id(), orders.id());
+        assert_eq!(set.len(), 3);
+        assert!(set.create("users", ColumnFamilyOptions::default()).is_err());
+        assert_eq!(set.get_by_name("orders").unwrap().handle(), &orders);
+
+        set.remove(&users).unwrap();
+        assert_eq!(set.len(), 2);
+        assert!(set.get(&users).is_err());
+        assert!(set.remove(&users).is_err());
+        assert!(set.get_by_name("users").is_none());
+
+        // a re-created column family gets a new id so the stale handle stays invalid
+        let users2 = set.create("users", ColumnFamilyOptions::default()).unwrap();
+        assert_ne!(users.id(), users2.id());
+        assert!(set.get(&users).is_err());
+        assert!(set.get(&users2).is_ok());
+    }
+
+    #[test]
+    fn test_recover_column_family() {
+        let mut set = ColumnFamilySet::new(ColumnFamilyOptions::default());
+        let users = set.recover(3, "users", ColumnFamilyOptions::default()).unwrap();
+        assert_eq!(set.get_by_id(3).unwrap().handle(), &users);
+        assert!(set.recover(3, "others", ColumnFamilyOptions::default()).is_err());
+        set.mark_id_used(5);
+        let orders = set.create("orders", ColumnFamilyOptions::default()).unwrap();
+        assert_eq!(orders.id(), 6);
+        assert_eq!(set.iter().count(), 3);
+    }
+}
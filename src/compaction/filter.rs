@@ -0,0 +1,154 @@
+use crate::util::slice::Slice;
+
+use std::sync::Arc;
+
+/// The decision made by a `CompactionFilter` for a key/value pair.
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Keep the entry as it is.
+    Keep,
+    /// Drop the entry from the output of the compaction.
+    Remove,
+    /// Keep the entry but replace its value with the given one.
+    ChangeValue(Vec<u8>),
+}
+
+/// A CompactionFilter is invoked for every key/value pair visited by a compaction and
+/// decides whether the pair is kept, removed or rewritten. This makes it possible to drop
+/// records based on the business rules (e.g. all the keys of a deleted tenant) without
+/// issuing deletions.
+///
+/// A shared CompactionFilter may be invoked concurrently by multiple compactions so the
+/// implementation must be thread-safe.
+pub trait CompactionFilter: Send + Sync {
+    /// Decides what to do with the `key`/`value` pair being compacted into `level`.
+    fn filter(&self, level: usize, key: &Slice, value: &Slice) -> Decision;
+
+    /// The name of the compaction filter. Used for logging.
+    fn name(&self) -> &str;
+}
+
+/// The information about the compaction a `CompactionFilter` is created for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactionFilterContext {
+    /// Whether all the table files are inputs of the compaction.
+    pub is_full_compaction: bool,
+    /// Whether the compaction is requested by the client.
+    pub is_manual_compaction: bool,
+}
+
+/// A CompactionFilterFactory creates a new `CompactionFilter` for each compaction, so the
+/// filter can keep per-compaction state without synchronization.
+pub trait CompactionFilterFactory: Send + Sync {
+    fn create_compaction_filter(&self, context: &CompactionFilterContext) -> Box<dyn CompactionFilter>;
+
+    /// The name of the compaction filter factory. Used for logging.
+    fn name(&self) -> &str;
+}
+
+/// Where the compactions get their `CompactionFilter` from.
+#[derive(Clone)]
+pub enum CompactionFilterSource {
+    /// A single filter instance shared by all the compactions.
+    Shared(Arc<dyn CompactionFilter>),
+    /// A factory asked for a new filter at the beginning of every compaction.
+    Factory(Arc<dyn CompactionFilterFactory>),
+}
+
+impl CompactionFilterSource {
+    /// Returns the filter to be used by the compaction described by `context`.
+    pub fn filter_for(&self, context: &CompactionFilterContext) -> Arc<dyn CompactionFilter> {
+        match self {
+            CompactionFilterSource::Shared(filter) => filter.clone(),
+            CompactionFilterSource::Factory(factory) => {
+                Arc::from(factory.create_compaction_filter(context))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct TenantFilter {
+        deleted_tenant: Vec<u8>,
+    }
+
+    impl CompactionFilter for TenantFilter {
+        fn filter(&self, _level: usize, key: &Slice, value: &Slice) -> Decision {
+            if key.to_slice().starts_with(&self.deleted_tenant) {
+                Decision::Remove
+            } else if value.size() == 0 {
+                Decision::ChangeValue(b"default".to_vec())
+            } else {
+                Decision::Keep
+            }
+        }
+
+        fn name(&self) -> &str {
+            "TenantFilter"
+        }
+    }
+
+    struct TenantFilterFactory {
+        created: AtomicUsize,
+    }
+
+    impl CompactionFilterFactory for TenantFilterFactory {
+        fn create_compaction_filter(&self, _: &CompactionFilterContext) -> Box<dyn CompactionFilter> {
+            self.created.fetch_add(1, Ordering::SeqCst);
+            Box::new(TenantFilter {
+                deleted_tenant: b"tenant1/".to_vec(),
+            })
+        }
+
+        fn name(&self) -> &str {
+            "TenantFilterFactory"
+        }
+    }
+
+    fn check_tenant_filter(filter: &dyn CompactionFilter) {
+        let tests = vec![
+            ("tenant1/a", "v", Decision::Remove),
+            ("tenant2/a", "v", Decision::Keep),
+            ("tenant2/b", "", Decision::ChangeValue(b"default".to_vec())),
+        ];
+        for (key, value, expect) in tests {
+            let decision = filter.filter(1, &Slice::from(key), &Slice::from(value));
+            assert_eq!(decision, expect, "filtering key {}", key);
+        }
+    }
+
+    #[test]
+    fn test_shared_filter() {
+        let source = CompactionFilterSource::Shared(Arc::new(TenantFilter {
+            deleted_tenant: b"tenant1/".to_vec(),
+        }));
+        let context = CompactionFilterContext {
+            is_full_compaction: false,
+            is_manual_compaction: false,
+        };
+        let f1 = source.filter_for(&context);
+        let f2 = source.filter_for(&context);
+        assert!(Arc::ptr_eq(&f1, &f2));
+        check_tenant_filter(f1.as_ref());
+    }
+
+    #[test]
+    fn test_filter_factory() {
+        let factory = Arc::new(TenantFilterFactory {
+            created: AtomicUsize::new(0),
+        });
+        let source = CompactionFilterSource::Factory(factory.clone());
+        let context = CompactionFilterContext {
+            is_full_compaction: true,
+            is_manual_compaction: true,
+        };
+        for _ in 0..3 {
+            check_tenant_filter(source.filter_for(&context).as_ref());
+        }
+        assert_eq!(factory.created.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,252 @@
+use super::filter::{CompactionFilter, Decision};
+use crate::config::{Config, ReadOptions};
+use crate::entry::{MergeContext, ValueType};
+use crate::env::Env;
+use crate::filename::table_file_name;
+use crate::level::version::{FileMetaData, Version};
+use crate::table::table_builder::TableBuilder;
+use crate::table::table_cache::{TableCache, TableFileWriter};
+use crate::table::merger::{EntrySource, MergingIterator};
+use crate::util::comparator::Comparator;
+use crate::util::error::TinyError;
+use crate::util::merge_operator::MergeOperator;
+use crate::util::slice::Slice;
+use crate::util::ttl;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// How `DB::compact_range` treats the files in the bottommost level of the range
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BottommostLevelCompaction {
+    /// Never rewrite the bottommost files.
+    Skip,
+    /// Rewrite the bottommost files only if a compaction filter is configured, so the
+    /// filter sees every entry in the range.
+    IfHaveCompactionFilter,
+    /// Always rewrite the bottommost files, e.g. to apply a new compression.
+    Force,
+}
+
+/// The options of `DB::compact_range`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompactRangeOptions {
+    pub bottommost_level_compaction: BottommostLevelCompaction,
+    /// If set, the compacted files are moved to the level afterwards. Fails with
+    /// `InvalidArgument` if the files would overlap the files of another level on the way.
+    pub target_level: Option<usize>,
+}
+
+impl Default for CompactRangeOptions {
+    fn default() -> Self {
+        CompactRangeOptions {
+            bottommost_level_compaction: BottommostLevelCompaction::IfHaveCompactionFilter,
+            target_level: None,
+        }
+    }
+}
+
+/// CompactionJob merges the input table files into new table files of the output level.
+/// Only the newest entry of a key is kept, onto which the merge operands are folded,
+/// and the compaction filter decides whether every kept value is written as it is,
+/// removed or rewritten.
+///
+/// An expired value is regarded as a deletion. A removed key and a deletion are written
+/// as a deletion marker unless the output is the bottommost level for the key, i.e. no
+/// deeper level may hold an older value of the key which would show up again. A deletion
+/// marker in the bottommost level is dropped.
+pub struct CompactionJob<'a> {
+    pub config: &'a Config,
+    pub table_cache: &'a TableCache,
+    pub comparator: &'a dyn Comparator<Slice>,
+    pub filter: Option<Arc<dyn CompactionFilter>>,
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    pub output_level: usize,
+    /// The input files ordered from the newest to the oldest
+    pub inputs: Vec<Arc<FileMetaData>>,
+    /// The version the inputs are taken from, which tells the levels below the output
+    pub version: Arc<Version>,
+}
+
+// The file being written by the compaction
+struct Output {
+    meta: FileMetaData,
+    builder: TableBuilder<TableFileWriter>,
+}
+
+impl CompactionJob<'_> {
+    /// Writes the output files numbered by `new_file_number` and returns their metadata
+    /// with the largest sequence of the keys dropped entirely, or 0 if none is dropped.
+    /// The outputs written are removed if the compaction fails.
+    pub fn run(&self, new_file_number: &mut dyn FnMut() -> u64) -> Result<(Vec<FileMetaData>, u64), TinyError> {
+        let mut outputs = vec![];
+        let result = self.merge(new_file_number, &mut outputs);
+        if result.is_err() {
+            let env = self.config.env.as_ref();
+            for meta in outputs.iter() {
+                let _ = env.remove_file(&table_file_name(&self.config.dir, meta.number));
+            }
+        }
+        result.map(|dropped_seq| (outputs, dropped_seq))
+    }
+
+    // Returns the largest sequence of the keys dropped entirely
+    fn merge(
+        &self,
+        new_file_number: &mut dyn FnMut() -> u64,
+        outputs: &mut Vec<FileMetaData>,
+    ) -> Result<u64, TinyError> {
+        let tables = self
+            .inputs
+            .iter()
+            .map(|f| {
+                self.table_cache
+                    .find_table(f.number, f.file_size, self.config.paranoid_checks)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // the inputs are read sequentially through the readahead buffers bypassing the
+        // block cache, and with the direct I/O if configured
+        let files = self
+            .inputs
+            .iter()
+            .map(|f| {
+                self.table_cache
+                    .open_compaction_input(f.number, self.config.use_direct_io_for_flush_and_compaction)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let read_options = ReadOptions::default();
+        let sources = tables
+            .iter()
+            .zip(files.iter())
+            .zip(self.inputs.iter())
+            .map(|((table, file), meta)| {
+                let iter = match file.readahead_reader(self.config.compaction_readahead_size) {
+                    Some(reader) => table.iter_with_readahead(&read_options, reader)?,
+                    // a memory mapped file is read from the mapping
+                    None => table.iter(&read_options)?,
+                };
+                // the entries of an ingested file are written at 0 and take the file's seqno
+                let smallest_seqno = meta.smallest_seqno;
+                let iter = iter.map(move |r| {
+                    r.map(|(key, mut entry)| {
+                        entry.seq = entry.seq.max(smallest_seqno);
+                        (key, entry)
+                    })
+                });
+                Ok(Box::new(iter) as EntrySource<'_>)
+            })
+            .collect::<Result<Vec<_>, TinyError>>()?;
+        let merged = MergingIterator::new(sources, Box::new(|a: &[u8], b: &[u8]| self.compare(a, b)));
+
+        // the values expired by now are dropped like the deletions
+        let now = self.config.clock.now_micros();
+        let mut output: Option<Output> = None;
+        let mut dropped_seq = 0;
+        for next in merged {
+            let (key, entries) = next?;
+            // fold the entries of the key from the newest input to the oldest one
+            let mut ctx = MergeContext::new(&key, self.merge_operator.as_deref());
+            let mut newest_seq = 0;
+            for entry in entries {
+                newest_seq = newest_seq.max(entry.seq);
+                if ctx.add(entry.expire(now))? {
+                    break;
+                }
+            }
+            let is_bottommost = self
+                .version
+                .is_bottommost_for_key(self.output_level, &key, self.comparator);
+            let entry = match ctx.finish(is_bottommost)? {
+                Some(entry) => entry,
+                None => {
+                    dropped_seq = dropped_seq.max(newest_seq);
+                    continue;
+                }
+            };
+
+            let (value_type, value) = match (entry.user_value(), self.filter.as_ref()) {
+                (Some(user_value), Some(f)) => {
+                    match f.filter(self.output_level, &Slice::from(&key), &Slice::from(user_value)) {
+                        Decision::Keep => (entry.value_type, entry.value),
+                        Decision::Remove => (ValueType::Deletion, vec![]),
+                        // a changed value keeps the expiration of the value
+                        Decision::ChangeValue(v) => match ttl::decode_value_with_expiry(&entry.value) {
+                            Some((_, expire_at)) if entry.value_type == ValueType::ValueWithExpiry => (
+                                ValueType::ValueWithExpiry,
+                                ttl::encode_value_with_expiry(&v, expire_at),
+                            ),
+                            _ => (ValueType::Value, v),
+                        },
+                    }
+                }
+                _ => (entry.value_type, entry.value),
+            };
+            if value_type == ValueType::Deletion && is_bottommost {
+                dropped_seq = dropped_seq.max(newest_seq);
+                continue;
+            }
+            if output.is_none() {
+                let mut out = self.open_output(new_file_number(), outputs)?;
+                out.meta.smallest = key.clone();
+                output = Some(out);
+            }
+            let out = output.as_mut().unwrap();
+            let seq = entry.seq;
+            out.builder.add(&key, value_type, seq, &value)?;
+            out.meta.largest = key;
+            out.meta.smallest_seqno = out.meta.smallest_seqno.min(seq);
+            out.meta.largest_seqno = out.meta.largest_seqno.max(seq);
+            if out.builder.estimated_file_size() >= self.config.target_file_size {
+                Self::finish_output(output.take().unwrap(), outputs)?;
+            }
+        }
+        if let Some(out) = output {
+            Self::finish_output(out, outputs)?;
+        }
+        Ok(dropped_seq)
+    }
+
+    // Creates the next output file, which is recorded in `outputs` to be removed on failure
+    fn open_output(&self, number: u64, outputs: &mut Vec<FileMetaData>) -> Result<Output, TinyError> {
+        let env: &dyn Env = self.config.env.as_ref();
+        let writer = TableFileWriter::create(
+            env,
+            &table_file_name(&self.config.dir, number),
+            self.config.use_direct_io_for_flush_and_compaction,
+        )?;
+        let meta = FileMetaData {
+            number,
+            file_size: 0,
+            smallest: vec![],
+            largest: vec![],
+            smallest_seqno: u64::MAX,
+            largest_seqno: 0,
+        };
+        outputs.push(meta.clone());
+        Ok(Output {
+            meta,
+            builder: TableBuilder::new(
+                self.config,
+                self.comparator.name(),
+                self.output_level,
+                writer,
+            ),
+        })
+    }
+
+    fn finish_output(out: Output, outputs: &mut [FileMetaData]) -> Result<(), TinyError> {
+        let mut meta = out.meta;
+        let (_, writer) = out.builder.finish()?;
+        meta.file_size = writer.finish()?;
+        // replace the placeholder recorded by `open_output`
+        let last = outputs.last_mut().unwrap();
+        invarint!(last.number == meta.number, "[compaction] output {} is not the last one", meta.number);
+        *last = meta;
+        Ok(())
+    }
+
+    #[inline]
+    fn compare(&self, a: &[u8], b: &[u8]) -> Ordering {
+        self.comparator.compare(&Slice::from(a), &Slice::from(b))
+    }
+}
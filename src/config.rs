@@ -1,6 +1,200 @@
+use crate::compaction::filter::CompactionFilterSource;
+use crate::env::posix::default_env;
+use crate::env::Env;
+use crate::table::block_cache::BlockCache;
+use crate::table::properties::TablePropertiesCollectorFactory;
+use crate::util::clock::{Clock, SystemClock};
+use crate::util::compression::{CompressionType, CompressorRegistry};
+use crate::util::direct_io::DEFAULT_COMPACTION_READAHEAD_SIZE;
+use crate::util::error::TinyError;
+use crate::util::filter_policy::FilterPolicy;
+use crate::util::merge_operator::MergeOperator;
+
+use std::sync::Arc;
+
+#[derive(Clone)]
 pub struct Config {
     /// Directory to store the main data in. Should exist and be writable.
     pub dir: String,
     /// Directory to store the value log in. Can be the same as Dir. Should exist and be writable.
     pub value_dir: String,
-}
\ No newline at end of file
+    /// The interface to the file system, the clock and the background threads.
+    /// Defaults to the local file system shared by the process.
+    pub env: Arc<dyn Env>,
+    /// The merge operator used to resolve the operands written by `DB::merge`.
+    /// `DB::merge` is rejected with `NotSupported` if it's not set.
+    pub merge_operator: Option<Arc<dyn MergeOperator>>,
+    /// The clock used to stamp and check the expiration of entries written by
+    /// `DB::put_with_ttl`. Defaults to the system clock.
+    pub clock: Arc<dyn Clock>,
+    /// The filter invoked for every key/value pair during compaction. Either a single
+    /// shared instance or a factory creating a filter per compaction.
+    pub compaction_filter: Option<CompactionFilterSource>,
+    /// The compression applied to the data blocks of the table files of each level.
+    /// Levels beyond the end use the last element and an empty vector disables compression.
+    pub compression_per_level: Vec<CompressionType>,
+    /// The codecs of the compression types, by which the blocks are compressed and
+    /// decompressed. A `CompressionType::Custom` in `compression_per_level` must be
+    /// registered, and so must the codecs of the existing table files.
+    pub compressors: CompressorRegistry,
+    /// The maximum size of the compression dictionary trained for each table file and
+    /// shared by all of its data blocks. 0 disables the dictionary compression.
+    pub compression_dict_max_bytes: usize,
+    /// How many bytes of key/value pairs are sampled to train the compression dictionary.
+    pub compression_dict_sample_bytes: usize,
+    /// If true, the checksums of all the data read internally (e.g. by the compactions and
+    /// the recovery) are verified and a mismatch fails the operation with `Corruption`.
+    pub paranoid_checks: bool,
+    /// The approximate size of the uncompressed data blocks of the table files.
+    pub block_size: usize,
+    /// The number of keys between restart points for delta encoding of keys in data blocks.
+    pub block_restart_interval: usize,
+    /// How the entries of a data block are indexed for point lookups.
+    pub data_block_index_type: DataBlockIndexType,
+    /// The number of keys per bucket of the data block hash index. Only used with
+    /// `DataBlockIndexType::BinaryAndHash`.
+    pub data_block_hash_table_util_ratio: f64,
+    /// The cache of the uncompressed blocks shared by all the table files.
+    pub block_cache: Arc<BlockCache>,
+    /// The filter consulted to skip the table files not containing the key of a point lookup.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// How the data blocks of a table file are indexed.
+    pub index_type: IndexType,
+    /// If true, the filter of a table file is partitioned and only the top-level index of
+    /// the partitions is pinned in memory, while the partitions are loaded through the
+    /// block cache on demand.
+    pub partition_filters: bool,
+    /// The target size of the partitions of the index and the filter.
+    pub metadata_block_size: usize,
+    /// The factories creating the collectors of the user properties of every table file.
+    pub table_properties_collector_factories: Vec<Arc<dyn TablePropertiesCollectorFactory>>,
+    /// If true, the table files are memory mapped and the uncompressed blocks are served
+    /// as views into the mapping instead of being copied. A mapping lives as long as the
+    /// file stays in the table cache or a block read from it is in use. Only supported by
+    /// the `Env`s backed by the local file system.
+    pub use_mmap_reads: bool,
+    /// The number of table files kept open by the table cache.
+    pub max_open_files: usize,
+    /// If true, the table files are read with O_DIRECT bypassing the page cache.
+    /// Only supported on Linux by the `Env`s backed by the local file system, and can't be
+    /// used together with `use_mmap_reads`.
+    pub use_direct_reads: bool,
+    /// If true, the flushes and compactions write their output and read their input
+    /// with O_DIRECT, so they don't evict the hot data from the page cache.
+    /// Only supported on Linux by the `Env`s backed by the local file system.
+    pub use_direct_io_for_flush_and_compaction: bool,
+    /// The size of the readahead buffer of the compaction inputs.
+    pub compaction_readahead_size: usize,
+    /// The size at which the output of a compaction is split into another table file.
+    pub target_file_size: u64,
+    /// The approximate size of the writes buffered in the memtable before it's flushed
+    /// into a table file in level 0.
+    pub write_buffer_size: usize,
+}
+
+impl Config {
+    /// Returns the compression for the data blocks of the table files in `level`
+    pub fn compression_for_level(&self, level: usize) -> CompressionType {
+        match self.compression_per_level.get(level) {
+            Some(t) => *t,
+            None => *self
+                .compression_per_level
+                .last()
+                .unwrap_or(&CompressionType::NoCompression),
+        }
+    }
+
+    /// Returns `InvalidArgument` if a compression in `compression_per_level` has no codec
+    /// in `compressors`
+    pub fn check_compression(&self) -> Result<(), TinyError> {
+        for t in self.compression_per_level.iter() {
+            if *t != CompressionType::NoCompression && self.compressors.get(*t).is_none() {
+                return Err(TinyError::InvalidArgument(format!(
+                    "[config] no codec is registered for {:?}",
+                    t
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the util ratio of the hash index to build into each data block,
+    /// or `None` if the data blocks don't have hash index
+    pub fn data_block_hash_util_ratio(&self) -> Option<f64> {
+        match self.data_block_index_type {
+            DataBlockIndexType::BinarySearch => None,
+            DataBlockIndexType::BinaryAndHash => Some(self.data_block_hash_table_util_ratio),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            dir: String::new(),
+            value_dir: String::new(),
+            env: default_env(),
+            merge_operator: None,
+            clock: Arc::new(SystemClock::new()),
+            compaction_filter: None,
+            compression_per_level: vec![],
+            compressors: CompressorRegistry::default(),
+            compression_dict_max_bytes: 0,
+            compression_dict_sample_bytes: 1 << 20,
+            paranoid_checks: false,
+            block_size: 4096,
+            block_restart_interval: 16,
+            data_block_index_type: DataBlockIndexType::BinarySearch,
+            data_block_hash_table_util_ratio: 0.75,
+            block_cache: Arc::new(BlockCache::new(8 << 20)),
+            filter_policy: None,
+            index_type: IndexType::BinarySearch,
+            partition_filters: false,
+            metadata_block_size: 4096,
+            table_properties_collector_factories: vec![],
+            use_mmap_reads: false,
+            max_open_files: 1000,
+            use_direct_reads: false,
+            use_direct_io_for_flush_and_compaction: false,
+            compaction_readahead_size: DEFAULT_COMPACTION_READAHEAD_SIZE,
+            target_file_size: 64 << 20,
+            write_buffer_size: 4 << 20,
+        }
+    }
+}
+
+/// The index of the entries of a data block
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DataBlockIndexType {
+    /// Binary search the restart points of the block
+    BinarySearch,
+    /// Additionally append a hash index mapping the keys to their restart intervals,
+    /// which lets point lookups skip the binary search
+    BinaryAndHash,
+}
+
+/// The index of the data blocks of a table file
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndexType {
+    /// A single index block, which is loaded into memory as a whole
+    BinarySearch,
+    /// A two-level index whose small top-level index is pinned in memory and the
+    /// partitions are loaded through the block cache on demand. Suited to large tables.
+    TwoLevelIndexSearch,
+}
+
+/// Options that control read operations
+#[derive(Clone, Copy, Default)]
+pub struct ReadOptions {
+    /// If true, all data read from underlying storage will be
+    /// verified against corresponding checksums.
+    pub verify_checksums: bool,
+}
+
+/// Options that control write operations
+#[derive(Clone, Copy, Default)]
+pub struct WriteOptions {
+    /// If true, the WAL is synced before the write returns, so the write survives a
+    /// machine crash. Otherwise it only survives a crash of the process.
+    pub sync: bool,
+}
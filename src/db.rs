@@ -1,24 +1,2707 @@
-use crate::config::Config;
+use crate::column_family::{
+    ColumnFamilyData, ColumnFamilyHandle, ColumnFamilyOptions, ColumnFamilySet,
+    DEFAULT_COLUMN_FAMILY_ID,
+};
+use crate::compaction::filter::{CompactionFilter, CompactionFilterContext};
+use crate::compaction::job::{BottommostLevelCompaction, CompactRangeOptions, CompactionJob};
+use crate::config::{Config, ReadOptions, WriteOptions};
+use crate::db_iter::DBIterator;
+use crate::entry::{encode_operands, Entry, MergeContext, ValueType};
+use crate::env::{copy_file, FileLock, WritableFile};
+use crate::filename::{
+    current_file_name, lock_file_name, log_file_name, manifest_file_name, parse_file_name,
+    table_file_name, value_log_file_name, FileType,
+};
+use crate::level::version::{FileMetaData, Version, VersionSet, NUM_LEVELS};
+use crate::level::version_edit::VersionEdit;
+use crate::mem::memtable::MemTable;
+use crate::record::reader::Reader;
+use crate::record::writer::Writer;
+use crate::table::merger::{EntrySource, MergingIterator};
+use crate::table::properties::TablePropertiesCollection;
+use crate::table::table_builder::TableBuilder;
+use crate::table::table_cache::{FileReadMode, TableCache, TableFile, TableFileWriter};
+use crate::table::table_reader::{Table, TableOptions};
+use crate::util::comparator::Comparator;
 use crate::util::error::TinyError;
 use crate::util::slice::Slice;
+use crate::util::ttl;
+use crate::write_batch::WriteBatch;
 
-pub struct DB {}
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io;
+use std::ops::DerefMut;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
 
-pub fn open_db(config: Config) -> DB {
-    DB::new()
+pub struct DB {
+    config: Config,
+    // all the column families share the WAL and the MANIFEST of the DB. Locked before
+    // `versions` if both are held.
+    column_families: Mutex<ColumnFamilySet>,
+    // serializes the writers so that a conditional write can check the
+    // latest value of the key without being interleaved by other writes.
+    // Holds the WAL being appended, which is None if the DB is not writable.
+    write_mutex: Mutex<Option<LogWriter>>,
+    // the lock on the LOCK file of `config.dir`, released when the DB is dropped
+    dir_lock: Option<FileLock>,
+    access_mode: AccessMode,
+    // the log numbers of the WAL files of the primary tailed by a secondary DB,
+    // mapped to the offsets replayed so far
+    tailed_logs: Mutex<BTreeMap<u64, u64>>,
+    // the number of the callers disabling the deletions of the obsolete files
+    file_deletions_disabled: Mutex<usize>,
+    versions: Mutex<VersionSet>,
+    // serializes the changes of the files in the levels, i.e. the ingestions and the
+    // compactions, each of which is computed from the current version and installed whole
+    file_mutex: Mutex<()>,
+    // the table files removed from the current version, which are deleted once no
+    // version refers to them and the file deletions are enabled
+    obsolete_files: Mutex<Vec<Arc<FileMetaData>>>,
+    table_cache: TableCache,
 }
 
+// The writer of the WAL file being appended
+type LogWriter = Writer<Box<dyn WritableFile>>;
+
+// A table file to be ingested
+struct ExternalFile {
+    path: String,
+    file_size: u64,
+    smallest: Vec<u8>,
+    largest: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum AccessMode {
+    ReadWrite,
+    // no file is written, e.g. by the writes, flushes or compactions
+    ReadOnly,
+    // read-only and following the changes of the primary DB opened on the same directory
+    Secondary,
+}
+
+/// Opens the DB in `config.dir`, which is created if missing.
+///
+/// The DB holds an exclusive lock on the LOCK file in the directory until dropped.
+/// Returns `Busy` if the directory is locked by another process or another DB in
+/// this process.
+pub fn open_db(config: Config) -> Result<DB, TinyError> {
+    if config.dir.is_empty() {
+        return Err(TinyError::InvalidArgument(
+            "[db] Config::dir is empty".to_owned(),
+        ));
+    }
+    FileReadMode::new(config.use_mmap_reads, config.use_direct_reads)?;
+    config.check_compression()?;
+    config.env.create_dir(&config.dir)?;
+    let lock = match config.env.lock_file(&lock_file_name(&config.dir)) {
+        Ok(lock) => lock,
+        Err(TinyError::IOError(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+            return Err(TinyError::Busy(format!(
+                "[db] {} is in use: {}",
+                config.dir, e
+            )))
+        }
+        Err(e) => return Err(e),
+    };
+    let mut db = DB::new(config);
+    db.dir_lock = Some(lock);
+    db.recover_versions()?;
+    db.mark_file_numbers_used()?;
+    db.versions
+        .lock()
+        .unwrap()
+        .create_manifest(db.config.env.as_ref(), &db.config.dir)?;
+    db.recover_logs()?;
+    // the recovered writes are flushed so that the recovered logs can be removed
+    let mut log = db.write_mutex.lock().unwrap();
+    db.flush_memtable(&mut log)?;
+    drop(log);
+    Ok(db)
+}
+
+/// Opens the existing DB in `config.dir` without modifying it. The state is recovered
+/// from the MANIFEST and the WAL into memory, and nothing is flushed or compacted, so
+/// the directory may be read-only or be opened by a read-write DB at the same time
+/// (whose later writes are not visible). All the writes fail with `NotSupported`.
+pub fn open_db_read_only(config: Config) -> Result<DB, TinyError> {
+    check_existing_dir(&config)?;
+    let mut db = DB::new(config);
+    db.access_mode = AccessMode::ReadOnly;
+    db.recover_versions()?;
+    db.mark_file_numbers_used()?;
+    db.recover_logs()?;
+    Ok(db)
+}
+
+/// Opens the DB in `config.dir` as a secondary of the primary DB opened on the same
+/// directory, usually by another process. The secondary doesn't write any file and
+/// rejects the writes with `NotSupported` like a read-only DB, but it can follow the
+/// primary by `DB::try_catch_up_with_primary`.
+pub fn open_db_as_secondary(config: Config) -> Result<DB, TinyError> {
+    check_existing_dir(&config)?;
+    let mut db = DB::new(config);
+    db.access_mode = AccessMode::Secondary;
+    db.recover_versions()?;
+    db.try_catch_up_with_primary()?;
+    Ok(db)
+}
+
+fn check_existing_dir(config: &Config) -> Result<(), TinyError> {
+    if config.dir.is_empty() {
+        return Err(TinyError::InvalidArgument(
+            "[db] Config::dir is empty".to_owned(),
+        ));
+    }
+    if !config.env.file_exists(&config.dir) {
+        return Err(TinyError::InvalidArgument(format!(
+            "[db] {} does not exist",
+            config.dir
+        )));
+    }
+    FileReadMode::new(config.use_mmap_reads, config.use_direct_reads)?;
+    Ok(())
+}
 
 impl DB {
-    pub fn new() -> DB {
-        DB {}
+    fn new(config: Config) -> DB {
+        let default_options = ColumnFamilyOptions {
+            merge_operator: config.merge_operator.clone(),
+            compaction_filter: config.compaction_filter.clone(),
+            ..ColumnFamilyOptions::default()
+        };
+        // the conflicting read modes are rejected by open_db
+        let read_mode = FileReadMode::new(config.use_mmap_reads, config.use_direct_reads)
+            .unwrap_or(FileReadMode::Buffered);
+        let table_cache = TableCache::new(
+            config.env.clone(),
+            &config.dir,
+            config.max_open_files,
+            read_mode,
+        )
+        .with_block_cache(config.block_cache.clone())
+        .with_filter_policy(config.filter_policy.clone())
+        .with_compressors(config.compressors.clone());
+        DB {
+            config,
+            column_families: Mutex::new(ColumnFamilySet::new(default_options)),
+            write_mutex: Mutex::new(None),
+            dir_lock: None,
+            access_mode: AccessMode::ReadWrite,
+            tailed_logs: Mutex::new(BTreeMap::new()),
+            file_deletions_disabled: Mutex::new(0),
+            versions: Mutex::new(VersionSet::new()),
+            file_mutex: Mutex::new(()),
+            obsolete_files: Mutex::new(vec![]),
+            table_cache,
+        }
+    }
+
+    // Replays the MANIFEST if the DB exists. The options of the column families are not
+    // persisted, so the column families other than the default one are recovered with
+    // `ColumnFamilyOptions::default()`.
+    fn recover_versions(&self) -> Result<(), TinyError> {
+        let cmp = self.default_comparator();
+        let mut families = self.column_families.lock().unwrap();
+        let mut versions = self.versions.lock().unwrap();
+        versions.recover(self.config.env.as_ref(), &self.config.dir, cmp.as_ref())?;
+        for (id, name) in versions.column_families() {
+            if id != DEFAULT_COLUMN_FAMILY_ID {
+                families.recover(id, name, ColumnFamilyOptions::default())?;
+            }
+        }
+        families.mark_id_used(versions.max_column_family());
+        Ok(())
+    }
+
+    // Replays the WAL files not flushed yet into the memtables
+    fn recover_logs(&self) -> Result<(), TinyError> {
+        let log_number = self.versions.lock().unwrap().log_number();
+        for number in self.list_files(&self.config.dir, FileType::Log)? {
+            if number < log_number {
+                continue;
+            }
+            let file = self
+                .config
+                .env
+                .new_sequential_file(&log_file_name(&self.config.dir, number))?;
+            let mut reader = Reader::new(file, true);
+            // a record torn by a crash ends the log
+            while let Some(record) = reader.read_record()? {
+                let batch = WriteBatch::from_data(record)?;
+                self.insert_into_memtable(&batch, Some(number));
+            }
+        }
+        Ok(())
+    }
+
+    // Inserts the changes of the batch into the memtables of their column families and
+    // advances the last sequence. A batch replayed from the WAL of `replayed_log` skips
+    // the column families which have flushed the log, and so do all the batches for the
+    // dropped column families.
+    fn insert_into_memtable(&self, batch: &WriteBatch, replayed_log: Option<u64>) {
+        let flushed_logs: HashMap<u32, u64> = match replayed_log {
+            Some(_) => {
+                let versions = self.versions.lock().unwrap();
+                versions
+                    .column_families()
+                    .filter_map(|(id, _)| versions.column_family_log_number(id).map(|n| (id, n)))
+                    .collect()
+            }
+            None => HashMap::new(),
+        };
+        let is_flushed = |cf: u32| {
+            replayed_log.is_some_and(|log| flushed_logs.get(&cf).is_none_or(|n| log < *n))
+        };
+        let families = self.column_families.lock().unwrap();
+        // the memtables changed are locked together so the batch becomes visible at once
+        let mut mems = HashMap::new();
+        for entry in batch.iter().flatten() {
+            let cf = entry.column_family;
+            if is_flushed(cf) || mems.contains_key(&cf) {
+                continue;
+            }
+            if let Some(family) = families.get_by_id(cf) {
+                mems.insert(cf, family.mem().write().unwrap());
+            }
+        }
+        let last_sequence = Self::add_to_memtables(batch, &mut mems, |_| false);
+        drop(mems);
+        drop(families);
+        let mut versions = self.versions.lock().unwrap();
+        if last_sequence > versions.last_sequence() {
+            versions.set_last_sequence(last_sequence);
+        }
+    }
+
+    // Adds the changes of the batch into the memtables of their column families in `mems`
+    // unless `is_flushed` tells the column family has flushed them, and returns the
+    // sequence of the last change
+    fn add_to_memtables<M: DerefMut<Target = MemTable>>(
+        batch: &WriteBatch,
+        mems: &mut HashMap<u32, M>,
+        is_flushed: impl Fn(u32) -> bool,
+    ) -> u64 {
+        let mut seq = batch.sequence();
+        // the batch is validated when it's built or read from the WAL
+        let mut operands = vec![];
+        for entry in batch.iter().flatten() {
+            let mem = match mems.get_mut(&entry.column_family) {
+                Some(mem) if !is_flushed(entry.column_family) => mem,
+                _ => {
+                    seq += 1;
+                    continue;
+                }
+            };
+            // a merge entry holds a list of operands
+            let value = match entry.value_type {
+                ValueType::Merge => {
+                    operands.clear();
+                    encode_operands(&[entry.value], &mut operands);
+                    operands.as_slice()
+                }
+                _ => entry.value,
+            };
+            mem.add(entry.key, Entry::new(entry.value_type, seq, value));
+            seq += 1;
+        }
+        seq - 1
+    }
+
+    // Makes sure no new file takes the number of a file already in the directory
+    // besides the ones recovered from the MANIFEST, e.g. the outputs left by a crash
+    fn mark_file_numbers_used(&self) -> Result<(), TinyError> {
+        let mut versions = self.versions.lock().unwrap();
+        for name in self.config.env.get_children(&self.config.dir)? {
+            if let Some((_, number)) = parse_file_name(&name) {
+                versions.mark_file_number_used(number);
+            }
+        }
+        Ok(())
+    }
+
+    fn default_family(&self) -> Arc<ColumnFamilyData> {
+        self.column_families.lock().unwrap().default_family().clone()
+    }
+
+    // Returns the column family of `handle` or `InvalidArgument` if it's dropped
+    fn column_family(&self, handle: &ColumnFamilyHandle) -> Result<Arc<ColumnFamilyData>, TinyError> {
+        self.column_families.lock().unwrap().get(handle).cloned()
+    }
+
+    fn default_comparator(&self) -> Arc<dyn Comparator<Slice> + Send + Sync> {
+        self.column_families
+            .lock()
+            .unwrap()
+            .default_family()
+            .options()
+            .comparator
+            .clone()
+    }
+
+    #[inline]
+    pub(crate) fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Serializes a write, which is rejected if the DB is opened read-only
+    fn lock_writes(&self) -> Result<MutexGuard<'_, Option<LogWriter>>, TinyError> {
+        self.check_writable()?;
+        Ok(self.write_mutex.lock().unwrap())
     }
+
+    fn check_writable(&self) -> Result<(), TinyError> {
+        match self.access_mode {
+            AccessMode::ReadWrite => Ok(()),
+            AccessMode::ReadOnly => Err(TinyError::NotSupported(
+                "[db] the DB is opened read-only".to_owned(),
+            )),
+            AccessMode::Secondary => Err(TinyError::NotSupported(
+                "[db] the DB is opened as a secondary".to_owned(),
+            )),
+        }
+    }
+
+    /// Replays the changes of the primary made since the last call: the new edits of
+    /// the MANIFEST and the new records of the WAL files. A record still being written
+    /// by the primary is left to the next call. Only supported by a secondary DB.
+    ///
+    /// The readers wait until the changes are replayed, which become visible at once.
+    pub fn try_catch_up_with_primary(&self) -> Result<(), TinyError> {
+        if self.access_mode != AccessMode::Secondary {
+            return Err(TinyError::NotSupported(
+                "[db] only a secondary DB can catch up with the primary".to_owned(),
+            ));
+        }
+        let env = self.config.env.as_ref();
+        let mut tailed_logs = self.tailed_logs.lock().unwrap();
+        let mut families = self.column_families.lock().unwrap();
+        // the memtables are locked before the versions as the readers do
+        let locked: Vec<_> = families.iter().cloned().collect();
+        // the column families created by the primary, whose memtables are locked later
+        let created: Vec<Arc<ColumnFamilyData>>;
+        let mut mems: HashMap<_, _> = locked
+            .iter()
+            .map(|family| (family.handle().id(), family.mem().write().unwrap()))
+            .collect();
+        let mut versions = self.versions.lock().unwrap();
+        let log_numbers: Vec<_> = versions
+            .column_families()
+            .filter_map(|(id, _)| versions.column_family_log_number(id).map(|n| (id, n)))
+            .collect();
+        let cmp = families.default_family().options().comparator.clone();
+        versions.catch_up(env, &self.config.dir, cmp.as_ref())?;
+
+        // follow the column families created and dropped by the primary
+        let dropped: Vec<_> = families
+            .iter()
+            .filter(|f| versions.column_family_log_number(f.handle().id()).is_none())
+            .map(|f| f.handle().clone())
+            .collect();
+        for handle in dropped {
+            mems.remove(&handle.id());
+            families.remove(&handle)?;
+        }
+        let new_families: Vec<_> = versions
+            .column_families()
+            .filter(|(id, _)| families.get_by_id(*id).is_none())
+            .map(|(id, name)| (id, name.to_owned()))
+            .collect();
+        for (id, name) in new_families {
+            families.recover(id, &name, ColumnFamilyOptions::default())?;
+        }
+        families.mark_id_used(versions.max_column_family());
+        created = families
+            .iter()
+            .filter(|f| !mems.contains_key(&f.handle().id()))
+            .cloned()
+            .collect();
+        // no reader can reach the new column families before the set is unlocked
+        for family in created.iter() {
+            mems.insert(family.handle().id(), family.mem().write().unwrap());
+        }
+
+        // the flushed writes are read from the table files of the new versions, so the
+        // memtables are rebuilt from the WAL files not flushed yet
+        let flushed = log_numbers
+            .iter()
+            .any(|(id, n)| versions.column_family_log_number(*id).is_some_and(|m| m != *n));
+        if flushed {
+            for family in families.iter() {
+                let mem = mems.get_mut(&family.handle().id()).unwrap();
+                **mem = MemTable::new(family.options().comparator.clone());
+            }
+            tailed_logs.clear();
+        }
+        let numbers = self.list_files(&self.config.dir, FileType::Log)?;
+        // the logs removed by the primary have been flushed
+        tailed_logs.retain(|number, _| numbers.binary_search(number).is_ok());
+        let log_number = versions.log_number();
+        for number in numbers.into_iter().filter(|n| *n >= log_number) {
+            let path = log_file_name(&self.config.dir, number);
+            let offset = tailed_logs.get(&number).cloned().unwrap_or(0);
+            let mut file = match env.new_sequential_file(&path) {
+                Ok(file) => file,
+                // removed after listed
+                Err(TinyError::IOError(ref e)) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            file.skip(offset)?;
+            let mut reader = Reader::with_offset(file, true, offset);
+            while let Some(record) = reader.read_record()? {
+                let batch = WriteBatch::from_data(record)?;
+                let is_flushed = |cf| versions.column_family_log_number(cf).is_none_or(|n| number < n);
+                let last_sequence = Self::add_to_memtables(&batch, &mut mems, is_flushed);
+                if last_sequence > versions.last_sequence() {
+                    versions.set_last_sequence(last_sequence);
+                }
+            }
+            tailed_logs.insert(number, reader.last_record_end());
+        }
+        Ok(())
+    }
+
     pub fn write(&self, key: Slice, value: Slice) -> Result<(), TinyError> {
-        println!("[write] key: {:?}, value: {:?}", &key, &value);
+        let mut batch = WriteBatch::new();
+        batch.put(key.to_slice(), value.to_slice());
+        self.write_batch(&WriteOptions::default(), batch)
+    }
+    /// Applies all the changes of the batch atomically: they are appended to the WAL as
+    /// one record and become visible together.
+    pub fn write_batch(&self, options: &WriteOptions, batch: WriteBatch) -> Result<(), TinyError> {
+        let mut log = self.lock_writes()?;
+        self.apply_batch(&mut log, options, batch)
+    }
+    /// Writes the key/value pair which is regarded as absent once `ttl` has elapsed.
+    /// The expiration timestamp is taken from `Config::clock` and stored alongside the value
+    /// in the format of `util::ttl::encode_value_with_expiry`.
+    pub fn put_with_ttl(&self, key: Slice, value: Slice, ttl: Duration) -> Result<(), TinyError> {
+        let expire_at = ttl::expire_at(self.config.clock.now_micros(), ttl);
+        let encoded = ttl::encode_value_with_expiry(value.to_slice(), expire_at);
+        let mut batch = WriteBatch::new();
+        batch.add(DEFAULT_COLUMN_FAMILY_ID, ValueType::ValueWithExpiry, key.to_slice(), &encoded);
+        self.write_batch(&WriteOptions::default(), batch)
+    }
+    /// Appends a merge operand for the given key. The operands are combined with
+    /// the existing value by the configured `MergeOperator` when the key is read,
+    /// flushed or compacted.
+    pub fn merge(&self, key: Slice, operand: Slice) -> Result<(), TinyError> {
+        if self.config.merge_operator.is_none() {
+            return Err(TinyError::NotSupported(
+                "DB::merge requires a merge operator in config".to_owned(),
+            ));
+        }
+        let mut batch = WriteBatch::new();
+        batch.merge(key.to_slice(), operand.to_slice());
+        self.write_batch(&WriteOptions::default(), batch)
+    }
+    pub fn delete(&self, key: Slice) -> Result<(), TinyError> {
+        let mut batch = WriteBatch::new();
+        batch.delete(key.to_slice());
+        self.write_batch(&WriteOptions::default(), batch)
+    }
+    /// Writes the key/value pair only if the key doesn't exist.
+    /// Returns whether the value is written.
+    pub fn put_if_absent(&self, key: Slice, value: Slice) -> Result<bool, TinyError> {
+        let mut log = self.lock_writes()?;
+        if self.get(key.clone())?.is_some() {
+            return Ok(false);
+        }
+        let mut batch = WriteBatch::new();
+        batch.put(key.to_slice(), value.to_slice());
+        self.apply_batch(&mut log, &WriteOptions::default(), batch).map(|_| true)
+    }
+    /// Replaces the value of the key with `new` only if the current value equals `expected`.
+    /// Returns whether the value is replaced.
+    pub fn compare_and_swap(&self, key: Slice, expected: Slice, new: Slice) -> Result<bool, TinyError> {
+        let mut log = self.lock_writes()?;
+        match self.get(key.clone())? {
+            Some(current) if current == expected.to_slice() => {
+                let mut batch = WriteBatch::new();
+                batch.put(key.to_slice(), new.to_slice());
+                self.apply_batch(&mut log, &WriteOptions::default(), batch).map(|_| true)
+            }
+            _ => Ok(false),
+        }
+    }
+    /// Deletes the key only if its current value equals `expected`.
+    /// Returns whether the key is deleted.
+    pub fn delete_if_equal(&self, key: Slice, expected: Slice) -> Result<bool, TinyError> {
+        let mut log = self.lock_writes()?;
+        match self.get(key.clone())? {
+            Some(current) if current == expected.to_slice() => {
+                let mut batch = WriteBatch::new();
+                batch.delete(key.to_slice());
+                self.apply_batch(&mut log, &WriteOptions::default(), batch).map(|_| true)
+            }
+            _ => Ok(false),
+        }
+    }
+    // Applies the batch atomically only if `validate` succeeds, which runs with the writes
+    // serialized so that no other write comes in between, e.g. the conflict check of an
+    // optimistic transaction
+    pub(crate) fn write_batch_if(
+        &self,
+        options: &WriteOptions,
+        batch: WriteBatch,
+        validate: impl FnOnce() -> Result<(), TinyError>,
+    ) -> Result<(), TinyError> {
+        let mut log = self.lock_writes()?;
+        validate()?;
+        self.apply_batch(&mut log, options, batch)
+    }
+
+    // Returns the sequence of the last write applied
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.versions.lock().unwrap().last_sequence()
+    }
+
+    // Returns the sequence of the latest entry of the key in the column family. If the key
+    // has no entry, which might have been dropped by a compaction, e.g. a deletion in the
+    // bottommost level, the largest sequence of the keys dropped so far is returned instead.
+    // Returns `InvalidArgument` if the column family is dropped.
+    pub(crate) fn latest_sequence_of(&self, handle: &ColumnFamilyHandle, key: &[u8]) -> Result<u64, TinyError> {
+        let family = self.column_family(handle)?;
+        let version = {
+            let mem = family.mem().read().unwrap();
+            if let Some(entry) = mem.get(key).and_then(|entries| entries.last()) {
+                return Ok(entry.seq);
+            }
+            self.versions.lock().unwrap().current(handle.id())
+        };
+        let cmp = family.options().comparator.as_ref();
+        for file in version.files_for_key(key, cmp) {
+            let table = self.table_cache.find_table(
+                file.number,
+                file.file_size,
+                self.config.paranoid_checks,
+            )?;
+            if let Some(entry) = table.get(&ReadOptions::default(), key, cmp)? {
+                // the entries of an ingested file are written at 0 and take the file's seqno
+                return Ok(entry.seq.max(file.smallest_seqno));
+            }
+        }
+        Ok(family.dropped_seq())
+    }
+
+    /// Returns the latest value of the key in the default column family, looked up in the
+    /// memtable first and then in the table files from the newest to the oldest. The merge
+    /// operands found on the way are applied onto the value they reach, and an expired value
+    /// written by `put_with_ttl` is regarded as deleted.
+    pub fn get(&self, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        self.get_opt(&ReadOptions::default(), key)
+    }
+
+    /// Returns the latest value of the key in the default column family, reading the table
+    /// files as `options` asks. See `get`.
+    /// Returns `TinyError::Corruption` if a block read fails the checksum verification.
+    pub fn get_opt(&self, options: &ReadOptions, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        self.get_from(options, &self.default_family(), key.to_slice())
+    }
+
+    fn get_from(
+        &self,
+        read_options: &ReadOptions,
+        family: &ColumnFamilyData,
+        key: &[u8],
+    ) -> Result<Option<Vec<u8>>, TinyError> {
+        let options = family.options();
+        let now = self.config.clock.now_micros();
+        let mut ctx = MergeContext::new(key, options.merge_operator.as_deref());
+        // the version is taken with the memtable locked, and a flush installs the version
+        // and empties the memtable at once, so every write is seen exactly once
+        let version = {
+            let mem = family.mem().read().unwrap();
+            if let Some(entries) = mem.get(key) {
+                for entry in entries.iter().rev() {
+                    ctx.add(entry.clone().expire(now))?;
+                }
+            }
+            self.versions.lock().unwrap().current(family.handle().id())
+        };
+        if !ctx.is_done() {
+            let cmp = options.comparator.as_ref();
+            for file in version.files_for_key(key, cmp) {
+                let table = self.table_cache.find_table(
+                    file.number,
+                    file.file_size,
+                    self.config.paranoid_checks,
+                )?;
+                if let Some(entry) = table.get(read_options, key, cmp)? {
+                    if ctx.add(entry.expire(now))? {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(ctx.finish(true)?.and_then(Entry::into_user_value))
+    }
+
+    /// Returns an iterator over the live keys of the default column family and their
+    /// values in the key order. See `DBIterator`.
+    pub fn iter(&self) -> Result<DBIterator<'_>, TinyError> {
+        self.iter_opt(&ReadOptions::default())
+    }
+
+    /// Returns an iterator over the live keys of the default column family, which reads
+    /// the table files as `options` asks. See `iter`.
+    pub fn iter_opt(&self, options: &ReadOptions) -> Result<DBIterator<'_>, TinyError> {
+        self.iter_from(options, &self.default_family())
+    }
+
+    /// Returns an iterator over the live keys of the column family and their values in
+    /// the key order. See `DBIterator`.
+    pub fn iter_cf(&self, handle: &ColumnFamilyHandle) -> Result<DBIterator<'_>, TinyError> {
+        self.iter_cf_opt(&ReadOptions::default(), handle)
+    }
+
+    /// Returns an iterator over the live keys of the column family, which reads the table
+    /// files as `options` asks. See `iter`.
+    pub fn iter_cf_opt(
+        &self,
+        options: &ReadOptions,
+        handle: &ColumnFamilyHandle,
+    ) -> Result<DBIterator<'_>, TinyError> {
+        let family = self.column_family(handle)?;
+        self.iter_from(options, &family)
+    }
+
+    fn iter_from(&self, read_options: &ReadOptions, family: &ColumnFamilyData) -> Result<DBIterator<'_>, TinyError> {
+        let options = family.options();
+        let (mem, version) = {
+            let mem = family.mem().read().unwrap();
+            let entries: Vec<_> = mem
+                .iter()
+                .flat_map(|(key, entries)| entries.iter().rev().map(move |e| Ok((key.to_vec(), e.clone()))))
+                .collect();
+            (entries, self.versions.lock().unwrap().current(family.handle().id()))
+        };
+        // the sources from the newest to the oldest
+        let mut sources: Vec<EntrySource<'_>> = vec![Box::new(mem.into_iter())];
+        let paranoid_checks = self.config.paranoid_checks;
+        for file in version.files(0).iter().rev() {
+            let table = self.table_cache.find_table(file.number, file.file_size, paranoid_checks)?;
+            sources.push(Box::new(table.iter(read_options)?));
+        }
+        for level in 1..NUM_LEVELS {
+            let files = version.files(level).to_vec();
+            if files.is_empty() {
+                continue;
+            }
+            // the files of the level are opened one by one as the iteration reaches them
+            let table_cache = &self.table_cache;
+            let read_options = *read_options;
+            let level_entries = files.into_iter().flat_map(move |file| -> EntrySource<'_> {
+                match table_cache
+                    .find_table(file.number, file.file_size, paranoid_checks)
+                    .and_then(|table| table.iter(&read_options))
+                {
+                    Ok(iter) => Box::new(iter),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            });
+            sources.push(Box::new(level_entries));
+        }
+        let cmp = options.comparator.clone();
+        let merged = MergingIterator::new(
+            sources,
+            Box::new(move |a: &[u8], b: &[u8]| cmp.compare(&Slice::from(a), &Slice::from(b))),
+        );
+        let now = self.config.clock.now_micros();
+        Ok(DBIterator::new(merged, options.merge_operator.clone(), now, version))
+    }
+
+    /// Adds the table files written by `SstFileWriter` to the default column family.
+    /// The files are copied into the DB and the external files are left untouched.
+    ///
+    /// All the entries of the files get one new sequence number, so they hide the earlier
+    /// writes of the same keys. The memtable is flushed first if it has a key in the range
+    /// of a file, so that the older writes in the memtable don't hide the file. Every file is placed in the deepest level such that neither
+    /// the level nor the levels above overlap the file, or level 0 if a file in level 0
+    /// overlaps it. The files become visible all at once.
+    ///
+    /// Returns `InvalidArgument` if a file is empty, is ordered by another comparator or
+    /// overlaps another file being ingested.
+    pub fn ingest_external_file(&self, paths: &[&str]) -> Result<(), TinyError> {
+        let mut log = self.lock_writes()?;
+        let cmp = self.default_comparator();
+        let mut files = Vec::with_capacity(paths.len());
+        for path in paths {
+            files.push(self.read_external_file(path, cmp.as_ref())?);
+        }
+        let compare = |a: &[u8], b: &[u8]| cmp.compare(&Slice::from(a), &Slice::from(b));
+        files.sort_by(|a, b| compare(&a.smallest, &b.smallest));
+        for pair in files.windows(2) {
+            if compare(&pair[0].largest, &pair[1].smallest) != Ordering::Less {
+                return Err(TinyError::InvalidArgument(format!(
+                    "[db] the ingested files {} and {} overlap",
+                    pair[0].path, pair[1].path
+                )));
+            }
+        }
+        let overlaps_mem = {
+            let family = self.default_family();
+            let mem = family.mem().read().unwrap();
+            files.iter().any(|f| mem.overlaps(Some(&f.smallest), Some(&f.largest)))
+        };
+        if overlaps_mem {
+            self.flush_memtable(&mut log)?;
+        }
+        let _files_guard = self.file_mutex.lock().unwrap();
+
+        let numbers: Vec<u64> = {
+            let mut versions = self.versions.lock().unwrap();
+            files.iter().map(|_| versions.new_file_number()).collect()
+        };
+        let env = self.config.env.as_ref();
+        for (i, (file, number)) in files.iter().zip(numbers.iter()).enumerate() {
+            let target = table_file_name(&self.config.dir, *number);
+            if let Err(e) = copy_file(env, &file.path, &target, Some(file.file_size)) {
+                for number in numbers[..=i].iter() {
+                    let _ = env.remove_file(&table_file_name(&self.config.dir, *number));
+                }
+                return Err(e);
+            }
+        }
+
+        let mut versions = self.versions.lock().unwrap();
+        let seq = versions.last_sequence() + 1;
+        let mut version = (*versions.current(DEFAULT_COLUMN_FAMILY_ID)).clone();
+        let mut edit = VersionEdit::new();
+        for (file, number) in files.into_iter().zip(numbers.iter()) {
+            let level = version.pick_level_for_file(&file.smallest, &file.largest, cmp.as_ref());
+            let meta = FileMetaData {
+                number: *number,
+                file_size: file.file_size,
+                smallest: file.smallest,
+                largest: file.largest,
+                smallest_seqno: seq,
+                largest_seqno: seq,
+            };
+            version.add_file(level, Arc::new(meta.clone()), cmp.as_ref());
+            edit.add_file(level, meta);
+        }
+        edit.last_sequence = Some(seq);
+        if let Err(e) = versions.log_and_apply(&mut edit, cmp.as_ref()) {
+            for number in numbers.iter() {
+                let _ = env.remove_file(&table_file_name(&self.config.dir, *number));
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Compacts the keys of the default column family in [`begin`, `end`]. See
+    /// `compact_range_cf`.
+    pub fn compact_range(
+        &self,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: &CompactRangeOptions,
+    ) -> Result<(), TinyError> {
+        self.compact_range_cf(&self.default_column_family(), begin, end, options)
+    }
+
+    /// Compacts the keys of the column family in [`begin`, `end`] down to the bottommost
+    /// level holding them, so the space taken by the overwritten values and the entries
+    /// removed by the compaction filter is reclaimed. A `None` bound is unbounded. The
+    /// memtables are flushed first if the one of the column family has a key in the range.
+    ///
+    /// The files overlapping the range are merged into the next level level by level.
+    /// Level 0 is compacted as a whole since its files may overlap each other, and it's
+    /// never the bottommost level. The files in the bottommost level are rewritten as
+    /// `options.bottommost_level_compaction` tells and are then moved to
+    /// `options.target_level` if it's set.
+    pub fn compact_range_cf(
+        &self,
+        handle: &ColumnFamilyHandle,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+        options: &CompactRangeOptions,
+    ) -> Result<(), TinyError> {
+        self.check_writable()?;
+        let family = self.column_family(handle)?;
+        if let Some(level) = options.target_level {
+            if level >= NUM_LEVELS {
+                return Err(TinyError::InvalidArgument(format!(
+                    "[db] target level {} is out of {} levels",
+                    level, NUM_LEVELS
+                )));
+            }
+        }
+        if family.mem().read().unwrap().overlaps(begin, end) {
+            let mut log = self.lock_writes()?;
+            self.flush_memtable(&mut log)?;
+        }
+        let _guard = self.file_mutex.lock().unwrap();
+        // dropped while waiting for the lock
+        self.column_family(handle)?;
+        let cmp = family.options().comparator.as_ref();
+        let last_level = {
+            let version = self.versions.lock().unwrap().current(handle.id());
+            (0..NUM_LEVELS)
+                .rev()
+                .find(|l| !version.overlapping_files(*l, begin, end, cmp).is_empty())
+        };
+        let last_level = match last_level {
+            Some(level) => level,
+            None => return Ok(()),
+        };
+        let context = CompactionFilterContext {
+            is_full_compaction: begin.is_none() && end.is_none(),
+            is_manual_compaction: true,
+        };
+        let filter = family.options().compaction_filter.as_ref().map(|s| s.filter_for(&context));
+
+        let bottommost = last_level.max(1);
+        for level in 0..bottommost {
+            self.compact_level(&family, level, level + 1, begin, end, filter.clone())?;
+        }
+        let rewrite_bottommost = match options.bottommost_level_compaction {
+            BottommostLevelCompaction::Skip => false,
+            BottommostLevelCompaction::IfHaveCompactionFilter => filter.is_some(),
+            BottommostLevelCompaction::Force => true,
+        };
+        // the files compacted out of level 0 just now need no rewrite
+        if rewrite_bottommost && last_level > 0 {
+            self.compact_level(&family, bottommost, bottommost, begin, end, filter)?;
+        }
+        if let Some(target) = options.target_level {
+            self.move_files_to_level(&family, bottommost, target, begin, end)?;
+        }
+        Ok(())
+    }
+
+    // Compacts the files of the column family in `level` overlapping [`begin`, `end`]
+    // together with the files they overlap in `output_level`, which is either the next
+    // level or `level` itself. Must be called with `file_mutex` held.
+    fn compact_level(
+        &self,
+        family: &ColumnFamilyData,
+        level: usize,
+        output_level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+        filter: Option<Arc<dyn CompactionFilter>>,
+    ) -> Result<(), TinyError> {
+        let cmp = family.options().comparator.as_ref();
+        // the inputs in `level` come first, of which there are `num_level_inputs`
+        let version = self.versions.lock().unwrap().current(family.handle().id());
+        let (inputs, num_level_inputs) = {
+            let mut inputs = version.overlapping_files(level, begin, end, cmp);
+            if level == 0 && !inputs.is_empty() {
+                // newest first
+                inputs = version.files(0).iter().rev().cloned().collect();
+            }
+            if inputs.is_empty() {
+                return Ok(());
+            }
+            let num_level_inputs = inputs.len();
+            if output_level != level {
+                let compare = |a: &[u8], b: &[u8]| cmp.compare(&Slice::from(a), &Slice::from(b));
+                let smallest = inputs.iter().map(|f| &f.smallest).min_by(|a, b| compare(a, b));
+                let largest = inputs.iter().map(|f| &f.largest).max_by(|a, b| compare(a, b));
+                let overlapped = version.overlapping_files(
+                    output_level,
+                    smallest.map(|k| k.as_slice()),
+                    largest.map(|k| k.as_slice()),
+                    cmp,
+                );
+                inputs.extend(overlapped);
+            }
+            (inputs, num_level_inputs)
+        };
+        let job = CompactionJob {
+            config: &self.config,
+            table_cache: &self.table_cache,
+            comparator: cmp,
+            filter,
+            merge_operator: family.options().merge_operator.clone(),
+            output_level,
+            inputs,
+            version,
+        };
+        let (outputs, dropped_seq) = job.run(&mut || self.versions.lock().unwrap().new_file_number())?;
+        let inputs = job.inputs;
+        // the inputs are deleted once no version refers to them
+        drop(job.version);
+
+        let mut edit = VersionEdit::new();
+        edit.column_family = family.handle().id();
+        for (i, f) in inputs.iter().enumerate() {
+            edit.delete_file(if i < num_level_inputs { level } else { output_level }, f.number);
+        }
+        for f in outputs.iter() {
+            edit.add_file(output_level, f.clone());
+        }
+        // recorded before the dropped keys disappear from the current version
+        family.record_dropped_seq(dropped_seq);
+        if let Err(e) = self.versions.lock().unwrap().log_and_apply(&mut edit, cmp) {
+            for f in outputs.iter() {
+                let _ = self.config.env.remove_file(&table_file_name(&self.config.dir, f.number));
+            }
+            return Err(e);
+        }
+        self.delete_obsolete_files(inputs);
+        Ok(())
+    }
+
+    // Moves the files of the column family in `level` overlapping [`begin`, `end`] to
+    // `target`, which fails if the files overlap a file in `target` or a level in between.
+    // Must be called with `file_mutex` held.
+    fn move_files_to_level(
+        &self,
+        family: &ColumnFamilyData,
+        level: usize,
+        target: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+    ) -> Result<(), TinyError> {
+        if level == target {
+            return Ok(());
+        }
+        let cmp = family.options().comparator.as_ref();
+        let mut versions = self.versions.lock().unwrap();
+        let version = versions.current(family.handle().id());
+        let files = version.overlapping_files(level, begin, end, cmp);
+        let (first, last) = match (files.first(), files.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Ok(()),
+        };
+        let (smallest, largest) = (Some(first.smallest.as_slice()), Some(last.largest.as_slice()));
+        for l in level.min(target)..=level.max(target) {
+            if l != level && !version.overlapping_files(l, smallest, largest, cmp).is_empty() {
+                return Err(TinyError::InvalidArgument(format!(
+                    "[db] can't move the files in level {} to level {} over the files in level {}",
+                    level, target, l
+                )));
+            }
+        }
+        let mut edit = VersionEdit::new();
+        edit.column_family = family.handle().id();
+        for f in files.iter() {
+            edit.delete_file(level, f.number);
+            edit.add_file(target, (**f).clone());
+        }
+        versions.log_and_apply(&mut edit, cmp)
+    }
+
+    // Checks the table file to be ingested and reads its key range
+    fn read_external_file(&self, path: &str, cmp: &dyn Comparator<Slice>) -> Result<ExternalFile, TinyError> {
+        let env = self.config.env.as_ref();
+        let file_size = env.get_file_size(path)?;
+        let file = TableFile::open(env, path, FileReadMode::Buffered)?;
+        let options = TableOptions {
+            verify_checksums: true,
+            compressors: self.config.compressors.clone(),
+            ..TableOptions::default()
+        };
+        let table = Table::open(Arc::new(file), file_size, &options)?;
+        if table.properties().comparator_name != cmp.name() {
+            return Err(TinyError::InvalidArgument(format!(
+                "[db] {} is ordered by {} instead of {}",
+                path,
+                table.properties().comparator_name,
+                cmp.name()
+            )));
+        }
+        match (table.smallest_key()?, table.largest_key()?) {
+            (Some(smallest), Some(largest)) => Ok(ExternalFile {
+                path: path.to_owned(),
+                file_size,
+                smallest,
+                largest,
+            }),
+            _ => Err(TinyError::InvalidArgument(format!("[db] {} is empty", path))),
+        }
+    }
+    /// Reads all the given keys against one consistent view of the default column family
+    /// and returns their values in the same order as `keys`, as `get` would return them.
+    ///
+    /// The keys are sorted by the comparator and duplicated keys are looked up only once.
+    /// The memtable and the version are taken together, and every table file is searched
+    /// once for all the keys still pending in its range, so the keys reach the files
+    /// grouped in the key order and share the data blocks they are in.
+    pub fn multi_get(&self, keys: &[Slice]) -> Result<Vec<Option<Vec<u8>>>, TinyError> {
+        self.multi_get_opt(&ReadOptions::default(), keys)
+    }
+
+    /// Reads all the given keys of the default column family, reading the table files as
+    /// `read_options` asks. See `multi_get`.
+    pub fn multi_get_opt(
+        &self,
+        read_options: &ReadOptions,
+        keys: &[Slice],
+    ) -> Result<Vec<Option<Vec<u8>>>, TinyError> {
+        let family = self.default_family();
+        let options = family.options();
+        let cmp = options.comparator.as_ref();
+        let compare = |a: &[u8], b: &[u8]| cmp.compare(&Slice::from(a), &Slice::from(b));
+        let mut sorted: Vec<usize> = (0..keys.len()).collect();
+        sorted.sort_by(|a, b| cmp.compare(&keys[*a], &keys[*b]));
+        // the distinct keys in order and the one every key of `keys` is
+        let mut unique: Vec<&[u8]> = vec![];
+        let mut slots = vec![0; keys.len()];
+        for i in sorted {
+            let key = keys[i].to_slice();
+            if unique.last().is_none_or(|last| compare(last, key) != Ordering::Equal) {
+                unique.push(key);
+            }
+            slots[i] = unique.len() - 1;
+        }
+
+        let now = self.config.clock.now_micros();
+        let mut contexts: Vec<_> = unique
+            .iter()
+            .map(|key| MergeContext::new(key, options.merge_operator.as_deref()))
+            .collect();
+        let version = {
+            let mem = family.mem().read().unwrap();
+            for (key, ctx) in unique.iter().zip(contexts.iter_mut()) {
+                for entry in mem.get(key).unwrap_or(&[]).iter().rev() {
+                    ctx.add(entry.clone().expire(now))?;
+                }
+            }
+            self.versions.lock().unwrap().current(family.handle().id())
+        };
+        // the files in the order of `Version::files_for_key`
+        let files = version
+            .files(0)
+            .iter()
+            .rev()
+            .chain((1..NUM_LEVELS).flat_map(|l| version.files(l).iter()));
+        for file in files {
+            let begin = unique.partition_point(|k| compare(k, &file.smallest) == Ordering::Less);
+            let end = unique.partition_point(|k| compare(k, &file.largest) != Ordering::Greater);
+            let pending: Vec<usize> = (begin..end).filter(|i| !contexts[*i].is_done()).collect();
+            if pending.is_empty() {
+                continue;
+            }
+            let table = self.table_cache.find_table(
+                file.number,
+                file.file_size,
+                self.config.paranoid_checks,
+            )?;
+            let pending_keys: Vec<&[u8]> = pending.iter().map(|i| unique[*i]).collect();
+            for (i, entry) in pending.iter().zip(table.multi_get(read_options, &pending_keys, cmp)?) {
+                if let Some(entry) = entry {
+                    contexts[*i].add(entry.expire(now))?;
+                }
+            }
+        }
+        let values = contexts
+            .into_iter()
+            .map(|ctx| Ok(ctx.finish(true)?.and_then(Entry::into_user_value)))
+            .collect::<Result<Vec<_>, TinyError>>()?;
+        Ok(slots.iter().map(|slot| values[*slot].clone()).collect())
+    }
+
+    /// Stops deleting the obsolete files, e.g. the table files replaced by compactions and
+    /// the WAL files flushed, so the files listed by `get_live_files` and
+    /// `get_sorted_wal_files` stay on disk. The calls nest and every call must be paired
+    /// with `enable_file_deletions`.
+    pub fn disable_file_deletions(&self) {
+        *self.file_deletions_disabled.lock().unwrap() += 1;
+    }
+
+    /// Undoes a `disable_file_deletions`. The obsolete files are deleted again once all the
+    /// callers have enabled the deletions.
+    pub fn enable_file_deletions(&self) {
+        let mut disabled = self.file_deletions_disabled.lock().unwrap();
+        invarint!(*disabled > 0, "[db] file deletions are not disabled");
+        *disabled -= 1;
+        if *disabled == 0 {
+            self.purge_obsolete_files(&mut self.obsolete_files.lock().unwrap());
+        }
+    }
+
+    // Records the files removed from the current version and deletes the obsolete files
+    // no longer referred to by any version, unless the file deletions are disabled
+    fn delete_obsolete_files(&self, files: Vec<Arc<FileMetaData>>) {
+        let disabled = self.file_deletions_disabled.lock().unwrap();
+        let mut obsolete = self.obsolete_files.lock().unwrap();
+        obsolete.extend(files);
+        if *disabled == 0 {
+            self.purge_obsolete_files(&mut obsolete);
+        }
+    }
+
+    fn purge_obsolete_files(&self, obsolete: &mut Vec<Arc<FileMetaData>>) {
+        // the logs older than the log number are flushed
+        let log_number = self.versions.lock().unwrap().log_number();
+        match self.list_files(&self.config.dir, FileType::Log) {
+            Ok(numbers) => {
+                for number in numbers.into_iter().filter(|n| *n < log_number) {
+                    let path = log_file_name(&self.config.dir, number);
+                    if let Err(e) = self.config.env.remove_file(&path) {
+                        log::warn!("[db] failed to delete the obsolete log {}: {}", path, e);
+                    }
+                }
+            }
+            Err(e) => log::warn!("[db] failed to list the logs: {}", e),
+        }
+        obsolete.retain(|f| {
+            // still read through a version
+            if Arc::strong_count(f) > 1 {
+                return true;
+            }
+            self.table_cache.evict(f.number);
+            let path = table_file_name(&self.config.dir, f.number);
+            if let Err(e) = self.config.env.remove_file(&path) {
+                log::warn!("[db] failed to delete the obsolete file {}: {}", path, e);
+            }
+            false
+        });
+    }
+
+    pub fn file_deletions_enabled(&self) -> bool {
+        *self.file_deletions_disabled.lock().unwrap() == 0
+    }
+
+    /// Returns the paths of the files a copy of the DB needs besides the WAL files:
+    /// CURRENT, the current MANIFEST, the table files in the current versions of the
+    /// column families and the value log files. Also returns the size of the MANIFEST
+    /// taken together with the table files, up to which the MANIFEST must be copied,
+    /// since the edits appended later may refer to the table files not listed.
+    pub fn get_live_files(&self) -> Result<(Vec<String>, u64), TinyError> {
+        let dir = &self.config.dir;
+        let mut files = vec![];
+        let mut tables = BTreeSet::new();
+        let manifest_size = {
+            let versions = self.versions.lock().unwrap();
+            if versions.manifest_number() > 0 {
+                files.push(current_file_name(dir));
+                files.push(manifest_file_name(dir, versions.manifest_number()));
+            }
+            for (id, _) in versions.column_families() {
+                tables.extend(versions.current(id).all_files().map(|f| f.number));
+            }
+            versions.manifest_size()
+        };
+        files.extend(tables.into_iter().map(|number| table_file_name(dir, number)));
+        let value_dir = self.value_dir();
+        for number in self.list_files(value_dir, FileType::ValueLog)? {
+            files.push(value_log_file_name(value_dir, number));
+        }
+        Ok((files, manifest_size))
+    }
+
+    /// Returns the paths of the WAL files ordered by the log number
+    pub fn get_sorted_wal_files(&self) -> Result<Vec<String>, TinyError> {
+        Ok(self
+            .list_files(&self.config.dir, FileType::Log)?
+            .into_iter()
+            .map(|number| log_file_name(&self.config.dir, number))
+            .collect())
+    }
+
+    // Returns the directory of the value log files
+    fn value_dir(&self) -> &str {
+        if self.config.value_dir.is_empty() {
+            &self.config.dir
+        } else {
+            &self.config.value_dir
+        }
+    }
+
+    // Returns the sorted numbers of the files of `file_type` in `dir`
+    fn list_files(&self, dir: &str, file_type: FileType) -> Result<Vec<u64>, TinyError> {
+        let mut numbers: Vec<u64> = self
+            .config
+            .env
+            .get_children(dir)?
+            .iter()
+            .filter_map(|name| match parse_file_name(name) {
+                Some((t, number)) if t == file_type => Some(number),
+                _ => None,
+            })
+            .collect();
+        numbers.sort_unstable();
+        Ok(numbers)
+    }
+
+    /// Returns the properties of all the live table files of all the column families keyed
+    /// by the file path. The properties are read from the properties block of each file, so
+    /// no data block is scanned.
+    pub fn get_properties_of_all_tables(&self) -> Result<TablePropertiesCollection, TinyError> {
+        let versions: Vec<Arc<Version>> = {
+            let versions = self.versions.lock().unwrap();
+            versions.column_families().map(|(id, _)| versions.current(id)).collect()
+        };
+        let mut collection = TablePropertiesCollection::new();
+        for file in versions.iter().flat_map(|v| v.all_files()) {
+            let table = self.table_cache.find_table(
+                file.number,
+                file.file_size,
+                self.config.paranoid_checks,
+            )?;
+            collection.insert(
+                table_file_name(&self.config.dir, file.number),
+                table.properties().clone(),
+            );
+        }
+        Ok(collection)
+    }
+
+    // Logs the batch in the WAL and inserts it into the memtable, which is flushed once
+    // it's full. `log` is the guard of `write_mutex`.
+    fn apply_batch(
+        &self,
+        log: &mut Option<LogWriter>,
+        options: &WriteOptions,
+        mut batch: WriteBatch,
+    ) -> Result<(), TinyError> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.check_batch(&batch)?;
+        batch.set_sequence(self.versions.lock().unwrap().last_sequence() + 1);
+        let writer = match log.as_mut() {
+            Some(writer) => writer,
+            None => {
+                return Err(TinyError::NotSupported(
+                    "[db] the DB has no WAL to write".to_owned(),
+                ))
+            }
+        };
+        writer.add_record(batch.data())?;
+        if options.sync {
+            writer.get_mut().sync()?;
+        } else {
+            writer.flush()?;
+        }
+        self.insert_into_memtable(&batch, None);
+        let memory_usage: usize = {
+            let families = self.column_families.lock().unwrap();
+            families.iter().map(|f| f.mem().read().unwrap().approximate_memory_usage()).sum()
+        };
+        if memory_usage >= self.config.write_buffer_size {
+            self.flush_memtable(log)?;
+        }
+        Ok(())
+    }
+
+    // Checks that every change of the batch is of a live column family, and that the
+    // column family of a merge has a merge operator
+    fn check_batch(&self, batch: &WriteBatch) -> Result<(), TinyError> {
+        let families = self.column_families.lock().unwrap();
+        for entry in batch.iter() {
+            let entry = entry?;
+            let family = families.get_by_id(entry.column_family).ok_or_else(|| {
+                TinyError::InvalidArgument(format!(
+                    "[db] column family {} does not exist",
+                    entry.column_family
+                ))
+            })?;
+            if entry.value_type == ValueType::Merge && family.options().merge_operator.is_none() {
+                return Err(TinyError::NotSupported(format!(
+                    "[db] column family [{}] has no merge operator",
+                    family.handle().name()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    // Switches to a new WAL and writes the memtable of every column family into a table
+    // file in level 0, after which the WAL files flushed by all the column families are
+    // removed. `log` is the guard of `write_mutex`.
+    //
+    // Every column family records the new WAL as its log number with its own edit, so the
+    // WAL is switched even if a column family fails to flush: the writes of the column
+    // families flushed would be skipped if replayed from the old WAL.
+    fn flush_memtable(&self, log: &mut Option<LogWriter>) -> Result<(), TinyError> {
+        let env = self.config.env.as_ref();
+        let log_number = self.versions.lock().unwrap().new_file_number();
+        let log_path = log_file_name(&self.config.dir, log_number);
+        let writer = Writer::new(env.new_writable_file(&log_path)?);
+        let families: Vec<_> = self.column_families.lock().unwrap().iter().cloned().collect();
+        let result = families
+            .iter()
+            .try_for_each(|family| self.flush_column_family(family, log_number));
+        *log = Some(writer);
+        self.delete_obsolete_files(vec![]);
+        result
+    }
+
+    // Writes the memtable of the column family into a table file in level 0 and records
+    // `log_number` as the oldest WAL file the column family needs
+    fn flush_column_family(&self, family: &ColumnFamilyData, log_number: u64) -> Result<(), TinyError> {
+        let cmp = family.options().comparator.clone();
+        let mut edit = VersionEdit::new();
+        edit.column_family = family.handle().id();
+        edit.log_number = Some(log_number);
+        let mut table = None;
+        {
+            let mem = family.mem().read().unwrap();
+            if !mem.is_empty() {
+                let number = self.versions.lock().unwrap().new_file_number();
+                edit.add_file(0, self.write_level0_table(&mem, number, family.options())?);
+                table = Some(number);
+            }
+        }
+        // the readers see either the memtable or the table file
+        let mut mem = family.mem().write().unwrap();
+        if let Err(e) = self.versions.lock().unwrap().log_and_apply(&mut edit, cmp.as_ref()) {
+            if let Some(number) = table {
+                let _ = self.config.env.remove_file(&table_file_name(&self.config.dir, number));
+            }
+            return Err(e);
+        }
+        *mem = MemTable::new(cmp);
+        Ok(())
+    }
+
+    // Writes the entries of the memtable into the table file of `number`, where the
+    // entries of every key are folded into one
+    fn write_level0_table(
+        &self,
+        mem: &MemTable,
+        number: u64,
+        options: &ColumnFamilyOptions,
+    ) -> Result<FileMetaData, TinyError> {
+        let path = table_file_name(&self.config.dir, number);
+        let writer = TableFileWriter::create(
+            self.config.env.as_ref(),
+            &path,
+            self.config.use_direct_io_for_flush_and_compaction,
+        )?;
+        let mut builder = TableBuilder::new(&self.config, options.comparator.name(), 0, writer);
+        let mut meta = FileMetaData {
+            number,
+            file_size: 0,
+            smallest: vec![],
+            largest: vec![],
+            smallest_seqno: u64::MAX,
+            largest_seqno: 0,
+        };
+        let result = mem
+            .iter()
+            .try_for_each(|(key, entries)| {
+                let mut ctx = MergeContext::new(key, options.merge_operator.as_deref());
+                for entry in entries.iter().rev() {
+                    ctx.add(entry.clone())?;
+                }
+                // an older entry of the key may be in the table files
+                let entry = match ctx.finish(false)? {
+                    Some(entry) => entry,
+                    None => return Ok(()),
+                };
+                if meta.smallest.is_empty() {
+                    meta.smallest = key.to_vec();
+                }
+                meta.largest = key.to_vec();
+                meta.smallest_seqno = meta.smallest_seqno.min(entry.seq);
+                meta.largest_seqno = meta.largest_seqno.max(entry.seq);
+                builder.add(key, entry.value_type, entry.seq, &entry.value)
+            })
+            .and_then(|_| builder.finish())
+            .and_then(|(_, writer)| writer.finish());
+        match result {
+            Ok(file_size) => {
+                meta.file_size = file_size;
+                Ok(meta)
+            }
+            Err(e) => {
+                let _ = self.config.env.remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Creates a new column family with its own memtable, levels and comparator.
+    /// Returns `InvalidArgument` if a column family with the same name exists.
+    ///
+    /// The column family is recorded in the MANIFEST, but its options are not, so it's
+    /// recovered with `ColumnFamilyOptions::default()` when the DB is opened again.
+    pub fn create_column_family(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamilyHandle, TinyError> {
+        let _guard = self.lock_writes()?;
+        let cmp = options.comparator.clone();
+        let mut families = self.column_families.lock().unwrap();
+        let handle = families.create(name, options)?;
+        let mut versions = self.versions.lock().unwrap();
+        let mut edit = VersionEdit::new();
+        edit.column_family = handle.id();
+        edit.add_column_family = Some(name.to_owned());
+        edit.max_column_family = Some(handle.id());
+        // no WAL file has a change of the new column family
+        edit.log_number = Some(versions.log_number());
+        if let Err(e) = versions.log_and_apply(&mut edit, cmp.as_ref()) {
+            families.remove(&handle)?;
+            return Err(e);
+        }
+        Ok(handle)
+    }
+    /// Drops the column family so that the handle can not be used anymore, and deletes
+    /// its table files once they are no longer read. The default column family can not be
+    /// dropped.
+    pub fn drop_column_family(&self, handle: &ColumnFamilyHandle) -> Result<(), TinyError> {
+        let _guard = self.lock_writes()?;
+        // no compaction of the column family is running
+        let _files_guard = self.file_mutex.lock().unwrap();
+        let mut families = self.column_families.lock().unwrap();
+        let cmp = families.get(handle)?.options().comparator.clone();
+        if handle.id() == DEFAULT_COLUMN_FAMILY_ID {
+            return families.remove(handle);
+        }
+        let files = {
+            let mut versions = self.versions.lock().unwrap();
+            let files = versions.current(handle.id()).all_files().cloned().collect();
+            let mut edit = VersionEdit::new();
+            edit.column_family = handle.id();
+            edit.drop_column_family = true;
+            versions.log_and_apply(&mut edit, cmp.as_ref())?;
+            files
+        };
+        families.remove(handle)?;
+        drop(families);
+        self.delete_obsolete_files(files);
         Ok(())
     }
-    pub fn get(&self, key: Vec<&u8>) -> Option<Slice> {
-        println!("[get] key: {:?}", &key);
-        None
+    pub fn default_column_family(&self) -> ColumnFamilyHandle {
+        self.column_families.lock().unwrap().default_handle()
+    }
+    /// Returns the handle of the live column family named `name`, e.g. one recovered
+    /// from the MANIFEST
+    pub fn column_family_handle(&self, name: &str) -> Option<ColumnFamilyHandle> {
+        let families = self.column_families.lock().unwrap();
+        families.get_by_name(name).map(|f| f.handle().clone())
+    }
+    pub fn write_cf(
+        &self,
+        handle: &ColumnFamilyHandle,
+        key: Slice,
+        value: Slice,
+    ) -> Result<(), TinyError> {
+        self.column_family(handle)?;
+        let mut batch = WriteBatch::new();
+        batch.put_cf(handle, key.to_slice(), value.to_slice());
+        self.write_batch(&WriteOptions::default(), batch)
+    }
+    pub fn delete_cf(&self, handle: &ColumnFamilyHandle, key: Slice) -> Result<(), TinyError> {
+        self.column_family(handle)?;
+        let mut batch = WriteBatch::new();
+        batch.delete_cf(handle, key.to_slice());
+        self.write_batch(&WriteOptions::default(), batch)
+    }
+    /// Returns the latest value of the key in the column family. See `get`.
+    pub fn get_cf(&self, handle: &ColumnFamilyHandle, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        self.get_cf_opt(&ReadOptions::default(), handle, key)
+    }
+
+    /// Returns the latest value of the key in the column family, reading the table files
+    /// as `options` asks. See `get`.
+    pub fn get_cf_opt(
+        &self,
+        options: &ReadOptions,
+        handle: &ColumnFamilyHandle,
+        key: Slice,
+    ) -> Result<Option<Vec<u8>>, TinyError> {
+        let family = self.column_family(handle)?;
+        self.get_from(options, &family, key.to_slice())
+    }
+}
+
+impl Drop for DB {
+    fn drop(&mut self) {
+        if let Some(lock) = self.dir_lock.take() {
+            if let Err(e) = self.config.env.unlock_file(lock) {
+                log::error!("[db] failed to unlock {}: {}", self.config.dir, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::mem::MemEnv;
+    use crate::compaction::filter::{CompactionFilterSource, Decision};
+    use crate::env::Env;
+    use crate::table::sst_file_writer::SstFileWriter;
+    use crate::entry::decode_operands;
+    use crate::config::IndexType;
+    use crate::table::block_cache::BlockCache;
+    use crate::util::comparator::BytewiseComparator;
+    use crate::util::clock::ManualClock;
+    use crate::util::filter_policy::BloomFilterPolicy;
+    use crate::util::merge_operator::{MergeOperator, U64AddOperator};
+    use std::io::{Read, Write};
+
+    #[test]
+    fn test_lock_dir() {
+        let dir = std::env::temp_dir().join(format!("tinydb_db_lock_{}", std::process::id()));
+        let config = Config {
+            dir: dir.to_str().unwrap().to_owned(),
+            ..Config::default()
+        };
+        let db = open_db(config.clone()).unwrap();
+        assert!(matches!(open_db(config.clone()), Err(TinyError::Busy(_))));
+        drop(db);
+        let db = open_db(config.clone()).unwrap();
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_direct_io_for_flush_and_compaction() {
+        let dir = std::env::temp_dir().join(format!("tinydb_db_direct_io_{}", std::process::id()));
+        let db = open_db(Config {
+            dir: dir.to_str().unwrap().to_owned(),
+            block_size: 256,
+            use_direct_io_for_flush_and_compaction: true,
+            compaction_readahead_size: 4096,
+            ..Config::default()
+        })
+        .unwrap();
+        for round in 0..3 {
+            for i in 0..200 {
+                let (key, value) = (format!("key{:04}", i), format!("value{}-{}", i, round));
+                db.write(Slice::from(key.as_str()), Slice::from(value.as_str())).unwrap();
+            }
+            flush(&db);
+        }
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        assert!(level_numbers(&db, 0).is_empty());
+        for i in 0..200 {
+            assert_eq!(get(&db, &format!("key{:04}", i)), Some(format!("value{}-2", i).into_bytes()));
+        }
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lock_dir_on_mem_env() {
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: Arc::new(MemEnv::new()),
+            ..Config::default()
+        };
+        let db = open_db(config.clone()).unwrap();
+        assert!(config.env.file_exists("/db/LOCK"));
+        assert!(matches!(open_db(config.clone()), Err(TinyError::Busy(_))));
+        // another DB on a different directory
+        let other = Config {
+            dir: "/db2".to_owned(),
+            ..config.clone()
+        };
+        open_db(other).unwrap();
+        drop(db);
+        open_db(config).unwrap();
+    }
+
+    #[test]
+    fn test_read_only() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        };
+        assert!(matches!(
+            open_db_read_only(config.clone()),
+            Err(TinyError::InvalidArgument(_))
+        ));
+        let db = open_db(config.clone()).unwrap();
+        // the flushed writes are read from the table files and the others from the WAL
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        db.write(Slice::from("b"), Slice::from("2")).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        db.write(Slice::from("a"), Slice::from("3")).unwrap();
+        db.delete(Slice::from("b")).unwrap();
+        db.write(Slice::from("c"), Slice::from("4")).unwrap();
+        // not blocked by the read-write DB
+        let read_only = open_db_read_only(config).unwrap();
+        db.write(Slice::from("d"), Slice::from("5")).unwrap();
+        drop(db);
+        let files = env.get_children("/db").unwrap();
+        let get = |key: &str| read_only.get(Slice::from(key)).unwrap();
+        assert_eq!(get("a"), Some(b"3".to_vec()));
+        assert_eq!(get("b"), None);
+        assert_eq!(get("c"), Some(b"4".to_vec()));
+        // the later writes are not visible
+        assert_eq!(get("d"), None);
+
+        let key = Slice::from("k".as_bytes());
+        let value = Slice::from("v".as_bytes());
+        let is_not_supported = |r: Result<(), TinyError>| matches!(r, Err(TinyError::NotSupported(_)));
+        assert!(is_not_supported(read_only.write(key.clone(), value.clone())));
+        assert!(is_not_supported(read_only.delete(key.clone())));
+        assert!(is_not_supported(
+            read_only.put_if_absent(key.clone(), value.clone()).map(|_| ())
+        ));
+        assert!(is_not_supported(
+            read_only.compare_and_swap(key.clone(), value.clone(), value.clone()).map(|_| ())
+        ));
+        assert!(is_not_supported(
+            read_only.create_column_family("cf", ColumnFamilyOptions::default()).map(|_| ())
+        ));
+        let default_cf = read_only.default_column_family();
+        assert!(is_not_supported(read_only.write_cf(&default_cf, key.clone(), value)));
+        assert!(read_only.get_cf(&default_cf, key).unwrap().is_none());
+        assert_eq!(env.get_children("/db").unwrap(), files);
+    }
+
+    #[test]
+    fn test_secondary() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        };
+        let get = |db: &DB, key: &str| db.get(Slice::from(key)).unwrap();
+        let primary = open_db(config.clone()).unwrap();
+        assert!(matches!(
+            primary.try_catch_up_with_primary(),
+            Err(TinyError::NotSupported(_))
+        ));
+        primary.write(Slice::from("a"), Slice::from("1")).unwrap();
+
+        let secondary = open_db_as_secondary(config.clone()).unwrap();
+        assert_eq!(get(&secondary, "a"), Some(b"1".to_vec()));
+        assert!(matches!(
+            secondary.write(Slice::from("k"), Slice::from("v")),
+            Err(TinyError::NotSupported(_))
+        ));
+
+        // the new writes and column families of the primary
+        primary.write(Slice::from("b"), Slice::from("2")).unwrap();
+        let cf = primary.create_column_family("cf", ColumnFamilyOptions::default()).unwrap();
+        primary.write_cf(&cf, Slice::from("c"), Slice::from("3")).unwrap();
+        assert_eq!(get(&secondary, "b"), None);
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(get(&secondary, "b"), Some(b"2".to_vec()));
+        let secondary_cf = secondary.column_family_handle("cf").unwrap();
+        assert_eq!(
+            secondary.get_cf(&secondary_cf, Slice::from("c")).unwrap(),
+            Some(b"3".to_vec())
+        );
+
+        // the flushed writes are read from the table files after the primary removes
+        // the logs, and an overwritten value is not shadowed by the one replayed before
+        primary.write(Slice::from("a"), Slice::from("4")).unwrap();
+        primary.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        primary.write(Slice::from("d"), Slice::from("5")).unwrap();
+        primary.drop_column_family(&cf).unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(get(&secondary, "a"), Some(b"4".to_vec()));
+        assert_eq!(get(&secondary, "b"), Some(b"2".to_vec()));
+        assert_eq!(get(&secondary, "d"), Some(b"5".to_vec()));
+        assert!(secondary.get_cf(&secondary_cf, Slice::from("c")).is_err());
+        assert_eq!(
+            secondary.tailed_logs.lock().unwrap().keys().cloned().collect::<Vec<_>>(),
+            secondary.list_files("/db", FileType::Log).unwrap()
+        );
+
+        // a record still being written is replayed once it's complete
+        primary.write(Slice::from("e"), Slice::from(&[7; 50000][..])).unwrap();
+        drop(primary);
+        let log = *secondary.list_files("/db", FileType::Log).unwrap().last().unwrap();
+        let path = log_file_name("/db", log);
+        let offset = secondary.tailed_logs.lock().unwrap()[&log];
+        let mut contents = vec![];
+        env.new_sequential_file(&path).unwrap().read_to_end(&mut contents).unwrap();
+        env.new_writable_file(&path)
+            .unwrap()
+            .write_all(&contents[..contents.len() - 10])
+            .unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(get(&secondary, "e"), None);
+        assert_eq!(secondary.tailed_logs.lock().unwrap()[&log], offset);
+        env.new_writable_file(&path).unwrap().write_all(&contents).unwrap();
+        secondary.try_catch_up_with_primary().unwrap();
+        assert_eq!(get(&secondary, "e"), Some(vec![7; 50000]));
+        assert_eq!(secondary.tailed_logs.lock().unwrap()[&log], contents.len() as u64);
+    }
+
+    // Writes the key/value pairs into the external table file at `path`
+    fn write_external_file(env: &Arc<dyn Env>, path: &str, kvs: &[(&str, &str)]) {
+        let config = Config {
+            env: env.clone(),
+            ..Config::default()
+        };
+        let mut writer = SstFileWriter::new(&config, &ColumnFamilyOptions::default());
+        writer.open(path).unwrap();
+        for (k, v) in kvs {
+            writer.put(k.as_bytes(), v.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+
+    fn level_of(db: &DB, number: u64) -> Option<usize> {
+        let version = db.versions.lock().unwrap().current(DEFAULT_COLUMN_FAMILY_ID);
+        (0..NUM_LEVELS).find(|l| version.files(*l).iter().any(|f| f.number == number))
+    }
+
+    #[test]
+    fn test_ingest_external_file() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        env.create_dir("/db").unwrap();
+        // the existing files are not overwritten by the ingested ones
+        env.new_writable_file(&log_file_name("/db", 5)).unwrap();
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        write_external_file(&env, "/a-c.sst", &[("a", "1"), ("b", "1"), ("c", "1")]);
+        write_external_file(&env, "/b-d.sst", &[("b", "2"), ("d", "2")]);
+        write_external_file(&env, "/x-y.sst", &[("x", "3"), ("y", "3")]);
+        write_external_file(&env, "/c-e.sst", &[("c", "4"), ("e", "4")]);
+
+        // 6 and 7 are taken by the MANIFEST and the WAL
+        db.ingest_external_file(&["/a-c.sst"]).unwrap();
+        assert_eq!(level_of(&db, 8), Some(NUM_LEVELS - 1));
+        assert!(env.file_exists("/a-c.sst"));
+        // the newer file is placed above the file it overlaps
+        db.ingest_external_file(&["/x-y.sst", "/b-d.sst"]).unwrap();
+        assert_eq!(level_of(&db, 9), Some(NUM_LEVELS - 2));
+        assert_eq!(level_of(&db, 10), Some(NUM_LEVELS - 1));
+        let version = db.versions.lock().unwrap().current(DEFAULT_COLUMN_FAMILY_ID);
+        assert_eq!(version.files(NUM_LEVELS - 2)[0].smallest_seqno, 2);
+        assert_eq!(db.versions.lock().unwrap().last_sequence(), 2);
+
+        assert_eq!(db.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(Slice::from("y")).unwrap(), Some(b"3".to_vec()));
+        assert_eq!(db.get(Slice::from("e")).unwrap(), None);
+        let properties = db.get_properties_of_all_tables().unwrap();
+        assert_eq!(properties.len(), 3);
+        assert_eq!(properties[&table_file_name("/db", 9)].num_entries, 2);
+
+        // nothing is ingested if the files overlap each other
+        let files = env.get_children("/db").unwrap();
+        assert!(matches!(
+            db.ingest_external_file(&["/c-e.sst", "/b-d.sst"]),
+            Err(TinyError::InvalidArgument(_))
+        ));
+        assert!(db.ingest_external_file(&["/c-e.sst", "/missing.sst"]).is_err());
+        assert_eq!(env.get_children("/db").unwrap().len(), files.len());
+        assert_eq!(db.versions.lock().unwrap().last_sequence(), 2);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_recover_versions() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        };
+        let db = open_db(config.clone()).unwrap();
+        write_external_file(&env, "/a-c.sst", &[("a", "1"), ("c", "1")]);
+        write_external_file(&env, "/b-d.sst", &[("b", "2"), ("d", "2")]);
+        db.ingest_external_file(&["/a-c.sst"]).unwrap();
+        db.ingest_external_file(&["/b-d.sst"]).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        let levels: Vec<_> = (0..NUM_LEVELS).map(|l| level_numbers(&db, l)).collect();
+        let next_file_number = db.versions.lock().unwrap().new_file_number();
+        drop(db);
+
+        let db = open_db(config.clone()).unwrap();
+        assert_eq!((0..NUM_LEVELS).map(|l| level_numbers(&db, l)).collect::<Vec<_>>(), levels);
+        assert_eq!(db.versions.lock().unwrap().last_sequence(), 2);
+        assert!(db.versions.lock().unwrap().new_file_number() > next_file_number);
+        assert_eq!(db.get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(Slice::from("c")).unwrap(), Some(b"1".to_vec()));
+        // only the MANIFEST created by the last open is kept
+        let manifests = env
+            .get_children("/db")
+            .unwrap()
+            .into_iter()
+            .filter(|name| name.starts_with("MANIFEST-"))
+            .count();
+        assert_eq!(manifests, 1);
+        drop(db);
+
+        let read_only = open_db_read_only(config.clone()).unwrap();
+        assert_eq!(read_only.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        env.new_writable_file("/db/CURRENT").unwrap().write_all(b"garbage").unwrap();
+        assert!(matches!(open_db(config), Err(TinyError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_ingest_over_memtable() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        db.write(Slice::from("b"), Slice::from("mem")).unwrap();
+        db.write(Slice::from("x"), Slice::from("mem")).unwrap();
+        write_external_file(&env, "/m-n.sst", &[("m", "1"), ("n", "1")]);
+        write_external_file(&env, "/a-c.sst", &[("a", "2"), ("b", "2"), ("c", "2")]);
+
+        // no key of the memtable is in [m, n]
+        db.ingest_external_file(&["/m-n.sst"]).unwrap();
+        assert!(level_numbers(&db, 0).is_empty());
+        assert_eq!(db.default_family().mem().read().unwrap().len(), 2);
+
+        // b is flushed first and then hidden by the ingested file
+        db.ingest_external_file(&["/a-c.sst"]).unwrap();
+        assert!(db.default_family().mem().read().unwrap().is_empty());
+        assert_eq!(level_numbers(&db, 0).len(), 2);
+        assert_eq!(db.get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(Slice::from("x")).unwrap(), Some(b"mem".to_vec()));
+        assert_eq!(db.get(Slice::from("m")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.versions.lock().unwrap().last_sequence(), 4);
+    }
+
+    #[test]
+    fn test_ingest_invalid_file() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        };
+        let db = open_db(config.clone()).unwrap();
+        env.new_writable_file("/bad.sst").unwrap().write_all(&[0; 100]).unwrap();
+        assert!(matches!(
+            db.ingest_external_file(&["/bad.sst"]),
+            Err(TinyError::Corruption(_))
+        ));
+
+        struct ReverseComparator {}
+        impl Comparator<Slice> for ReverseComparator {
+            fn compare(&self, a: &Slice, b: &Slice) -> Ordering {
+                b.compare(a)
+            }
+            fn name(&self) -> &str {
+                "reverse"
+            }
+        }
+        let options = ColumnFamilyOptions {
+            comparator: Arc::new(ReverseComparator {}),
+            ..ColumnFamilyOptions::default()
+        };
+        let mut writer = SstFileWriter::new(&config, &options);
+        writer.open("/reverse.sst").unwrap();
+        writer.put(b"b", b"1").unwrap();
+        writer.put(b"a", b"1").unwrap();
+        writer.finish().unwrap();
+        assert!(matches!(
+            db.ingest_external_file(&["/reverse.sst"]),
+            Err(TinyError::InvalidArgument(_))
+        ));
+
+        write_external_file(&env, "/a.sst", &[("a", "1")]);
+        let read_only = open_db_read_only(config).unwrap();
+        assert!(matches!(
+            read_only.ingest_external_file(&["/a.sst"]),
+            Err(TinyError::NotSupported(_))
+        ));
+    }
+
+    // Writes the pairs into a table file of the DB and adds the file to `level`
+    fn add_table(db: &DB, level: usize, seq: u64, kvs: &[(&str, &str)]) -> u64 {
+        let mut versions = db.versions.lock().unwrap();
+        let number = versions.new_file_number();
+        let path = table_file_name(&db.config.dir, number);
+        write_external_file(&db.config.env, &path, kvs);
+        let meta = FileMetaData {
+            number,
+            file_size: db.config.env.get_file_size(&path).unwrap(),
+            smallest: kvs[0].0.as_bytes().to_vec(),
+            largest: kvs[kvs.len() - 1].0.as_bytes().to_vec(),
+            smallest_seqno: seq,
+            largest_seqno: seq,
+        };
+        let mut edit = VersionEdit::new();
+        edit.add_file(level, meta);
+        edit.last_sequence = Some(seq.max(versions.last_sequence()));
+        versions.log_and_apply(&mut edit, &BytewiseComparator::new()).unwrap();
+        number
+    }
+
+    fn level_numbers(db: &DB, level: usize) -> Vec<u64> {
+        let version = db.versions.lock().unwrap().current(DEFAULT_COLUMN_FAMILY_ID);
+        version.files(level).iter().map(|f| f.number).collect()
+    }
+
+    fn table_entries(db: &DB, number: u64) -> Vec<(Vec<u8>, Entry)> {
+        let path = table_file_name(&db.config.dir, number);
+        let size = db.config.env.get_file_size(&path).unwrap();
+        db.table_cache.find_table(number, size, true).unwrap().entries().unwrap()
+    }
+
+    fn table_count(env: &Arc<dyn Env>) -> usize {
+        let children = env.get_children("/db").unwrap();
+        children.iter().filter(|n| n.ends_with(".sst")).count()
+    }
+
+    #[test]
+    fn test_compact_range() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        add_table(&db, 4, 1, &[("c", "1"), ("y", "1"), ("z", "1")]);
+        add_table(&db, 2, 2, &[("a", "2"), ("b", "2"), ("x", "2")]);
+        add_table(&db, 0, 3, &[("a", "3"), ("d", "3")]);
+        add_table(&db, 0, 4, &[("b", "4"), ("c", "4")]);
+
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        for level in 0..NUM_LEVELS {
+            assert_eq!(level_numbers(&db, level).len(), (level == 4) as usize);
+        }
+        assert_eq!(table_count(&env), 1);
+        let version = db.versions.lock().unwrap().current(DEFAULT_COLUMN_FAMILY_ID);
+        let file = &version.files(4)[0];
+        assert_eq!((file.smallest_seqno, file.largest_seqno), (1, 4));
+        assert_eq!((file.smallest.as_slice(), file.largest.as_slice()), (&b"a"[..], &b"z"[..]));
+        let expected = [("a", "3"), ("b", "4"), ("c", "4"), ("d", "3"), ("x", "2"), ("y", "1")];
+        for (k, v) in expected.iter() {
+            assert_eq!(db.get(Slice::from(*k)).unwrap(), Some(v.as_bytes().to_vec()));
+        }
+        // every entry keeps the seqno of its own write
+        let seqnos: Vec<_> = table_entries(&db, file.number).into_iter().map(|(_, e)| e.seq).collect();
+        assert_eq!(seqnos, vec![3, 4, 4, 3, 2, 1, 1]);
+        // the bottommost level is left alone without a compaction filter
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        assert_eq!(level_numbers(&db, 4), vec![file.number]);
+        let options = CompactRangeOptions {
+            bottommost_level_compaction: BottommostLevelCompaction::Force,
+            ..CompactRangeOptions::default()
+        };
+        db.compact_range(None, None, &options).unwrap();
+        assert_ne!(level_numbers(&db, 4), vec![file.number]);
+        // still referred to by `version`
+        assert_eq!(table_count(&env), 2);
+        drop(version);
+        db.disable_file_deletions();
+        db.compact_range(None, None, &options).unwrap();
+        assert_eq!(table_count(&env), 3);
+        db.enable_file_deletions();
+        assert_eq!(table_count(&env), 1);
+
+        let read_only = open_db_read_only(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        assert!(matches!(
+            read_only.compact_range(None, None, &CompactRangeOptions::default()),
+            Err(TinyError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_compaction_writes_tombstones() {
+        struct RemoveValueFilter {}
+        impl CompactionFilter for RemoveValueFilter {
+            fn filter(&self, _level: usize, _key: &Slice, value: &Slice) -> Decision {
+                if value.to_slice() == b"removed" {
+                    Decision::Remove
+                } else {
+                    Decision::Keep
+                }
+            }
+            fn name(&self) -> &str {
+                "RemoveValueFilter"
+            }
+        }
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1 << 20);
+        let filter: Option<Arc<dyn CompactionFilter>> = Some(Arc::new(RemoveValueFilter {}));
+        add_table(&db, 3, 1, &[("a", "1"), ("b", "1"), ("c", "1")]);
+        add_table(&db, 1, 2, &[("b", "removed"), ("c", "2")]);
+        db.delete(Slice::from("c")).unwrap();
+        let mut log = db.write_mutex.lock().unwrap();
+        db.flush_memtable(&mut log).unwrap();
+        drop(log);
+
+        // level 3 still has b and c, so both are written as deletions into level 2
+        db.compact_level(&db.default_family(), 0, 1, None, None, filter.clone()).unwrap();
+        db.compact_level(&db.default_family(), 1, 2, None, None, filter.clone()).unwrap();
+        let level2 = level_numbers(&db, 2);
+        assert_eq!(level2.len(), 1);
+        let entries: Vec<_> = table_entries(&db, level2[0])
+            .into_iter()
+            .map(|(k, e)| (k, e.value_type, e.seq))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![(b"b".to_vec(), ValueType::Deletion, 2), (b"c".to_vec(), ValueType::Deletion, 3)]
+        );
+        assert_eq!(get(&db, "a"), Some(b"1".to_vec()));
+        assert_eq!(get(&db, "b"), None);
+        assert_eq!(get(&db, "c"), None);
+
+        // the deletions are dropped in the bottommost level together with the values
+        db.compact_level(&db.default_family(), 2, 3, None, None, filter).unwrap();
+        let level3 = level_numbers(&db, 3);
+        let keys: Vec<_> = table_entries(&db, level3[0]).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"a".to_vec()]);
+        assert_eq!(get(&db, "b"), None);
+    }
+
+    #[test]
+    fn test_compact_range_flushes_memtable() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1 << 20);
+        add_table(&db, 2, 1, &[("a", "1"), ("m", "1")]);
+        db.write(Slice::from("z"), Slice::from("2")).unwrap();
+        db.compact_range(Some(b"a"), Some(b"b"), &CompactRangeOptions::default()).unwrap();
+        assert_eq!(db.default_family().mem().read().unwrap().len(), 1);
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        assert!(db.default_family().mem().read().unwrap().is_empty());
+        assert!(level_numbers(&db, 0).is_empty());
+        assert_eq!(get(&db, "z"), Some(b"2".to_vec()));
+    }
+
+    #[test]
+    fn test_compact_range_with_filter() {
+        struct RemoveFilter {}
+        impl CompactionFilter for RemoveFilter {
+            fn filter(&self, _level: usize, key: &Slice, _value: &Slice) -> Decision {
+                if key.to_slice() == b"b" {
+                    Decision::Remove
+                } else {
+                    Decision::Keep
+                }
+            }
+            fn name(&self) -> &str {
+                "RemoveFilter"
+            }
+        }
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            compaction_filter: Some(CompactionFilterSource::Shared(Arc::new(RemoveFilter {}))),
+            ..Config::default()
+        })
+        .unwrap();
+        let number = add_table(&db, 3, 1, &[("a", "1"), ("b", "1"), ("c", "1")]);
+        let options = CompactRangeOptions {
+            bottommost_level_compaction: BottommostLevelCompaction::Skip,
+            ..CompactRangeOptions::default()
+        };
+        db.compact_range(Some(b"a"), Some(b"b"), &options).unwrap();
+        assert_eq!(level_numbers(&db, 3), vec![number]);
+        db.compact_range(Some(b"a"), Some(b"b"), &CompactRangeOptions::default()).unwrap();
+        assert_ne!(level_numbers(&db, 3), vec![number]);
+        assert_eq!(db.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(db.get(Slice::from("b")).unwrap(), None);
+        assert_eq!(table_count(&env), 1);
+    }
+
+    #[test]
+    fn test_compact_range_to_target_level() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            target_file_size: 1,
+            ..Config::default()
+        })
+        .unwrap();
+        add_table(&db, 3, 1, &[("a", "1"), ("c", "1")]);
+        add_table(&db, 1, 2, &[("a", "2"), ("b", "2")]);
+        let untouched = add_table(&db, 1, 3, &[("m", "3"), ("n", "3")]);
+        let options = CompactRangeOptions {
+            target_level: Some(5),
+            ..CompactRangeOptions::default()
+        };
+        db.compact_range(Some(b"a"), Some(b"b"), &options).unwrap();
+        assert_eq!(level_numbers(&db, 1), vec![untouched]);
+        // a file per key, of which the ones in the range are moved
+        assert_eq!(level_numbers(&db, 3).len(), 1);
+        assert_eq!(level_numbers(&db, 5).len(), 2);
+        assert_eq!(db.get(Slice::from("a")).unwrap(), Some(b"2".to_vec()));
+        assert_eq!(db.get(Slice::from("c")).unwrap(), Some(b"1".to_vec()));
+
+        // [p, r] can't move over [r, s]
+        add_table(&db, 6, 4, &[("p", "4"), ("r", "4")]);
+        add_table(&db, 5, 5, &[("r", "5"), ("s", "5")]);
+        let options = CompactRangeOptions {
+            target_level: Some(2),
+            bottommost_level_compaction: BottommostLevelCompaction::Skip,
+        };
+        assert!(matches!(
+            db.compact_range(Some(b"p"), Some(b"p"), &options),
+            Err(TinyError::InvalidArgument(_))
+        ));
+        let options = CompactRangeOptions {
+            target_level: Some(NUM_LEVELS),
+            ..CompactRangeOptions::default()
+        };
+        assert!(matches!(
+            db.compact_range(None, None, &options),
+            Err(TinyError::InvalidArgument(_))
+        ));
+    }
+
+    fn open_mem_db(env: &Arc<dyn Env>, write_buffer_size: usize) -> DB {
+        open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            write_buffer_size,
+            ..Config::default()
+        })
+        .unwrap()
+    }
+
+    fn get(db: &DB, key: &str) -> Option<Vec<u8>> {
+        db.get(Slice::from(key)).unwrap()
+    }
+
+    #[test]
+    fn test_write_and_recover() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1 << 20);
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        db.write(Slice::from("b"), Slice::from("1")).unwrap();
+        db.write(Slice::from("a"), Slice::from("2")).unwrap();
+        db.delete(Slice::from("b")).unwrap();
+        let mut batch = WriteBatch::new();
+        batch.put(b"c", b"3");
+        batch.delete(b"a");
+        batch.put(b"d", b"4");
+        db.write_batch(&WriteOptions { sync: true }, batch).unwrap();
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(get(&db, "b"), None);
+        assert_eq!(get(&db, "c"), Some(b"3".to_vec()));
+        assert_eq!(db.versions.lock().unwrap().last_sequence(), 7);
+        assert_eq!(table_count(&env), 0);
+        drop(db);
+
+        // the WAL is replayed and flushed into level 0
+        let db = open_mem_db(&env, 1 << 20);
+        assert_eq!(level_numbers(&db, 0).len(), 1);
+        assert_eq!(db.get_sorted_wal_files().unwrap().len(), 1);
+        assert_eq!(db.versions.lock().unwrap().last_sequence(), 7);
+        assert_eq!(get(&db, "a"), None);
+        assert_eq!(get(&db, "c"), Some(b"3".to_vec()));
+        assert_eq!(get(&db, "d"), Some(b"4".to_vec()));
+        // the tombstone in the memtable hides the value in the table file
+        db.delete(Slice::from("c")).unwrap();
+        assert_eq!(get(&db, "c"), None);
+        db.write(Slice::from("a"), Slice::from("5")).unwrap();
+        assert_eq!(get(&db, "a"), Some(b"5".to_vec()));
+    }
+
+    #[test]
+    fn test_flush_full_memtable() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1000);
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            db.write(Slice::from(key.as_str()), Slice::from(key.as_str())).unwrap();
+        }
+        assert!(level_numbers(&db, 0).len() > 1);
+        // the flushed logs are removed
+        assert_eq!(db.get_sorted_wal_files().unwrap().len(), 1);
+        for i in 0..100 {
+            let key = format!("key{:03}", i);
+            assert_eq!(get(&db, &key), Some(key.into_bytes()));
+        }
+        let version = db.versions.lock().unwrap().current(DEFAULT_COLUMN_FAMILY_ID);
+        let seqnos: Vec<_> = version.files(0).iter().map(|f| (f.smallest_seqno, f.largest_seqno)).collect();
+        assert!(seqnos.windows(2).all(|w| w[0].1 < w[1].0));
+    }
+
+    #[test]
+    fn test_conditional_writes() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1 << 20);
+        let (k, v1, v2) = (Slice::from("k"), Slice::from("v1"), Slice::from("v2"));
+        assert!(db.put_if_absent(k.clone(), v1.clone()).unwrap());
+        assert!(!db.put_if_absent(k.clone(), v2.clone()).unwrap());
+        assert_eq!(get(&db, "k"), Some(b"v1".to_vec()));
+
+        assert!(!db.compare_and_swap(k.clone(), v2.clone(), v2.clone()).unwrap());
+        assert_eq!(get(&db, "k"), Some(b"v1".to_vec()));
+        assert!(db.compare_and_swap(k.clone(), v1.clone(), v2.clone()).unwrap());
+        assert_eq!(get(&db, "k"), Some(b"v2".to_vec()));
+        // a missing key matches no value
+        assert!(!db.compare_and_swap(Slice::from("x"), v1.clone(), v2.clone()).unwrap());
+        assert_eq!(get(&db, "x"), None);
+
+        assert!(!db.delete_if_equal(k.clone(), v1.clone()).unwrap());
+        assert_eq!(get(&db, "k"), Some(b"v2".to_vec()));
+        assert!(db.delete_if_equal(k.clone(), v2).unwrap());
+        assert_eq!(get(&db, "k"), None);
+        // absent again after the deletion
+        assert!(db.put_if_absent(k, v1).unwrap());
+    }
+
+    #[test]
+    fn test_concurrent_compare_and_swap() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = Arc::new(open_mem_db(&env, 1000));
+        db.write(Slice::from("counter"), Slice::from("0")).unwrap();
+        let (threads, increments) = (4, 50);
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let db = db.clone();
+                std::thread::spawn(move || {
+                    let mut swapped = 0;
+                    while swapped < increments {
+                        let current = get(&db, "counter").unwrap();
+                        let n: u64 = String::from_utf8(current.clone()).unwrap().parse().unwrap();
+                        let next = (n + 1).to_string();
+                        let ok = db
+                            .compare_and_swap(Slice::from("counter"), Slice::from(&current), Slice::from(next.as_str()))
+                            .unwrap();
+                        if ok {
+                            swapped += 1;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let expected = (threads * increments).to_string();
+        assert_eq!(get(&db, "counter"), Some(expected.into_bytes()));
+    }
+
+    fn flush(db: &DB) {
+        let mut log = db.write_mutex.lock().unwrap();
+        db.flush_memtable(&mut log).unwrap();
+    }
+
+    #[test]
+    fn test_merge() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let config = Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            merge_operator: Some(Arc::new(U64AddOperator::new())),
+            ..Config::default()
+        };
+        let db = open_db(config.clone()).unwrap();
+        let n = |n: u64| U64AddOperator::encode(n);
+        db.merge(Slice::from("k"), Slice::from(&n(1))).unwrap();
+        db.merge(Slice::from("k"), Slice::from(&n(2))).unwrap();
+        assert_eq!(get(&db, "k"), Some(n(3)));
+        // onto a value and a deletion in the memtable
+        db.write(Slice::from("v"), Slice::from(&n(10))).unwrap();
+        db.merge(Slice::from("v"), Slice::from(&n(5))).unwrap();
+        assert_eq!(get(&db, "v"), Some(n(15)));
+        db.delete(Slice::from("v")).unwrap();
+        db.merge(Slice::from("v"), Slice::from(&n(7))).unwrap();
+        assert_eq!(get(&db, "v"), Some(n(7)));
+
+        // the operands are kept through the WAL and the flush
+        drop(db);
+        let db = open_db(config).unwrap();
+        assert_eq!(get(&db, "k"), Some(n(3)));
+        assert_eq!(get(&db, "v"), Some(n(7)));
+        let level0 = level_numbers(&db, 0);
+        let types: Vec<_> = table_entries(&db, level0[0]).into_iter().map(|(_, e)| e.value_type).collect();
+        // k has no base value yet while v is merged onto its deletion
+        assert_eq!(types, vec![ValueType::Merge, ValueType::Value]);
+
+        // onto the operands in a table file
+        db.merge(Slice::from("k"), Slice::from(&n(4))).unwrap();
+        assert_eq!(get(&db, "k"), Some(n(7)));
+        flush(&db);
+        assert_eq!(get(&db, "k"), Some(n(7)));
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        let bottom = level_numbers(&db, 1);
+        let entries = table_entries(&db, bottom[0]);
+        assert_eq!(entries[0].1.value_type, ValueType::Value);
+        assert_eq!(get(&db, "k"), Some(n(7)));
+    }
+
+    #[test]
+    fn test_merge_without_partial_merge() {
+        // appends the operands with commas and can't combine them without the value
+        struct AppendOperator {}
+        impl MergeOperator for AppendOperator {
+            fn full_merge(&self, _key: &Slice, existing: Option<&Slice>, operands: &[Slice]) -> Option<Vec<u8>> {
+                let mut parts: Vec<&[u8]> = existing.iter().map(|v| v.to_slice()).collect();
+                parts.extend(operands.iter().map(|o| o.to_slice()));
+                Some(parts.join(&b","[..]))
+            }
+            fn name(&self) -> &str {
+                "AppendOperator"
+            }
+        }
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            merge_operator: Some(Arc::new(AppendOperator {})),
+            ..Config::default()
+        })
+        .unwrap();
+        add_table(&db, 2, 1, &[("k", "base")]);
+        db.merge(Slice::from("k"), Slice::from("a")).unwrap();
+        db.merge(Slice::from("k"), Slice::from("b")).unwrap();
+        flush(&db);
+        db.merge(Slice::from("k"), Slice::from("c")).unwrap();
+        flush(&db);
+        assert_eq!(get(&db, "k"), Some(b"base,a,b,c".to_vec()));
+        // level 1 is above the base value, so the operands stay as they are
+        db.compact_level(&db.default_family(), 0, 1, None, None, None).unwrap();
+        let level1 = level_numbers(&db, 1);
+        let entry = table_entries(&db, level1[0]).pop().unwrap().1;
+        assert_eq!(entry.value_type, ValueType::Merge);
+        assert_eq!(decode_operands(&entry.value).unwrap(), vec![&b"a"[..], b"b", b"c"]);
+        assert_eq!(get(&db, "k"), Some(b"base,a,b,c".to_vec()));
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        assert_eq!(get(&db, "k"), Some(b"base,a,b,c".to_vec()));
+        assert_eq!(table_count(&env), 1);
+    }
+
+    #[test]
+    fn test_put_with_ttl() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let clock = Arc::new(ManualClock::new(1_000_000));
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            clock: clock.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        add_table(&db, 3, 1, &[("k", "old"), ("z", "old")]);
+        let ttl = Duration::from_secs(10);
+        db.put_with_ttl(Slice::from("k"), Slice::from("v"), ttl).unwrap();
+        db.put_with_ttl(Slice::from("long"), Slice::from("v"), ttl * 2).unwrap();
+        db.write(Slice::from("plain"), Slice::from("v")).unwrap();
+        assert_eq!(get(&db, "k"), Some(b"v".to_vec()));
+        let keys = [Slice::from("k"), Slice::from("long"), Slice::from("z")];
+        assert_eq!(
+            db.multi_get(&keys).unwrap(),
+            vec![Some(b"v".to_vec()), Some(b"v".to_vec()), Some(b"old".to_vec())]
+        );
+
+        // the expired value hides the older value of the key
+        clock.advance_micros(10_000_000);
+        assert_eq!(get(&db, "k"), None);
+        assert_eq!(get(&db, "long"), Some(b"v".to_vec()));
+        assert_eq!(get(&db, "plain"), Some(b"v".to_vec()));
+        assert_eq!(db.multi_get(&keys).unwrap()[..2], [None, Some(b"v".to_vec())]);
+        flush(&db);
+        assert_eq!(get(&db, "k"), None);
+
+        // the compaction drops the expired value together with the older one
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        let bottom = level_numbers(&db, 3);
+        let keys: Vec<_> = table_entries(&db, bottom[0]).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"long".to_vec(), b"plain".to_vec(), b"z".to_vec()]);
+        clock.advance_micros(10_000_000);
+        assert_eq!(get(&db, "long"), None);
+    }
+
+    fn family_levels(db: &DB, handle: &ColumnFamilyHandle) -> Vec<usize> {
+        let version = db.versions.lock().unwrap().current(handle.id());
+        (0..NUM_LEVELS).map(|l| version.files(l).len()).collect()
+    }
+
+    fn collect(iter: DBIterator<'_>) -> Vec<(String, String)> {
+        iter.map(|r| {
+            let (k, v) = r.unwrap();
+            (String::from_utf8(k).unwrap(), String::from_utf8(v).unwrap())
+        })
+        .collect()
+    }
+
+    fn pairs(kvs: &[(&str, &str)]) -> Vec<(String, String)> {
+        kvs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_column_families() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1 << 20);
+        let users = db.create_column_family("users", ColumnFamilyOptions::default()).unwrap();
+        assert!(matches!(
+            db.create_column_family("users", ColumnFamilyOptions::default()),
+            Err(TinyError::InvalidArgument(_))
+        ));
+        db.write(Slice::from("k"), Slice::from("default")).unwrap();
+        db.write_cf(&users, Slice::from("k"), Slice::from("users")).unwrap();
+        assert_eq!(get(&db, "k"), Some(b"default".to_vec()));
+        assert_eq!(db.get_cf(&users, Slice::from("k")).unwrap(), Some(b"users".to_vec()));
+
+        // one batch across the column families
+        let mut batch = WriteBatch::new();
+        batch.put(b"a", b"default");
+        batch.put_cf(&users, b"b", b"users");
+        batch.delete_cf(&users, b"k");
+        db.write_batch(&WriteOptions::default(), batch).unwrap();
+        assert_eq!(get(&db, "k"), Some(b"default".to_vec()));
+        assert_eq!(db.get_cf(&users, Slice::from("k")).unwrap(), None);
+        assert_eq!(collect(db.iter().unwrap()), pairs(&[("a", "default"), ("k", "default")]));
+        assert_eq!(collect(db.iter_cf(&users).unwrap()), pairs(&[("b", "users")]));
+        // nothing of a batch is written if a change is invalid
+        let mut batch = WriteBatch::new();
+        batch.put(b"x", b"1");
+        batch.merge_cf(&users, b"b", b"1");
+        assert!(matches!(
+            db.write_batch(&WriteOptions::default(), batch),
+            Err(TinyError::NotSupported(_))
+        ));
+        assert_eq!(get(&db, "x"), None);
+
+        // every column family is flushed into its own levels
+        flush(&db);
+        assert_eq!(level_numbers(&db, 0).len(), 1);
+        assert_eq!(family_levels(&db, &users)[0], 1);
+        db.compact_range_cf(&users, None, None, &CompactRangeOptions::default()).unwrap();
+        assert_eq!(family_levels(&db, &users), vec![0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(level_numbers(&db, 0).len(), 1);
+        assert_eq!(db.get_properties_of_all_tables().unwrap().len(), 2);
+        assert_eq!(db.get_cf(&users, Slice::from("b")).unwrap(), Some(b"users".to_vec()));
+
+        // the files of a dropped column family are deleted
+        let temp = db.create_column_family("temp", ColumnFamilyOptions::default()).unwrap();
+        db.write_cf(&temp, Slice::from("t"), Slice::from("1")).unwrap();
+        flush(&db);
+        assert_eq!(table_count(&env), 3);
+        db.drop_column_family(&temp).unwrap();
+        assert_eq!(table_count(&env), 2);
+        assert!(matches!(db.get_cf(&temp, Slice::from("t")), Err(TinyError::InvalidArgument(_))));
+        assert!(db.iter_cf(&temp).is_err());
+        let mut batch = WriteBatch::new();
+        batch.put_cf(&temp, b"t", b"2");
+        assert!(matches!(
+            db.write_batch(&WriteOptions::default(), batch),
+            Err(TinyError::InvalidArgument(_))
+        ));
+        assert!(db.drop_column_family(&db.default_column_family()).is_err());
+
+        // the column families and their unflushed writes are recovered
+        db.write_cf(&users, Slice::from("c"), Slice::from("wal")).unwrap();
+        drop(db);
+        let db = open_mem_db(&env, 1 << 20);
+        let recovered = db.column_family_handle("users").unwrap();
+        assert_eq!(recovered, users);
+        assert!(db.column_family_handle("temp").is_none());
+        assert_eq!(
+            collect(db.iter_cf(&users).unwrap()),
+            pairs(&[("b", "users"), ("c", "wal")])
+        );
+        assert_eq!(get(&db, "c"), None);
+        let next = db.create_column_family("next", ColumnFamilyOptions::default()).unwrap();
+        assert!(next.id() > temp.id());
+    }
+
+    #[test]
+    fn test_get_live_files() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        let cf = db.create_column_family("cf", ColumnFamilyOptions::default()).unwrap();
+        db.write(Slice::from("a"), Slice::from("1")).unwrap();
+        db.write_cf(&cf, Slice::from("b"), Slice::from("2")).unwrap();
+        db.compact_range(None, None, &CompactRangeOptions::default()).unwrap();
+        // the compaction replaces the table files, which are kept while the deletions
+        // are disabled but are not live any more
+        db.disable_file_deletions();
+        let options = CompactRangeOptions {
+            bottommost_level_compaction: BottommostLevelCompaction::Force,
+            ..CompactRangeOptions::default()
+        };
+        db.compact_range(None, None, &options).unwrap();
+        db.compact_range_cf(&cf, None, None, &options).unwrap();
+        // a table file left by a crash
+        env.new_writable_file(&table_file_name("/db", 1000)).unwrap();
+
+        let expected: Vec<_> = {
+            let versions = db.versions.lock().unwrap();
+            let mut numbers: Vec<_> = [DEFAULT_COLUMN_FAMILY_ID, cf.id()]
+                .iter()
+                .flat_map(|id| versions.current(*id).all_files().map(|f| f.number).collect::<Vec<_>>())
+                .collect();
+            numbers.sort_unstable();
+            numbers.into_iter().map(|n| table_file_name("/db", n)).collect()
+        };
+        assert_eq!(expected.len(), 2);
+        let (live, manifest_size) = db.get_live_files().unwrap();
+        assert_eq!(env.get_file_size(&live[1]).unwrap(), manifest_size);
+        assert_eq!(live[0], current_file_name("/db"));
+        assert!(parse_file_name(live[1].trim_start_matches("/db/")).is_some_and(|(t, _)| t == FileType::Manifest));
+        assert_eq!(&live[2..], &expected[..]);
+        assert!(db.list_files("/db", FileType::Table).unwrap().len() > expected.len() + 1);
+    }
+
+    #[test]
+    fn test_iter() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let clock = Arc::new(ManualClock::new(0));
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            clock: clock.clone(),
+            merge_operator: Some(Arc::new(U64AddOperator::new())),
+            ..Config::default()
+        })
+        .unwrap();
+        add_table(&db, 3, 1, &[("a", "3"), ("b", "3"), ("c", "3"), ("e", "3")]);
+        add_table(&db, 3, 2, &[("x", "3")]);
+        add_table(&db, 1, 3, &[("b", "1"), ("d", "1")]);
+        db.delete(Slice::from("c")).unwrap();
+        flush(&db);
+        db.write(Slice::from("d"), Slice::from("mem")).unwrap();
+        db.delete(Slice::from("e")).unwrap();
+        db.put_with_ttl(Slice::from("f"), Slice::from("ttl"), Duration::from_secs(1)).unwrap();
+        db.merge(Slice::from("n"), Slice::from(&U64AddOperator::encode(1))).unwrap();
+        db.merge(Slice::from("n"), Slice::from(&U64AddOperator::encode(2))).unwrap();
+
+        let iter = db.iter().unwrap();
+        // the writes after the iterator is created are not seen
+        db.write(Slice::from("a"), Slice::from("new")).unwrap();
+        let expected: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"a".to_vec(), b"3".to_vec()),
+            (b"b".to_vec(), b"1".to_vec()),
+            (b"d".to_vec(), b"mem".to_vec()),
+            (b"f".to_vec(), b"ttl".to_vec()),
+            (b"n".to_vec(), U64AddOperator::encode(3)),
+            (b"x".to_vec(), b"3".to_vec()),
+        ];
+        assert_eq!(iter.collect::<Result<Vec<_>, _>>().unwrap(), expected);
+
+        clock.advance_micros(1_000_000);
+        let keys: Vec<_> = db.iter().unwrap().map(|r| r.unwrap().0).collect();
+        assert_eq!(keys, vec![b"a".to_vec(), b"b".to_vec(), b"d".to_vec(), b"n".to_vec(), b"x".to_vec()]);
+    }
+
+    #[test]
+    fn test_multi_get() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            merge_operator: Some(Arc::new(U64AddOperator::new())),
+            ..Config::default()
+        })
+        .unwrap();
+        let n = |n: u64| U64AddOperator::encode(n);
+        add_table(&db, 3, 1, &[("a", "3"), ("b", "3"), ("c", "3")]);
+        add_table(&db, 3, 2, &[("x", "3"), ("y", "3")]);
+        add_table(&db, 1, 3, &[("b", "1"), ("m", "1")]);
+        db.write(Slice::from("n"), Slice::from(&n(1))).unwrap();
+        db.delete(Slice::from("c")).unwrap();
+        flush(&db);
+        db.merge(Slice::from("n"), Slice::from(&n(2))).unwrap();
+        db.write(Slice::from("y"), Slice::from("mem")).unwrap();
+
+        // unsorted keys with duplicates and missing ones
+        let keys: Vec<_> = ["y", "b", "missing", "a", "y", "c", "n", "b", "x", "m"]
+            .iter()
+            .map(|k| Slice::from(*k))
+            .collect();
+        let expected: Vec<Option<Vec<u8>>> = vec![
+            Some(b"mem".to_vec()),
+            Some(b"1".to_vec()),
+            None,
+            Some(b"3".to_vec()),
+            Some(b"mem".to_vec()),
+            None,
+            Some(n(3)),
+            Some(b"1".to_vec()),
+            Some(b"3".to_vec()),
+            Some(b"1".to_vec()),
+        ];
+        assert_eq!(db.multi_get(&keys).unwrap(), expected);
+        let singles: Vec<_> = keys.iter().map(|k| db.get(k.clone()).unwrap()).collect();
+        assert_eq!(singles, expected);
+        assert!(db.multi_get(&[]).unwrap().is_empty());
+
+        // not blocked by a writer holding the write lock
+        let _log = db.write_mutex.lock().unwrap();
+        assert_eq!(db.multi_get(&keys[..2]).unwrap(), expected[..2]);
+    }
+
+    #[test]
+    fn test_verify_checksums() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let db = open_mem_db(&env, 1 << 20);
+        let number = add_table(&db, 1, 1, &[("a", "1"), ("b", "corrupted"), ("c", "3")]);
+        // flip a byte of a value in the data block
+        let path = table_file_name("/db", number);
+        let size = env.get_file_size(&path).unwrap() as usize;
+        let mut data = vec![0; size];
+        env.new_random_access_file(&path).unwrap().read_exact_at(0, &mut data).unwrap();
+        let pos = data.windows(9).position(|w| w == b"corrupted").unwrap();
+        data[pos] ^= 0xff;
+        let mut file = env.new_writable_file(&path).unwrap();
+        file.write_all(&data).unwrap();
+        file.sync().unwrap();
+        drop(file);
+
+        let verify = ReadOptions { verify_checksums: true };
+        assert!(matches!(db.get_opt(&verify, Slice::from("a")), Err(TinyError::Corruption(_))));
+        assert!(matches!(
+            db.multi_get_opt(&verify, &[Slice::from("c")]),
+            Err(TinyError::Corruption(_))
+        ));
+        assert!(matches!(
+            db.iter_opt(&verify).unwrap().next(),
+            Some(Err(TinyError::Corruption(_)))
+        ));
+        // the corrupted block is read as it is unless the checksums are verified, and is
+        // served from the block cache afterwards
+        assert!(db.iter().unwrap().all(|r| r.is_ok()));
+        assert_ne!(get(&db, "b"), Some(b"corrupted".to_vec()));
+    }
+
+    #[test]
+    fn test_partitioned_tables() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let block_cache = Arc::new(BlockCache::new(1 << 20));
+        let db = open_db(Config {
+            dir: "/db".to_owned(),
+            env: env.clone(),
+            block_size: 64,
+            block_cache: block_cache.clone(),
+            filter_policy: Some(Arc::new(BloomFilterPolicy::new(10))),
+            index_type: IndexType::TwoLevelIndexSearch,
+            partition_filters: true,
+            metadata_block_size: 128,
+            ..Config::default()
+        })
+        .unwrap();
+        for i in 0..300 {
+            let (key, value) = (format!("key{:04}", i * 2), format!("{}", i));
+            db.write(Slice::from(key.as_str()), Slice::from(value.as_str())).unwrap();
+        }
+        flush(&db);
+        let number = level_numbers(&db, 0)[0];
+        let path = table_file_name("/db", number);
+        let table = db.table_cache.find_table(number, env.get_file_size(&path).unwrap(), true).unwrap();
+        assert!(table.properties().index_partitions > 1);
+        assert!(table.properties().filter_size > 0);
+
+        for i in 0..300 {
+            assert_eq!(get(&db, &format!("key{:04}", i * 2)), Some(format!("{}", i).into_bytes()));
+            assert_eq!(get(&db, &format!("key{:04}", i * 2 + 1)), None);
+        }
+        assert!(block_cache.total_charge() > 0);
+        assert_eq!(db.iter().unwrap().count(), 300);
+    }
+
+    #[test]
+    fn test_open_without_dir() {
+        assert!(matches!(
+            open_db(Config::default()),
+            Err(TinyError::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,62 @@
+use crate::entry::MergeContext;
+use crate::level::version::Version;
+use crate::table::merger::MergingIterator;
+use crate::util::error::TinyError;
+use crate::util::merge_operator::MergeOperator;
+
+use std::sync::Arc;
+
+/// DBIterator yields the live keys of a column family and their values in the key order,
+/// as `DB::get` would return them when the iterator is created. The memtable is copied
+/// and the table files of the version are read while iterating, so the later writes,
+/// flushes and compactions are not seen. An error is yielded once and ends the iteration.
+pub struct DBIterator<'a> {
+    merged: MergingIterator<'a>,
+    merge_operator: Option<Arc<dyn MergeOperator>>,
+    // the values expired by now are skipped
+    now: u64,
+    // keeps the files being read from being deleted
+    _version: Arc<Version>,
+}
+
+impl<'a> DBIterator<'a> {
+    pub(crate) fn new(
+        merged: MergingIterator<'a>,
+        merge_operator: Option<Arc<dyn MergeOperator>>,
+        now: u64,
+        version: Arc<Version>,
+    ) -> Self {
+        DBIterator {
+            merged,
+            merge_operator,
+            now,
+            _version: version,
+        }
+    }
+}
+
+impl Iterator for DBIterator<'_> {
+    type Item = Result<(Vec<u8>, Vec<u8>), TinyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (key, entries) = match self.merged.next()? {
+                Ok(next) => next,
+                Err(e) => return Some(Err(e)),
+            };
+            let mut ctx = MergeContext::new(&key, self.merge_operator.as_deref());
+            let folded = entries
+                .into_iter()
+                .try_for_each(|entry| ctx.add(entry.expire(self.now)).map(|_| ()))
+                .and_then(|_| ctx.finish(true));
+            match folded {
+                Ok(entry) => {
+                    if let Some(value) = entry.and_then(|e| e.into_user_value()) {
+                        return Some(Ok((key, value)));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
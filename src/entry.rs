@@ -0,0 +1,349 @@
+use crate::util::error::TinyError;
+use crate::util::merge_operator::MergeOperator;
+use crate::util::slice::Slice;
+use crate::util::ttl;
+use crate::util::varint::{get_length_prefixed, put_length_prefixed};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+// type (1 byte) + sequence number (8 bytes)
+const ENTRY_HEADER_SIZE: usize = 1 + 8;
+
+/// The type of the change of a key made by a write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ValueType {
+    /// The key is deleted
+    Deletion = 0,
+    /// The key is set to the value
+    Value = 1,
+    /// The value holds the merge operands of the key written by `DB::merge`, which are
+    /// applied to the older value of the key by the merge operator
+    Merge = 2,
+    /// The key is set to the value until the expiration timestamp appended to the value
+    /// by `ttl::encode_value_with_expiry`
+    ValueWithExpiry = 3,
+}
+
+impl ValueType {
+    pub fn from_u8(b: u8) -> Option<ValueType> {
+        match b {
+            0 => Some(ValueType::Deletion),
+            1 => Some(ValueType::Value),
+            2 => Some(ValueType::Merge),
+            3 => Some(ValueType::ValueWithExpiry),
+            _ => None,
+        }
+    }
+}
+
+/// Entry is a change of a key kept by a memtable or a table file: the type, the sequence
+/// number of the write and the value of the write. The value of a merge entry is the
+/// list of its operands encoded by `encode_operands`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    pub value_type: ValueType,
+    pub seq: u64,
+    pub value: Vec<u8>,
+}
+
+impl Entry {
+    pub fn new(value_type: ValueType, seq: u64, value: &[u8]) -> Self {
+        Entry {
+            value_type,
+            seq,
+            value: value.to_vec(),
+        }
+    }
+
+    /// Appends the entry in the format stored with the key in a table file:
+    ///
+    /// ```text
+    /// +-----------+---------------------+-------+
+    /// | type (u8) | seq (fixed64 LE)    | value |
+    /// +-----------+---------------------+-------+
+    /// ```
+    pub fn encode_to(value_type: ValueType, seq: u64, value: &[u8], dst: &mut Vec<u8>) {
+        let mut header = [0u8; ENTRY_HEADER_SIZE];
+        header[0] = value_type as u8;
+        LittleEndian::write_u64(&mut header[1..], seq);
+        dst.extend_from_slice(&header);
+        dst.extend_from_slice(value);
+    }
+
+    /// Returns the entry as a deletion if it has expired at `now_micros`, which hides the
+    /// older entries of the key like the deletion does
+    pub fn expire(self, now_micros: u64) -> Self {
+        match self.value_type {
+            ValueType::ValueWithExpiry if ttl::live_value(&self.value, now_micros).is_none() => Entry {
+                value_type: ValueType::Deletion,
+                seq: self.seq,
+                value: vec![],
+            },
+            _ => self,
+        }
+    }
+
+    /// Returns the user value of a value entry, or `None` for the other types
+    pub fn user_value(&self) -> Option<&[u8]> {
+        match self.value_type {
+            ValueType::Value => Some(&self.value),
+            ValueType::ValueWithExpiry => ttl::decode_value_with_expiry(&self.value).map(|(v, _)| v),
+            ValueType::Deletion | ValueType::Merge => None,
+        }
+    }
+
+    /// Takes the user value out of a value entry, or returns `None` for the other types
+    pub fn into_user_value(self) -> Option<Vec<u8>> {
+        match self.value_type {
+            ValueType::Value => Some(self.value),
+            _ => self.user_value().map(|v| v.to_vec()),
+        }
+    }
+
+    /// Decodes the entry encoded by `encode_to`.
+    /// Returns `TinyError::Corruption` if `data` is malformed.
+    pub fn decode(data: &[u8]) -> Result<Self, TinyError> {
+        if data.len() < ENTRY_HEADER_SIZE {
+            return Err(TinyError::Corruption(format!(
+                "[entry] {} bytes are too short for an entry",
+                data.len()
+            )));
+        }
+        let value_type = ValueType::from_u8(data[0]).ok_or_else(|| {
+            TinyError::Corruption(format!("[entry] unknown value type {}", data[0]))
+        })?;
+        Ok(Entry {
+            value_type,
+            seq: LittleEndian::read_u64(&data[1..ENTRY_HEADER_SIZE]),
+            value: data[ENTRY_HEADER_SIZE..].to_vec(),
+        })
+    }
+}
+
+/// Appends the merge operands ordered from the oldest to the newest as the value of a
+/// merge entry
+pub fn encode_operands<T: AsRef<[u8]>>(operands: &[T], dst: &mut Vec<u8>) {
+    for operand in operands {
+        put_length_prefixed(dst, operand.as_ref());
+    }
+}
+
+/// Decodes the merge operands encoded by `encode_operands`.
+/// Returns `TinyError::Corruption` if `data` is malformed.
+pub fn decode_operands(mut data: &[u8]) -> Result<Vec<&[u8]>, TinyError> {
+    let mut operands = vec![];
+    while !data.is_empty() {
+        let operand = get_length_prefixed(&mut data)
+            .ok_or_else(|| TinyError::Corruption("[entry] bad merge operand".to_owned()))?;
+        operands.push(operand);
+    }
+    Ok(operands)
+}
+
+/// MergeContext folds the entries of a key fed from the newest to the oldest into the
+/// single entry they amount to. The merge operands are collected until a value or a
+/// deletion is found, onto which they are applied by the merge operator. The expired
+/// entries should be fed as deletions by `Entry::expire`.
+pub struct MergeContext<'a> {
+    key: &'a [u8],
+    merge_operator: Option<&'a dyn MergeOperator>,
+    // the sequence number of the newest entry
+    seq: Option<u64>,
+    // the operands collected so far from the newest to the oldest
+    operands: Vec<Vec<u8>>,
+    // the value or the deletion ending the operands
+    base: Option<Entry>,
+}
+
+impl<'a> MergeContext<'a> {
+    pub fn new(key: &'a [u8], merge_operator: Option<&'a dyn MergeOperator>) -> Self {
+        MergeContext {
+            key,
+            merge_operator,
+            seq: None,
+            operands: vec![],
+            base: None,
+        }
+    }
+
+    /// Feeds the next older entry of the key. Returns true once a value or a deletion
+    /// is found, after which the older entries don't matter.
+    pub fn add(&mut self, entry: Entry) -> Result<bool, TinyError> {
+        if self.is_done() {
+            return Ok(true);
+        }
+        self.seq.get_or_insert(entry.seq);
+        match entry.value_type {
+            ValueType::Merge => {
+                let operands = decode_operands(&entry.value)?;
+                self.operands.extend(operands.iter().rev().map(|o| o.to_vec()));
+                Ok(false)
+            }
+            _ => {
+                self.base = Some(entry);
+                Ok(true)
+            }
+        }
+    }
+
+    #[inline]
+    pub fn is_done(&self) -> bool {
+        self.base.is_some()
+    }
+
+    /// Returns the entry the fed entries amount to, or `None` if nothing is fed.
+    ///
+    /// The operands with no value or deletion found are applied onto nothing if
+    /// `is_bottommost` tells no older entry of the key exists, or else are kept as a
+    /// merge entry, in which the adjacent operands are combined if the merge operator can.
+    /// Returns `TinyError::Corruption` if the merge operator fails to merge.
+    pub fn finish(mut self, is_bottommost: bool) -> Result<Option<Entry>, TinyError> {
+        let seq = match self.seq {
+            Some(seq) => seq,
+            None => return Ok(None),
+        };
+        if self.operands.is_empty() {
+            return Ok(self.base);
+        }
+        let merge_operator = self.merge_operator.ok_or_else(|| {
+            TinyError::NotSupported("[entry] a merge operator is required to read merge operands".to_owned())
+        })?;
+        let key = Slice::from(self.key);
+        self.operands.reverse();
+        if self.base.is_none() && !is_bottommost {
+            let mut combined: Vec<Vec<u8>> = vec![];
+            for operand in self.operands {
+                let merged = combined.last().and_then(|left| {
+                    merge_operator.partial_merge(&key, &Slice::from(left), &Slice::from(&operand))
+                });
+                match merged {
+                    Some(merged) => *combined.last_mut().unwrap() = merged,
+                    None => combined.push(operand),
+                }
+            }
+            let mut value = vec![];
+            encode_operands(&combined, &mut value);
+            return Ok(Some(Entry {
+                value_type: ValueType::Merge,
+                seq,
+                value,
+            }));
+        }
+        // a value with an expiry is merged as a plain value
+        let existing = self.base.as_ref().and_then(|e| e.user_value()).map(Slice::from);
+        let operands: Vec<Slice> = self.operands.iter().map(Slice::from).collect();
+        match merge_operator.full_merge(&key, existing.as_ref(), &operands) {
+            Some(value) => Ok(Some(Entry {
+                value_type: ValueType::Value,
+                seq,
+                value,
+            })),
+            None => Err(TinyError::Corruption(format!(
+                "[entry] {} failed to merge {} operands of {:?}",
+                merge_operator.name(),
+                operands.len(),
+                self.key
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::merge_operator::U64AddOperator;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut buf = vec![];
+        Entry::encode_to(ValueType::Value, 7, b"value", &mut buf);
+        assert_eq!(Entry::decode(&buf).unwrap(), Entry::new(ValueType::Value, 7, b"value"));
+        buf.clear();
+        Entry::encode_to(ValueType::Deletion, u64::MAX, b"", &mut buf);
+        let entry = Entry::decode(&buf).unwrap();
+        assert_eq!((entry.value_type, entry.seq), (ValueType::Deletion, u64::MAX));
+        assert!(entry.value.is_empty());
+
+        assert!(matches!(Entry::decode(&buf[..8]), Err(TinyError::Corruption(_))));
+        buf[0] = ValueType::Merge as u8;
+        assert_eq!(Entry::decode(&buf).unwrap().value_type, ValueType::Merge);
+        buf[0] = 100;
+        assert!(matches!(Entry::decode(&buf), Err(TinyError::Corruption(_))));
+    }
+
+    fn merge_entry(seq: u64, operands: &[u64]) -> Entry {
+        let operands: Vec<_> = operands.iter().map(|n| U64AddOperator::encode(*n)).collect();
+        let mut value = vec![];
+        encode_operands(&operands, &mut value);
+        Entry::new(ValueType::Merge, seq, &value)
+    }
+
+    #[test]
+    fn test_merge_context() {
+        let op = U64AddOperator::new();
+        let value = |n: u64| Entry::new(ValueType::Value, 1, &U64AddOperator::encode(n));
+
+        // onto a value
+        let mut ctx = MergeContext::new(b"k", Some(&op));
+        assert!(!ctx.add(merge_entry(5, &[3])).unwrap());
+        assert!(!ctx.add(merge_entry(4, &[1, 2])).unwrap());
+        assert!(ctx.add(value(10)).unwrap());
+        assert!(ctx.add(value(100)).unwrap());
+        let entry = ctx.finish(false).unwrap().unwrap();
+        assert_eq!((entry.value_type, entry.seq), (ValueType::Value, 5));
+        assert_eq!(U64AddOperator::decode(&entry.value), Some(16));
+
+        // onto a deletion
+        let mut ctx = MergeContext::new(b"k", Some(&op));
+        ctx.add(merge_entry(5, &[3])).unwrap();
+        ctx.add(Entry::new(ValueType::Deletion, 2, b"")).unwrap();
+        assert_eq!(ctx.finish(false).unwrap().unwrap().value, U64AddOperator::encode(3));
+
+        // kept as combined operands above the bottommost level
+        let mut ctx = MergeContext::new(b"k", Some(&op));
+        ctx.add(merge_entry(5, &[3])).unwrap();
+        ctx.add(merge_entry(4, &[1, 2])).unwrap();
+        assert_eq!(ctx.finish(false).unwrap(), Some(merge_entry(5, &[6])));
+        let mut ctx = MergeContext::new(b"k", Some(&op));
+        ctx.add(merge_entry(5, &[3])).unwrap();
+        assert_eq!(ctx.finish(true).unwrap().unwrap().value, U64AddOperator::encode(3));
+
+        // without the merge operator or with bad operands
+        let mut ctx = MergeContext::new(b"k", None);
+        ctx.add(merge_entry(5, &[3])).unwrap();
+        assert!(matches!(ctx.finish(true), Err(TinyError::NotSupported(_))));
+        let mut ctx = MergeContext::new(b"k", Some(&op));
+        ctx.add(Entry::new(ValueType::Merge, 1, b"\x01x")).unwrap();
+        ctx.add(Entry::new(ValueType::Value, 1, b"")).unwrap();
+        assert!(ctx.finish(true).is_err());
+
+        // values and deletions pass through
+        let mut ctx = MergeContext::new(b"k", None);
+        ctx.add(Entry::new(ValueType::Deletion, 2, b"")).unwrap();
+        assert_eq!(ctx.finish(false).unwrap(), Some(Entry::new(ValueType::Deletion, 2, b"")));
+        assert_eq!(MergeContext::new(b"k", None).finish(true).unwrap(), None);
+    }
+
+    #[test]
+    fn test_expire() {
+        let value = ttl::encode_value_with_expiry(b"v", 100);
+        let entry = Entry::new(ValueType::ValueWithExpiry, 7, &value);
+        assert_eq!(entry.user_value(), Some(&b"v"[..]));
+        assert_eq!(entry.clone().into_user_value(), Some(b"v".to_vec()));
+        assert_eq!(entry.clone().expire(99), entry);
+        let expired = entry.expire(100);
+        assert_eq!((expired.value_type, expired.seq), (ValueType::Deletion, 7));
+        assert_eq!(expired.user_value(), None);
+        assert_eq!(expired.into_user_value(), None);
+        let plain = Entry::new(ValueType::Value, 1, b"v");
+        assert_eq!(plain.clone().expire(u64::MAX), plain);
+
+        // merged onto the user value
+        let op = U64AddOperator::new();
+        let mut ctx = MergeContext::new(b"k", Some(&op));
+        ctx.add(merge_entry(5, &[3])).unwrap();
+        let value = ttl::encode_value_with_expiry(&U64AddOperator::encode(2), 100);
+        ctx.add(Entry::new(ValueType::ValueWithExpiry, 1, &value)).unwrap();
+        assert_eq!(ctx.finish(false).unwrap().unwrap().value, U64AddOperator::encode(5));
+    }
+}
@@ -0,0 +1,498 @@
+use super::{Env, FileLock, RandomAccessFile, SequentialFile, WritableFile};
+use crate::util::error::TinyError;
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+
+// The durability of a file written through the FaultInjectionEnv
+#[derive(Clone, Copy)]
+struct FileState {
+    // the size of the file written so far
+    pos: u64,
+    // the size of the file at the last sync, `None` if never synced
+    synced: Option<u64>,
+}
+
+struct FaultState {
+    // the files written since the last crash
+    files: HashMap<String, FileState>,
+    // bumped by every crash so the files opened before can not be written any more
+    epoch: u64,
+    // the number of the mutating operations that still succeed, `None` if never failing
+    ops_before_failure: Option<u64>,
+}
+
+impl FaultState {
+    // Counts a mutating operation and fails it if a failure is injected
+    fn check(&mut self, op: &str, path: &str) -> io::Result<()> {
+        match self.ops_before_failure.as_mut() {
+            Some(0) => Err(io::Error::other(format!(
+                "[fault injection] injected failure of {} {}",
+                op, path
+            ))),
+            Some(n) => {
+                *n -= 1;
+                Ok(())
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+struct FaultInjectionWritableFile {
+    inner: Box<dyn WritableFile>,
+    path: String,
+    epoch: u64,
+    state: Arc<Mutex<FaultState>>,
+}
+
+impl FaultInjectionWritableFile {
+    fn check(&self, state: &mut FaultState, op: &str) -> io::Result<()> {
+        if state.epoch != self.epoch {
+            return Err(io::Error::other(format!(
+                "[fault injection] {} is opened before the crash",
+                self.path
+            )));
+        }
+        state.check(op, &self.path)
+    }
+}
+
+impl Write for FaultInjectionWritableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+        self.check(&mut state, "write")?;
+        // write through so nothing is left in the buffer of `inner` by a crash
+        self.inner.write_all(buf)?;
+        self.inner.flush()?;
+        let file = state.files.entry(self.path.clone()).or_insert(FileState {
+            pos: 0,
+            synced: None,
+        });
+        file.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for FaultInjectionWritableFile {
+    fn sync(&mut self) -> Result<(), TinyError> {
+        let state = self.state.clone();
+        let mut state = state.lock().unwrap();
+        self.check(&mut state, "sync")?;
+        self.inner.sync()?;
+        if let Some(file) = state.files.get_mut(&self.path) {
+            file.synced = Some(file.pos);
+        }
+        Ok(())
+    }
+}
+
+/// FaultInjectionEnv wraps another `Env` to test the crash consistency. It remembers how
+/// much of every file written through it has been synced, so `crash` can drop the unsynced
+/// data the way a power loss does. It can also fail the mutating operations (creating,
+/// writing, syncing, renaming and removing files) after a given number of them and corrupt
+/// the files.
+///
+/// The data written is passed to the base Env immediately, so the reads see the unsynced
+/// data until the crash as they do with the page cache.
+pub struct FaultInjectionEnv {
+    base: Arc<dyn Env>,
+    state: Arc<Mutex<FaultState>>,
+    read_hook: Mutex<Option<ReadHook>>,
+}
+
+// The paths matched and the callback run before one of them is opened for reading
+type ReadHook = (Box<dyn Fn(&str) -> bool + Send + Sync>, Box<dyn FnOnce() + Send>);
+
+impl FaultInjectionEnv {
+    pub fn new(base: Arc<dyn Env>) -> Self {
+        FaultInjectionEnv {
+            base,
+            state: Arc::new(Mutex::new(FaultState {
+                files: HashMap::new(),
+                epoch: 0,
+                ops_before_failure: None,
+            })),
+            read_hook: Mutex::new(None),
+        }
+    }
+
+    /// Runs `hook` once right before the next file whose path passes `matches` is opened
+    /// for sequential reads, e.g. to change the DB between the steps of a reader.
+    pub fn before_read(
+        &self,
+        matches: impl Fn(&str) -> bool + Send + Sync + 'static,
+        hook: impl FnOnce() + Send + 'static,
+    ) {
+        *self.read_hook.lock().unwrap() = Some((Box::new(matches), Box::new(hook)));
+    }
+
+    /// Lets the next `n` mutating operations succeed and fails all the following ones
+    /// until `clear_failures` is called.
+    pub fn fail_after_operations(&self, n: u64) {
+        self.state.lock().unwrap().ops_before_failure = Some(n);
+    }
+
+    pub fn clear_failures(&self) {
+        self.state.lock().unwrap().ops_before_failure = None;
+    }
+
+    /// Simulates a power loss: the files never synced are removed and the others are
+    /// truncated to the size at their last sync. The files opened before can not be
+    /// written any more.
+    pub fn crash(&self) -> Result<(), TinyError> {
+        let mut state = self.state.lock().unwrap();
+        state.epoch += 1;
+        for (path, file) in state.files.drain() {
+            match file.synced {
+                None => {
+                    if self.base.file_exists(&path) {
+                        self.base.remove_file(&path)?;
+                    }
+                }
+                Some(size) if size < file.pos => self.truncate(&path, size)?,
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Flips all the bits of the bytes in [offset, offset + len) of the file, which is
+    /// not counted as an operation.
+    pub fn corrupt_file(&self, path: &str, offset: u64, len: usize) -> Result<(), TinyError> {
+        let mut data = vec![];
+        self.base.new_sequential_file(path)?.read_to_end(&mut data)?;
+        let start = (offset as usize).min(data.len());
+        let end = (start + len).min(data.len());
+        for b in data[start..end].iter_mut() {
+            *b = !*b;
+        }
+        self.rewrite(path, &data)
+    }
+
+    fn truncate(&self, path: &str, size: u64) -> Result<(), TinyError> {
+        let mut data = vec![0; size as usize];
+        self.base.new_sequential_file(path)?.read_exact(&mut data)?;
+        self.rewrite(path, &data)
+    }
+
+    fn rewrite(&self, path: &str, data: &[u8]) -> Result<(), TinyError> {
+        let mut file = self.base.new_writable_file(path)?;
+        file.write_all(data)?;
+        file.sync()
+    }
+
+    fn open_writable(&self, path: &str, append: bool) -> Result<Box<dyn WritableFile>, TinyError> {
+        let mut state = self.state.lock().unwrap();
+        state.check("open", path)?;
+        let existing = if append && self.base.file_exists(path) {
+            Some(self.base.get_file_size(path)?)
+        } else {
+            None
+        };
+        let inner = if append {
+            self.base.new_appendable_file(path)?
+        } else {
+            self.base.new_writable_file(path)?
+        };
+        match existing {
+            // the existing content is durable unless written since the last crash
+            Some(size) => {
+                state.files.entry(path.to_owned()).or_insert(FileState {
+                    pos: size,
+                    synced: Some(size),
+                });
+            }
+            None => {
+                state.files.insert(
+                    path.to_owned(),
+                    FileState {
+                        pos: 0,
+                        synced: None,
+                    },
+                );
+            }
+        }
+        Ok(Box::new(FaultInjectionWritableFile {
+            inner,
+            path: path.to_owned(),
+            epoch: state.epoch,
+            state: self.state.clone(),
+        }))
+    }
+}
+
+impl Env for FaultInjectionEnv {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>, TinyError> {
+        let hook = {
+            let mut read_hook = self.read_hook.lock().unwrap();
+            match read_hook.as_ref() {
+                Some((matches, _)) if matches(path) => read_hook.take().map(|(_, hook)| hook),
+                _ => None,
+            }
+        };
+        if let Some(hook) = hook {
+            hook();
+        }
+        self.base.new_sequential_file(path)
+    }
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>, TinyError> {
+        self.base.new_random_access_file(path)
+    }
+
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError> {
+        self.open_writable(path, false)
+    }
+
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError> {
+        self.open_writable(path, true)
+    }
+
+    fn file_exists(&self, path: &str) -> bool {
+        self.base.file_exists(path)
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>, TinyError> {
+        self.base.get_children(dir)
+    }
+
+    fn get_file_size(&self, path: &str) -> Result<u64, TinyError> {
+        self.base.get_file_size(path)
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), TinyError> {
+        let mut state = self.state.lock().unwrap();
+        state.check("remove", path)?;
+        self.base.remove_file(path)?;
+        state.files.remove(path);
+        Ok(())
+    }
+
+    fn create_dir(&self, dir: &str) -> Result<(), TinyError> {
+        self.base.create_dir(dir)
+    }
+
+    fn remove_dir(&self, dir: &str) -> Result<(), TinyError> {
+        self.base.remove_dir(dir)
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        let mut state = self.state.lock().unwrap();
+        state.check("rename", src)?;
+        self.base.rename_file(src, target)?;
+        // the renaming itself is regarded as durable
+        match state.files.remove(src) {
+            Some(file) => {
+                state.files.insert(target.to_owned(), file);
+            }
+            None => {
+                state.files.remove(target);
+            }
+        }
+        Ok(())
+    }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        let mut state = self.state.lock().unwrap();
+        state.check("link", src)?;
+        self.base.link_file(src, target)?;
+        // the link is regarded as durable, while the data may be not
+        if let Some(file) = state.files.get(src).cloned() {
+            state.files.insert(target.to_owned(), file);
+        }
+        Ok(())
+    }
+
+    fn lock_file(&self, path: &str) -> Result<FileLock, TinyError> {
+        self.base.lock_file(path)
+    }
+
+    fn unlock_file(&self, lock: FileLock) -> Result<(), TinyError> {
+        self.base.unlock_file(lock)
+    }
+
+    fn schedule(&self, task: Box<dyn FnOnce() + Send>) {
+        self.base.schedule(task)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.base.now_micros()
+    }
+
+    fn sleep_for_micros(&self, micros: u64) {
+        self.base.sleep_for_micros(micros)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, WriteOptions};
+    use crate::db::open_db;
+    use crate::env::mem::MemEnv;
+    use crate::record::reader::Reader;
+    use crate::record::writer::Writer;
+    use crate::record::HEADER_SIZE;
+    use crate::util::slice::Slice;
+    use crate::write_batch::WriteBatch;
+    use rand::Rng;
+
+    const LOG: &str = "/db/000001.log";
+
+    fn new_env() -> FaultInjectionEnv {
+        let env = FaultInjectionEnv::new(Arc::new(MemEnv::new()));
+        env.create_dir("/db").unwrap();
+        env
+    }
+
+    fn read_log(env: &dyn Env, path: &str) -> Result<Vec<Vec<u8>>, TinyError> {
+        let mut reader = Reader::new(env.new_sequential_file(path)?, true);
+        let mut records = vec![];
+        while let Some(record) = reader.read_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_drop_unsynced_data() {
+        let env = new_env();
+        let mut f = env.new_writable_file("/db/a").unwrap();
+        f.write_all(b"synced").unwrap();
+        f.sync().unwrap();
+        f.write_all(b" unsynced").unwrap();
+        let mut g = env.new_writable_file("/db/b").unwrap();
+        g.write_all(b"never synced").unwrap();
+        assert_eq!(env.get_file_size("/db/a").unwrap(), 15);
+
+        env.crash().unwrap();
+        assert_eq!(env.get_file_size("/db/a").unwrap(), 6);
+        assert!(!env.file_exists("/db/b"));
+        // the files opened before the crash are dead
+        assert!(f.write_all(b"x").is_err());
+        assert!(f.sync().is_err());
+
+        // the content surviving the crash is durable
+        let mut f = env.new_appendable_file("/db/a").unwrap();
+        f.write_all(b" appended").unwrap();
+        env.crash().unwrap();
+        let mut data = String::new();
+        env.new_sequential_file("/db/a").unwrap().read_to_string(&mut data).unwrap();
+        assert_eq!(data, "synced");
+    }
+
+    #[test]
+    fn test_rename() {
+        let env = new_env();
+        let mut f = env.new_writable_file("/db/tmp").unwrap();
+        f.write_all(b"content").unwrap();
+        env.rename_file("/db/tmp", "/db/CURRENT").unwrap();
+        // renaming doesn't make the data durable
+        env.crash().unwrap();
+        assert!(!env.file_exists("/db/CURRENT"));
+
+        let mut f = env.new_writable_file("/db/tmp").unwrap();
+        f.write_all(b"content").unwrap();
+        f.sync().unwrap();
+        env.rename_file("/db/tmp", "/db/CURRENT").unwrap();
+        env.crash().unwrap();
+        assert_eq!(env.get_file_size("/db/CURRENT").unwrap(), 7);
+    }
+
+    #[test]
+    fn test_fail_after_operations() {
+        let env = new_env();
+        env.fail_after_operations(2);
+        let mut f = env.new_writable_file("/db/a").unwrap();
+        f.write_all(b"data").unwrap();
+        assert!(f.sync().is_err());
+        assert!(env.rename_file("/db/a", "/db/b").is_err());
+        assert!(env.remove_file("/db/a").is_err());
+        assert!(env.new_writable_file("/db/c").is_err());
+        env.clear_failures();
+        f.sync().unwrap();
+        env.rename_file("/db/a", "/db/b").unwrap();
+        env.remove_file("/db/b").unwrap();
+    }
+
+    #[test]
+    fn test_corrupt_file() {
+        let env = new_env();
+        let mut writer = Writer::new(env.new_writable_file(LOG).unwrap());
+        writer.add_record(b"hello").unwrap();
+        writer.get_mut().sync().unwrap();
+        assert_eq!(read_log(&env, LOG).unwrap(), vec![b"hello".to_vec()]);
+        env.corrupt_file(LOG, HEADER_SIZE as u64 + 1, 1).unwrap();
+        assert!(matches!(read_log(&env, LOG), Err(TinyError::Corruption(_))));
+    }
+
+    // Writes random key/value pairs through a DB, syncing the WAL now and then while the
+    // memtables are flushed as they fill up, optionally with the operations failing at a
+    // random point. After the crash, the reopened DB must hold every write acknowledged
+    // by a sync, and the others only as a prefix of the writes in their order.
+    fn run_crash_test(inject_failures: bool) {
+        let mut rng = rand::thread_rng();
+        for _ in 0..50 {
+            let env = Arc::new(new_env());
+            let config = Config {
+                dir: "/db".to_owned(),
+                env: env.clone(),
+                write_buffer_size: 16 << 10,
+                ..Config::default()
+            };
+            let db = open_db(config.clone()).unwrap();
+            if inject_failures {
+                env.fail_after_operations(rng.gen_range(0, 100));
+            }
+            let mut written = vec![];
+            let mut synced = 0;
+            for i in 0..rng.gen_range(1, 200) {
+                let key = format!("{:04}", i);
+                let value = vec![i as u8; rng.gen_range(0, 1000)];
+                let options = WriteOptions {
+                    sync: rng.gen_range(0, 5) == 0,
+                };
+                let mut batch = WriteBatch::new();
+                batch.put(key.as_bytes(), &value);
+                if db.write_batch(&options, batch).is_err() {
+                    break;
+                }
+                written.push((key, value));
+                if options.sync {
+                    synced = written.len();
+                }
+            }
+            drop(db);
+
+            env.crash().unwrap();
+            env.clear_failures();
+            let db = open_db(config).unwrap();
+            let recovered = written
+                .iter()
+                .take_while(|(key, _)| db.get(Slice::from(key.as_str())).unwrap().is_some())
+                .count();
+            assert!(recovered >= synced, "{} writes are acknowledged but {} are recovered", synced, recovered);
+            for (i, (key, value)) in written.iter().enumerate() {
+                let expected = if i < recovered { Some(value.clone()) } else { None };
+                assert_eq!(db.get(Slice::from(key.as_str())).unwrap(), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_synced_records_survive_crash() {
+        run_crash_test(false);
+    }
+
+    #[test]
+    fn test_synced_records_survive_crash_with_failures() {
+        run_crash_test(true);
+    }
+}
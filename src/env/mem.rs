@@ -0,0 +1,372 @@
+use super::thread_pool::ThreadPool;
+use super::{Env, FileLock, RandomAccessFile, SequentialFile, WritableFile};
+use crate::util::clock::{Clock, SystemClock};
+use crate::util::error::TinyError;
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+
+type FileData = Arc<RwLock<Vec<u8>>>;
+
+#[derive(Default)]
+struct MemFileSystem {
+    files: BTreeMap<String, FileData>,
+    dirs: BTreeSet<String>,
+    locks: HashSet<String>,
+}
+
+fn normalize(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    if trimmed.is_empty() && path.starts_with('/') {
+        "/".to_owned()
+    } else {
+        trimmed.to_owned()
+    }
+}
+
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) => "/",
+        Some(i) => &path[..i],
+        None => "",
+    }
+}
+
+fn not_found(path: &str) -> TinyError {
+    TinyError::IOError(io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("[mem env] {} not found", path),
+    ))
+}
+
+impl MemFileSystem {
+    fn file(&self, path: &str) -> Result<FileData, TinyError> {
+        self.files.get(&normalize(path)).cloned().ok_or_else(|| not_found(path))
+    }
+
+    fn has_children(&self, dir: &str) -> bool {
+        self.files.keys().chain(self.dirs.iter()).any(|p| parent(p) == dir)
+    }
+}
+
+struct MemSequentialFile {
+    data: FileData,
+    pos: usize,
+}
+
+impl Read for MemSequentialFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.read().unwrap();
+        let start = self.pos.min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        self.pos = start + n;
+        Ok(n)
+    }
+}
+
+impl SequentialFile for MemSequentialFile {
+    fn skip(&mut self, n: u64) -> Result<(), TinyError> {
+        self.pos += n as usize;
+        Ok(())
+    }
+}
+
+struct MemRandomAccessFile {
+    data: FileData,
+}
+
+impl RandomAccessFile for MemRandomAccessFile {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, TinyError> {
+        let data = self.data.read().unwrap();
+        let start = (offset as usize).min(data.len());
+        let n = buf.len().min(data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+}
+
+struct MemWritableFile {
+    data: FileData,
+}
+
+impl Write for MemWritableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.write().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl WritableFile for MemWritableFile {
+    fn sync(&mut self) -> Result<(), TinyError> {
+        Ok(())
+    }
+}
+
+/// MemEnv is an `Env` keeping all the files in memory, which is useful for the tests.
+/// The background tasks still run in real threads.
+pub struct MemEnv {
+    fs: Mutex<MemFileSystem>,
+    pool: ThreadPool,
+    clock: Arc<dyn Clock>,
+}
+
+impl MemEnv {
+    pub fn new() -> Self {
+        Self::with_clock(Arc::new(SystemClock::new()))
+    }
+
+    /// Create a MemEnv whose `now_micros` is given by `clock`, e.g. a `ManualClock`
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        MemEnv {
+            fs: Mutex::new(MemFileSystem::default()),
+            pool: ThreadPool::new("tinydb-mem-bg", 1),
+            clock,
+        }
+    }
+
+    fn open_writable(&self, path: &str, truncate: bool) -> Result<Box<dyn WritableFile>, TinyError> {
+        let mut fs = self.fs.lock().unwrap();
+        let data = fs.files.entry(normalize(path)).or_default().clone();
+        if truncate {
+            data.write().unwrap().clear();
+        }
+        Ok(Box::new(MemWritableFile { data }))
+    }
+}
+
+impl Default for MemEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Env for MemEnv {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>, TinyError> {
+        let data = self.fs.lock().unwrap().file(path)?;
+        Ok(Box::new(MemSequentialFile { data, pos: 0 }))
+    }
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>, TinyError> {
+        let data = self.fs.lock().unwrap().file(path)?;
+        Ok(Box::new(MemRandomAccessFile { data }))
+    }
+
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError> {
+        self.open_writable(path, true)
+    }
+
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError> {
+        self.open_writable(path, false)
+    }
+
+    fn file_exists(&self, path: &str) -> bool {
+        let fs = self.fs.lock().unwrap();
+        let path = normalize(path);
+        fs.files.contains_key(&path) || fs.dirs.contains(&path)
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>, TinyError> {
+        let fs = self.fs.lock().unwrap();
+        let dir = normalize(dir);
+        if !fs.dirs.contains(&dir) {
+            return Err(not_found(&dir));
+        }
+        Ok(fs
+            .files
+            .keys()
+            .chain(fs.dirs.iter())
+            .filter(|p| parent(p) == dir)
+            .map(|p| p[p.rfind('/').map_or(0, |i| i + 1)..].to_owned())
+            .collect())
+    }
+
+    fn get_file_size(&self, path: &str) -> Result<u64, TinyError> {
+        let data = self.fs.lock().unwrap().file(path)?;
+        let size = data.read().unwrap().len() as u64;
+        Ok(size)
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), TinyError> {
+        match self.fs.lock().unwrap().files.remove(&normalize(path)) {
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn create_dir(&self, dir: &str) -> Result<(), TinyError> {
+        let mut fs = self.fs.lock().unwrap();
+        let mut dir = normalize(dir);
+        while !dir.is_empty() && fs.dirs.insert(dir.clone()) {
+            dir = parent(&dir).to_owned();
+        }
+        Ok(())
+    }
+
+    fn remove_dir(&self, dir: &str) -> Result<(), TinyError> {
+        let mut fs = self.fs.lock().unwrap();
+        let dir = normalize(dir);
+        if !fs.dirs.contains(&dir) {
+            return Err(not_found(&dir));
+        }
+        if fs.has_children(&dir) {
+            return Err(TinyError::IOError(io::Error::other(format!(
+                "[mem env] directory {} is not empty",
+                dir
+            ))));
+        }
+        fs.dirs.remove(&dir);
+        Ok(())
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        let mut fs = self.fs.lock().unwrap();
+        let data = fs.files.remove(&normalize(src)).ok_or_else(|| not_found(src))?;
+        fs.files.insert(normalize(target), data);
+        Ok(())
+    }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        let mut fs = self.fs.lock().unwrap();
+        let data = fs.file(src)?;
+        let target = normalize(target);
+        if fs.files.contains_key(&target) {
+            return Err(TinyError::IOError(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("[mem env] {} exists", target),
+            )));
+        }
+        fs.files.insert(target, data);
+        Ok(())
+    }
+
+    fn lock_file(&self, path: &str) -> Result<FileLock, TinyError> {
+        let mut fs = self.fs.lock().unwrap();
+        let path = normalize(path);
+        if !fs.locks.insert(path.clone()) {
+            return Err(TinyError::IOError(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("[mem env] lock {} is held", path),
+            )));
+        }
+        fs.files.entry(path.clone()).or_default();
+        Ok(FileLock::new(&path, Box::new(())))
+    }
+
+    fn unlock_file(&self, lock: FileLock) -> Result<(), TinyError> {
+        self.fs.lock().unwrap().locks.remove(lock.path());
+        Ok(())
+    }
+
+    fn schedule(&self, task: Box<dyn FnOnce() + Send>) {
+        self.pool.schedule(task)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.clock.now_micros()
+    }
+
+    fn sleep_for_micros(&self, micros: u64) {
+        thread::sleep(Duration::from_micros(micros))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::reader::Reader;
+    use crate::record::writer::Writer;
+    use crate::util::clock::ManualClock;
+
+    #[test]
+    fn test_files() {
+        let env = MemEnv::new();
+        env.create_dir("/db/sub").unwrap();
+        assert!(env.file_exists("/db"));
+        assert!(env.get_children("/db/sub").unwrap().is_empty());
+        assert!(env.new_sequential_file("/db/f").is_err());
+        assert!(env.get_file_size("/db/f").is_err());
+
+        let mut f = env.new_writable_file("/db/f").unwrap();
+        f.write_all(b"hello ").unwrap();
+        f.sync().unwrap();
+        let mut f = env.new_appendable_file("/db/f").unwrap();
+        f.write_all(b"world").unwrap();
+        assert_eq!(env.get_file_size("/db/f").unwrap(), 11);
+
+        let mut f = env.new_sequential_file("/db/f").unwrap();
+        f.skip(6).unwrap();
+        let mut buf = String::new();
+        f.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "world");
+        let f = env.new_random_access_file("/db/f").unwrap();
+        let mut buf = [0u8; 5];
+        f.read_exact_at(0, &mut buf).unwrap();
+        assert_eq!(&buf, b"hello");
+        assert!(f.read_exact_at(8, &mut buf).is_err());
+
+        env.rename_file("/db/f", "/db/g").unwrap();
+        let mut children = env.get_children("/db/").unwrap();
+        children.sort();
+        assert_eq!(children, vec!["g".to_owned(), "sub".to_owned()]);
+        // the linked file shares the data
+        env.link_file("/db/g", "/db/h").unwrap();
+        assert!(env.link_file("/db/g", "/db/h").is_err());
+        env.new_appendable_file("/db/h").unwrap().write_all(b"!").unwrap();
+        assert_eq!(env.get_file_size("/db/g").unwrap(), 12);
+        env.remove_file("/db/h").unwrap();
+        // truncated by reopening
+        env.new_writable_file("/db/g").unwrap();
+        assert_eq!(env.get_file_size("/db/g").unwrap(), 0);
+
+        assert!(env.remove_dir("/db").is_err());
+        env.remove_file("/db/g").unwrap();
+        assert!(env.remove_file("/db/g").is_err());
+        env.remove_dir("/db/sub").unwrap();
+        env.remove_dir("/db").unwrap();
+        assert!(!env.file_exists("/db"));
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let env = MemEnv::new();
+        let lock = env.lock_file("/db/LOCK").unwrap();
+        assert_eq!(lock.path(), "/db/LOCK");
+        assert!(env.lock_file("/db/LOCK").is_err());
+        env.unlock_file(lock).unwrap();
+        let lock = env.lock_file("/db/LOCK").unwrap();
+        env.unlock_file(lock).unwrap();
+    }
+
+    #[test]
+    fn test_clock() {
+        let clock = Arc::new(ManualClock::new(100));
+        let env = MemEnv::with_clock(clock.clone());
+        assert_eq!(env.now_micros(), 100);
+        clock.advance_micros(5);
+        assert_eq!(env.now_micros(), 105);
+    }
+
+    #[test]
+    fn test_log_on_mem_env() {
+        let env = MemEnv::new();
+        env.create_dir("/db").unwrap();
+        let mut writer = Writer::new(env.new_writable_file("/db/000001.log").unwrap());
+        for i in 0..100 {
+            writer.add_record(format!("record{}", i).as_bytes()).unwrap();
+        }
+        writer.flush().unwrap();
+        let mut reader = Reader::new(env.new_sequential_file("/db/000001.log").unwrap(), true);
+        for i in 0..100 {
+            assert_eq!(reader.read_record().unwrap(), Some(format!("record{}", i).into_bytes()));
+        }
+        assert_eq!(reader.read_record().unwrap(), None);
+    }
+}
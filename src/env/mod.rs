@@ -0,0 +1,169 @@
+pub mod fault_injection;
+pub mod mem;
+pub mod posix;
+pub mod thread_pool;
+
+use crate::util::direct_io::DirectWriter;
+use crate::util::error::TinyError;
+use crate::util::mmap::MmapFile;
+
+use std::io::{self, Read, Write};
+
+/// A file read sequentially, e.g. the WAL and the MANIFEST during recovery.
+pub trait SequentialFile: Read + Send {
+    /// Skips `n` bytes. It's no slower than reading the same data.
+    fn skip(&mut self, n: u64) -> Result<(), TinyError>;
+}
+
+/// A file read at random offsets, e.g. the table files.
+/// The reads may be issued concurrently so the implementation must be thread-safe.
+pub trait RandomAccessFile: Send + Sync {
+    /// Reads up to `buf.len()` bytes at `offset` and returns the number of bytes read,
+    /// which is less than `buf.len()` only if the end of file is reached.
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, TinyError>;
+
+    /// Reads exactly `buf.len()` bytes at `offset`.
+    /// Returns `Corruption` if the end of file is reached first.
+    fn read_exact_at(&self, offset: u64, buf: &mut [u8]) -> Result<(), TinyError> {
+        let read = self.read_at(offset, buf)?;
+        if read < buf.len() {
+            return Err(TinyError::Corruption(format!(
+                "[env] read [{}, {}) beyond the end of file",
+                offset,
+                offset + buf.len() as u64
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A file written sequentially. The writes may be buffered until `flush` or `sync`.
+pub trait WritableFile: Write + Send {
+    /// Flushes the buffered data and makes the file durable
+    fn sync(&mut self) -> Result<(), TinyError>;
+}
+
+/// The lock acquired by `Env::lock_file`, which is released by `Env::unlock_file`
+pub struct FileLock {
+    path: String,
+    // the implementation specific state
+    inner: Box<dyn std::any::Any + Send + Sync>,
+}
+
+impl FileLock {
+    pub fn new(path: &str, inner: Box<dyn std::any::Any + Send + Sync>) -> Self {
+        FileLock {
+            path: path.to_owned(),
+            inner,
+        }
+    }
+
+    #[inline]
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    #[inline]
+    pub fn inner(&self) -> &(dyn std::any::Any + Send + Sync) {
+        self.inner.as_ref()
+    }
+}
+
+/// An Env is the interface used by the DB to access the operating system: the file system,
+/// the file locks, the clock and the background threads. All the file access of the DB
+/// goes through the Env so it can be replaced, e.g. by `mem::MemEnv` in the tests.
+///
+/// The Env may be shared by multiple DBs so the implementation must be thread-safe.
+pub trait Env: Send + Sync {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>, TinyError>;
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>, TinyError>;
+
+    /// Creates a new file at `path`. An existing file is truncated.
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError>;
+
+    /// Opens the file at `path` for appending. The file is created if missing.
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError>;
+
+    fn file_exists(&self, path: &str) -> bool;
+
+    /// Returns the names of the entries in `dir`, not including "." and "..".
+    fn get_children(&self, dir: &str) -> Result<Vec<String>, TinyError>;
+
+    fn get_file_size(&self, path: &str) -> Result<u64, TinyError>;
+
+    fn remove_file(&self, path: &str) -> Result<(), TinyError>;
+
+    /// Creates the directory and its missing parents. It's not an error if the directory exists.
+    fn create_dir(&self, dir: &str) -> Result<(), TinyError>;
+
+    /// Removes the empty directory
+    fn remove_dir(&self, dir: &str) -> Result<(), TinyError>;
+
+    /// Renames `src` to `target`, replacing the existing `target`
+    fn rename_file(&self, src: &str, target: &str) -> Result<(), TinyError>;
+
+    /// Creates `target` as a hard link to `src`, so the two names refer to the same file.
+    /// Fails if `target` exists. The Envs without hard links return `NotSupported`.
+    fn link_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        Err(TinyError::NotSupported(format!(
+            "[env] can not link {} to {}",
+            src, target
+        )))
+    }
+
+    /// Memory maps the whole file at `path` for reading. The Envs not backed by the local
+    /// file system return `NotSupported`.
+    fn new_mmap_file(&self, path: &str) -> Result<MmapFile, TinyError> {
+        Err(TinyError::NotSupported(format!("[env] can not memory map {}", path)))
+    }
+
+    /// Opens the file at `path` for the direct reads bypassing the page cache, which are
+    /// aligned to `direct_io::DIRECT_IO_ALIGNMENT`. The Envs not backed by the local file
+    /// system return `NotSupported`.
+    fn new_direct_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>, TinyError> {
+        Err(TinyError::NotSupported(format!("[env] can not open {} for direct reads", path)))
+    }
+
+    /// Creates a new file at `path` written with the direct I/O bypassing the page cache
+    /// through a buffer of `buffer_size` bytes. An existing file is truncated. The Envs
+    /// not backed by the local file system return `NotSupported`.
+    fn new_direct_writable_file(&self, path: &str, buffer_size: usize) -> Result<DirectWriter, TinyError> {
+        let _ = buffer_size;
+        Err(TinyError::NotSupported(format!("[env] can not create {} for direct writes", path)))
+    }
+
+    /// Acquires an exclusive lock on the file at `path`, which is created if missing.
+    /// Fails if someone else holds the lock instead of waiting for it.
+    fn lock_file(&self, path: &str) -> Result<FileLock, TinyError>;
+
+    /// Releases the lock acquired by `lock_file`
+    fn unlock_file(&self, lock: FileLock) -> Result<(), TinyError>;
+
+    /// Runs `task` once in a background thread
+    fn schedule(&self, task: Box<dyn FnOnce() + Send>);
+
+    /// Returns the number of microseconds since some fixed point in time
+    fn now_micros(&self) -> u64;
+
+    fn sleep_for_micros(&self, micros: u64);
+}
+
+/// Copies `src` to the new file `target` and syncs it. Only the first `size` bytes are
+/// copied if given, e.g. from a file still being appended.
+/// Returns the number of bytes copied.
+pub fn copy_file(env: &dyn Env, src: &str, target: &str, size: Option<u64>) -> Result<u64, TinyError> {
+    let src_file = env.new_sequential_file(src)?;
+    let mut target_file = env.new_writable_file(target)?;
+    let copied = io::copy(&mut src_file.take(size.unwrap_or(u64::MAX)), &mut target_file)?;
+    if let Some(size) = size {
+        if copied < size {
+            return Err(TinyError::IOError(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("[env] {} is shorter than {} bytes", src, size),
+            )));
+        }
+    }
+    target_file.sync()?;
+    Ok(copied)
+}
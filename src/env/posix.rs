@@ -0,0 +1,345 @@
+use super::thread_pool::ThreadPool;
+use super::{Env, FileLock, RandomAccessFile, SequentialFile, WritableFile};
+use crate::util::clock::{Clock, SystemClock};
+use crate::util::direct_io::{self, DirectWriter};
+use crate::util::error::TinyError;
+use crate::util::mmap::MmapFile;
+
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+const WRITABLE_FILE_BUFFER_SIZE: usize = 64 << 10;
+
+impl SequentialFile for File {
+    fn skip(&mut self, n: u64) -> Result<(), TinyError> {
+        self.seek(SeekFrom::Current(n as i64))?;
+        Ok(())
+    }
+}
+
+impl RandomAccessFile for File {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> Result<usize, TinyError> {
+        let mut read = 0;
+        while read < buf.len() {
+            match FileExt::read_at(self, &mut buf[read..], offset + read as u64) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(read)
+    }
+}
+
+/// PosixWritableFile buffers the writes in user space until flushed
+pub struct PosixWritableFile {
+    writer: BufWriter<File>,
+}
+
+impl Write for PosixWritableFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl WritableFile for PosixWritableFile {
+    fn sync(&mut self) -> Result<(), TinyError> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        Ok(())
+    }
+}
+
+// The paths of the files locked by this process. `flock` is bound to the open file
+// so it doesn't distinguish a second open in the same process from other processes,
+// and the table tells the two cases apart.
+fn lock_table() -> &'static Mutex<HashSet<String>> {
+    static LOCK_TABLE: OnceLock<Mutex<HashSet<String>>> = OnceLock::new();
+    LOCK_TABLE.get_or_init(|| Mutex::new(HashSet::new()))
+}
+
+// The file of a lock acquired by PosixEnv, which leaves the lock table when dropped
+struct PosixFileLock {
+    file: File,
+    path: String,
+}
+
+impl Drop for PosixFileLock {
+    fn drop(&mut self) {
+        lock_table().lock().unwrap().remove(&self.path);
+    }
+}
+
+/// Returns the `PosixEnv` shared by the whole process
+pub fn default_env() -> Arc<dyn Env> {
+    static DEFAULT_ENV: OnceLock<Arc<PosixEnv>> = OnceLock::new();
+    DEFAULT_ENV.get_or_init(|| Arc::new(PosixEnv::new())).clone()
+}
+
+/// PosixEnv is the `Env` backed by the local file system
+pub struct PosixEnv {
+    pool: ThreadPool,
+    clock: SystemClock,
+}
+
+impl PosixEnv {
+    /// Create an Env running the background tasks by one thread
+    pub fn new() -> Self {
+        Self::with_background_threads(1)
+    }
+
+    pub fn with_background_threads(num_threads: usize) -> Self {
+        PosixEnv {
+            pool: ThreadPool::new("tinydb-bg", num_threads),
+            clock: SystemClock::new(),
+        }
+    }
+
+    fn open_writable(&self, path: &str, append: bool) -> Result<Box<dyn WritableFile>, TinyError> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(Box::new(PosixWritableFile {
+            writer: BufWriter::with_capacity(WRITABLE_FILE_BUFFER_SIZE, file),
+        }))
+    }
+}
+
+impl Default for PosixEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Env for PosixEnv {
+    fn new_sequential_file(&self, path: &str) -> Result<Box<dyn SequentialFile>, TinyError> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn new_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>, TinyError> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn new_writable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError> {
+        self.open_writable(path, false)
+    }
+
+    fn new_appendable_file(&self, path: &str) -> Result<Box<dyn WritableFile>, TinyError> {
+        self.open_writable(path, true)
+    }
+
+    fn file_exists(&self, path: &str) -> bool {
+        fs::metadata(path).is_ok()
+    }
+
+    fn get_children(&self, dir: &str) -> Result<Vec<String>, TinyError> {
+        let mut children = vec![];
+        for entry in fs::read_dir(dir)? {
+            children.push(entry?.file_name().to_string_lossy().into_owned());
+        }
+        Ok(children)
+    }
+
+    fn get_file_size(&self, path: &str) -> Result<u64, TinyError> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), TinyError> {
+        Ok(fs::remove_file(path)?)
+    }
+
+    fn create_dir(&self, dir: &str) -> Result<(), TinyError> {
+        Ok(fs::create_dir_all(dir)?)
+    }
+
+    fn remove_dir(&self, dir: &str) -> Result<(), TinyError> {
+        Ok(fs::remove_dir(dir)?)
+    }
+
+    fn rename_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        Ok(fs::rename(src, target)?)
+    }
+
+    fn link_file(&self, src: &str, target: &str) -> Result<(), TinyError> {
+        Ok(fs::hard_link(src, target)?)
+    }
+
+    fn new_mmap_file(&self, path: &str) -> Result<MmapFile, TinyError> {
+        // the mapping stays valid after the file is closed
+        MmapFile::new(&File::open(path)?)
+    }
+
+    fn new_direct_random_access_file(&self, path: &str) -> Result<Box<dyn RandomAccessFile>, TinyError> {
+        Ok(Box::new(direct_io::open_for_direct_read(path)?))
+    }
+
+    fn new_direct_writable_file(&self, path: &str, buffer_size: usize) -> Result<DirectWriter, TinyError> {
+        Ok(DirectWriter::new(direct_io::create_for_direct_write(path)?, buffer_size))
+    }
+
+    fn lock_file(&self, path: &str) -> Result<FileLock, TinyError> {
+        let mut table = lock_table().lock().unwrap();
+        if table.contains(path) {
+            return Err(TinyError::IOError(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                format!("[posix env] lock {} is already held by this process", path),
+            )));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Err(TinyError::IOError(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("[posix env] lock {} is held by another process", path),
+                )));
+            }
+            return Err(TinyError::IOError(e));
+        }
+        table.insert(path.to_owned());
+        Ok(FileLock::new(
+            path,
+            Box::new(PosixFileLock {
+                file,
+                path: path.to_owned(),
+            }),
+        ))
+    }
+
+    fn unlock_file(&self, lock: FileLock) -> Result<(), TinyError> {
+        if let Some(lock) = lock.inner().downcast_ref::<PosixFileLock>() {
+            if unsafe { libc::flock(lock.file.as_raw_fd(), libc::LOCK_UN) } != 0 {
+                return Err(TinyError::IOError(io::Error::last_os_error()));
+            }
+        }
+        Ok(())
+    }
+
+    fn schedule(&self, task: Box<dyn FnOnce() + Send>) {
+        self.pool.schedule(task)
+    }
+
+    fn now_micros(&self) -> u64 {
+        self.clock.now_micros()
+    }
+
+    fn sleep_for_micros(&self, micros: u64) {
+        thread::sleep(Duration::from_micros(micros))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::sync::mpsc::channel;
+
+    fn test_dir(name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("tinydb_posix_env_{}_{}", name, std::process::id()));
+        dir.to_str().unwrap().to_owned()
+    }
+
+    #[test]
+    fn test_files() {
+        let env = PosixEnv::new();
+        let dir = test_dir("files");
+        env.create_dir(&dir).unwrap();
+        env.create_dir(&dir).unwrap();
+        let path = format!("{}/f", dir);
+        assert!(!env.file_exists(&path));
+
+        let mut f = env.new_writable_file(&path).unwrap();
+        f.write_all(b"hello ").unwrap();
+        f.sync().unwrap();
+        drop(f);
+        let mut f = env.new_appendable_file(&path).unwrap();
+        f.write_all(b"world").unwrap();
+        f.flush().unwrap();
+        drop(f);
+        assert_eq!(env.get_file_size(&path).unwrap(), 11);
+
+        let mut f = env.new_sequential_file(&path).unwrap();
+        f.skip(6).unwrap();
+        let mut buf = String::new();
+        f.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "world");
+
+        let f = env.new_random_access_file(&path).unwrap();
+        let mut buf = [0u8; 5];
+        f.read_exact_at(6, &mut buf).unwrap();
+        assert_eq!(&buf, b"world");
+        assert_eq!(f.read_at(8, &mut buf).unwrap(), 3);
+        assert!(f.read_exact_at(8, &mut buf).is_err());
+
+        let renamed = format!("{}/g", dir);
+        env.rename_file(&path, &renamed).unwrap();
+        assert_eq!(env.get_children(&dir).unwrap(), vec!["g".to_owned()]);
+        let linked = format!("{}/h", dir);
+        env.link_file(&renamed, &linked).unwrap();
+        assert!(env.link_file(&renamed, &linked).is_err());
+        env.remove_file(&renamed).unwrap();
+        assert_eq!(env.get_file_size(&linked).unwrap(), 11);
+        env.remove_file(&linked).unwrap();
+        env.remove_dir(&dir).unwrap();
+        assert!(!env.file_exists(&dir));
+    }
+
+    #[test]
+    fn test_lock_file() {
+        let env = PosixEnv::new();
+        let dir = test_dir("lock");
+        env.create_dir(&dir).unwrap();
+        let path = format!("{}/LOCK", dir);
+        let lock = env.lock_file(&path).unwrap();
+        assert!(env.lock_file(&path).is_err());
+        env.unlock_file(lock).unwrap();
+        let lock = env.lock_file(&path).unwrap();
+        env.unlock_file(lock).unwrap();
+
+        // locked by another open file as if by another process
+        let file = File::open(&path).unwrap();
+        assert_eq!(unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) }, 0);
+        match env.lock_file(&path) {
+            Err(TinyError::IOError(e)) => assert!(e.to_string().contains("another process")),
+            _ => panic!("the lock is held by another process"),
+        }
+        drop(file);
+        // dropping the lock releases it as well
+        drop(env.lock_file(&path).unwrap());
+        let lock = env.lock_file(&path).unwrap();
+        env.unlock_file(lock).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_schedule() {
+        let env = PosixEnv::new();
+        let (sender, receiver) = channel();
+        for i in 0..10 {
+            let sender = sender.clone();
+            env.schedule(Box::new(move || sender.send(i).unwrap()));
+        }
+        let received: Vec<i32> = receiver.iter().take(10).collect();
+        // a single background thread runs the tasks in order
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// ThreadPool runs the scheduled tasks by a fixed number of threads in FIFO order.
+/// Dropping the pool waits for all the scheduled tasks to finish.
+pub struct ThreadPool {
+    sender: Mutex<Option<Sender<Task>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    pub fn new(name: &str, num_threads: usize) -> Self {
+        invarint!(
+            num_threads >= 1,
+            "[thread pool] the number of threads must be at least 1"
+        );
+        let (sender, receiver) = channel::<Task>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..num_threads)
+            .map(|i| {
+                let receiver = receiver.clone();
+                thread::Builder::new()
+                    .name(format!("{}-{}", name, i))
+                    .spawn(move || Self::run(&receiver))
+                    .unwrap()
+            })
+            .collect();
+        ThreadPool {
+            sender: Mutex::new(Some(sender)),
+            workers,
+        }
+    }
+
+    fn run(receiver: &Mutex<Receiver<Task>>) {
+        loop {
+            let task = receiver.lock().unwrap().recv();
+            match task {
+                Ok(task) => task(),
+                // the pool is dropped
+                Err(_) => return,
+            }
+        }
+    }
+
+    pub fn schedule(&self, task: Box<dyn FnOnce() + Send>) {
+        if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+            // the workers live as long as the sender
+            let _ = sender.send(task);
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.sender.lock().unwrap().take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_run_all_tasks() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new("test", 4);
+        for _ in 0..100 {
+            let counter = counter.clone();
+            pool.schedule(Box::new(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }));
+        }
+        drop(pool);
+        assert_eq!(counter.load(Ordering::SeqCst), 100);
+    }
+}
@@ -0,0 +1,131 @@
+// The names of the files in the DB directory:
+//
+//   LOCK               the lock held by the DB opened on the directory
+//   CURRENT            the name of the current MANIFEST
+//   MANIFEST-000002    the edits of the versions
+//   000003.log         a WAL file
+//   000004.sst         a table file
+//   000005.vlog        a value log file, stored in `Config::value_dir`
+//   000006.dbtmp       a temporary file, e.g. the next CURRENT before renamed
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileType {
+    Lock,
+    Current,
+    Manifest,
+    Log,
+    Table,
+    ValueLog,
+    Temp,
+}
+
+pub fn lock_file_name(dir: &str) -> String {
+    format!("{}/LOCK", dir)
+}
+
+pub fn current_file_name(dir: &str) -> String {
+    format!("{}/CURRENT", dir)
+}
+
+pub fn manifest_file_name(dir: &str, number: u64) -> String {
+    format!("{}/MANIFEST-{:06}", dir, number)
+}
+
+pub fn log_file_name(dir: &str, number: u64) -> String {
+    format!("{}/{:06}.log", dir, number)
+}
+
+pub fn table_file_name(dir: &str, number: u64) -> String {
+    format!("{}/{:06}.sst", dir, number)
+}
+
+pub fn value_log_file_name(dir: &str, number: u64) -> String {
+    format!("{}/{:06}.vlog", dir, number)
+}
+
+pub fn temp_file_name(dir: &str, number: u64) -> String {
+    format!("{}/{:06}.dbtmp", dir, number)
+}
+
+/// Returns the type and the number of the file named `name`, which has no directory part.
+/// The number of a file without one (e.g. CURRENT) is 0.
+/// Returns `None` if the file doesn't belong to the DB.
+pub fn parse_file_name(name: &str) -> Option<(FileType, u64)> {
+    match name {
+        "LOCK" => return Some((FileType::Lock, 0)),
+        "CURRENT" => return Some((FileType::Current, 0)),
+        _ => {}
+    }
+    if let Some(number) = name.strip_prefix("MANIFEST-") {
+        return parse_number(number).map(|n| (FileType::Manifest, n));
+    }
+    let (number, suffix) = name.split_at(name.find('.')?);
+    let file_type = match suffix {
+        ".log" => FileType::Log,
+        ".sst" => FileType::Table,
+        ".vlog" => FileType::ValueLog,
+        ".dbtmp" => FileType::Temp,
+        _ => return None,
+    };
+    parse_number(number).map(|n| (file_type, n))
+}
+
+fn parse_number(s: &str) -> Option<u64> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_name() {
+        let cases = [
+            ("LOCK", FileType::Lock, 0),
+            ("CURRENT", FileType::Current, 0),
+            ("MANIFEST-000002", FileType::Manifest, 2),
+            ("000003.log", FileType::Log, 3),
+            ("100.sst", FileType::Table, 100),
+            ("18446744073709551615.vlog", FileType::ValueLog, u64::MAX),
+            ("000006.dbtmp", FileType::Temp, 6),
+        ];
+        for (name, file_type, number) in cases.iter() {
+            assert_eq!(parse_file_name(name), Some((*file_type, *number)), "{}", name);
+        }
+        for name in [
+            "",
+            "LOCK.old",
+            "MANIFEST-",
+            "MANIFEST-12a",
+            ".log",
+            "+3.log",
+            "3.txt",
+            "18446744073709551616.sst",
+        ]
+        .iter()
+        {
+            assert_eq!(parse_file_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_construct_file_names() {
+        for (path, number) in [
+            (log_file_name("/db", 7), 7),
+            (table_file_name("/db", 8), 8),
+            (value_log_file_name("/db", 9), 9),
+            (manifest_file_name("/db", 10), 10),
+            (temp_file_name("/db", 11), 11),
+        ]
+        .iter()
+        {
+            let name = path.strip_prefix("/db/").unwrap();
+            assert_eq!(parse_file_name(name).unwrap().1, *number);
+        }
+        assert_eq!(parse_file_name(&lock_file_name("")[1..]).unwrap().0, FileType::Lock);
+        assert_eq!(parse_file_name(&current_file_name("")[1..]).unwrap().0, FileType::Current);
+    }
+}
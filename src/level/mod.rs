@@ -0,0 +1,2 @@
+pub mod version;
+pub mod version_edit;
@@ -0,0 +1,719 @@
+use super::version_edit::VersionEdit;
+use crate::column_family::{DEFAULT_COLUMN_FAMILY_ID, DEFAULT_COLUMN_FAMILY_NAME};
+use crate::env::{Env, WritableFile};
+use crate::filename::{
+    current_file_name, manifest_file_name, parse_file_name, temp_file_name, FileType,
+};
+use crate::record::reader::Reader;
+use crate::record::writer::Writer;
+use crate::util::comparator::Comparator;
+use crate::util::error::TinyError;
+use crate::util::slice::Slice;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+/// The number of the levels of the table files
+pub const NUM_LEVELS: usize = 7;
+
+// How many times `VersionSet::catch_up` reads CURRENT again if the MANIFEST it names is
+// removed before opened
+const MAX_CATCH_UP_ATTEMPTS: usize = 3;
+
+/// The metadata of a table file in a version
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileMetaData {
+    pub number: u64,
+    pub file_size: u64,
+    /// The smallest key in the file
+    pub smallest: Vec<u8>,
+    /// The largest key in the file
+    pub largest: Vec<u8>,
+    /// The smallest sequence number of the entries in the file
+    pub smallest_seqno: u64,
+    /// The largest sequence number of the entries in the file
+    pub largest_seqno: u64,
+}
+
+#[inline]
+fn compare(cmp: &dyn Comparator<Slice>, a: &[u8], b: &[u8]) -> Ordering {
+    cmp.compare(&Slice::from(a), &Slice::from(b))
+}
+
+impl FileMetaData {
+    /// Returns whether the key range of the file overlaps [`begin`, `end`].
+    /// A `None` bound is unbounded.
+    pub fn overlaps(&self, begin: Option<&[u8]>, end: Option<&[u8]>, cmp: &dyn Comparator<Slice>) -> bool {
+        begin.is_none_or(|b| compare(cmp, &self.largest, b) != Ordering::Less)
+            && end.is_none_or(|e| compare(cmp, &self.smallest, e) != Ordering::Greater)
+    }
+}
+
+/// Version is the set of the table files of every level. The files in level 0 are
+/// ordered from the oldest to the newest and their key ranges may overlap, while the
+/// files in the other levels are disjoint and ordered by the key ranges.
+///
+/// A version is immutable once installed by `VersionSet::log_and_apply`, so a reader can
+/// keep reading the files of the version it got while the files are being changed.
+#[derive(Clone, Debug, Default)]
+pub struct Version {
+    files: [Vec<Arc<FileMetaData>>; NUM_LEVELS],
+}
+
+impl Version {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the files in `level`
+    #[inline]
+    pub fn files(&self, level: usize) -> &[Arc<FileMetaData>] {
+        &self.files[level]
+    }
+
+    /// Returns the files of all the levels
+    pub fn all_files(&self) -> impl Iterator<Item = &Arc<FileMetaData>> {
+        self.files.iter().flatten()
+    }
+
+    /// Returns the files in `level` overlapping [`begin`, `end`] ordered as in the level.
+    /// A `None` bound is unbounded.
+    pub fn overlapping_files(
+        &self,
+        level: usize,
+        begin: Option<&[u8]>,
+        end: Option<&[u8]>,
+        cmp: &dyn Comparator<Slice>,
+    ) -> Vec<Arc<FileMetaData>> {
+        self.files[level]
+            .iter()
+            .filter(|f| f.overlaps(begin, end, cmp))
+            .cloned()
+            .collect()
+    }
+
+    /// Adds the file to `level`.
+    /// REQUIRES: the file doesn't overlap the files in `level` unless `level` is 0
+    pub fn add_file(&mut self, level: usize, file: Arc<FileMetaData>, cmp: &dyn Comparator<Slice>) {
+        let files = &mut self.files[level];
+        if level == 0 {
+            files.push(file);
+            return;
+        }
+        let i = files.partition_point(|f| compare(cmp, &f.largest, &file.smallest) == Ordering::Less);
+        invarint!(
+            i == files.len() || compare(cmp, &file.largest, &files[i].smallest) == Ordering::Less,
+            "[version] file {} overlaps file {} in level {}",
+            file.number,
+            files[i].number,
+            level
+        );
+        files.insert(i, file);
+    }
+
+    /// Removes the file of `number` from `level`
+    pub fn remove_file(&mut self, level: usize, number: u64) {
+        self.files[level].retain(|f| f.number != number);
+    }
+
+    /// Returns the files which may contain `key` in the order to be searched: the files in
+    /// level 0 from the newest to the oldest, then at most one file per deeper level.
+    pub fn files_for_key(&self, key: &[u8], cmp: &dyn Comparator<Slice>) -> Vec<Arc<FileMetaData>> {
+        let mut files: Vec<_> = self.files[0]
+            .iter()
+            .rev()
+            .filter(|f| f.overlaps(Some(key), Some(key), cmp))
+            .cloned()
+            .collect();
+        for level_files in self.files[1..].iter() {
+            let i = level_files.partition_point(|f| compare(cmp, &f.largest, key) == Ordering::Less);
+            if let Some(f) = level_files.get(i) {
+                if compare(cmp, &f.smallest, key) != Ordering::Greater {
+                    files.push(f.clone());
+                }
+            }
+        }
+        files
+    }
+
+    /// Returns whether no level deeper than `level` has a file which may contain `key`,
+    /// i.e. a compaction into `level` sees the oldest entry of the key
+    pub fn is_bottommost_for_key(&self, level: usize, key: &[u8], cmp: &dyn Comparator<Slice>) -> bool {
+        self.files[level + 1..].iter().all(|level_files| {
+            let i = level_files.partition_point(|f| compare(cmp, &f.largest, key) == Ordering::Less);
+            level_files
+                .get(i)
+                .is_none_or(|f| compare(cmp, &f.smallest, key) == Ordering::Greater)
+        })
+    }
+
+    /// Returns the level a file of the key range [`smallest`, `largest`] is added to, so
+    /// that the newer entries are always found before the older ones: level 0 if the range
+    /// overlaps a file in level 0, otherwise the deepest level above the first level
+    /// overlapping the range and no deeper than the bottommost level.
+    pub fn pick_level_for_file(&self, smallest: &[u8], largest: &[u8], cmp: &dyn Comparator<Slice>) -> usize {
+        let overlaps = |level: usize| {
+            self.files[level]
+                .iter()
+                .any(|f| f.overlaps(Some(smallest), Some(largest), cmp))
+        };
+        if overlaps(0) {
+            return 0;
+        }
+        let mut level = 0;
+        while level + 1 < NUM_LEVELS && !overlaps(level + 1) {
+            level += 1;
+        }
+        level
+    }
+
+    /// Returns the deepest level having files, or 0 if the version is empty
+    pub fn bottommost_level(&self) -> usize {
+        (0..NUM_LEVELS).rev().find(|l| !self.files[*l].is_empty()).unwrap_or(0)
+    }
+}
+
+/// VersionSet holds the current version of every column family and allocates the file
+/// numbers and the sequence numbers of the DB.
+///
+/// Every change of a current version is a `VersionEdit` appended to the MANIFEST by
+/// `log_and_apply` before it's installed, and CURRENT names the MANIFEST in use, so
+/// `recover` rebuilds the versions by replaying the edits.
+pub struct VersionSet {
+    // the live column families by id, including the default one
+    families: BTreeMap<u32, FamilyVersion>,
+    // the largest id ever given to a column family
+    max_column_family: u32,
+    next_file_number: u64,
+    last_sequence: u64,
+    // the number and the writer of the MANIFEST appended by `log_and_apply`
+    manifest_number: u64,
+    manifest: Option<Writer<Box<dyn WritableFile>>>,
+    // the end of the last edit written to or read from the MANIFEST
+    manifest_offset: u64,
+}
+
+// The state of a column family in the `VersionSet`
+struct FamilyVersion {
+    name: String,
+    current: Arc<Version>,
+    // the WAL files older than the log are flushed by the column family
+    log_number: u64,
+}
+
+impl VersionSet {
+    pub fn new() -> Self {
+        let mut families = BTreeMap::new();
+        families.insert(
+            DEFAULT_COLUMN_FAMILY_ID,
+            FamilyVersion {
+                name: DEFAULT_COLUMN_FAMILY_NAME.to_owned(),
+                current: Arc::new(Version::new()),
+                log_number: 0,
+            },
+        );
+        VersionSet {
+            families,
+            max_column_family: DEFAULT_COLUMN_FAMILY_ID,
+            next_file_number: 1,
+            last_sequence: 0,
+            manifest_number: 0,
+            manifest: None,
+            manifest_offset: 0,
+        }
+    }
+
+    /// Returns the current version of the column family, or an empty version if the
+    /// column family doesn't exist, e.g. it's just dropped
+    pub fn current(&self, column_family: u32) -> Arc<Version> {
+        match self.families.get(&column_family) {
+            Some(family) => family.current.clone(),
+            None => Arc::new(Version::new()),
+        }
+    }
+
+    /// Returns the ids and the names of the live column families ordered by the id
+    pub fn column_families(&self) -> impl Iterator<Item = (u32, &str)> {
+        self.families.iter().map(|(id, f)| (*id, f.name.as_str()))
+    }
+
+    /// Returns the largest id ever given to a column family, including the dropped ones
+    #[inline]
+    pub fn max_column_family(&self) -> u32 {
+        self.max_column_family
+    }
+
+    /// Allocates a new file number
+    pub fn new_file_number(&mut self) -> u64 {
+        let number = self.next_file_number;
+        self.next_file_number += 1;
+        number
+    }
+
+    /// Makes sure the numbers up to `number` are never allocated, e.g. the numbers of
+    /// the files found in the directory
+    pub fn mark_file_number_used(&mut self, number: u64) {
+        self.next_file_number = self.next_file_number.max(number + 1);
+    }
+
+    #[inline]
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+
+    pub fn set_last_sequence(&mut self, seq: u64) {
+        invarint!(seq >= self.last_sequence, "[version] the last sequence goes backward");
+        self.last_sequence = seq;
+    }
+
+    /// Returns the number of the oldest WAL file not flushed yet by some column family
+    pub fn log_number(&self) -> u64 {
+        self.families.values().map(|f| f.log_number).min().unwrap_or(0)
+    }
+
+    /// Returns the number of the oldest WAL file not flushed yet by the column family,
+    /// or `None` if the column family doesn't exist
+    pub fn column_family_log_number(&self, column_family: u32) -> Option<u64> {
+        self.families.get(&column_family).map(|f| f.log_number)
+    }
+
+    /// Returns the number of the MANIFEST recovered or created, or 0 if there is none
+    #[inline]
+    pub fn manifest_number(&self) -> u64 {
+        self.manifest_number
+    }
+
+    /// Returns the size of the MANIFEST up to the last edit written or read, whose edits
+    /// make up the current versions. The edits beyond it may refer to files which are not
+    /// in the current versions yet.
+    #[inline]
+    pub fn manifest_size(&self) -> u64 {
+        self.manifest_offset
+    }
+
+    /// Replays the MANIFEST named by CURRENT in `dir`. Returns false if the directory
+    /// has no CURRENT, i.e. the DB is new. The files of every column family are ordered
+    /// by `cmp`.
+    pub fn recover(&mut self, env: &dyn Env, dir: &str, cmp: &dyn Comparator<Slice>) -> Result<bool, TinyError> {
+        let number = match read_current_file(env, dir)? {
+            Some(number) => number,
+            None => return Ok(false),
+        };
+        let file = env.new_sequential_file(&manifest_file_name(dir, number))?;
+        self.replay_manifest(Reader::new(file, true), cmp)?;
+        self.manifest_number = number;
+        self.mark_file_number_used(number);
+        Ok(true)
+    }
+
+    /// Applies the edits appended to the MANIFEST by another DB since the last `recover`
+    /// or `catch_up`, e.g. the primary DB followed by a secondary one. The versions are
+    /// rebuilt if CURRENT names another MANIFEST. An edit still being written is left to
+    /// the next call.
+    ///
+    /// The MANIFEST named by CURRENT may be removed before it's opened if the other DB
+    /// switches to a new one in between, in which case CURRENT is read again.
+    pub fn catch_up(&mut self, env: &dyn Env, dir: &str, cmp: &dyn Comparator<Slice>) -> Result<(), TinyError> {
+        let mut attempts = 1;
+        loop {
+            match self.try_catch_up(env, dir, cmp) {
+                Err(TinyError::IOError(e))
+                    if e.kind() == io::ErrorKind::NotFound && attempts < MAX_CATCH_UP_ATTEMPTS =>
+                {
+                    log::info!("[version] the MANIFEST is switched while caught up: {}", e);
+                    attempts += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn try_catch_up(&mut self, env: &dyn Env, dir: &str, cmp: &dyn Comparator<Slice>) -> Result<(), TinyError> {
+        let number = match read_current_file(env, dir)? {
+            Some(number) => number,
+            None => return Ok(()),
+        };
+        if number != self.manifest_number {
+            let mut versions = VersionSet::new();
+            versions.recover(env, dir, cmp)?;
+            // the sequences already seen never go backward
+            versions.last_sequence = versions.last_sequence.max(self.last_sequence);
+            versions.mark_file_number_used(self.next_file_number - 1);
+            *self = versions;
+            return Ok(());
+        }
+        let mut file = env.new_sequential_file(&manifest_file_name(dir, number))?;
+        file.skip(self.manifest_offset)?;
+        self.replay_manifest(Reader::with_offset(file, true, self.manifest_offset), cmp)
+    }
+
+    fn replay_manifest<R: Read>(&mut self, mut reader: Reader<R>, cmp: &dyn Comparator<Slice>) -> Result<(), TinyError> {
+        while let Some(record) = reader.read_record()? {
+            let edit = VersionEdit::decode_from(&record)?;
+            if !self.is_applicable(&edit) {
+                return Err(TinyError::Corruption(format!(
+                    "[version] the edit of column family {} doesn't apply",
+                    edit.column_family
+                )));
+            }
+            self.apply(&edit, cmp);
+            self.manifest_offset = reader.last_record_end();
+        }
+        Ok(())
+    }
+
+    /// Writes the current state into a new MANIFEST, which becomes the one named by
+    /// CURRENT and appended by the following `log_and_apply`. The previous MANIFEST
+    /// is removed.
+    pub fn create_manifest(&mut self, env: &dyn Env, dir: &str) -> Result<(), TinyError> {
+        let number = self.new_file_number();
+        // a record per column family, of which the first one is the default column family
+        let mut snapshot = vec![];
+        for (id, family) in self.families.iter() {
+            let mut edit = VersionEdit::new();
+            edit.column_family = *id;
+            if *id != DEFAULT_COLUMN_FAMILY_ID {
+                edit.add_column_family = Some(family.name.clone());
+            }
+            edit.log_number = Some(family.log_number);
+            for level in 0..NUM_LEVELS {
+                for f in family.current.files(level) {
+                    edit.add_file(level, (**f).clone());
+                }
+            }
+            snapshot.push(edit);
+        }
+        snapshot[0].max_column_family = Some(self.max_column_family);
+        snapshot[0].next_file_number = Some(self.next_file_number);
+        snapshot[0].last_sequence = Some(self.last_sequence);
+        let path = manifest_file_name(dir, number);
+        let result = Self::write_manifest(env, &path, &snapshot)
+            .and_then(|writer| set_current_file(env, dir, number).map(|_| writer));
+        let writer = match result {
+            Ok(writer) => writer,
+            Err(e) => {
+                let _ = env.remove_file(&path);
+                return Err(e);
+            }
+        };
+        let old = std::mem::replace(&mut self.manifest_number, number);
+        self.manifest_offset = writer.size();
+        self.manifest = Some(writer);
+        if old > 0 {
+            if let Err(e) = env.remove_file(&manifest_file_name(dir, old)) {
+                log::warn!("[version] failed to remove the old MANIFEST {}: {}", old, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_manifest(
+        env: &dyn Env,
+        path: &str,
+        snapshot: &[VersionEdit],
+    ) -> Result<Writer<Box<dyn WritableFile>>, TinyError> {
+        let mut writer = Writer::new(env.new_writable_file(path)?);
+        for edit in snapshot {
+            let mut record = vec![];
+            edit.encode_to(&mut record);
+            writer.add_record(&record)?;
+        }
+        writer.get_mut().sync()?;
+        Ok(writer)
+    }
+
+    /// Appends the edit to the MANIFEST and installs the version it results in. The
+    /// edit records the next file number and the last sequence too. Nothing is changed if
+    /// the MANIFEST fails to be written. Without a MANIFEST, e.g. in a read-only DB, the
+    /// edit is only applied in memory.
+    ///
+    /// Returns `InvalidArgument` if the edit changes a column family which doesn't exist
+    /// or adds one which does.
+    pub fn log_and_apply(&mut self, edit: &mut VersionEdit, cmp: &dyn Comparator<Slice>) -> Result<(), TinyError> {
+        if !self.is_applicable(edit) {
+            return Err(TinyError::InvalidArgument(format!(
+                "[version] the edit of column family {} doesn't apply",
+                edit.column_family
+            )));
+        }
+        edit.next_file_number = Some(self.next_file_number);
+        edit.last_sequence.get_or_insert(self.last_sequence);
+        if let Some(writer) = self.manifest.as_mut() {
+            let mut record = vec![];
+            edit.encode_to(&mut record);
+            writer.add_record(&record)?;
+            writer.get_mut().sync()?;
+            self.manifest_offset = writer.size();
+        }
+        self.apply(edit, cmp);
+        Ok(())
+    }
+
+    fn is_applicable(&self, edit: &VersionEdit) -> bool {
+        let exists = self.families.contains_key(&edit.column_family);
+        match edit.add_column_family {
+            Some(_) => !exists && !edit.drop_column_family,
+            None => exists && !(edit.drop_column_family && edit.column_family == DEFAULT_COLUMN_FAMILY_ID),
+        }
+    }
+
+    // REQUIRES: `is_applicable(edit)`
+    fn apply(&mut self, edit: &VersionEdit, cmp: &dyn Comparator<Slice>) {
+        if let Some(n) = edit.next_file_number {
+            self.mark_file_number_used(n - 1);
+        }
+        if let Some(seq) = edit.last_sequence {
+            self.last_sequence = self.last_sequence.max(seq);
+        }
+        if let Some(id) = edit.max_column_family {
+            self.max_column_family = self.max_column_family.max(id);
+        }
+        if let Some(name) = edit.add_column_family.as_ref() {
+            self.max_column_family = self.max_column_family.max(edit.column_family);
+            self.families.insert(
+                edit.column_family,
+                FamilyVersion {
+                    name: name.clone(),
+                    current: Arc::new(Version::new()),
+                    log_number: 0,
+                },
+            );
+        }
+        if edit.drop_column_family {
+            self.families.remove(&edit.column_family);
+            return;
+        }
+        let family = self.families.get_mut(&edit.column_family).unwrap();
+        if let Some(n) = edit.log_number {
+            family.log_number = family.log_number.max(n);
+        }
+        if edit.deleted_files.is_empty() && edit.new_files.is_empty() {
+            return;
+        }
+        let mut version = (*family.current).clone();
+        for (level, number) in edit.deleted_files.iter() {
+            version.remove_file(*level, *number);
+        }
+        for (level, f) in edit.new_files.iter() {
+            version.add_file(*level, Arc::new(f.clone()), cmp);
+        }
+        family.current = Arc::new(version);
+    }
+}
+
+/// Returns the number of the MANIFEST named by CURRENT in `dir`, or `None` if there
+/// is no CURRENT
+pub fn read_current_file(env: &dyn Env, dir: &str) -> Result<Option<u64>, TinyError> {
+    let path = current_file_name(dir);
+    if !env.file_exists(&path) {
+        return Ok(None);
+    }
+    let mut contents = String::new();
+    env.new_sequential_file(&path)?.read_to_string(&mut contents)?;
+    match parse_file_name(contents.trim_end_matches('\n')) {
+        Some((FileType::Manifest, number)) if contents.ends_with('\n') => Ok(Some(number)),
+        _ => Err(TinyError::Corruption(format!(
+            "[version] CURRENT names no MANIFEST: {:?}",
+            contents
+        ))),
+    }
+}
+
+/// Points CURRENT in `dir` to the MANIFEST of `number` by renaming a temporary file
+/// over it
+pub fn set_current_file(env: &dyn Env, dir: &str, number: u64) -> Result<(), TinyError> {
+    let temp = temp_file_name(dir, number);
+    let contents = format!("MANIFEST-{:06}\n", number);
+    let result = env.new_writable_file(&temp).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync()
+    });
+    let result = result.and_then(|_| env.rename_file(&temp, &current_file_name(dir)));
+    if result.is_err() {
+        let _ = env.remove_file(&temp);
+    }
+    result
+}
+
+impl Default for VersionSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::fault_injection::FaultInjectionEnv;
+    use crate::env::mem::MemEnv;
+    use crate::util::comparator::BytewiseComparator;
+
+    use std::sync::Mutex;
+
+    fn file(number: u64, smallest: &str, largest: &str) -> Arc<FileMetaData> {
+        Arc::new(FileMetaData {
+            number,
+            file_size: 0,
+            smallest: smallest.as_bytes().to_vec(),
+            largest: largest.as_bytes().to_vec(),
+            smallest_seqno: 0,
+            largest_seqno: 0,
+        })
+    }
+
+    fn numbers(files: &[Arc<FileMetaData>]) -> Vec<u64> {
+        files.iter().map(|f| f.number).collect()
+    }
+
+    #[test]
+    fn test_files_for_key() {
+        let cmp = BytewiseComparator::new();
+        let mut v = Version::new();
+        v.add_file(0, file(1, "a", "m"), &cmp);
+        v.add_file(0, file(2, "k", "z"), &cmp);
+        v.add_file(1, file(4, "n", "p"), &cmp);
+        v.add_file(1, file(3, "c", "e"), &cmp);
+        v.add_file(2, file(5, "a", "z"), &cmp);
+        assert_eq!(numbers(v.files(1)), vec![3, 4]);
+        assert_eq!(numbers(&v.files_for_key(b"l", &cmp)), vec![2, 1, 5]);
+        assert_eq!(numbers(&v.files_for_key(b"d", &cmp)), vec![1, 3, 5]);
+        assert_eq!(numbers(&v.files_for_key(b"o", &cmp)), vec![2, 4, 5]);
+        assert_eq!(numbers(&v.overlapping_files(1, Some(b"e"), None, &cmp)), vec![3, 4]);
+        assert_eq!(numbers(&v.overlapping_files(1, None, Some(b"d"), &cmp)), vec![3]);
+        assert_eq!(v.bottommost_level(), 2);
+        assert!(!v.is_bottommost_for_key(1, b"o", &cmp));
+        assert!(v.is_bottommost_for_key(2, b"o", &cmp));
+        assert!(v.is_bottommost_for_key(NUM_LEVELS - 1, b"o", &cmp));
+        v.remove_file(2, 5);
+        assert_eq!(v.bottommost_level(), 1);
+        assert!(v.files_for_key(b"0", &cmp).is_empty());
+    }
+
+    #[test]
+    fn test_pick_level_for_file() {
+        let cmp = BytewiseComparator::new();
+        let mut v = Version::new();
+        assert_eq!(v.pick_level_for_file(b"a", b"b", &cmp), NUM_LEVELS - 1);
+        v.add_file(3, file(1, "c", "f"), &cmp);
+        assert_eq!(v.pick_level_for_file(b"a", b"b", &cmp), NUM_LEVELS - 1);
+        assert_eq!(v.pick_level_for_file(b"a", b"c", &cmp), 2);
+        v.add_file(0, file(2, "x", "y"), &cmp);
+        assert_eq!(v.pick_level_for_file(b"y", b"z", &cmp), 0);
+        assert_eq!(v.pick_level_for_file(b"g", b"h", &cmp), NUM_LEVELS - 1);
+    }
+
+    #[test]
+    fn test_column_families() {
+        let env = MemEnv::new();
+        env.create_dir("/db").unwrap();
+        let cmp = BytewiseComparator::new();
+        let mut versions = VersionSet::new();
+        versions.create_manifest(&env, "/db").unwrap();
+        let mut add = VersionEdit::new();
+        add.column_family = 1;
+        add.add_column_family = Some("users".to_owned());
+        versions.log_and_apply(&mut add.clone(), &cmp).unwrap();
+        assert!(versions.log_and_apply(&mut add, &cmp).is_err());
+        let mut edit = VersionEdit::new();
+        edit.column_family = 1;
+        edit.log_number = Some(8);
+        edit.add_file(2, (*file(7, "a", "b")).clone());
+        versions.log_and_apply(&mut edit, &cmp).unwrap();
+        let mut add = VersionEdit::new();
+        add.column_family = 2;
+        add.add_column_family = Some("orders".to_owned());
+        versions.log_and_apply(&mut add, &cmp).unwrap();
+        let mut drop = VersionEdit::new();
+        drop.column_family = 2;
+        drop.drop_column_family = true;
+        versions.log_and_apply(&mut drop.clone(), &cmp).unwrap();
+        assert!(matches!(versions.log_and_apply(&mut drop, &cmp), Err(TinyError::InvalidArgument(_))));
+        let mut drop_default = VersionEdit::new();
+        drop_default.drop_column_family = true;
+        assert!(versions.log_and_apply(&mut drop_default, &cmp).is_err());
+        assert_eq!(numbers(versions.current(1).files(2)), vec![7]);
+        assert!(versions.current(2).all_files().next().is_none());
+        // the default column family flushed nothing yet
+        assert_eq!(versions.log_number(), 0);
+
+        for _ in 0..2 {
+            let mut recovered = VersionSet::new();
+            assert!(recovered.recover(&env, "/db", &cmp).unwrap());
+            let families: Vec<_> = recovered.column_families().map(|(id, name)| (id, name.to_owned())).collect();
+            assert_eq!(families, vec![(0, "default".to_owned()), (1, "users".to_owned())]);
+            assert_eq!(numbers(recovered.current(1).files(2)), vec![7]);
+            assert_eq!(recovered.column_family_log_number(1), Some(8));
+            assert_eq!(recovered.column_family_log_number(2), None);
+            // the id of the dropped column family is not reused
+            assert_eq!(recovered.max_column_family(), 2);
+            // the snapshot of the next MANIFEST keeps the column families
+            recovered.create_manifest(&env, "/db").unwrap();
+        }
+    }
+
+    #[test]
+    fn test_catch_up() {
+        let env = MemEnv::new();
+        env.create_dir("/db").unwrap();
+        let cmp = BytewiseComparator::new();
+        let mut primary = VersionSet::new();
+        primary.create_manifest(&env, "/db").unwrap();
+        let mut secondary = VersionSet::new();
+        assert!(secondary.recover(&env, "/db", &cmp).unwrap());
+
+        let mut edit = VersionEdit::new();
+        edit.add_file(0, (*file(5, "a", "b")).clone());
+        edit.last_sequence = Some(9);
+        primary.log_and_apply(&mut edit, &cmp).unwrap();
+        secondary.catch_up(&env, "/db", &cmp).unwrap();
+        assert_eq!(numbers(secondary.current(0).files(0)), vec![5]);
+        assert_eq!(secondary.last_sequence(), 9);
+        // nothing new
+        secondary.catch_up(&env, "/db", &cmp).unwrap();
+        assert_eq!(numbers(secondary.current(0).files(0)), vec![5]);
+
+        // the versions are rebuilt from a new MANIFEST
+        primary.create_manifest(&env, "/db").unwrap();
+        let mut edit = VersionEdit::new();
+        edit.delete_file(0, 5);
+        edit.add_file(1, (*file(6, "a", "b")).clone());
+        primary.log_and_apply(&mut edit, &cmp).unwrap();
+        secondary.catch_up(&env, "/db", &cmp).unwrap();
+        assert_eq!(secondary.manifest_number(), primary.manifest_number());
+        assert!(secondary.current(0).files(0).is_empty());
+        assert_eq!(numbers(secondary.current(0).files(1)), vec![6]);
+        assert_eq!(secondary.last_sequence(), 9);
+    }
+
+    #[test]
+    fn test_manifest_switched_during_catch_up() {
+        let env = Arc::new(FaultInjectionEnv::new(Arc::new(MemEnv::new())));
+        env.create_dir("/db").unwrap();
+        let cmp = BytewiseComparator::new();
+        let primary = Arc::new(Mutex::new(VersionSet::new()));
+        primary.lock().unwrap().create_manifest(env.as_ref(), "/db").unwrap();
+        let mut secondary = VersionSet::new();
+        assert!(secondary.recover(env.as_ref(), "/db", &cmp).unwrap());
+        let old_manifest = manifest_file_name("/db", secondary.manifest_number());
+
+        // the MANIFEST named by CURRENT is removed right before the secondary opens it
+        let (switched, switch_env) = (primary.clone(), env.clone());
+        env.before_read(
+            move |path| path == old_manifest,
+            move || {
+                let mut primary = switched.lock().unwrap();
+                primary.create_manifest(switch_env.as_ref(), "/db").unwrap();
+                let mut edit = VersionEdit::new();
+                edit.add_file(0, (*file(5, "a", "b")).clone());
+                edit.last_sequence = Some(9);
+                primary.log_and_apply(&mut edit, &BytewiseComparator::new()).unwrap();
+            },
+        );
+        secondary.catch_up(env.as_ref(), "/db", &cmp).unwrap();
+        assert_eq!(secondary.manifest_number(), primary.lock().unwrap().manifest_number());
+        assert_eq!(numbers(secondary.current(0).files(0)), vec![5]);
+        assert_eq!(secondary.last_sequence(), 9);
+    }
+}
@@ -0,0 +1,235 @@
+use super::version::FileMetaData;
+use crate::util::error::TinyError;
+use crate::util::varint::{get_length_prefixed, get_u64, put_length_prefixed, put_u64};
+
+// The tags of the fields of an encoded edit, each of which is followed by the field:
+//
+//   LOG_NUMBER        varint
+//   NEXT_FILE_NUMBER  varint
+//   LAST_SEQUENCE     varint
+//   NEW_FILE          level, number, file size (varints), smallest, largest
+//                     (length prefixed), smallest seqno, largest seqno (varints)
+//   DELETED_FILE      level, number (varints)
+//   COLUMN_FAMILY     varint, omitted for the default column family
+//   ADD_COLUMN_FAMILY name (length prefixed)
+//   DROP_COLUMN_FAMILY
+//   MAX_COLUMN_FAMILY varint
+const LOG_NUMBER: u64 = 1;
+const NEXT_FILE_NUMBER: u64 = 2;
+const LAST_SEQUENCE: u64 = 3;
+const NEW_FILE: u64 = 4;
+const DELETED_FILE: u64 = 5;
+const COLUMN_FAMILY: u64 = 6;
+const ADD_COLUMN_FAMILY: u64 = 7;
+const DROP_COLUMN_FAMILY: u64 = 8;
+const MAX_COLUMN_FAMILY: u64 = 9;
+
+/// VersionEdit is a change of the `VersionSet`, which is appended to the MANIFEST as a
+/// record. Replaying the edits in the MANIFEST recovers the current version.
+///
+/// The files and the log number of an edit belong to `column_family`, which the edit
+/// may add or drop too.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VersionEdit {
+    pub column_family: u32,
+    /// The name of the column family added by the edit
+    pub add_column_family: Option<String>,
+    pub drop_column_family: bool,
+    /// The largest id ever given to a column family, so a dropped id is never reused
+    pub max_column_family: Option<u32>,
+    /// The WAL files older than the log are flushed by the column family and their
+    /// changes of it are no longer needed for recovery
+    pub log_number: Option<u64>,
+    pub next_file_number: Option<u64>,
+    pub last_sequence: Option<u64>,
+    /// The files added to the levels as (level, file)
+    pub new_files: Vec<(usize, FileMetaData)>,
+    /// The files removed from the levels as (level, file number)
+    pub deleted_files: Vec<(usize, u64)>,
+}
+
+impl VersionEdit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_file(&mut self, level: usize, file: FileMetaData) {
+        self.new_files.push((level, file));
+    }
+
+    pub fn delete_file(&mut self, level: usize, number: u64) {
+        self.deleted_files.push((level, number));
+    }
+
+    pub fn encode_to(&self, dst: &mut Vec<u8>) {
+        if self.column_family != 0 {
+            put_u64(dst, COLUMN_FAMILY);
+            put_u64(dst, u64::from(self.column_family));
+        }
+        if let Some(name) = self.add_column_family.as_ref() {
+            put_u64(dst, ADD_COLUMN_FAMILY);
+            put_length_prefixed(dst, name.as_bytes());
+        }
+        if self.drop_column_family {
+            put_u64(dst, DROP_COLUMN_FAMILY);
+        }
+        for (tag, field) in [
+            (MAX_COLUMN_FAMILY, self.max_column_family.map(u64::from)),
+            (LOG_NUMBER, self.log_number),
+            (NEXT_FILE_NUMBER, self.next_file_number),
+            (LAST_SEQUENCE, self.last_sequence),
+        ]
+        .iter()
+        {
+            if let Some(n) = field {
+                put_u64(dst, *tag);
+                put_u64(dst, *n);
+            }
+        }
+        for (level, number) in self.deleted_files.iter() {
+            put_u64(dst, DELETED_FILE);
+            put_u64(dst, *level as u64);
+            put_u64(dst, *number);
+        }
+        for (level, f) in self.new_files.iter() {
+            put_u64(dst, NEW_FILE);
+            put_u64(dst, *level as u64);
+            put_u64(dst, f.number);
+            put_u64(dst, f.file_size);
+            put_length_prefixed(dst, &f.smallest);
+            put_length_prefixed(dst, &f.largest);
+            put_u64(dst, f.smallest_seqno);
+            put_u64(dst, f.largest_seqno);
+        }
+    }
+
+    /// Decodes the edit encoded by `encode_to`.
+    /// Returns `TinyError::Corruption` if `src` is malformed.
+    pub fn decode_from(mut src: &[u8]) -> Result<Self, TinyError> {
+        let mut edit = VersionEdit::new();
+        while !src.is_empty() {
+            let tag = get_u64(&mut src).ok_or_else(|| corruption("tag"))?;
+            match tag {
+                COLUMN_FAMILY => edit.column_family = get_column_family(&mut src)?,
+                ADD_COLUMN_FAMILY => {
+                    let name = get_length_prefixed(&mut src)
+                        .and_then(|name| String::from_utf8(name.to_vec()).ok())
+                        .ok_or_else(|| corruption("column family name"))?;
+                    edit.add_column_family = Some(name);
+                }
+                DROP_COLUMN_FAMILY => edit.drop_column_family = true,
+                MAX_COLUMN_FAMILY => edit.max_column_family = Some(get_column_family(&mut src)?),
+                LOG_NUMBER => edit.log_number = Some(get_number(&mut src, "log number")?),
+                NEXT_FILE_NUMBER => {
+                    edit.next_file_number = Some(get_number(&mut src, "next file number")?)
+                }
+                LAST_SEQUENCE => {
+                    edit.last_sequence = Some(get_number(&mut src, "last sequence")?)
+                }
+                DELETED_FILE => {
+                    let level = get_level(&mut src)?;
+                    let number = get_number(&mut src, "deleted file")?;
+                    edit.deleted_files.push((level, number));
+                }
+                NEW_FILE => {
+                    let level = get_level(&mut src)?;
+                    let number = get_number(&mut src, "new file")?;
+                    let file_size = get_number(&mut src, "file size")?;
+                    let mut get_key = || {
+                        get_length_prefixed(&mut src)
+                            .map(|k| k.to_vec())
+                            .ok_or_else(|| corruption("file key"))
+                    };
+                    let smallest = get_key()?;
+                    let largest = get_key()?;
+                    let smallest_seqno = get_number(&mut src, "smallest seqno")?;
+                    let largest_seqno = get_number(&mut src, "largest seqno")?;
+                    edit.new_files.push((
+                        level,
+                        FileMetaData {
+                            number,
+                            file_size,
+                            smallest,
+                            largest,
+                            smallest_seqno,
+                            largest_seqno,
+                        },
+                    ));
+                }
+                _ => return Err(corruption(&format!("tag {}", tag))),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+fn corruption(field: &str) -> TinyError {
+    TinyError::Corruption(format!("[version edit] bad {}", field))
+}
+
+fn get_number(src: &mut &[u8], field: &str) -> Result<u64, TinyError> {
+    get_u64(src).ok_or_else(|| corruption(field))
+}
+
+fn get_column_family(src: &mut &[u8]) -> Result<u32, TinyError> {
+    match get_u64(src) {
+        Some(id) if id <= u64::from(u32::MAX) => Ok(id as u32),
+        _ => Err(corruption("column family")),
+    }
+}
+
+fn get_level(src: &mut &[u8]) -> Result<usize, TinyError> {
+    match get_u64(src) {
+        Some(level) if level < super::version::NUM_LEVELS as u64 => Ok(level as usize),
+        _ => Err(corruption("level")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        let mut edit = VersionEdit::new();
+        assert_eq!(VersionEdit::decode_from(&[]).unwrap(), edit);
+        edit.log_number = Some(3);
+        edit.next_file_number = Some(1 << 40);
+        edit.last_sequence = Some(100);
+        edit.delete_file(2, 7);
+        edit.add_file(
+            6,
+            FileMetaData {
+                number: 8,
+                file_size: 4096,
+                smallest: b"a".to_vec(),
+                largest: vec![],
+                smallest_seqno: 5,
+                largest_seqno: 99,
+            },
+        );
+        let mut buf = vec![];
+        edit.encode_to(&mut buf);
+        assert_eq!(VersionEdit::decode_from(&buf).unwrap(), edit);
+
+        let mut add = VersionEdit::new();
+        add.column_family = 3;
+        add.add_column_family = Some("users".to_owned());
+        add.max_column_family = Some(3);
+        add.log_number = Some(9);
+        let mut buf2 = vec![];
+        add.encode_to(&mut buf2);
+        assert_eq!(VersionEdit::decode_from(&buf2).unwrap(), add);
+        let mut drop = VersionEdit::new();
+        drop.column_family = 3;
+        drop.drop_column_family = true;
+        buf2.clear();
+        drop.encode_to(&mut buf2);
+        assert_eq!(VersionEdit::decode_from(&buf2).unwrap(), drop);
+
+        // truncated in the middle of the new file
+        assert!(VersionEdit::decode_from(&buf[..buf.len() - 1]).is_err());
+        // unknown tag
+        assert!(VersionEdit::decode_from(&[99, 1]).is_err());
+    }
+}
@@ -5,9 +5,23 @@ extern crate rand;
 
 #[macro_use]
 pub mod util;
+pub mod backup;
+pub mod cache;
+pub mod column_family;
+pub mod compaction;
+pub mod checkpoint;
 pub mod config;
 pub mod db;
+pub mod db_iter;
+pub mod entry;
+pub mod env;
+pub mod filename;
+pub mod level;
+pub mod record;
 pub mod mem;
+pub mod table;
+pub mod transaction;
+pub mod write_batch;
 
 #[cfg(test)]
 mod tests {
@@ -3,7 +3,7 @@ use std::sync::atomic::{AtomicUsize, Ordering, AtomicPtr};
 use core::mem;
 
 use super::skiplist::{Node, MAX_HEIGHT, MAX_NODE_SIZE};
-use std::slice;
+use std::{ptr, slice};
 
 pub trait Arena {
     /// Allocate memory for a node by given height.
@@ -41,17 +41,6 @@ impl AggressiveArena {
         }
     }
 
-    pub(super) fn display_all(&self) -> Vec<u8> {
-        let mut result = Vec::with_capacity(self.mem.capacity());
-        unsafe {
-            let ptr = self.mem.as_ptr();
-            for i in 0..self.offset.load(Ordering::Acquire) {
-                let p = ptr.add(i) as *mut u8;
-                result.push(*p);
-            }
-        }
-        result
-    }
 }
 
 impl Arena for AggressiveArena {
@@ -59,7 +48,17 @@ impl Arena for AggressiveArena {
         let ptr_size = mem::size_of::<*mut u8>();
         // truncate node size to reduce waste
         let used_node_size = MAX_NODE_SIZE - (MAX_HEIGHT - height) * ptr_size;
-        let n = self.offset.fetch_add(used_node_size, Ordering::SeqCst);
+        // the bytes allocated before may leave the offset unaligned for a node
+        let base = self.mem.as_ptr() as usize;
+        let align = mem::align_of::<Node>();
+        let aligned = |offset: usize| offset + (align - (base + offset) % align) % align;
+        let n = aligned(
+            self.offset
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |offset| {
+                    Some(aligned(offset) + used_node_size)
+                })
+                .unwrap(),
+        );
         unsafe {
             let node_ptr = self.mem.as_ptr().add(n) as *mut u8;
             // get the actually to-be-used memory of node and spilt it into 2 parts:
@@ -69,7 +68,7 @@ impl Arena for AggressiveArena {
                 .split_at_mut(used_node_size - height * ptr_size);
             let node = node_part.as_mut_ptr() as *mut Node;
             // FIXME: Box::from_raw can be unsafe when releasing memory
-            let next_nodes = Box::from_raw(slice::from_raw_parts_mut(
+            let next_nodes = Box::from_raw(ptr::slice_from_raw_parts_mut(
                 nexts_part.as_mut_ptr() as *mut AtomicPtr<Node>,
                 height,
             ));
@@ -84,7 +83,7 @@ impl Arena for AggressiveArena {
         unsafe {
             let ptr = self.mem.as_ptr().add(start) as *mut u8;
             for (i, b) in data.to_slice().iter().enumerate() {
-                let p = ptr.add(i) as *mut u8;
+                let p = ptr.add(i);
                 p.replace(*b);
             }
         }
@@ -102,7 +101,7 @@ impl Arena for AggressiveArena {
         );
 
         unsafe {
-            let ptr = self.mem.as_ptr().add(start) as *const u8;
+            let ptr = self.mem.as_ptr().add(start);
             Slice::new(ptr,count)
 
         }
@@ -148,7 +147,8 @@ mod tests {
         let node = arena.alloc_node(MAX_HEIGHT);
         unsafe {
             assert_eq!((*node).height, MAX_HEIGHT);
-            assert_eq!((*node).next_nodes.len(), MAX_HEIGHT);
+            let next_nodes = &(*node).next_nodes;
+            assert_eq!(next_nodes.len(), MAX_HEIGHT);
             assert_eq!((*node).key_size, 0);
             assert_eq!((*node).key_offset, 0);
             assert_eq!((*node).value_size, 0);
@@ -224,7 +224,7 @@ mod tests {
             t.join().unwrap();
         }
         let mem_ptr = arena.mem.as_ptr();
-        for (index, offset, expect) in results.lock().unwrap().drain(..) {
+        for (_index, offset, expect) in results.lock().unwrap().drain(..) {
             unsafe {
                 let ptr = mem_ptr.add(offset) as *mut u8;
                 for (i, b) in expect.iter().enumerate() {
@@ -248,7 +248,7 @@ mod tests {
     #[test]
     fn test_has_room_for() {
         let arena = AggressiveArena::new(1);
-        assert_eq!(arena.has_room_for(100), false);
+        assert!(!arena.has_room_for(100));
     }
     #[test]
     fn test_simple_alloc_bytes() {
@@ -256,7 +256,7 @@ mod tests {
         let input = vec![1u8, 2u8, 3u8, 4u8, 5u8];
         let offset = arena.alloc_bytes(&Slice::from(&input));
         unsafe {
-            let ptr = arena.mem.as_mut_ptr().add(offset as usize) as *mut u8;
+            let ptr = arena.mem.as_mut_ptr().add(offset as usize);
             for (i, b) in input.clone().iter().enumerate() {
                 let p = ptr.add(i);
                 assert_eq!(*p, *b);
@@ -0,0 +1,162 @@
+use crate::entry::{Entry, ValueType};
+use crate::util::comparator::Comparator;
+use crate::util::slice::Slice;
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::Arc;
+
+// The approximate memory taken by an entry besides the key and the value
+const ENTRY_OVERHEAD: usize = 64;
+
+// A key ordered by the comparator of the memtable
+struct MemKey {
+    key: Vec<u8>,
+    cmp: Arc<dyn Comparator<Slice> + Send + Sync>,
+}
+
+impl Ord for MemKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cmp.compare(&Slice::from(&self.key), &Slice::from(&other.key))
+    }
+}
+
+impl PartialOrd for MemKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MemKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MemKey {}
+
+/// MemTable keeps the entries of every key written since the last flush in the order
+/// of the comparator. It's flushed into a table file in level 0 once it grows beyond
+/// `Config::write_buffer_size`.
+///
+/// A value or a deletion replaces the entries of the key, while the merge entries are
+/// stacked onto them until they are folded by a read or the flush.
+pub struct MemTable {
+    cmp: Arc<dyn Comparator<Slice> + Send + Sync>,
+    // the entries of every key from the oldest to the newest
+    table: BTreeMap<MemKey, Vec<Entry>>,
+    memory_usage: usize,
+}
+
+impl MemTable {
+    pub fn new(cmp: Arc<dyn Comparator<Slice> + Send + Sync>) -> Self {
+        MemTable {
+            cmp,
+            table: BTreeMap::new(),
+            memory_usage: 0,
+        }
+    }
+
+    /// Adds the entry of `key`, which replaces the older entries of the key unless it's
+    /// a merge entry
+    pub fn add(&mut self, key: &[u8], entry: Entry) {
+        self.memory_usage += entry.value.len() + ENTRY_OVERHEAD;
+        let mem_key = self.mem_key(key);
+        let entries = match self.table.get_mut(&mem_key) {
+            Some(entries) => entries,
+            None => {
+                self.memory_usage += key.len();
+                self.table.entry(mem_key).or_default()
+            }
+        };
+        if entry.value_type != ValueType::Merge {
+            let replaced: usize = entries.iter().map(|e| e.value.len() + ENTRY_OVERHEAD).sum();
+            self.memory_usage -= replaced;
+            entries.clear();
+        }
+        entries.push(entry);
+    }
+
+    /// Returns the entries of `key` from the oldest to the newest
+    pub fn get(&self, key: &[u8]) -> Option<&[Entry]> {
+        self.table.get(&self.mem_key(key)).map(|e| e.as_slice())
+    }
+
+    /// Returns whether any key in [`begin`, `end`] is in the memtable.
+    /// A `None` bound is unbounded.
+    pub fn overlaps(&self, begin: Option<&[u8]>, end: Option<&[u8]>) -> bool {
+        let bound = |key: Option<&[u8]>| match key {
+            Some(key) => Bound::Included(self.mem_key(key)),
+            None => Bound::Unbounded,
+        };
+        self.table.range((bound(begin), bound(end))).next().is_some()
+    }
+
+    /// Returns the entries of every key from the oldest to the newest in the order of
+    /// the keys
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[Entry])> {
+        self.table.iter().map(|(k, e)| (k.key.as_slice(), e.as_slice()))
+    }
+
+    /// Returns the approximate number of bytes taken by the entries
+    #[inline]
+    pub fn approximate_memory_usage(&self) -> usize {
+        self.memory_usage
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+
+    #[inline]
+    fn mem_key(&self, key: &[u8]) -> MemKey {
+        MemKey {
+            key: key.to_vec(),
+            cmp: self.cmp.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::BytewiseComparator;
+
+    #[test]
+    fn test_add_and_get() {
+        let mut mem = MemTable::new(Arc::new(BytewiseComparator::new()));
+        assert!(mem.is_empty());
+        mem.add(b"b", Entry::new(ValueType::Value, 1, b"1"));
+        mem.add(b"a", Entry::new(ValueType::Value, 2, b"2"));
+        let usage = mem.approximate_memory_usage();
+        mem.add(b"b", Entry::new(ValueType::Deletion, 3, b""));
+        assert_eq!(mem.len(), 2);
+        assert!(mem.approximate_memory_usage() < usage);
+        assert_eq!(mem.get(b"a"), Some(&[Entry::new(ValueType::Value, 2, b"2")][..]));
+        assert_eq!(mem.get(b"b").unwrap()[0].value_type, ValueType::Deletion);
+        assert_eq!(mem.get(b"c"), None);
+        assert!(mem.overlaps(Some(b"0"), Some(b"a")));
+        assert!(mem.overlaps(Some(b"aa"), None));
+        assert!(!mem.overlaps(Some(b"aa"), Some(b"ab")));
+        assert!(!mem.overlaps(None, Some(b"0")));
+        let keys: Vec<_> = mem.iter().map(|(k, e)| (k.to_vec(), e.len())).collect();
+        assert_eq!(keys, vec![(b"a".to_vec(), 1), (b"b".to_vec(), 1)]);
+
+        // the merge entries are stacked until a value replaces them
+        mem.add(b"a", Entry::new(ValueType::Merge, 4, b"m1"));
+        mem.add(b"a", Entry::new(ValueType::Merge, 5, b"m2"));
+        let seqnos: Vec<_> = mem.get(b"a").unwrap().iter().map(|e| e.seq).collect();
+        assert_eq!(seqnos, vec![2, 4, 5]);
+        let usage = mem.approximate_memory_usage();
+        mem.add(b"a", Entry::new(ValueType::Value, 6, b"6"));
+        assert_eq!(mem.get(b"a").unwrap().len(), 1);
+        assert!(mem.approximate_memory_usage() < usage);
+    }
+}
@@ -1,2 +1,3 @@
 pub mod arena;
-pub mod skiplist;
\ No newline at end of file
+pub mod memtable;
+pub mod skiplist;
@@ -5,12 +5,15 @@ use crate::util::comparator::Comparator;
 use std::sync::atomic::{AtomicPtr, Ordering, AtomicUsize};
 use std::cmp::Ordering as CmpOrdering;
 use std::rc::Rc;
+use std::mem;
 use std::ptr;
 use rand::random;
 
 const BRANCHING: u32 = 4;
 pub const MAX_HEIGHT: usize = 12;
-pub const MAX_NODE_SIZE: usize = 10;
+/// The size of a node with the most levels, i.e. the `Node` struct followed by `MAX_HEIGHT`
+/// next pointers in the arena
+pub const MAX_NODE_SIZE: usize = mem::size_of::<Node>() + MAX_HEIGHT * mem::size_of::<AtomicPtr<Node>>();
 
 #[derive(Debug)]
 #[repr(C)]
@@ -58,14 +61,12 @@ impl Node {
 
     #[inline]
     pub fn key<A: Arena>(&self, arena: &A) -> Slice {
-        let raw = arena.get(self.key_offset as usize, self.key_size as usize);
-        Slice::from(raw)
+        arena.get(self.key_offset as usize, self.key_size as usize)
     }
 
     #[inline]
     pub fn value<A: Arena>(&self, arena: &A) -> Slice {
-        let raw = arena.get(self.value_offset as usize, self.value_size as usize);
-        Slice::from(raw)
+        arena.get(self.value_offset as usize, self.value_size as usize)
     }
 }
 
@@ -73,10 +74,7 @@ pub struct SkipList<A: Arena> {
     //should be handled atomically
     pub max_height: AtomicUsize,
     //comparator is used to compare the key of node
-    pub comparator: Rc<Comparator<Slice>>,
-    // references of this SkipList
-    // This not only represents in memory refs but also 'refs' in read request
-    refs: AtomicUsize,
+    pub comparator: Rc<dyn Comparator<Slice>>,
     // head node
     pub head: *mut Node,
     // arena contains all the nodes data
@@ -85,7 +83,7 @@ pub struct SkipList<A: Arena> {
 
 impl SkipList<AggressiveArena> {
     /// Create a new Skiplist with the given arena capacity
-    pub fn new(arena_cap: usize, cmp: Rc<Comparator<Slice>>) -> Self {
+    pub fn new(arena_cap: usize, cmp: Rc<dyn Comparator<Slice>>) -> Self {
         let arena = AggressiveArena::new(arena_cap);
         let head = arena.alloc_node(MAX_HEIGHT);
         SkipList {
@@ -93,7 +91,6 @@ impl SkipList<AggressiveArena> {
             max_height: AtomicUsize::new(1),
             arena,
             head,
-            refs: AtomicUsize::new(1),
         }
     }
 
@@ -102,7 +99,7 @@ impl SkipList<AggressiveArena> {
         let node = self.find_greater_or_equal(key, &mut prev);
         unsafe {
             invarint!(
-                &(*node).key(&self.arena)!=key,
+                node.is_null() || &(*node).key(&self.arena)!=key,
                 "[skiplist] duplicate insertion [key={:?}] is not allowed",
                 key
             );
@@ -111,16 +108,17 @@ impl SkipList<AggressiveArena> {
         let height = rand_height();
         let max_height = self.max_height.load(Ordering::Acquire);
         if height > max_height {
-            for i in max_height..height {
-                prev[i] = self.head;
+            for p in prev.iter_mut().take(height).skip(max_height) {
+                *p = self.head;
             }
             self.max_height.store(height, Ordering::Release);
         }
         let new_node = Node::new(key, value, height, &self.arena);
         unsafe {
-            for i in 0..height {
-                (*new_node).set_next(i, (*(prev[i])).get_next(i));
-                (*(prev[i])).set_next(i, new_node);
+            // `prev` is indexed from 0 while the levels start from 1
+            for (i, p) in prev.iter().enumerate().take(height) {
+                (*new_node).set_next(i + 1, (**p).get_next(i + 1));
+                (**p).set_next(i + 1, new_node);
             }
         }
     }
@@ -132,11 +130,11 @@ impl SkipList<AggressiveArena> {
     pub fn find_greater_or_equal(&self, key: &Slice, prev_nodes: &mut [*mut Node]) -> *mut Node {
         let mut level = self.max_height.load(Ordering::Acquire);
         let mut node = self.head;
-        let arena = &self.arena;
         loop {
             unsafe {
                 let next = (*node).get_next(level);
-                if self.key_is_less_than(key, next) {
+                // the end of a level is regarded as greater than any key
+                if next.is_null() || self.key_is_less_than(key, next) {
                     // we need to record the prev node
                     prev_nodes[level - 1] = node;
                     if level == 1 {
@@ -160,7 +158,7 @@ impl SkipList<AggressiveArena> {
             unsafe {
                 let next = (*node).get_next(level);
                 if next.is_null()
-                    || self.comparator.compare(&((*next)).key(arena), key) != CmpOrdering::Less {
+                    || self.comparator.compare(&(*next).key(arena), key) != CmpOrdering::Less {
                     if level == 1 {
                         return node;
                     } else {
@@ -176,7 +174,6 @@ impl SkipList<AggressiveArena> {
     pub fn find_last(&self) -> *mut Node {
         let mut level = self.max_height.load(Ordering::Acquire);
         let mut node = self.head;
-        let arena = &self.arena;
         loop {
             unsafe {
                 let next = (*node).get_next(level);
@@ -192,16 +189,13 @@ impl SkipList<AggressiveArena> {
         }
     }
 
-    /// Return whether the give key is less than the give node's key.
+    /// Return whether the give key is less than the give node's key, false if the node is null.
     fn key_is_less_than(&self, key: &Slice, n: *mut Node) -> bool {
         if n.is_null() {
-            true
+            false
         } else {
             let node_key = unsafe { (*n).key(&self.arena) };
-            match self.comparator.compare(key, &node_key) {
-                CmpOrdering::Less => true,
-                _ => false
-            }
+            self.comparator.compare(key, &node_key) == CmpOrdering::Less
         }
     }
 }
@@ -210,7 +204,7 @@ impl SkipList<AggressiveArena> {
 pub fn rand_height() -> usize {
     let mut height = 1;
     loop {
-        if height < MAX_HEIGHT && random::<u32>() % BRANCHING == 0 {
+        if height < MAX_HEIGHT && random::<u32>().is_multiple_of(BRANCHING) {
             height += 1;
         } else {
             break;
@@ -227,14 +221,14 @@ mod tests {
     use std::ptr;
     use std::rc::Rc;
 
-    fn new_test_skl() -> Skiplist<AggressiveArena> {
-        Skiplist::new(64 << 20, Rc::new(BytewiseComparator::new()))
+    fn new_test_skl() -> SkipList<AggressiveArena> {
+        SkipList::new(64 << 20, Rc::new(BytewiseComparator::new()))
     }
     #[test]
     fn test_rand_height() {
         for _ in 0..100 {
             let height = rand_height();
-            assert_eq!(height < MAX_HEIGHT, true);
+            assert!(height < MAX_HEIGHT);
         }
     }
 
@@ -244,7 +238,7 @@ mod tests {
         let vec = vec![1u8, 2u8, 3u8];
         let key = Slice::from(vec.as_slice());
         // return false if node is nullptr
-        assert_eq!(false, skl.key_is_less_than(&key, ptr::null_mut()));
+        assert!(!skl.key_is_less_than(&key, ptr::null_mut()));
 
         let n = Node::new(
             &Slice::from(vec![1u8, 2u8].as_slice()),
@@ -252,7 +246,7 @@ mod tests {
             1,
             &skl.arena,
         );
-        assert_eq!(false, skl.key_is_less_than(&key, n));
+        assert!(!skl.key_is_less_than(&key, n));
 
         let n2 = Node::new(
             &Slice::from(vec![1u8, 2u8, 4u8].as_slice()),
@@ -260,7 +254,7 @@ mod tests {
             1,
             &skl.arena,
         );
-        assert_eq!(true, skl.key_is_less_than(&key, n2));
+        assert!(skl.key_is_less_than(&key, n2));
     }
 
     #[test]
@@ -307,7 +301,22 @@ mod tests {
     fn test_find_less_than() {}
 
     #[test]
-    fn test_basic() {}
+    fn test_basic() {
+        let skl = new_test_skl();
+        for key in ["key3", "key1", "key5", "key2", "key4"].iter() {
+            skl.insert(&Slice::from(*key), &Slice::from(*key));
+        }
+        let mut prev = [ptr::null_mut(); MAX_HEIGHT];
+        let mut node = skl.find_greater_or_equal(&Slice::from("key0"), &mut prev);
+        let mut keys = vec![];
+        while !node.is_null() {
+            unsafe {
+                keys.push((*node).key(&skl.arena).to_slice().to_vec());
+                node = (*node).get_next(1);
+            }
+        }
+        assert_eq!(keys, vec![b"key1".to_vec(), b"key2".to_vec(), b"key3".to_vec(), b"key4".to_vec(), b"key5".to_vec()]);
+    }
 }
 
 
@@ -0,0 +1,40 @@
+// A log file (e.g. the WAL) is a sequence of 32KB blocks. A record never starts
+// within the last 6 bytes of a block and is split into fragments when it doesn't
+// fit in the rest of the block. Every fragment is stored as:
+//
+// +--------------------+--------------+------------+-----------+
+// | checksum (u32 LE)  | length (u16) | type (u8)  | data      |
+// +--------------------+--------------+------------+-----------+
+//
+// where the checksum is the masked crc32c of the type and the data.
+pub mod reader;
+pub mod writer;
+
+pub const BLOCK_SIZE: usize = 32768;
+
+// checksum (4 bytes) + length (2 bytes) + type (1 byte)
+pub const HEADER_SIZE: usize = 4 + 2 + 1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RecordType {
+    // Reserved for preallocated files
+    Zero = 0,
+    Full = 1,
+    // For fragments
+    First = 2,
+    Middle = 3,
+    Last = 4,
+}
+
+impl RecordType {
+    pub fn from_u8(b: u8) -> Option<RecordType> {
+        match b {
+            0 => Some(RecordType::Zero),
+            1 => Some(RecordType::Full),
+            2 => Some(RecordType::First),
+            3 => Some(RecordType::Middle),
+            4 => Some(RecordType::Last),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,280 @@
+use super::{RecordType, BLOCK_SIZE, HEADER_SIZE};
+use crate::util::crc32;
+use crate::util::error::TinyError;
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::{ErrorKind, Read};
+
+/// Reader reads the records written by `record::Writer`.
+///
+/// A record truncated at the end of the file, which is left by a writer crashing in the
+/// middle of `add_record`, is regarded as the end of the log instead of a corruption.
+pub struct Reader<R: Read> {
+    src: R,
+    // whether the checksums of the records are verified
+    checksum: bool,
+    // the block being read
+    buffer: Vec<u8>,
+    // the read position in `buffer`
+    pos: usize,
+    // whether the last block has been read from `src`
+    eof: bool,
+    // the offsets in the log of `buffer` and of the next block to read
+    block_start: u64,
+    next_block_start: u64,
+    // the offset in the log right after the last record read
+    last_record_end: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(src: R, checksum: bool) -> Self {
+        Self::with_offset(src, checksum, 0)
+    }
+
+    /// Create a reader of the records starting at `offset` of the log, where `src` is
+    /// positioned. `offset` must be the end of a record, e.g. `last_record_end` of a
+    /// previous reader, so that a log still being written can be read incrementally.
+    pub fn with_offset(src: R, checksum: bool, offset: u64) -> Self {
+        Reader {
+            src,
+            checksum,
+            buffer: Vec::with_capacity(BLOCK_SIZE),
+            pos: 0,
+            eof: false,
+            block_start: offset,
+            next_block_start: offset,
+            last_record_end: offset,
+        }
+    }
+
+    /// Returns the offset in the log right after the last record read. A record
+    /// truncated at the end of the log is not counted as read.
+    #[inline]
+    pub fn last_record_end(&self) -> u64 {
+        self.last_record_end
+    }
+
+    /// Reads the next record. Returns `None` when reaching the end of the log.
+    pub fn read_record(&mut self) -> Result<Option<Vec<u8>>, TinyError> {
+        let mut record: Option<Vec<u8>> = None;
+        loop {
+            let (t, data) = match self.read_physical_record()? {
+                Some(r) => r,
+                None => return Ok(None),
+            };
+            match (t, record.as_mut()) {
+                (RecordType::Full, None) => {
+                    self.last_record_end = self.block_start + self.pos as u64;
+                    return Ok(Some(data));
+                }
+                (RecordType::First, None) => record = Some(data),
+                (RecordType::Middle, Some(r)) => r.extend_from_slice(&data),
+                (RecordType::Last, Some(r)) => {
+                    r.extend_from_slice(&data);
+                    self.last_record_end = self.block_start + self.pos as u64;
+                    return Ok(record);
+                }
+                (t, _) => {
+                    return Err(TinyError::Corruption(format!(
+                        "[log] unexpected {:?} record{}",
+                        t,
+                        if record.is_some() {
+                            " in the middle of a fragmented record"
+                        } else {
+                            " without a beginning"
+                        }
+                    )))
+                }
+            }
+        }
+    }
+
+    fn read_physical_record(&mut self) -> Result<Option<(RecordType, Vec<u8>)>, TinyError> {
+        loop {
+            if self.buffer.len() - self.pos < HEADER_SIZE {
+                if self.eof {
+                    return Ok(None);
+                }
+                // skip the trailer of the block and read the next one
+                self.read_block()?;
+                continue;
+            }
+            let header = &self.buffer[self.pos..self.pos + HEADER_SIZE];
+            let length = LittleEndian::read_u16(&header[4..6]) as usize;
+            let type_byte = header[6];
+            if self.pos + HEADER_SIZE + length > self.buffer.len() {
+                if self.eof {
+                    // the writer died in the middle of writing the record
+                    return Ok(None);
+                }
+                return Err(TinyError::Corruption(format!(
+                    "[log] bad record length {}",
+                    length
+                )));
+            }
+            if type_byte == RecordType::Zero as u8 && length == 0 {
+                // skip the zeros of the preallocated file area
+                self.pos = self.buffer.len();
+                continue;
+            }
+            let data = &self.buffer[self.pos + HEADER_SIZE..self.pos + HEADER_SIZE + length];
+            if self.checksum {
+                let expected = crc32::unmask(LittleEndian::read_u32(&header[..4]));
+                let actual = crc32::extend(crc32::value(&[type_byte]), data);
+                if actual != expected {
+                    return Err(TinyError::Corruption(format!(
+                        "[log] record checksum mismatch: expected {:#x} but got {:#x}",
+                        expected, actual
+                    )));
+                }
+            }
+            let t = RecordType::from_u8(type_byte).ok_or_else(|| {
+                TinyError::Corruption(format!("[log] unknown record type {}", type_byte))
+            })?;
+            let data = data.to_vec();
+            self.pos += HEADER_SIZE + length;
+            return Ok(Some((t, data)));
+        }
+    }
+
+    fn read_block(&mut self) -> Result<(), TinyError> {
+        // the first block is partial when starting in the middle of the log
+        let block_size = BLOCK_SIZE - (self.next_block_start % BLOCK_SIZE as u64) as usize;
+        self.buffer.resize(block_size, 0);
+        self.pos = 0;
+        self.block_start = self.next_block_start;
+        let mut n = 0;
+        while n < block_size {
+            match self.src.read(&mut self.buffer[n..]) {
+                Ok(0) => break,
+                Ok(read) => n += read,
+                Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(TinyError::IOError(e)),
+            }
+        }
+        self.buffer.truncate(n);
+        self.next_block_start += n as u64;
+        if n < block_size {
+            self.eof = true;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::writer::Writer;
+    use super::*;
+
+    fn big_string(partial: &str, n: usize) -> Vec<u8> {
+        partial.as_bytes().iter().cycle().take(n).cloned().collect()
+    }
+
+    fn write_records(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut writer = Writer::new(vec![]);
+        for r in records {
+            writer.add_record(r).unwrap();
+        }
+        writer.into_inner()
+    }
+
+    fn read_all(data: &[u8], checksum: bool) -> Result<Vec<Vec<u8>>, TinyError> {
+        let mut reader = Reader::new(data, checksum);
+        let mut records = vec![];
+        while let Some(r) = reader.read_record()? {
+            records.push(r);
+        }
+        Ok(records)
+    }
+
+    #[test]
+    fn test_read_write() {
+        let records = vec![
+            b"foo".to_vec(),
+            b"bar".to_vec(),
+            vec![],
+            big_string("small", 10000),
+            big_string("medium", 50000),
+            big_string("large", 100000),
+            b"xxxx".to_vec(),
+        ];
+        let data = write_records(&records);
+        assert_eq!(read_all(&data, true).unwrap(), records);
+    }
+
+    #[test]
+    fn test_marginal_trailer() {
+        // make a trailer that is exactly the same length as an empty record
+        let n = BLOCK_SIZE - 2 * HEADER_SIZE;
+        for extra in 0..3 {
+            let records = vec![big_string("foo", n + extra), vec![], b"bar".to_vec()];
+            let data = write_records(&records);
+            assert_eq!(read_all(&data, true).unwrap(), records);
+        }
+    }
+
+    #[test]
+    fn test_append_to_existing() {
+        let first = write_records(&[big_string("a", BLOCK_SIZE - 3)]);
+        let mut writer = Writer::with_offset(first.clone(), first.len() as u64);
+        writer.add_record(b"second").unwrap();
+        // including the trailer padded
+        let size = writer.size();
+        let data = writer.into_inner();
+        assert_eq!(size, data.len() as u64);
+        assert_eq!(
+            read_all(&data, true).unwrap(),
+            vec![big_string("a", BLOCK_SIZE - 3), b"second".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_truncated_tail() {
+        let records = vec![b"foo".to_vec(), big_string("bar", 40000)];
+        let data = write_records(&records);
+        let lens = (0..HEADER_SIZE + 5)
+            .chain((0..data.len()).step_by(997))
+            .chain(BLOCK_SIZE - 3..BLOCK_SIZE + HEADER_SIZE + 3)
+            .chain(data.len() - 3..data.len());
+        for len in lens {
+            let read = read_all(&data[..len], true).unwrap();
+            assert!(read.len() <= 1, "truncated at {}", len);
+            if len >= HEADER_SIZE + 3 {
+                assert_eq!(read, vec![b"foo".to_vec()]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_incrementally() {
+        let records: Vec<Vec<u8>> = (0..20)
+            .map(|i| big_string(&i.to_string(), i * 4000))
+            .collect();
+        let data = write_records(&records);
+        // the log grows in random steps and every reader resumes where the last one stopped
+        let mut offset = 0;
+        let mut read = vec![];
+        let mut len = 0;
+        while len < data.len() {
+            len = (len + 7919).min(data.len());
+            let mut reader = Reader::with_offset(&data[offset as usize..len], true, offset);
+            while let Some(r) = reader.read_record().unwrap() {
+                read.push(r);
+            }
+            offset = reader.last_record_end();
+        }
+        assert_eq!(offset, data.len() as u64);
+        assert_eq!(read, records);
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        let mut data = write_records(&[b"foo".to_vec()]);
+        data[HEADER_SIZE] ^= 0x1;
+        match read_all(&data, true) {
+            Err(TinyError::Corruption(_)) => {}
+            r => panic!("expect corruption but got {:?}", r),
+        }
+        assert_eq!(read_all(&data, false).unwrap(), vec![b"goo".to_vec()]);
+    }
+}
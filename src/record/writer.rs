@@ -0,0 +1,106 @@
+use super::{RecordType, BLOCK_SIZE, HEADER_SIZE};
+use crate::util::crc32;
+use crate::util::error::TinyError;
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::io::Write;
+
+/// Writer appends records to a log file in the format described in `record`.
+pub struct Writer<W: Write> {
+    dest: W,
+    // current offset in block
+    block_offset: usize,
+    // the size of the log including the data written before the writer is created
+    size: u64,
+}
+
+impl<W: Write> Writer<W> {
+    /// Create a writer that will append data to `dest`, which must be initially empty
+    pub fn new(dest: W) -> Self {
+        Self::with_offset(dest, 0)
+    }
+
+    /// Create a writer that will append data to `dest`, which has `dest_len` bytes already
+    pub fn with_offset(dest: W, dest_len: u64) -> Self {
+        Writer {
+            dest,
+            block_offset: (dest_len % BLOCK_SIZE as u64) as usize,
+            size: dest_len,
+        }
+    }
+
+    pub fn add_record(&mut self, data: &[u8]) -> Result<(), TinyError> {
+        let mut left = data;
+        let mut begin = true;
+        // Fragment the record if necessary and emit it. Note that if `data`
+        // is empty, we still want to iterate once to emit a single
+        // zero-length record
+        loop {
+            let leftover = BLOCK_SIZE - self.block_offset;
+            if leftover < HEADER_SIZE {
+                // Switch to a new block
+                if leftover > 0 {
+                    // Fill the trailer with zeros
+                    self.dest.write_all(&[0u8; HEADER_SIZE - 1][..leftover])?;
+                    self.size += leftover as u64;
+                }
+                self.block_offset = 0;
+            }
+            let avail = BLOCK_SIZE - self.block_offset - HEADER_SIZE;
+            let fragment_len = left.len().min(avail);
+            let end = fragment_len == left.len();
+            let record_type = match (begin, end) {
+                (true, true) => RecordType::Full,
+                (true, false) => RecordType::First,
+                (false, true) => RecordType::Last,
+                (false, false) => RecordType::Middle,
+            };
+            self.emit_physical_record(record_type, &left[..fragment_len])?;
+            left = &left[fragment_len..];
+            begin = false;
+            if end {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Flushes the buffered data to the underlying destination
+    pub fn flush(&mut self) -> Result<(), TinyError> {
+        self.dest.flush()?;
+        Ok(())
+    }
+
+    /// Returns the size of the log, i.e. the end of the last record added
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.dest
+    }
+
+    #[inline]
+    pub fn into_inner(self) -> W {
+        self.dest
+    }
+
+    fn emit_physical_record(&mut self, t: RecordType, data: &[u8]) -> Result<(), TinyError> {
+        invarint!(
+            data.len() <= 0xffff,
+            "[log] fragment length {} must fit in 2 bytes",
+            data.len()
+        );
+        let mut header = [0u8; HEADER_SIZE];
+        let crc = crc32::extend(crc32::value(&[t as u8]), data);
+        LittleEndian::write_u32(&mut header[..4], crc32::mask(crc));
+        LittleEndian::write_u16(&mut header[4..6], data.len() as u16);
+        header[6] = t as u8;
+        self.dest.write_all(&header)?;
+        self.dest.write_all(data)?;
+        self.block_offset += HEADER_SIZE + data.len();
+        self.size += (HEADER_SIZE + data.len()) as u64;
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+use super::block_builder::HASH_INDEX_FLAG;
+use super::data_block_hash_index::{DataBlockHashIndex, COLLISION, NO_ENTRY};
+use super::format::BlockContents;
+use crate::util::comparator::Comparator;
+use crate::util::error::TinyError;
+use crate::util::slice::Slice;
+use crate::util::varint::read_u64;
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::cmp::Ordering;
+
+/// Block is the uncompressed contents of a block built by `BlockBuilder`.
+pub struct Block {
+    data: BlockContents,
+    // offset in data of restart array
+    restart_offset: usize,
+    num_restarts: usize,
+    hash_index: Option<DataBlockHashIndex>,
+}
+
+/// A key and the value referring to the block contents
+pub type KeyValue<'a> = (Vec<u8>, &'a [u8]);
+
+// An entry decoded from the block
+struct Entry<'a> {
+    shared: usize,
+    key_delta: &'a [u8],
+    value: &'a [u8],
+    // offset of the next entry
+    next: usize,
+}
+
+fn corruption(msg: &str) -> TinyError {
+    TinyError::Corruption(format!("[block] {}", msg))
+}
+
+impl Block {
+    pub fn new(data: Vec<u8>) -> Result<Self, TinyError> {
+        Self::from_contents(BlockContents::Owned(data))
+    }
+
+    /// Create a block from the contents which may be a view into a memory mapped file
+    pub fn from_contents(data: BlockContents) -> Result<Self, TinyError> {
+        if data.len() < 4 {
+            return Err(corruption("block is too short"));
+        }
+        let mut end = data.len() - 4;
+        let footer = LittleEndian::read_u32(&data[end..]);
+        let num_restarts = (footer & !HASH_INDEX_FLAG) as usize;
+        let hash_index = if footer & HASH_INDEX_FLAG != 0 {
+            let index = DataBlockHashIndex::new(&data, end)
+                .ok_or_else(|| corruption("bad hash index"))?;
+            end = index.offset();
+            Some(index)
+        } else {
+            None
+        };
+        if num_restarts == 0 || num_restarts > end / 4 {
+            return Err(corruption("bad restart array"));
+        }
+        Ok(Block {
+            restart_offset: end - num_restarts * 4,
+            data,
+            num_restarts,
+            hash_index,
+        })
+    }
+
+    /// Returns the size of the block contents
+    #[inline]
+    pub fn size(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns whether the block has a hash index
+    #[inline]
+    pub fn has_hash_index(&self) -> bool {
+        self.hash_index.is_some()
+    }
+
+    /// Returns the value of the entry whose key equals `key`.
+    ///
+    /// If the block has a hash index, the restart interval containing the key is found
+    /// by hashing and only that interval is scanned. Otherwise, or if the bucket of the
+    /// key has a collision, the restart points are binary searched.
+    pub fn get(&self, key: &[u8], cmp: &dyn Comparator<Slice>) -> Result<Option<&[u8]>, TinyError> {
+        let found = match self.hash_index.as_ref().map(|index| index.lookup(&self.data, key)) {
+            Some(NO_ENTRY) => return Ok(None),
+            Some(COLLISION) | None => self.seek(key, cmp)?,
+            Some(restart) if (restart as usize) < self.num_restarts => {
+                self.scan(restart as usize, key, cmp, true)?
+            }
+            Some(restart) => {
+                return Err(corruption(&format!(
+                    "hash index points to restart {} but there are only {}",
+                    restart, self.num_restarts
+                )))
+            }
+        };
+        Ok(found
+            .filter(|(k, _)| cmp.compare(&Slice::from(k), &Slice::from(key)) == Ordering::Equal)
+            .map(|(_, v)| v))
+    }
+
+    /// Returns the first entry whose key is no less than `key`, or `None` if all the keys
+    /// in the block are less than `key`.
+    pub fn seek(
+        &self,
+        key: &[u8],
+        cmp: &dyn Comparator<Slice>,
+    ) -> Result<Option<KeyValue<'_>>, TinyError> {
+        let restart = self.binary_search(key, cmp)?;
+        self.scan(restart, key, cmp, false)
+    }
+
+    /// Returns all the entries in the block in order
+    pub fn entries(&self) -> Result<Vec<KeyValue<'_>>, TinyError> {
+        let mut entries = vec![];
+        let mut offset = 0;
+        let mut current = vec![];
+        while offset < self.restart_offset {
+            let entry = self.decode_entry(offset)?;
+            if entry.shared > current.len() {
+                return Err(corruption("bad shared length"));
+            }
+            current.truncate(entry.shared);
+            current.extend_from_slice(entry.key_delta);
+            entries.push((current.clone(), entry.value));
+            offset = entry.next;
+        }
+        Ok(entries)
+    }
+
+    #[inline]
+    fn restart_point(&self, index: usize) -> usize {
+        LittleEndian::read_u32(&self.data[self.restart_offset + index * 4..]) as usize
+    }
+
+    fn decode_entry(&self, offset: usize) -> Result<Entry<'_>, TinyError> {
+        let limit = &self.data[..self.restart_offset];
+        let mut pos = offset;
+        let mut fields = [0usize; 3];
+        for f in fields.iter_mut() {
+            let (v, n) = read_u64(&limit[pos.min(limit.len())..]);
+            if n <= 0 {
+                return Err(corruption("bad entry header"));
+            }
+            *f = v as usize;
+            pos += n as usize;
+        }
+        let [shared, non_shared, value_len] = fields;
+        if limit.len() - pos < non_shared || limit.len() - pos - non_shared < value_len {
+            return Err(corruption("bad entry length"));
+        }
+        Ok(Entry {
+            shared,
+            key_delta: &limit[pos..pos + non_shared],
+            value: &limit[pos + non_shared..pos + non_shared + value_len],
+            next: pos + non_shared + value_len,
+        })
+    }
+
+    // Returns the index of the last restart point whose key is less than `key`,
+    // or 0 if there is no such restart point
+    fn binary_search(&self, key: &[u8], cmp: &dyn Comparator<Slice>) -> Result<usize, TinyError> {
+        let mut left = 0;
+        let mut right = self.num_restarts - 1;
+        while left < right {
+            let mid = left + (right - left).div_ceil(2);
+            let entry = self.decode_entry(self.restart_point(mid))?;
+            if entry.shared != 0 {
+                return Err(corruption("restart point shares key prefix"));
+            }
+            if cmp.compare(&Slice::from(entry.key_delta), &Slice::from(key)) == Ordering::Less {
+                left = mid;
+            } else {
+                right = mid - 1;
+            }
+        }
+        Ok(left)
+    }
+
+    // Scans the entries from the given restart point until reaching a key no less than
+    // `key` and returns the entry. Stops at the end of the restart interval if
+    // `within_interval` is true.
+    fn scan(
+        &self,
+        restart: usize,
+        key: &[u8],
+        cmp: &dyn Comparator<Slice>,
+        within_interval: bool,
+    ) -> Result<Option<KeyValue<'_>>, TinyError> {
+        let mut offset = self.restart_point(restart);
+        let end = if within_interval && restart + 1 < self.num_restarts {
+            self.restart_point(restart + 1)
+        } else {
+            self.restart_offset
+        };
+        let mut current = vec![];
+        while offset < end {
+            let entry = self.decode_entry(offset)?;
+            if entry.shared > current.len() {
+                return Err(corruption("bad shared length"));
+            }
+            current.truncate(entry.shared);
+            current.extend_from_slice(entry.key_delta);
+            if cmp.compare(&Slice::from(&current), &Slice::from(key)) != Ordering::Less {
+                return Ok(Some((current, entry.value)));
+            }
+            offset = entry.next;
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::block_builder::BlockBuilder;
+    use super::*;
+    use crate::util::comparator::BytewiseComparator;
+
+    fn build_block(n: usize, restart_interval: usize, hash_util_ratio: Option<f64>) -> Block {
+        let mut builder = BlockBuilder::new(restart_interval, hash_util_ratio);
+        for i in 0..n {
+            builder.add(
+                format!("key{:05}", i * 2).as_bytes(),
+                format!("value{}", i).as_bytes(),
+            );
+        }
+        Block::new(builder.finish().to_vec()).unwrap()
+    }
+
+    fn check_block(block: &Block, n: usize) {
+        let cmp = BytewiseComparator::new();
+        for i in 0..n {
+            let value = block.get(format!("key{:05}", i * 2).as_bytes(), &cmp).unwrap();
+            assert_eq!(value, Some(format!("value{}", i).as_bytes()));
+            let missing = block.get(format!("key{:05}", i * 2 + 1).as_bytes(), &cmp).unwrap();
+            assert_eq!(missing, None);
+        }
+        assert_eq!(block.get(b"", &cmp).unwrap(), None);
+        assert_eq!(block.get(b"zzz", &cmp).unwrap(), None);
+    }
+
+    #[test]
+    fn test_empty_block() {
+        let block = build_block(0, 16, None);
+        assert_eq!(block.get(b"a", &BytewiseComparator::new()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_get_with_binary_search() {
+        for interval in [1, 2, 16].iter() {
+            let block = build_block(500, *interval, None);
+            assert!(!block.has_hash_index());
+            check_block(&block, 500);
+        }
+    }
+
+    #[test]
+    fn test_get_with_hash_index() {
+        for ratio in [0.5, 0.75, 4.0].iter() {
+            let block = build_block(500, 16, Some(*ratio));
+            assert!(block.has_hash_index());
+            check_block(&block, 500);
+        }
+    }
+
+    #[test]
+    fn test_hash_index_dropped_for_too_many_restarts() {
+        let block = build_block(300, 1, Some(0.75));
+        assert!(!block.has_hash_index());
+        check_block(&block, 300);
+    }
+
+    #[test]
+    fn test_seek_and_entries() {
+        let cmp = BytewiseComparator::new();
+        let block = build_block(100, 4, None);
+        let (key, value) = block.seek(b"key00011", &cmp).unwrap().unwrap();
+        assert_eq!(key, b"key00012".to_vec());
+        assert_eq!(value, b"value6");
+        let (key, _) = block.seek(b"", &cmp).unwrap().unwrap();
+        assert_eq!(key, b"key00000".to_vec());
+        assert!(block.seek(b"key00199", &cmp).unwrap().is_none());
+
+        let entries = block.entries().unwrap();
+        assert_eq!(entries.len(), 100);
+        for (i, (key, value)) in entries.iter().enumerate() {
+            assert_eq!(key, &format!("key{:05}", i * 2).into_bytes());
+            assert_eq!(*value, format!("value{}", i).as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_corrupted_block() {
+        assert!(Block::new(vec![]).is_err());
+        assert!(Block::new(vec![0, 0, 0, 0]).is_err());
+        assert!(Block::new(vec![0, 0, 0, 0, 2, 0, 0, 0]).is_err());
+        let mut builder = BlockBuilder::new(16, None);
+        builder.add(b"key", b"value");
+        let mut data = builder.finish().to_vec();
+        // make the value length too large
+        data[2] = 100;
+        let block = Block::new(data).unwrap();
+        assert!(block.get(b"key", &BytewiseComparator::new()).is_err());
+    }
+}
@@ -0,0 +1,148 @@
+use super::data_block_hash_index::DataBlockHashIndexBuilder;
+use crate::util::varint::put_u64;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The bit of the block footer indicating the block has a hash index
+pub const HASH_INDEX_FLAG: u32 = 1 << 31;
+
+/// BlockBuilder generates blocks where keys are prefix-compressed:
+///
+/// When we store a key, we drop the prefix shared with the previous
+/// string.  This helps reduce the space requirement significantly.
+/// Furthermore, once every K keys, we do not apply the prefix
+/// compression and store the entire key.  We call this a "restart
+/// point".  The tail end of the block stores the offsets of all of the
+/// restart points, and can be used to do a binary search when looking
+/// for a particular key.  Values are stored as-is (without compression)
+/// immediately following the corresponding key.
+///
+/// An entry for a particular key-value pair has the form:
+///     shared_bytes: varint
+///     unshared_bytes: varint
+///     value_length: varint
+///     key_delta: char[unshared_bytes]
+///     value: char[value_length]
+/// shared_bytes == 0 for restart points.
+///
+/// The trailer of the block has the form:
+///     restarts: uint32[num_restarts]
+///     hash index (optional, see `DataBlockHashIndexBuilder`)
+///     footer: uint32 (num_restarts | HASH_INDEX_FLAG if the hash index exists)
+pub struct BlockBuilder {
+    block_restart_interval: usize,
+    // destination buffer
+    buffer: Vec<u8>,
+    // restart points
+    restarts: Vec<u32>,
+    // number of entries emitted since restart
+    counter: usize,
+    // has finish() been called?
+    finished: bool,
+    last_key: Vec<u8>,
+    hash_index: Option<DataBlockHashIndexBuilder>,
+}
+
+impl BlockBuilder {
+    /// Create a BlockBuilder. If `hash_util_ratio` is given, a hash index with the
+    /// ratio of keys per bucket is appended to the block.
+    pub fn new(block_restart_interval: usize, hash_util_ratio: Option<f64>) -> Self {
+        invarint!(
+            block_restart_interval >= 1,
+            "[block builder] block restart interval must be at least 1"
+        );
+        BlockBuilder {
+            block_restart_interval,
+            buffer: vec![],
+            restarts: vec![0],
+            counter: 0,
+            finished: false,
+            last_key: vec![],
+            hash_index: hash_util_ratio.map(DataBlockHashIndexBuilder::new),
+        }
+    }
+
+    /// Adds the key/value pair to the block.
+    /// REQUIRES: finish() has not been called since the last call to reset().
+    /// REQUIRES: key is larger than any previously added key
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        invarint!(!self.finished, "[block builder] add() after finish()");
+        let mut shared = 0;
+        if self.counter < self.block_restart_interval {
+            // See how much sharing to do with previous string
+            let min_len = self.last_key.len().min(key.len());
+            while shared < min_len && self.last_key[shared] == key[shared] {
+                shared += 1;
+            }
+        } else {
+            // Restart compression
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+        }
+        let non_shared = key.len() - shared;
+
+        // Add "<shared><non_shared><value_size>" to buffer
+        put_u64(&mut self.buffer, shared as u64);
+        put_u64(&mut self.buffer, non_shared as u64);
+        put_u64(&mut self.buffer, value.len() as u64);
+
+        // Add string delta to buffer followed by value
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(value);
+
+        if let Some(index) = self.hash_index.as_mut() {
+            index.add(key, self.restarts.len() - 1);
+        }
+
+        self.last_key.truncate(shared);
+        self.last_key.extend_from_slice(&key[shared..]);
+        self.counter += 1;
+    }
+
+    /// Finishes building the block and returns a slice that refers to the block contents.
+    /// The returned slice will remain valid for the lifetime of this builder or until
+    /// reset() is called.
+    pub fn finish(&mut self) -> &[u8] {
+        let mut buf = [0u8; 4];
+        for restart in self.restarts.iter() {
+            LittleEndian::write_u32(&mut buf, *restart);
+            self.buffer.extend_from_slice(&buf);
+        }
+        let mut footer = self.restarts.len() as u32;
+        if let Some(index) = self.hash_index.as_mut() {
+            if index.valid() {
+                index.finish(&mut self.buffer);
+                footer |= HASH_INDEX_FLAG;
+            }
+        }
+        LittleEndian::write_u32(&mut buf, footer);
+        self.buffer.extend_from_slice(&buf);
+        self.finished = true;
+        &self.buffer
+    }
+
+    /// Reset the contents as if the BlockBuilder was just constructed.
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.restarts.clear();
+        self.restarts.push(0);
+        self.counter = 0;
+        self.finished = false;
+        self.last_key.clear();
+        if let Some(index) = self.hash_index.as_mut() {
+            index.reset();
+        }
+    }
+
+    /// Returns an estimate of the current (uncompressed) size of the block we are building.
+    pub fn current_size_estimate(&self) -> usize {
+        // raw data buffer + restart array + restart array length
+        self.buffer.len() + self.restarts.len() * 4 + 4
+    }
+
+    /// Returns true iff no entries have been added since the last reset()
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+}
@@ -0,0 +1,40 @@
+use super::block::Block;
+use super::format::BlockHandle;
+use crate::cache::lru::LRUCache;
+use crate::util::error::TinyError;
+
+use std::sync::Arc;
+
+/// The key of a block in the block cache: (table id, offset of the block in the table file)
+pub type BlockCacheKey = (u64, u64);
+
+/// The uncompressed blocks cached in the block cache
+pub enum CachedBlock {
+    /// A data block or an index block
+    Block(Block),
+    /// A filter created by the filter policy
+    Filter(Vec<u8>),
+}
+
+/// The cache shared by the tables for their uncompressed blocks
+pub type BlockCache = LRUCache<BlockCacheKey, CachedBlock>;
+
+/// Returns the block of the table at `handle` from the cache, or reads it by `load`
+/// and inserts it into the cache if missing.
+pub fn lookup_or_load(
+    cache: &BlockCache,
+    table_id: u64,
+    handle: &BlockHandle,
+    load: &mut dyn FnMut(&BlockHandle) -> Result<CachedBlock, TinyError>,
+) -> Result<Arc<CachedBlock>, TinyError> {
+    let key = (table_id, handle.offset);
+    if let Some(cached) = cache.get(&key) {
+        return Ok(cached);
+    }
+    let block = load(handle)?;
+    let charge = match &block {
+        CachedBlock::Block(b) => b.size(),
+        CachedBlock::Filter(f) => f.len(),
+    };
+    Ok(cache.insert(key, block, charge))
+}
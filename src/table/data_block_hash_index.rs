@@ -0,0 +1,180 @@
+use crate::util::hash::hash;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The bucket doesn't contain any key.
+pub const NO_ENTRY: u8 = 255;
+/// The bucket contains keys from different restart intervals.
+pub const COLLISION: u8 = 254;
+/// The restart intervals beyond this can not be indexed since the bucket values
+/// are stored in 1 byte.
+pub const MAX_RESTART_SUPPORTED: usize = 253;
+
+const HASH_SEED: u32 = 0x2f51_7b3d;
+
+/// The size of `num_buckets` (u16) stored after the buckets
+pub const NUM_BUCKETS_SIZE: usize = 2;
+
+/// DataBlockHashIndexBuilder builds a hash table mapping every key of a data block
+/// to the restart interval containing it, so that a point lookup can jump to the
+/// interval directly instead of binary searching the restart points:
+///
+/// ```text
+/// +-----------------------------+--------------------+
+/// | buckets (u8 * num_buckets)  | num_buckets (u16)  |
+/// +-----------------------------+--------------------+
+/// ```
+///
+/// A bucket holds the restart index of the keys hashed into it, `NO_ENTRY` if there is no
+/// such key, or `COLLISION` if the keys come from different restart intervals.
+pub struct DataBlockHashIndexBuilder {
+    // the number of keys per bucket
+    util_ratio: f64,
+    // (key hash, restart index)
+    entries: Vec<(u32, u8)>,
+    valid: bool,
+}
+
+impl DataBlockHashIndexBuilder {
+    pub fn new(util_ratio: f64) -> Self {
+        let positive = util_ratio > 0.0;
+        invarint!(
+            positive,
+            "[data block hash index] util ratio must be positive but got {}",
+            util_ratio
+        );
+        DataBlockHashIndexBuilder {
+            util_ratio,
+            entries: vec![],
+            valid: true,
+        }
+    }
+
+    pub fn add(&mut self, key: &[u8], restart_index: usize) {
+        if restart_index > MAX_RESTART_SUPPORTED {
+            self.valid = false;
+            return;
+        }
+        self.entries.push((hash(key, HASH_SEED), restart_index as u8));
+    }
+
+    /// Returns whether the index can be built. The index is dropped if the block
+    /// has too many restart intervals.
+    #[inline]
+    pub fn valid(&self) -> bool {
+        self.valid
+    }
+
+    /// Appends the hash index to `dst`
+    pub fn finish(&mut self, dst: &mut Vec<u8>) {
+        let num_buckets = ((self.entries.len() as f64 / self.util_ratio) as usize)
+            .max(1)
+            .min(u16::MAX as usize);
+        let mut buckets = vec![NO_ENTRY; num_buckets];
+        for (h, restart_index) in self.entries.iter() {
+            let bucket = &mut buckets[*h as usize % num_buckets];
+            if *bucket == NO_ENTRY {
+                *bucket = *restart_index;
+            } else if *bucket != *restart_index {
+                *bucket = COLLISION;
+            }
+        }
+        dst.extend_from_slice(&buckets);
+        let mut buf = [0u8; NUM_BUCKETS_SIZE];
+        LittleEndian::write_u16(&mut buf, num_buckets as u16);
+        dst.extend_from_slice(&buf);
+    }
+
+    pub fn reset(&mut self) {
+        self.entries.clear();
+        self.valid = true;
+    }
+}
+
+/// DataBlockHashIndex reads the hash index written by `DataBlockHashIndexBuilder`.
+pub struct DataBlockHashIndex {
+    // the offset of the buckets in the block
+    offset: usize,
+    num_buckets: usize,
+}
+
+impl DataBlockHashIndex {
+    /// Parses the hash index ending at `data[..end]`. Returns `None` if the index is malformed.
+    pub fn new(data: &[u8], end: usize) -> Option<Self> {
+        if end < NUM_BUCKETS_SIZE || end > data.len() {
+            return None;
+        }
+        let num_buckets = LittleEndian::read_u16(&data[end - NUM_BUCKETS_SIZE..end]) as usize;
+        if num_buckets == 0 || num_buckets + NUM_BUCKETS_SIZE > end {
+            return None;
+        }
+        Some(DataBlockHashIndex {
+            offset: end - NUM_BUCKETS_SIZE - num_buckets,
+            num_buckets,
+        })
+    }
+
+    /// The offset in the block where the hash index begins
+    #[inline]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the restart index of the interval `key` might be in, `NO_ENTRY` if the key
+    /// is not in the block, or `COLLISION` if the caller has to binary search.
+    pub fn lookup(&self, data: &[u8], key: &[u8]) -> u8 {
+        let bucket = hash(key, HASH_SEED) as usize % self.num_buckets;
+        data[self.offset + bucket]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_and_lookup() {
+        let mut builder = DataBlockHashIndexBuilder::new(0.75);
+        let keys: Vec<String> = (0..100).map(|i| format!("key{:03}", i)).collect();
+        for (i, key) in keys.iter().enumerate() {
+            builder.add(key.as_bytes(), i / 16);
+        }
+        assert!(builder.valid());
+        let mut data = b"prefix".to_vec();
+        builder.finish(&mut data);
+
+        let index = DataBlockHashIndex::new(&data, data.len()).unwrap();
+        assert_eq!(index.offset(), 6);
+        for (i, key) in keys.iter().enumerate() {
+            let r = index.lookup(&data, key.as_bytes());
+            assert!(r == (i / 16) as u8 || r == COLLISION, "key {}", key);
+        }
+        let mut no_entry = 0;
+        for i in 100..200 {
+            let r = index.lookup(&data, format!("key{:03}", i).as_bytes());
+            if r == NO_ENTRY {
+                no_entry += 1;
+            }
+        }
+        assert!(no_entry > 0);
+    }
+
+    #[test]
+    fn test_too_many_restarts() {
+        let mut builder = DataBlockHashIndexBuilder::new(1.0);
+        builder.add(b"a", MAX_RESTART_SUPPORTED);
+        assert!(builder.valid());
+        builder.add(b"b", MAX_RESTART_SUPPORTED + 1);
+        assert!(!builder.valid());
+        builder.reset();
+        assert!(builder.valid());
+    }
+
+    #[test]
+    fn test_malformed_index() {
+        assert!(DataBlockHashIndex::new(&[], 0).is_none());
+        assert!(DataBlockHashIndex::new(&[0, 0], 2).is_none());
+        assert!(DataBlockHashIndex::new(&[1, 10, 0], 3).is_none());
+        assert!(DataBlockHashIndex::new(&[1, 1, 0], 3).is_some());
+    }
+}
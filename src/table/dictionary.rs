@@ -0,0 +1,159 @@
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// The name of the meta block storing the compression dictionary of a table file.
+pub const COMPRESSION_DICT_BLOCK_NAME: &str = "tinydb.compression_dict";
+
+// the length of the substrings whose frequencies are counted
+const DMER_SIZE: usize = 8;
+// the length of the candidate segments the dictionary is made of
+const SEGMENT_SIZE: usize = 32;
+
+/// DictionarySampler collects the key/value pairs added to a table file until
+/// `max_sample_bytes` is reached. The table builder buffers the data blocks in the
+/// meantime, trains the dictionary with `train_dictionary` once the sampler is full
+/// (or the file is finished), and then compresses every data block of the file with it.
+pub struct DictionarySampler {
+    max_sample_bytes: usize,
+    sampled_bytes: usize,
+    samples: Vec<Vec<u8>>,
+}
+
+impl DictionarySampler {
+    pub fn new(max_sample_bytes: usize) -> Self {
+        DictionarySampler {
+            max_sample_bytes,
+            sampled_bytes: 0,
+            samples: vec![],
+        }
+    }
+
+    /// Samples the key/value pair if there is still room
+    pub fn add(&mut self, key: &[u8], value: &[u8]) {
+        let size = key.len() + value.len();
+        if self.sampled_bytes + size > self.max_sample_bytes {
+            return;
+        }
+        self.samples.push([key, value].concat());
+        self.sampled_bytes += size;
+    }
+
+    /// Returns whether the sampler has collected enough data to train a dictionary
+    pub fn is_full(&self) -> bool {
+        self.sampled_bytes >= self.max_sample_bytes
+    }
+
+    #[inline]
+    pub fn samples(&self) -> &[Vec<u8>] {
+        &self.samples
+    }
+}
+
+/// Trains a dictionary of at most `max_dict_bytes` from the given samples.
+///
+/// Every sample is split into segments which are scored by how many other samples share
+/// their substrings. The segments are picked greedily by score, with the substrings covered
+/// by the picked segments no longer counted, and the best segments are put at the end of
+/// the dictionary where they are the cheapest to refer to.
+pub fn train_dictionary(samples: &[Vec<u8>], max_dict_bytes: usize) -> Vec<u8> {
+    // dmer -> the number of samples containing it
+    let mut freqs: HashMap<&[u8], usize> = HashMap::new();
+    for sample in samples {
+        let dmers: HashSet<&[u8]> = sample.windows(DMER_SIZE).collect();
+        for dmer in dmers {
+            *freqs.entry(dmer).or_insert(0) += 1;
+        }
+    }
+
+    let score = |freqs: &HashMap<&[u8], usize>, segment: &[u8]| -> usize {
+        let dmers: HashSet<&[u8]> = segment.windows(DMER_SIZE).collect();
+        // a dmer only seen in one sample is useless
+        dmers.iter().map(|d| freqs[d].saturating_sub(1)).sum()
+    };
+
+    // (score, sample index, segment offset)
+    let mut candidates = BinaryHeap::new();
+    for (i, sample) in samples.iter().enumerate() {
+        for offset in (0..sample.len()).step_by(SEGMENT_SIZE) {
+            let segment = &sample[offset..sample.len().min(offset + SEGMENT_SIZE)];
+            let s = score(&freqs, segment);
+            if s > 0 {
+                candidates.push((s, i, offset));
+            }
+        }
+    }
+
+    let mut picked: Vec<&[u8]> = vec![];
+    let mut dict_size = 0;
+    while let Some((s, i, offset)) = candidates.pop() {
+        let sample = &samples[i];
+        let segment = &sample[offset..sample.len().min(offset + SEGMENT_SIZE)];
+        if dict_size + segment.len() > max_dict_bytes {
+            continue;
+        }
+        // the scores get stale once other segments are picked
+        let current = score(&freqs, segment);
+        if current == 0 {
+            continue;
+        }
+        if current < s {
+            candidates.push((current, i, offset));
+            continue;
+        }
+        for dmer in segment.windows(DMER_SIZE) {
+            freqs.insert(dmer, 0);
+        }
+        picked.push(segment);
+        dict_size += segment.len();
+    }
+    picked.reverse();
+    picked.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::compression::{CompressionType, CompressorRegistry};
+
+    fn json_doc(i: usize) -> Vec<u8> {
+        format!(
+            "{{\"id\":{},\"type\":\"session\",\"status\":\"active\",\"region\":\"eu-west-{}\",\"agent\":\"Mozilla/5.0\"}}",
+            i,
+            i % 3
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_sampler() {
+        let mut sampler = DictionarySampler::new(10);
+        sampler.add(b"k1", b"v1");
+        sampler.add(b"k2", b"value2");
+        assert!(!sampler.is_full());
+        sampler.add(b"k3", b"v3");
+        assert_eq!(sampler.samples().len(), 2);
+        sampler.add(b"k", b"v");
+        assert!(sampler.is_full());
+        assert_eq!(sampler.samples().len(), 3);
+    }
+
+    #[test]
+    fn test_train_dictionary() {
+        assert!(train_dictionary(&[], 1024).is_empty());
+        let mut sampler = DictionarySampler::new(1 << 20);
+        for i in 0..100 {
+            sampler.add(format!("key{:05}", i).as_bytes(), &json_doc(i));
+        }
+        let dict = train_dictionary(sampler.samples(), 256);
+        assert!(!dict.is_empty());
+        assert!(dict.len() <= 256);
+
+        let registry = CompressorRegistry::default();
+        let c = registry.get(CompressionType::LZ4).unwrap();
+        let doc = json_doc(1000);
+        let (mut plain, mut with_dict) = (vec![], vec![]);
+        c.compress(&doc, &mut plain);
+        c.compress_with_dict(&doc, &dict, &mut with_dict);
+        assert!(with_dict.len() < plain.len());
+        assert_eq!(c.decompress_with_dict(&with_dict, &dict).unwrap(), doc);
+    }
+}
@@ -0,0 +1,361 @@
+use crate::util::compression::{CompressionType, Compressor, CompressorRegistry};
+use crate::util::crc32;
+use crate::util::error::TinyError;
+use crate::util::mmap::MmapSlice;
+use crate::util::varint::{put_u64, read_u64};
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::ops::Deref;
+
+/// 1-byte compression type + 4-byte masked crc32c
+pub const BLOCK_TRAILER_SIZE: usize = 5;
+
+/// A block is compressed only if it shrinks by at least 1/8.
+/// Otherwise the raw contents are stored to save the decompression cost on reading.
+const MIN_COMPRESSION_RATIO_DENOMINATOR: usize = 8;
+
+/// The maximum encoding length of a BlockHandle
+pub const MAX_BLOCK_HANDLE_ENCODED_LENGTH: usize = 10 + 10;
+
+/// BlockHandle is a pointer to the extent of a file that stores a data
+/// block or a meta block.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BlockHandle {
+    /// The offset of the block in the file
+    pub offset: u64,
+    /// The size of the stored block, excluding the trailer
+    pub size: u64,
+}
+
+impl BlockHandle {
+    pub fn new(offset: u64, size: u64) -> Self {
+        BlockHandle { offset, size }
+    }
+
+    /// Appends the varint encoded offset and size to `dst`
+    pub fn encode_to(&self, dst: &mut Vec<u8>) {
+        put_u64(dst, self.offset);
+        put_u64(dst, self.size);
+    }
+
+    /// Decodes a BlockHandle from the beginning of `src` and returns it with
+    /// the number of bytes read
+    pub fn decode_from(src: &[u8]) -> Result<(Self, usize), TinyError> {
+        let (offset, n) = read_u64(src);
+        if n > 0 {
+            let (size, m) = read_u64(&src[n as usize..]);
+            if m > 0 {
+                return Ok((BlockHandle { offset, size }, (n + m) as usize));
+            }
+        }
+        Err(TinyError::Corruption("[table] bad block handle".to_owned()))
+    }
+}
+
+/// The magic number at the end of every table file
+pub const TABLE_MAGIC_NUMBER: u64 = 0x7469_6e79_6462_5342;
+
+/// The footer is made of two padded block handles and the magic number
+pub const FOOTER_ENCODED_LENGTH: usize = 2 * MAX_BLOCK_HANDLE_ENCODED_LENGTH + 8;
+
+/// Footer is stored at the end of every table file:
+///
+/// ```text
+/// +------------------+---------------+-----------------+---------------------------+
+/// | metaindex handle | index handle  | zero padding to | magic number (fixed64 LE) |
+/// |                  |               | 40 bytes        |                           |
+/// +------------------+---------------+-----------------+---------------------------+
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Footer {
+    /// The block mapping the names of the meta blocks to their handles
+    pub metaindex_handle: BlockHandle,
+    /// The block mapping the last key of every data block to its handle
+    pub index_handle: BlockHandle,
+}
+
+impl Footer {
+    pub fn encode_to(&self, dst: &mut Vec<u8>) {
+        let start = dst.len();
+        self.metaindex_handle.encode_to(dst);
+        self.index_handle.encode_to(dst);
+        dst.resize(start + 2 * MAX_BLOCK_HANDLE_ENCODED_LENGTH, 0);
+        let mut buf = [0u8; 8];
+        LittleEndian::write_u64(&mut buf, TABLE_MAGIC_NUMBER);
+        dst.extend_from_slice(&buf);
+    }
+
+    /// Decodes the footer from `src` of `FOOTER_ENCODED_LENGTH` bytes
+    pub fn decode_from(src: &[u8]) -> Result<Self, TinyError> {
+        if src.len() != FOOTER_ENCODED_LENGTH
+            || LittleEndian::read_u64(&src[FOOTER_ENCODED_LENGTH - 8..]) != TABLE_MAGIC_NUMBER
+        {
+            return Err(TinyError::Corruption(
+                "[table] not a table file (bad magic number)".to_owned(),
+            ));
+        }
+        let (metaindex_handle, n) = BlockHandle::decode_from(src)?;
+        let (index_handle, _) = BlockHandle::decode_from(&src[n..])?;
+        Ok(Footer {
+            metaindex_handle,
+            index_handle,
+        })
+    }
+}
+
+/// Encodes the block contents into `dst` in the layout of the table files:
+///
+/// ```text
+/// +-------------------------+------------------------+---------------------+
+/// | contents (maybe         | compression type (u8)  | crc32c (fixed32 LE) |
+/// | compressed)             |                        |                     |
+/// +-------------------------+------------------------+---------------------+
+/// ```
+///
+/// The masked crc32c covers both the contents and the compression type.
+/// `dict` is the compression dictionary shared by the data blocks of the table file,
+/// which is empty if the table doesn't have one.
+/// The block is compressed by the codec of `compression_type` in `compressors`, and stored
+/// uncompressed if the compression doesn't save enough space or there's no such codec.
+/// Returns the compression type actually applied.
+pub fn write_block(
+    raw: &[u8],
+    compression_type: CompressionType,
+    compressors: &CompressorRegistry,
+    dict: &[u8],
+    dst: &mut Vec<u8>,
+) -> CompressionType {
+    let start = dst.len();
+    let mut compression = CompressionType::NoCompression;
+    if let Some(c) = compressors.get(compression_type) {
+        c.compress_with_dict(raw, dict, dst);
+        let compressed_len = dst.len() - start;
+        if compressed_len < raw.len() - raw.len() / MIN_COMPRESSION_RATIO_DENOMINATOR {
+            compression = compression_type;
+        } else {
+            dst.truncate(start);
+        }
+    }
+    if compression == CompressionType::NoCompression {
+        dst.extend_from_slice(raw);
+    }
+    dst.push(compression.to_u8());
+    let crc = crc32::mask(crc32::value(&dst[start..]));
+    let mut buf = [0u8; 4];
+    LittleEndian::write_u32(&mut buf, crc);
+    dst.extend_from_slice(&buf);
+    compression
+}
+
+/// The contents of a block, either owned or viewed in a memory mapped table file
+#[derive(Clone)]
+pub enum BlockContents {
+    Owned(Vec<u8>),
+    Mapped(MmapSlice),
+}
+
+impl BlockContents {
+    // Returns the prefix of `len` bytes without copying the mapped contents
+    fn truncate(self, len: usize) -> Result<Self, TinyError> {
+        match self {
+            BlockContents::Owned(mut v) => {
+                v.truncate(len);
+                Ok(BlockContents::Owned(v))
+            }
+            BlockContents::Mapped(m) => Ok(BlockContents::Mapped(m.sub(0, len)?)),
+        }
+    }
+}
+
+impl Deref for BlockContents {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            BlockContents::Owned(v) => v,
+            BlockContents::Mapped(m) => m,
+        }
+    }
+}
+
+/// Decodes a block with its trailer written by `write_block` with the same dictionary and
+/// returns the uncompressed contents, which are decompressed by the codec in `compressors`.
+/// The checksum is checked if `verify_checksum` is true.
+/// Returns `TinyError::Corruption` if the block is malformed or its codec isn't registered.
+pub fn read_block(
+    data: &[u8],
+    compressors: &CompressorRegistry,
+    dict: &[u8],
+    verify_checksum: bool,
+) -> Result<Vec<u8>, TinyError> {
+    let (contents_len, compressor) = check_trailer(data, compressors, verify_checksum)?;
+    match compressor {
+        Some(c) => c.decompress_with_dict(&data[..contents_len], dict),
+        None => Ok(data[..contents_len].to_vec()),
+    }
+}
+
+/// Like `read_block` but takes the stored block as `BlockContents`. An uncompressed
+/// block is returned without copying, so a block read from a memory mapped
+/// table file stays a view into the mapping.
+pub fn decode_block(
+    data: BlockContents,
+    compressors: &CompressorRegistry,
+    dict: &[u8],
+    verify_checksum: bool,
+) -> Result<BlockContents, TinyError> {
+    let (contents_len, compressor) = check_trailer(&data, compressors, verify_checksum)?;
+    match compressor {
+        Some(c) => c
+            .decompress_with_dict(&data[..contents_len], dict)
+            .map(BlockContents::Owned),
+        None => data.truncate(contents_len),
+    }
+}
+
+// Checks the trailer of the stored block and returns the length of the contents and
+// the codec to decompress them
+fn check_trailer<'a>(
+    data: &[u8],
+    compressors: &'a CompressorRegistry,
+    verify_checksum: bool,
+) -> Result<(usize, Option<&'a dyn Compressor>), TinyError> {
+    if data.len() < BLOCK_TRAILER_SIZE {
+        return Err(TinyError::Corruption(
+            "[table] block is too short to contain a trailer".to_owned(),
+        ));
+    }
+    let contents_len = data.len() - BLOCK_TRAILER_SIZE;
+    let trailer = &data[contents_len..];
+    if verify_checksum {
+        let expected = crc32::unmask(LittleEndian::read_u32(&trailer[1..]));
+        let actual = crc32::value(&data[..data.len() - 4]);
+        if actual != expected {
+            return Err(TinyError::Corruption(format!(
+                "[table] block checksum mismatch: expected {:#x} but got {:#x}",
+                expected, actual
+            )));
+        }
+    }
+    match CompressionType::from_u8(trailer[0]) {
+        CompressionType::NoCompression => Ok((contents_len, None)),
+        t => match compressors.get(t) {
+            Some(c) => Ok((contents_len, Some(c))),
+            None => Err(TinyError::Corruption(format!(
+                "[table] unknown block compression type {}",
+                trailer[0]
+            ))),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::compression::Lz4Compressor;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_block_handle() {
+        for (offset, size) in [(0, 0), (1, 2), (u64::MAX, 1 << 40)].iter() {
+            let handle = BlockHandle::new(*offset, *size);
+            let mut buf = vec![];
+            handle.encode_to(&mut buf);
+            assert!(buf.len() <= MAX_BLOCK_HANDLE_ENCODED_LENGTH);
+            buf.push(7);
+            assert_eq!(
+                BlockHandle::decode_from(&buf).unwrap(),
+                (handle, buf.len() - 1)
+            );
+            assert!(BlockHandle::decode_from(&buf[..buf.len() - 2]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_footer() {
+        let footer = Footer {
+            metaindex_handle: BlockHandle::new(u64::MAX, u64::MAX),
+            index_handle: BlockHandle::new(100, 5),
+        };
+        let mut buf = vec![];
+        footer.encode_to(&mut buf);
+        assert_eq!(buf.len(), FOOTER_ENCODED_LENGTH);
+        assert_eq!(Footer::decode_from(&buf).unwrap(), footer);
+        assert!(Footer::decode_from(&buf[1..]).is_err());
+        buf[FOOTER_ENCODED_LENGTH - 1] ^= 1;
+        assert!(Footer::decode_from(&buf).is_err());
+    }
+
+    #[test]
+    fn test_write_and_read_block() {
+        let compressors = CompressorRegistry::default();
+        let compressible = b"key00001value00001".repeat(50);
+        let mut buf = vec![];
+        let t = write_block(&compressible, CompressionType::LZ4, &compressors, &[], &mut buf);
+        assert_eq!(t, CompressionType::LZ4);
+        assert!(buf.len() < compressible.len());
+        assert_eq!(read_block(&buf, &compressors, &[], true).unwrap(), compressible);
+
+        let mut buf = vec![];
+        let t = write_block(&compressible, CompressionType::NoCompression, &compressors, &[], &mut buf);
+        assert_eq!(t, CompressionType::NoCompression);
+        assert_eq!(buf.len(), compressible.len() + BLOCK_TRAILER_SIZE);
+        assert_eq!(read_block(&buf, &compressors, &[], true).unwrap(), compressible);
+    }
+
+    #[test]
+    fn test_fallback_to_raw() {
+        let compressors = CompressorRegistry::default();
+        let incompressible: Vec<u8> = (0..=255u8).collect();
+        let mut buf = vec![9u8];
+        let t = write_block(&incompressible, CompressionType::LZ4, &compressors, &[], &mut buf);
+        assert_eq!(t, CompressionType::NoCompression);
+        assert_eq!(buf[0], 9);
+        assert_eq!(read_block(&buf[1..], &compressors, &[], true).unwrap(), incompressible);
+    }
+
+    #[test]
+    fn test_read_corrupted_block() {
+        let compressors = CompressorRegistry::default();
+        assert!(read_block(&[], &compressors, &[], false).is_err());
+        assert!(read_block(&[1, 2, 3, 200, 0, 0, 0, 0], &compressors, &[], false).is_err());
+        assert!(read_block(&[1, 2, 3, CompressionType::LZ4.to_u8(), 0, 0, 0, 0], &compressors, &[], false).is_err());
+
+        // the codec of a user-defined type isn't registered for the read
+        let mut custom = CompressorRegistry::default();
+        custom.register(CompressionType::Custom(100), Arc::new(Lz4Compressor {})).unwrap();
+        let raw = b"key00001value00001".repeat(50);
+        let mut buf = vec![];
+        assert_eq!(
+            write_block(&raw, CompressionType::Custom(100), &custom, &[], &mut buf),
+            CompressionType::Custom(100)
+        );
+        assert_eq!(read_block(&buf, &custom, &[], true).unwrap(), raw);
+        assert!(matches!(read_block(&buf, &compressors, &[], true), Err(TinyError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let compressors = CompressorRegistry::default();
+        let raw = b"key00001value00001".repeat(50);
+        for compression in [CompressionType::NoCompression, CompressionType::LZ4].iter() {
+            let mut buf = vec![];
+            write_block(&raw, *compression, &compressors, &[], &mut buf);
+            for i in 0..buf.len() {
+                let mut corrupted = buf.clone();
+                corrupted[i] ^= 0x1;
+                match read_block(&corrupted, &compressors, &[], true) {
+                    Err(TinyError::Corruption(_)) => {}
+                    r => panic!("flipping byte {} should be detected but got {:?}", i, r),
+                }
+            }
+            // the corrupted contents are returned as is if the checksum is not verified
+            let mut corrupted = buf.clone();
+            corrupted[0] ^= 0x1;
+            if *compression == CompressionType::NoCompression {
+                assert_ne!(read_block(&corrupted, &compressors, &[], false).unwrap(), raw);
+            }
+        }
+    }
+}
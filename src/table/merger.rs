@@ -0,0 +1,142 @@
+use super::table_reader::KeyEntry;
+use crate::entry::Entry;
+use crate::util::error::TinyError;
+
+use std::cmp::Ordering;
+
+/// A source of a `MergingIterator`, which yields the keys in order
+pub type EntrySource<'a> = Box<dyn Iterator<Item = Result<KeyEntry, TinyError>> + 'a>;
+
+/// Compares two keys of the sources
+pub type KeyComparator<'a> = Box<dyn Fn(&[u8], &[u8]) -> Ordering + 'a>;
+
+/// A key with all of its entries from the newest to the oldest
+pub type KeyEntries = (Vec<u8>, Vec<Entry>);
+
+/// MergingIterator merges the sources ordered from the newest to the oldest, e.g. a
+/// memtable and the table files, into the keys in order. Every key is yielded once with
+/// all of its entries from the newest to the oldest, where the entries from the same
+/// source are yielded in the order of the source. An error of a source is yielded once
+/// and ends the iteration.
+pub struct MergingIterator<'a> {
+    sources: Vec<EntrySource<'a>>,
+    // the next entry of every source, which are read on the first `next`
+    heads: Vec<Option<KeyEntry>>,
+    compare: KeyComparator<'a>,
+    started: bool,
+}
+
+impl<'a> MergingIterator<'a> {
+    pub fn new(sources: Vec<EntrySource<'a>>, compare: KeyComparator<'a>) -> Self {
+        MergingIterator {
+            heads: sources.iter().map(|_| None).collect(),
+            sources,
+            compare,
+            started: false,
+        }
+    }
+
+    fn advance(&mut self, i: usize) -> Result<(), TinyError> {
+        self.heads[i] = self.sources[i].next().transpose()?;
+        Ok(())
+    }
+
+    fn next_key(&mut self) -> Result<Option<KeyEntries>, TinyError> {
+        if !self.started {
+            self.started = true;
+            for i in 0..self.sources.len() {
+                self.advance(i)?;
+            }
+        }
+        // the smallest key of the heads, taken from the newest source having it
+        let mut smallest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            if let Some((key, _)) = head {
+                let is_smaller = smallest.is_none_or(|s| {
+                    (self.compare)(key, &self.heads[s].as_ref().unwrap().0) == Ordering::Less
+                });
+                if is_smaller {
+                    smallest = Some(i);
+                }
+            }
+        }
+        let key = match smallest {
+            Some(i) => self.heads[i].as_ref().unwrap().0.clone(),
+            None => return Ok(None),
+        };
+        let mut entries = vec![];
+        for i in 0..self.heads.len() {
+            while self.heads[i]
+                .as_ref()
+                .is_some_and(|(k, _)| (self.compare)(k, &key) == Ordering::Equal)
+            {
+                entries.push(self.heads[i].take().unwrap().1);
+                self.advance(i)?;
+            }
+        }
+        Ok(Some((key, entries)))
+    }
+}
+
+impl Iterator for MergingIterator<'_> {
+    type Item = Result<KeyEntries, TinyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_key() {
+            Ok(next) => next.map(Ok),
+            Err(e) => {
+                // stop after the error
+                self.sources.clear();
+                self.heads.clear();
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::ValueType;
+
+    fn source(entries: &[(&str, u64)]) -> EntrySource<'static> {
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|(k, seq)| Ok((k.as_bytes().to_vec(), Entry::new(ValueType::Value, *seq, b""))))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+
+    #[test]
+    fn test_merging_iterator() {
+        let sources = vec![
+            source(&[("b", 9), ("b", 8), ("d", 7)]),
+            source(&[]),
+            source(&[("a", 5), ("b", 4), ("e", 3)]),
+            source(&[("b", 1), ("d", 2)]),
+        ];
+        let iter = MergingIterator::new(sources, Box::new(|a: &[u8], b: &[u8]| a.cmp(b)));
+        let merged: Vec<_> = iter
+            .map(|r| {
+                let (key, entries) = r.unwrap();
+                (String::from_utf8(key).unwrap(), entries.iter().map(|e| e.seq).collect::<Vec<_>>())
+            })
+            .collect();
+        let expected = vec![
+            ("a".to_owned(), vec![5]),
+            ("b".to_owned(), vec![9, 8, 4, 1]),
+            ("d".to_owned(), vec![7, 2]),
+            ("e".to_owned(), vec![3]),
+        ];
+        assert_eq!(merged, expected);
+
+        let failing: EntrySource<'static> =
+            Box::new(vec![Err(TinyError::Corruption("bad".to_owned()))].into_iter());
+        let mut iter = MergingIterator::new(
+            vec![source(&[("a", 1)]), failing],
+            Box::new(|a: &[u8], b: &[u8]| a.cmp(b)),
+        );
+        assert!(matches!(iter.next(), Some(Err(TinyError::Corruption(_)))));
+        assert!(iter.next().is_none());
+    }
+}
@@ -0,0 +1,14 @@
+pub mod block;
+pub mod block_builder;
+pub mod block_cache;
+pub mod data_block_hash_index;
+pub mod dictionary;
+pub mod format;
+pub mod merger;
+pub mod partitioned_filter;
+pub mod partitioned_index;
+pub mod properties;
+pub mod sst_file_writer;
+pub mod table_builder;
+pub mod table_cache;
+pub mod table_reader;
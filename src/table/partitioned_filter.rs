@@ -0,0 +1,228 @@
+use super::block::Block;
+use super::block_builder::BlockBuilder;
+use super::block_cache::{lookup_or_load, BlockCache, CachedBlock};
+use super::format::BlockHandle;
+use crate::util::comparator::Comparator;
+use crate::util::error::TinyError;
+use crate::util::filter_policy::FilterPolicy;
+use crate::util::slice::Slice;
+
+use std::sync::Arc;
+
+/// PartitionedFilterBuilder splits the filter of a table into partitions, each created from
+/// `keys_per_partition` consecutive keys. Like `PartitionedIndexBuilder`, a top-level index
+/// maps the last key of every partition to the handle of the partition.
+pub struct PartitionedFilterBuilder {
+    policy: Arc<dyn FilterPolicy>,
+    keys_per_partition: usize,
+    // the keys of the partition being built
+    keys: Vec<Vec<u8>>,
+    // (last key, filter) of the finished partitions
+    partitions: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl PartitionedFilterBuilder {
+    pub fn new(policy: Arc<dyn FilterPolicy>, keys_per_partition: usize) -> Self {
+        invarint!(
+            keys_per_partition >= 1,
+            "[partitioned filter] keys per partition must be at least 1"
+        );
+        PartitionedFilterBuilder {
+            policy,
+            keys_per_partition,
+            keys: vec![],
+            partitions: vec![],
+        }
+    }
+
+    /// Adds a key to the filter.
+    /// REQUIRES: key is no less than any previously added key
+    pub fn add_key(&mut self, key: &[u8]) {
+        if self.keys.last().map(Vec::as_slice) == Some(key) {
+            return;
+        }
+        if self.keys.len() >= self.keys_per_partition {
+            self.cut_partition();
+        }
+        self.keys.push(key.to_vec());
+    }
+
+    fn cut_partition(&mut self) {
+        if let Some(last_key) = self.keys.last().cloned() {
+            let mut filter = vec![];
+            self.policy.create_filter(&self.keys, &mut filter);
+            self.partitions.push((last_key, filter));
+            self.keys.clear();
+        }
+    }
+
+    /// Returns the number of partitions built so far
+    pub fn num_partitions(&self) -> usize {
+        self.partitions.len() + if self.keys.is_empty() { 0 } else { 1 }
+    }
+
+    /// Writes the partitions followed by the top-level index by `write_block`, which writes
+    /// the given block contents into the table file and returns its handle.
+    /// Returns the handle of the top-level index.
+    pub fn finish(
+        &mut self,
+        write_block: &mut dyn FnMut(&[u8]) -> Result<BlockHandle, TinyError>,
+    ) -> Result<BlockHandle, TinyError> {
+        self.cut_partition();
+        let mut top_level = BlockBuilder::new(1, None);
+        for (last_key, filter) in self.partitions.drain(..) {
+            let handle = write_block(&filter)?;
+            let mut encoded = vec![];
+            handle.encode_to(&mut encoded);
+            top_level.add(&last_key, &encoded);
+        }
+        write_block(top_level.finish())
+    }
+}
+
+/// PartitionedFilterReader checks the keys against a partitioned filter. The top-level
+/// index is pinned in memory for the lifetime of the reader.
+pub struct PartitionedFilterReader {
+    table_id: u64,
+    policy: Arc<dyn FilterPolicy>,
+    top_level: Block,
+    cache: Arc<BlockCache>,
+}
+
+impl PartitionedFilterReader {
+    /// Create a reader of the table `table_id` with the contents of the top-level index.
+    /// The partitions are cached in `cache`.
+    pub fn new(
+        table_id: u64,
+        policy: Arc<dyn FilterPolicy>,
+        top_level: Vec<u8>,
+        cache: Arc<BlockCache>,
+    ) -> Result<Self, TinyError> {
+        Ok(PartitionedFilterReader {
+            table_id,
+            policy,
+            top_level: Block::new(top_level)?,
+            cache,
+        })
+    }
+
+    /// Returns false if `key` is definitely not in the table.
+    /// `load` reads the filter partition at the given handle, which is only called if the
+    /// partition is not in the block cache.
+    pub fn key_may_match(
+        &self,
+        key: &[u8],
+        cmp: &dyn Comparator<Slice>,
+        load: &mut dyn FnMut(&BlockHandle) -> Result<Vec<u8>, TinyError>,
+    ) -> Result<bool, TinyError> {
+        Ok(self.keys_may_match(&[key], cmp, load)?[0])
+    }
+
+    /// Like `key_may_match` but checks the `keys` sorted by `cmp` at once. The adjacent keys
+    /// falling into the same partition are checked against the partition loaded once.
+    pub fn keys_may_match(
+        &self,
+        keys: &[&[u8]],
+        cmp: &dyn Comparator<Slice>,
+        load: &mut dyn FnMut(&BlockHandle) -> Result<Vec<u8>, TinyError>,
+    ) -> Result<Vec<bool>, TinyError> {
+        let mut results = Vec::with_capacity(keys.len());
+        // the partition checked last with its offset
+        let mut last: Option<(u64, Arc<CachedBlock>)> = None;
+        for key in keys {
+            let partition_handle = match self.top_level.seek(key, cmp)? {
+                Some((_, encoded)) => BlockHandle::decode_from(encoded)?.0,
+                // the key is larger than all the keys in the table
+                None => {
+                    results.push(false);
+                    continue;
+                }
+            };
+            if last.as_ref().is_none_or(|(offset, _)| *offset != partition_handle.offset) {
+                let partition = lookup_or_load(&self.cache, self.table_id, &partition_handle, &mut |h| {
+                    Ok(CachedBlock::Filter(load(h)?))
+                })?;
+                last = Some((partition_handle.offset, partition));
+            }
+            match last.as_ref().unwrap().1.as_ref() {
+                CachedBlock::Filter(filter) => results.push(self.policy.key_may_match(key, filter)),
+                CachedBlock::Block(_) => {
+                    return Err(TinyError::Corruption(format!(
+                        "[table] block at {} in the cache is not a filter partition",
+                        partition_handle.offset
+                    )))
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    /// Returns the memory used by the pinned top-level index
+    pub fn top_level_size(&self) -> usize {
+        self.top_level.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::BytewiseComparator;
+    use crate::util::filter_policy::BloomFilterPolicy;
+
+    #[test]
+    fn test_partitioned_filter() {
+        let cmp = BytewiseComparator::new();
+        let policy: Arc<dyn FilterPolicy> = Arc::new(BloomFilterPolicy::new(10));
+        let mut builder = PartitionedFilterBuilder::new(policy.clone(), 100);
+        for i in 0..1000 {
+            let key = format!("key{:06}", i * 2);
+            // duplicated keys are only added once
+            builder.add_key(key.as_bytes());
+            builder.add_key(key.as_bytes());
+        }
+        assert_eq!(builder.num_partitions(), 10);
+
+        let mut file: Vec<Vec<u8>> = vec![];
+        let top_level = builder
+            .finish(&mut |c| {
+                file.push(c.to_vec());
+                Ok(BlockHandle::new(file.len() as u64 - 1, c.len() as u64))
+            })
+            .unwrap();
+        assert_eq!(file.len(), 11);
+
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let top_level = file[top_level.offset as usize].clone();
+        let reader = PartitionedFilterReader::new(1, policy.clone(), top_level.clone(), cache).unwrap();
+        let reads = std::cell::Cell::new(0);
+        let mut load = |h: &BlockHandle| {
+            reads.set(reads.get() + 1);
+            Ok(file[h.offset as usize].clone())
+        };
+        for i in 0..1000 {
+            let key = format!("key{:06}", i * 2);
+            assert!(reader.key_may_match(key.as_bytes(), &cmp, &mut load).unwrap());
+        }
+        let mut false_positives = 0;
+        for i in 0..1000 {
+            let key = format!("key{:06}", i * 2 + 1);
+            if reader.key_may_match(key.as_bytes(), &cmp, &mut load).unwrap() {
+                false_positives += 1;
+            }
+        }
+        assert!(false_positives < 50, "{} false positives", false_positives);
+        assert!(!reader.key_may_match(b"key999999", &cmp, &mut load).unwrap());
+        assert_eq!(reads.get(), 10);
+
+        // a partition is looked up once for all the sorted keys in it
+        let uncached = PartitionedFilterReader::new(2, policy, top_level, Arc::new(BlockCache::new(0))).unwrap();
+        reads.set(0);
+        let keys: Vec<String> = (0..300).map(|i| format!("key{:06}", i * 2)).collect();
+        let mut keys: Vec<&[u8]> = keys.iter().map(|k| k.as_bytes()).collect();
+        keys.push(b"key999999");
+        let results = uncached.keys_may_match(&keys, &cmp, &mut load).unwrap();
+        assert!(results[..300].iter().all(|r| *r));
+        assert!(!results[300]);
+        assert_eq!(reads.get(), 3);
+    }
+}
@@ -0,0 +1,253 @@
+use super::block::Block;
+use super::block_builder::BlockBuilder;
+use super::block_cache::{lookup_or_load, BlockCache, CachedBlock};
+use super::format::BlockHandle;
+use crate::util::comparator::Comparator;
+use crate::util::error::TinyError;
+use crate::util::slice::Slice;
+
+use std::sync::Arc;
+
+/// PartitionedIndexBuilder builds a two-level index for the data blocks of a table.
+///
+/// The index entries (a key no less than the last key of a data block -> the handle of
+/// the data block) are split into partitions of about `metadata_block_size` bytes.
+/// The top-level index maps the last key of every partition to the handle of the
+/// partition, so only the small top-level index has to stay in memory while the
+/// partitions are read through the block cache on demand.
+pub struct PartitionedIndexBuilder {
+    metadata_block_size: usize,
+    // the partition being built
+    current: BlockBuilder,
+    last_key: Vec<u8>,
+    // (last key, contents) of the finished partitions
+    partitions: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl PartitionedIndexBuilder {
+    pub fn new(metadata_block_size: usize) -> Self {
+        PartitionedIndexBuilder {
+            metadata_block_size,
+            current: BlockBuilder::new(1, None),
+            last_key: vec![],
+            partitions: vec![],
+        }
+    }
+
+    /// Adds the index entry of a data block. `key` must be no less than the last key
+    /// of the data block and less than the first key of the next data block.
+    pub fn add_index_entry(&mut self, key: &[u8], handle: &BlockHandle) {
+        let mut encoded = vec![];
+        handle.encode_to(&mut encoded);
+        self.current.add(key, &encoded);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        if self.current.current_size_estimate() >= self.metadata_block_size {
+            self.cut_partition();
+        }
+    }
+
+    fn cut_partition(&mut self) {
+        if self.current.is_empty() {
+            return;
+        }
+        let contents = self.current.finish().to_vec();
+        self.partitions.push((self.last_key.clone(), contents));
+        self.current.reset();
+    }
+
+    /// Returns the number of partitions built so far
+    pub fn num_partitions(&self) -> usize {
+        self.partitions.len() + if self.current.is_empty() { 0 } else { 1 }
+    }
+
+    /// Writes the partitions followed by the top-level index by `write_block`, which writes
+    /// the given block contents into the table file and returns its handle.
+    /// Returns the handle of the top-level index.
+    pub fn finish(
+        &mut self,
+        write_block: &mut dyn FnMut(&[u8]) -> Result<BlockHandle, TinyError>,
+    ) -> Result<BlockHandle, TinyError> {
+        self.cut_partition();
+        let mut top_level = BlockBuilder::new(1, None);
+        for (last_key, contents) in self.partitions.drain(..) {
+            let handle = write_block(&contents)?;
+            let mut encoded = vec![];
+            handle.encode_to(&mut encoded);
+            top_level.add(&last_key, &encoded);
+        }
+        write_block(top_level.finish())
+    }
+}
+
+/// PartitionedIndexReader finds the data block that may contain a key in a two-level
+/// index. The top-level index is pinned in memory for the lifetime of the reader.
+pub struct PartitionedIndexReader {
+    table_id: u64,
+    top_level: Block,
+    cache: Arc<BlockCache>,
+}
+
+impl PartitionedIndexReader {
+    /// Create a reader of the table `table_id` with the uncompressed contents of the
+    /// top-level index. The partitions are cached in `cache`.
+    pub fn new(table_id: u64, top_level: Vec<u8>, cache: Arc<BlockCache>) -> Result<Self, TinyError> {
+        Ok(PartitionedIndexReader {
+            table_id,
+            top_level: Block::new(top_level)?,
+            cache,
+        })
+    }
+
+    /// Returns the handle of the first data block whose keys may be no less than `key`,
+    /// or `None` if all the keys in the table are less than `key`.
+    /// `load` reads and uncompresses the index partition at the given handle,
+    /// which is only called if the partition is not in the block cache.
+    pub fn find(
+        &self,
+        key: &[u8],
+        cmp: &dyn Comparator<Slice>,
+        load: &mut dyn FnMut(&BlockHandle) -> Result<Vec<u8>, TinyError>,
+    ) -> Result<Option<BlockHandle>, TinyError> {
+        let partition_handle = match self.top_level.seek(key, cmp)? {
+            Some((_, encoded)) => BlockHandle::decode_from(encoded)?.0,
+            None => return Ok(None),
+        };
+        let partition = self.partition(&partition_handle, load)?;
+        match Self::partition_block(&partition, &partition_handle)?.seek(key, cmp)? {
+            Some((_, encoded)) => Ok(Some(BlockHandle::decode_from(encoded)?.0)),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the handles of all the data blocks in order. `load` is called as in `find`.
+    pub fn data_block_handles(
+        &self,
+        load: &mut dyn FnMut(&BlockHandle) -> Result<Vec<u8>, TinyError>,
+    ) -> Result<Vec<BlockHandle>, TinyError> {
+        let mut handles = vec![];
+        for (_, encoded) in self.top_level.entries()? {
+            let partition_handle = BlockHandle::decode_from(encoded)?.0;
+            let partition = self.partition(&partition_handle, load)?;
+            for (_, encoded) in Self::partition_block(&partition, &partition_handle)?.entries()? {
+                handles.push(BlockHandle::decode_from(encoded)?.0);
+            }
+        }
+        Ok(handles)
+    }
+
+    /// Returns the last key of the table, which is the last key of the top-level index
+    pub fn last_key(&self) -> Result<Option<Vec<u8>>, TinyError> {
+        Ok(self.top_level.entries()?.pop().map(|(k, _)| k))
+    }
+
+    fn partition(
+        &self,
+        handle: &BlockHandle,
+        load: &mut dyn FnMut(&BlockHandle) -> Result<Vec<u8>, TinyError>,
+    ) -> Result<Arc<CachedBlock>, TinyError> {
+        lookup_or_load(&self.cache, self.table_id, handle, &mut |h| {
+            Ok(CachedBlock::Block(Block::new(load(h)?)?))
+        })
+    }
+
+    fn partition_block<'a>(partition: &'a CachedBlock, handle: &BlockHandle) -> Result<&'a Block, TinyError> {
+        match partition {
+            CachedBlock::Block(block) => Ok(block),
+            CachedBlock::Filter(_) => Err(TinyError::Corruption(format!(
+                "[table] block at {} in the cache is not an index partition",
+                handle.offset
+            ))),
+        }
+    }
+
+    /// Returns the memory used by the pinned top-level index
+    pub fn top_level_size(&self) -> usize {
+        self.top_level.size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::comparator::BytewiseComparator;
+
+    use std::collections::HashMap;
+
+    // writes the blocks into a map from offset to contents
+    struct MemFile {
+        blocks: HashMap<u64, Vec<u8>>,
+        offset: u64,
+        reads: usize,
+    }
+
+    impl MemFile {
+        fn write(&mut self, contents: &[u8]) -> Result<BlockHandle, TinyError> {
+            let handle = BlockHandle::new(self.offset, contents.len() as u64);
+            self.blocks.insert(self.offset, contents.to_vec());
+            self.offset += contents.len() as u64;
+            Ok(handle)
+        }
+
+        fn read(&mut self, handle: &BlockHandle) -> Result<Vec<u8>, TinyError> {
+            self.reads += 1;
+            Ok(self.blocks[&handle.offset].clone())
+        }
+    }
+
+    #[test]
+    fn test_partitioned_index() {
+        let cmp = BytewiseComparator::new();
+        let mut file = MemFile {
+            blocks: HashMap::new(),
+            offset: 0,
+            reads: 0,
+        };
+        let mut builder = PartitionedIndexBuilder::new(256);
+        // data block i holds the keys in ["key{i*10}", "key{i*10+9}"]
+        for i in 0..200u64 {
+            let key = format!("key{:06}", i * 10 + 9);
+            builder.add_index_entry(key.as_bytes(), &BlockHandle::new(i * 4096, 4096));
+        }
+        assert!(builder.num_partitions() > 1);
+        let top_level = builder.finish(&mut |c| file.write(c)).unwrap();
+
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let top_level = file.read(&top_level).unwrap();
+        let reader = PartitionedIndexReader::new(1, top_level, cache.clone()).unwrap();
+        file.reads = 0;
+        for i in 0..2000u64 {
+            let key = format!("key{:06}", i);
+            let handle = reader.find(key.as_bytes(), &cmp, &mut |h| file.read(h)).unwrap();
+            assert_eq!(handle, Some(BlockHandle::new(i / 10 * 4096, 4096)));
+        }
+        assert!(reader.find(b"key999999", &cmp, &mut |h| file.read(h)).unwrap().is_none());
+        // every partition is read only once
+        let partitions = file.reads;
+        assert!(partitions > 1 && partitions < 200);
+        assert!(cache.total_charge() > 0);
+        for i in 0..2000u64 {
+            let key = format!("key{:06}", i);
+            reader.find(key.as_bytes(), &cmp, &mut |h| file.read(h)).unwrap();
+        }
+        assert_eq!(file.reads, partitions);
+    }
+
+    #[test]
+    fn test_empty_index() {
+        let mut file = MemFile {
+            blocks: HashMap::new(),
+            offset: 0,
+            reads: 0,
+        };
+        let mut builder = PartitionedIndexBuilder::new(4096);
+        assert_eq!(builder.num_partitions(), 0);
+        let top_level = builder.finish(&mut |c| file.write(c)).unwrap();
+        let top_level = file.read(&top_level).unwrap();
+        let reader = PartitionedIndexReader::new(1, top_level, Arc::new(BlockCache::new(100))).unwrap();
+        let found = reader
+            .find(b"a", &BytewiseComparator::new(), &mut |h| file.read(h))
+            .unwrap();
+        assert!(found.is_none());
+    }
+}
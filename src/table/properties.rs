@@ -0,0 +1,346 @@
+use super::block::Block;
+use super::block_builder::BlockBuilder;
+use crate::util::compression::CompressionType;
+use crate::util::error::TinyError;
+use crate::util::varint::{put_u64, read_u64};
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// The name of the meta block storing the properties of a table file
+pub const PROPERTIES_BLOCK_NAME: &str = "tinydb.properties";
+
+/// The prefix of the names of the built-in properties, which can't be used by
+/// the user collected properties
+pub const RESERVED_PROPERTY_PREFIX: &str = "tinydb.";
+
+const NUM_ENTRIES: &str = "tinydb.num.entries";
+const NUM_DELETIONS: &str = "tinydb.num.deletions";
+const RAW_KEY_SIZE: &str = "tinydb.raw.key.size";
+const RAW_VALUE_SIZE: &str = "tinydb.raw.value.size";
+const DATA_SIZE: &str = "tinydb.data.size";
+const INDEX_SIZE: &str = "tinydb.index.size";
+const INDEX_PARTITIONS: &str = "tinydb.index.partitions";
+const FILTER_SIZE: &str = "tinydb.filter.size";
+const FILTER_POLICY: &str = "tinydb.filter.policy";
+const COMPRESSION: &str = "tinydb.compression";
+const COMPARATOR: &str = "tinydb.comparator";
+const CREATION_TIME: &str = "tinydb.creation.time";
+const SMALLEST_SEQNO: &str = "tinydb.smallest.seqno";
+const LARGEST_SEQNO: &str = "tinydb.largest.seqno";
+
+/// The metadata of a table file, which can be read without scanning the data blocks.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TableProperties {
+    pub num_entries: u64,
+    pub num_deletions: u64,
+    /// The total size of the keys before compression
+    pub raw_key_size: u64,
+    /// The total size of the values before compression
+    pub raw_value_size: u64,
+    /// The total size of the stored data blocks
+    pub data_size: u64,
+    /// The total size of the stored index blocks (including the partitions)
+    pub index_size: u64,
+    /// The number of the index partitions, 0 if the index isn't partitioned
+    pub index_partitions: u64,
+    /// The total size of the stored filter blocks (including the partitions)
+    pub filter_size: u64,
+    /// The name of the filter policy creating the filter, empty if there is no filter
+    pub filter_policy_name: String,
+    /// The name of the compression applied to the data blocks
+    pub compression_name: String,
+    /// The name of the comparator ordering the keys
+    pub comparator_name: String,
+    /// The time the table file is created in seconds since the UNIX epoch
+    pub creation_time: u64,
+    pub smallest_seqno: u64,
+    pub largest_seqno: u64,
+    /// The properties added by the `TablePropertiesCollector`s
+    pub user_collected_properties: BTreeMap<String, Vec<u8>>,
+}
+
+/// The properties of the table files keyed by the file path
+pub type TablePropertiesCollection = HashMap<String, Arc<TableProperties>>;
+
+fn put_str(props: &mut BTreeMap<String, Vec<u8>>, name: &str, value: &str) {
+    props.insert(name.to_owned(), value.as_bytes().to_vec());
+}
+
+fn put_num(props: &mut BTreeMap<String, Vec<u8>>, name: &str, value: u64) {
+    let mut buf = vec![];
+    put_u64(&mut buf, value);
+    props.insert(name.to_owned(), buf);
+}
+
+impl TableProperties {
+    /// Encodes the properties into the contents of the properties block
+    pub fn encode(&self) -> Vec<u8> {
+        let mut props = self.user_collected_properties.clone();
+        put_num(&mut props, NUM_ENTRIES, self.num_entries);
+        put_num(&mut props, NUM_DELETIONS, self.num_deletions);
+        put_num(&mut props, RAW_KEY_SIZE, self.raw_key_size);
+        put_num(&mut props, RAW_VALUE_SIZE, self.raw_value_size);
+        put_num(&mut props, DATA_SIZE, self.data_size);
+        put_num(&mut props, INDEX_SIZE, self.index_size);
+        put_num(&mut props, INDEX_PARTITIONS, self.index_partitions);
+        put_num(&mut props, FILTER_SIZE, self.filter_size);
+        put_str(&mut props, FILTER_POLICY, &self.filter_policy_name);
+        put_str(&mut props, COMPRESSION, &self.compression_name);
+        put_str(&mut props, COMPARATOR, &self.comparator_name);
+        put_num(&mut props, CREATION_TIME, self.creation_time);
+        put_num(&mut props, SMALLEST_SEQNO, self.smallest_seqno);
+        put_num(&mut props, LARGEST_SEQNO, self.largest_seqno);
+
+        let mut builder = BlockBuilder::new(1, None);
+        for (name, value) in props.iter() {
+            builder.add(name.as_bytes(), value);
+        }
+        builder.finish().to_vec()
+    }
+
+    /// Decodes the contents of the properties block. The unknown properties
+    /// prefixed by `RESERVED_PROPERTY_PREFIX` are ignored.
+    pub fn decode(contents: Vec<u8>) -> Result<Self, TinyError> {
+        let block = Block::new(contents)?;
+        let mut props = TableProperties::default();
+        for (name, value) in block.entries()? {
+            let name = String::from_utf8(name).map_err(|_| {
+                TinyError::Corruption("[table] property name is not utf-8".to_owned())
+            })?;
+            let num = || match read_u64(value) {
+                (v, n) if n > 0 => Ok(v),
+                _ => Err(TinyError::Corruption(format!("[table] bad property {}", name))),
+            };
+            let string = || String::from_utf8_lossy(value).into_owned();
+            match name.as_str() {
+                NUM_ENTRIES => props.num_entries = num()?,
+                NUM_DELETIONS => props.num_deletions = num()?,
+                RAW_KEY_SIZE => props.raw_key_size = num()?,
+                RAW_VALUE_SIZE => props.raw_value_size = num()?,
+                DATA_SIZE => props.data_size = num()?,
+                INDEX_SIZE => props.index_size = num()?,
+                INDEX_PARTITIONS => props.index_partitions = num()?,
+                FILTER_SIZE => props.filter_size = num()?,
+                FILTER_POLICY => props.filter_policy_name = string(),
+                COMPRESSION => props.compression_name = string(),
+                COMPARATOR => props.comparator_name = string(),
+                CREATION_TIME => props.creation_time = num()?,
+                SMALLEST_SEQNO => props.smallest_seqno = num()?,
+                LARGEST_SEQNO => props.largest_seqno = num()?,
+                _ if name.starts_with(RESERVED_PROPERTY_PREFIX) => {}
+                _ => {
+                    props.user_collected_properties.insert(name, value.to_vec());
+                }
+            }
+        }
+        Ok(props)
+    }
+}
+
+/// The type of an entry added to a table file
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntryType {
+    Put,
+    Delete,
+    Merge,
+    Other,
+}
+
+/// A TablePropertiesCollector is fed with every entry added to a table file and
+/// aggregates them into the user collected properties of the file.
+pub trait TablePropertiesCollector {
+    /// Called for every entry added to the table file in the key order.
+    fn add_user_key(&mut self, key: &[u8], value: &[u8], entry_type: EntryType, seq: u64) -> Result<(), TinyError>;
+
+    /// Called once all the entries are added. The collected properties should be inserted
+    /// into `properties`, whose names must not start with `RESERVED_PROPERTY_PREFIX`.
+    fn finish(&mut self, properties: &mut BTreeMap<String, Vec<u8>>) -> Result<(), TinyError>;
+
+    /// The name of the collector. Used for logging.
+    fn name(&self) -> &str;
+}
+
+/// The information about the table file a `TablePropertiesCollector` is created for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TablePropertiesCollectorContext {
+    pub column_family_id: u32,
+    /// The level the table file is written into
+    pub level: usize,
+}
+
+/// A TablePropertiesCollectorFactory creates a new `TablePropertiesCollector` for each
+/// table file being built.
+pub trait TablePropertiesCollectorFactory: Send + Sync {
+    fn create_table_properties_collector(
+        &self,
+        context: &TablePropertiesCollectorContext,
+    ) -> Box<dyn TablePropertiesCollector>;
+
+    /// The name of the factory. Used for logging.
+    fn name(&self) -> &str;
+}
+
+/// TablePropertiesBuilder collects the built-in properties of a table file being built
+/// and runs the user `TablePropertiesCollector`s.
+/// The sizes of the blocks are filled in by the table builder through `properties_mut`.
+pub struct TablePropertiesBuilder {
+    properties: TableProperties,
+    collectors: Vec<Box<dyn TablePropertiesCollector>>,
+}
+
+impl TablePropertiesBuilder {
+    pub fn new(
+        comparator_name: &str,
+        compression: CompressionType,
+        creation_time: u64,
+        collectors: Vec<Box<dyn TablePropertiesCollector>>,
+    ) -> Self {
+        TablePropertiesBuilder {
+            properties: TableProperties {
+                comparator_name: comparator_name.to_owned(),
+                compression_name: format!("{:?}", compression),
+                creation_time,
+                smallest_seqno: u64::MAX,
+                ..TableProperties::default()
+            },
+            collectors,
+        }
+    }
+
+    /// Records an entry added to the table file
+    pub fn add(&mut self, key: &[u8], value: &[u8], entry_type: EntryType, seq: u64) -> Result<(), TinyError> {
+        let props = &mut self.properties;
+        props.num_entries += 1;
+        if entry_type == EntryType::Delete {
+            props.num_deletions += 1;
+        }
+        props.raw_key_size += key.len() as u64;
+        props.raw_value_size += value.len() as u64;
+        props.smallest_seqno = props.smallest_seqno.min(seq);
+        props.largest_seqno = props.largest_seqno.max(seq);
+        for c in self.collectors.iter_mut() {
+            c.add_user_key(key, value, entry_type, seq)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    pub fn properties_mut(&mut self) -> &mut TableProperties {
+        &mut self.properties
+    }
+
+    /// Finishes the user collectors and returns the properties of the table file.
+    /// Returns `InvalidArgument` if a collector adds a property with the reserved prefix.
+    pub fn finish(mut self) -> Result<TableProperties, TinyError> {
+        if self.properties.num_entries == 0 {
+            self.properties.smallest_seqno = 0;
+        }
+        for c in self.collectors.iter_mut() {
+            let mut collected = BTreeMap::new();
+            c.finish(&mut collected)?;
+            if let Some(name) = collected.keys().find(|n| n.starts_with(RESERVED_PROPERTY_PREFIX)) {
+                return Err(TinyError::InvalidArgument(format!(
+                    "[table] property {} collected by {} uses the reserved prefix {}",
+                    name,
+                    c.name(),
+                    RESERVED_PROPERTY_PREFIX
+                )));
+            }
+            self.properties.user_collected_properties.append(&mut collected);
+        }
+        Ok(self.properties)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // counts the keys with the given prefix
+    struct PrefixCounter {
+        prefix: Vec<u8>,
+        count: u64,
+    }
+
+    impl TablePropertiesCollector for PrefixCounter {
+        fn add_user_key(&mut self, key: &[u8], _: &[u8], entry_type: EntryType, _: u64) -> Result<(), TinyError> {
+            if entry_type == EntryType::Put && key.starts_with(&self.prefix) {
+                self.count += 1;
+            }
+            Ok(())
+        }
+
+        fn finish(&mut self, properties: &mut BTreeMap<String, Vec<u8>>) -> Result<(), TinyError> {
+            let name = format!("prefix.count.{}", String::from_utf8_lossy(&self.prefix));
+            properties.insert(name, self.count.to_string().into_bytes());
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            "PrefixCounter"
+        }
+    }
+
+    #[test]
+    fn test_collect_and_encode() {
+        let collector = Box::new(PrefixCounter {
+            prefix: b"user/".to_vec(),
+            count: 0,
+        });
+        let mut builder =
+            TablePropertiesBuilder::new("leveldb.BytewiseComparator", CompressionType::LZ4, 1234, vec![collector]);
+        builder.add(b"order/1", b"v1", EntryType::Put, 10).unwrap();
+        builder.add(b"user/1", b"value", EntryType::Put, 7).unwrap();
+        builder.add(b"user/2", b"", EntryType::Delete, 12).unwrap();
+        builder.add(b"user/3", b"v", EntryType::Put, 9).unwrap();
+        builder.properties_mut().data_size = 4096;
+        builder.properties_mut().index_size = 100;
+        let props = builder.finish().unwrap();
+        assert_eq!(props.num_entries, 4);
+        assert_eq!(props.num_deletions, 1);
+        assert_eq!(props.raw_key_size, 25);
+        assert_eq!(props.raw_value_size, 8);
+        assert_eq!(props.smallest_seqno, 7);
+        assert_eq!(props.largest_seqno, 12);
+        assert_eq!(props.compression_name, "LZ4");
+        assert_eq!(
+            props.user_collected_properties.get("prefix.count.user/"),
+            Some(&b"2".to_vec())
+        );
+
+        let decoded = TableProperties::decode(props.encode()).unwrap();
+        assert_eq!(decoded, props);
+    }
+
+    #[test]
+    fn test_empty_table() {
+        let builder = TablePropertiesBuilder::new("cmp", CompressionType::NoCompression, 0, vec![]);
+        let props = builder.finish().unwrap();
+        assert_eq!(props.smallest_seqno, 0);
+        assert_eq!(props.largest_seqno, 0);
+        assert_eq!(TableProperties::decode(props.encode()).unwrap(), props);
+    }
+
+    #[test]
+    fn test_reserved_prefix() {
+        struct BadCollector {}
+        impl TablePropertiesCollector for BadCollector {
+            fn add_user_key(&mut self, _: &[u8], _: &[u8], _: EntryType, _: u64) -> Result<(), TinyError> {
+                Ok(())
+            }
+            fn finish(&mut self, properties: &mut BTreeMap<String, Vec<u8>>) -> Result<(), TinyError> {
+                properties.insert(NUM_ENTRIES.to_owned(), vec![0]);
+                Ok(())
+            }
+            fn name(&self) -> &str {
+                "BadCollector"
+            }
+        }
+        let builder = TablePropertiesBuilder::new("cmp", CompressionType::NoCompression, 0, vec![Box::new(BadCollector {})]);
+        match builder.finish() {
+            Err(TinyError::InvalidArgument(_)) => {}
+            r => panic!("expect InvalidArgument but got {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use super::table_builder::TableBuilder;
+use super::table_cache::TableFileWriter;
+use crate::column_family::ColumnFamilyOptions;
+use crate::config::Config;
+use crate::entry::ValueType;
+use crate::util::comparator::Comparator;
+use crate::util::error::TinyError;
+use crate::util::slice::Slice;
+
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// The information about a table file written by `SstFileWriter`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExternalSstFileInfo {
+    pub file_path: String,
+    pub smallest_key: Vec<u8>,
+    pub largest_key: Vec<u8>,
+    pub num_entries: u64,
+    pub file_size: u64,
+}
+
+// The file being written
+struct OpenedFile {
+    path: String,
+    builder: TableBuilder<TableFileWriter>,
+    smallest_key: Vec<u8>,
+    last_key: Vec<u8>,
+    num_entries: u64,
+}
+
+/// SstFileWriter builds a table file out of the DB from the key/value pairs given in
+/// the order of the comparator, which can be added to a DB by `DB::ingest_external_file`.
+/// The file is written with the table options of the `Config` and must be ingested
+/// into a DB ordered by the same comparator.
+pub struct SstFileWriter {
+    config: Config,
+    comparator: Arc<dyn Comparator<Slice> + Send + Sync>,
+    file: Option<OpenedFile>,
+}
+
+impl SstFileWriter {
+    pub fn new(config: &Config, options: &ColumnFamilyOptions) -> Self {
+        SstFileWriter {
+            config: config.clone(),
+            comparator: options.comparator.clone(),
+            file: None,
+        }
+    }
+
+    /// Creates the table file at `path` to write.
+    /// Returns `InvalidArgument` if the previous file isn't finished.
+    pub fn open(&mut self, path: &str) -> Result<(), TinyError> {
+        if let Some(file) = self.file.as_ref() {
+            return Err(TinyError::InvalidArgument(format!(
+                "[sst file writer] {} is not finished",
+                file.path
+            )));
+        }
+        self.config.check_compression()?;
+        let dest = TableFileWriter::create(self.config.env.as_ref(), path, false)?;
+        // the ingested files are usually placed in the bottommost level
+        let builder = TableBuilder::new(&self.config, self.comparator.name(), 0, dest);
+        self.file = Some(OpenedFile {
+            path: path.to_owned(),
+            builder,
+            smallest_key: vec![],
+            last_key: vec![],
+            num_entries: 0,
+        });
+        Ok(())
+    }
+
+    /// Adds the key/value pair to the file.
+    /// Returns `InvalidArgument` if `key` isn't after the keys added before.
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> Result<(), TinyError> {
+        let file = self.file.as_mut().ok_or_else(|| {
+            TinyError::InvalidArgument("[sst file writer] no file is opened".to_owned())
+        })?;
+        if file.num_entries > 0
+            && self
+                .comparator
+                .compare(&Slice::from(key), &Slice::from(&file.last_key))
+                != Ordering::Greater
+        {
+            return Err(TinyError::InvalidArgument(
+                "[sst file writer] keys must be added in strictly increasing order".to_owned(),
+            ));
+        }
+        // the entries get the global sequence number assigned by the ingestion
+        file.builder.add(key, ValueType::Value, 0, value)?;
+        if file.num_entries == 0 {
+            file.smallest_key = key.to_vec();
+        }
+        file.last_key.clear();
+        file.last_key.extend_from_slice(key);
+        file.num_entries += 1;
+        Ok(())
+    }
+
+    /// Finishes and syncs the file. The writer can open another file afterwards.
+    /// Returns `InvalidArgument` and removes the file if no key is added.
+    pub fn finish(&mut self) -> Result<ExternalSstFileInfo, TinyError> {
+        let file = self.file.take().ok_or_else(|| {
+            TinyError::InvalidArgument("[sst file writer] no file is opened".to_owned())
+        })?;
+        if file.num_entries == 0 {
+            drop(file.builder);
+            let _ = self.config.env.remove_file(&file.path);
+            return Err(TinyError::InvalidArgument(format!(
+                "[sst file writer] can't create {} with no entries",
+                file.path
+            )));
+        }
+        let (_, dest) = file.builder.finish()?;
+        let file_size = dest.finish()?;
+        Ok(ExternalSstFileInfo {
+            file_path: file.path,
+            smallest_key: file.smallest_key,
+            largest_key: file.last_key,
+            num_entries: file.num_entries,
+            file_size,
+        })
+    }
+
+    /// Returns the number of bytes written to the opened file so far
+    pub fn file_size(&self) -> u64 {
+        self.file.as_ref().map_or(0, |f| f.builder.file_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{IndexType, ReadOptions};
+    use crate::env::mem::MemEnv;
+    use crate::env::Env;
+    use crate::table::block_cache::BlockCache;
+    use crate::table::table_cache::{FileReadMode, TableFile};
+    use crate::table::table_reader::{Table, TableOptions};
+    use crate::util::comparator::BytewiseComparator;
+    use crate::util::compression::{CompressionType, Compressor};
+    use crate::util::filter_policy::{BloomFilterPolicy, FilterPolicy};
+
+    use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+    fn new_config(env: Arc<dyn Env>) -> Config {
+        Config {
+            env,
+            block_size: 64,
+            ..Config::default()
+        }
+    }
+
+    fn open_table(env: &Arc<dyn Env>, path: &str, options: &TableOptions) -> Arc<Table> {
+        let file = Arc::new(TableFile::open(env.as_ref(), path, FileReadMode::Buffered).unwrap());
+        let options = TableOptions {
+            verify_checksums: true,
+            ..options.clone()
+        };
+        Arc::new(Table::open(file, env.get_file_size(path).unwrap(), &options).unwrap())
+    }
+
+    #[test]
+    fn test_write_and_read_table() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let mut writer = SstFileWriter::new(&new_config(env.clone()), &ColumnFamilyOptions::default());
+        writer.open("/1.sst").unwrap();
+        let kvs: Vec<_> = (0..100)
+            .map(|i| (format!("key{:03}", i).into_bytes(), format!("value{}", i).into_bytes()))
+            .collect();
+        for (k, v) in kvs.iter() {
+            writer.put(k, v).unwrap();
+        }
+        assert!(writer.file_size() > 0);
+        let info = writer.finish().unwrap();
+        assert_eq!(info.smallest_key, b"key000");
+        assert_eq!(info.largest_key, b"key099");
+        assert_eq!(info.num_entries, 100);
+        assert_eq!(info.file_size, env.get_file_size("/1.sst").unwrap());
+
+        let table = open_table(&env, "/1.sst", &TableOptions::default());
+        let cmp = BytewiseComparator::new();
+        let options = ReadOptions::default();
+        let entries: Vec<_> = table
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(k, e)| {
+                assert_eq!((e.value_type, e.seq), (ValueType::Value, 0));
+                (k, e.value)
+            })
+            .collect();
+        assert_eq!(entries, kvs);
+        assert_eq!(table.smallest_key().unwrap().unwrap(), b"key000");
+        assert_eq!(table.largest_key().unwrap().unwrap(), b"key099");
+        for (k, v) in kvs.iter() {
+            assert_eq!(table.get(&options, k, &cmp).unwrap().map(|e| e.value).as_ref(), Some(v));
+        }
+        assert_eq!(table.get(&options, b"key", &cmp).unwrap(), None);
+        assert_eq!(table.get(&options, b"key0500", &cmp).unwrap(), None);
+        assert_eq!(table.get(&options, b"key100", &cmp).unwrap(), None);
+        let keys: Vec<&[u8]> = vec![b"key", b"key000", b"key001", b"key0500", b"key099", b"key100"];
+        let values: Vec<_> = table
+            .multi_get(&options, &keys, &cmp)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.map(|e| e.value))
+            .collect();
+        assert_eq!(
+            values,
+            vec![None, Some(b"value0".to_vec()), Some(b"value1".to_vec()), None, Some(b"value99".to_vec()), None]
+        );
+
+        let props = table.properties();
+        assert_eq!(props.num_entries, 100);
+        assert_eq!(props.comparator_name, cmp.name());
+        assert!(props.data_size > 0 && props.index_size > 0);
+    }
+
+    #[test]
+    fn test_compression_dictionary() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let kvs: Vec<_> = (0..200)
+            .map(|i| {
+                let doc = format!(
+                    "{{\"id\":{},\"type\":\"session\",\"status\":\"active\",\"region\":\"eu-west-{}\"}}",
+                    i,
+                    i % 3
+                );
+                (format!("key{:03}", i).into_bytes(), doc.into_bytes())
+            })
+            .collect();
+        let cmp = BytewiseComparator::new();
+        let mut data_sizes = vec![];
+        // the dictionary is trained once the samples are full, before the file is finished
+        for (path, dict_bytes) in [("/plain.sst", 0), ("/dict.sst", 1024)].iter() {
+            let config = Config {
+                env: env.clone(),
+                block_size: 256,
+                compression_per_level: vec![CompressionType::LZ4],
+                compression_dict_max_bytes: *dict_bytes,
+                compression_dict_sample_bytes: 4096,
+                ..Config::default()
+            };
+            let mut writer = SstFileWriter::new(&config, &ColumnFamilyOptions::default());
+            writer.open(path).unwrap();
+            for (k, v) in kvs.iter() {
+                writer.put(k, v).unwrap();
+            }
+            writer.finish().unwrap();
+
+            let table = open_table(&env, path, &TableOptions::default());
+            let entries: Vec<_> = table.entries().unwrap().into_iter().map(|(k, e)| (k, e.value)).collect();
+            assert_eq!(entries, kvs);
+            let options = ReadOptions::default();
+            assert_eq!(table.get(&options, b"key123", &cmp).unwrap().unwrap().value, kvs[123].1);
+            data_sizes.push(table.properties().data_size);
+        }
+        assert!(data_sizes[1] < data_sizes[0]);
+    }
+
+    // A run-length codec counting the blocks it decompresses
+    struct RunLengthCompressor {
+        decompressed: AtomicUsize,
+    }
+
+    impl Compressor for RunLengthCompressor {
+        fn compress(&self, src: &[u8], dst: &mut Vec<u8>) {
+            let mut i = 0;
+            while i < src.len() {
+                let run = src[i..].iter().take(255).take_while(|b| **b == src[i]).count();
+                dst.push(run as u8);
+                dst.push(src[i]);
+                i += run;
+            }
+        }
+
+        fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, TinyError> {
+            if src.len() % 2 == 1 {
+                return Err(TinyError::Corruption("[rle] truncated run".to_owned()));
+            }
+            self.decompressed.fetch_add(1, AtomicOrdering::SeqCst);
+            Ok(src.chunks(2).flat_map(|run| vec![run[1]; run[0] as usize]).collect())
+        }
+    }
+
+    #[test]
+    fn test_user_defined_compression() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let codec = Arc::new(RunLengthCompressor {
+            decompressed: AtomicUsize::new(0),
+        });
+        let mut config = Config {
+            env: env.clone(),
+            block_size: 256,
+            compression_per_level: vec![CompressionType::Custom(100)],
+            ..Config::default()
+        };
+        let mut writer = SstFileWriter::new(&config, &ColumnFamilyOptions::default());
+        assert!(matches!(writer.open("/1.sst"), Err(TinyError::InvalidArgument(_))));
+
+        config.compressors.register(CompressionType::Custom(100), codec.clone()).unwrap();
+        let mut writer = SstFileWriter::new(&config, &ColumnFamilyOptions::default());
+        writer.open("/1.sst").unwrap();
+        let kvs: Vec<_> = (0..100)
+            .map(|i| (format!("key{:03}", i).into_bytes(), vec![b'a' + (i % 26) as u8; 100]))
+            .collect();
+        for (k, v) in kvs.iter() {
+            writer.put(k, v).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let table = open_table(
+            &env,
+            "/1.sst",
+            &TableOptions {
+                compressors: config.compressors.clone(),
+                ..TableOptions::default()
+            },
+        );
+        let entries: Vec<_> = table.entries().unwrap().into_iter().map(|(k, e)| (k, e.value)).collect();
+        assert_eq!(entries, kvs);
+        assert!(codec.decompressed.load(AtomicOrdering::SeqCst) > 0);
+        assert!(table.properties().data_size < 100 * 100);
+
+        // the data blocks can't be read without the codec
+        let table = open_table(&env, "/1.sst", &TableOptions::default());
+        assert!(matches!(table.entries(), Err(TinyError::Corruption(_))));
+    }
+
+    #[test]
+    fn test_partitioned_index_and_filter() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let policy: Arc<dyn FilterPolicy> = Arc::new(BloomFilterPolicy::new(10));
+        let config = Config {
+            env: env.clone(),
+            block_size: 64,
+            index_type: IndexType::TwoLevelIndexSearch,
+            filter_policy: Some(policy.clone()),
+            partition_filters: true,
+            metadata_block_size: 128,
+            ..Config::default()
+        };
+        let mut writer = SstFileWriter::new(&config, &ColumnFamilyOptions::default());
+        writer.open("/1.sst").unwrap();
+        let kvs: Vec<_> = (0..500)
+            .map(|i| (format!("key{:04}", i * 2).into_bytes(), format!("value{}", i).into_bytes()))
+            .collect();
+        for (k, v) in kvs.iter() {
+            writer.put(k, v).unwrap();
+        }
+        writer.finish().unwrap();
+
+        let cache = Arc::new(BlockCache::new(1 << 20));
+        let table = open_table(
+            &env,
+            "/1.sst",
+            &TableOptions {
+                table_id: 1,
+                block_cache: Some(cache.clone()),
+                filter_policy: Some(policy),
+                ..TableOptions::default()
+            },
+        );
+        let props = table.properties();
+        assert!(props.index_partitions > 1);
+        assert!(props.filter_size > 0);
+        assert_eq!(props.filter_policy_name, "tinydb.BuiltinBloomFilter");
+        let cmp = BytewiseComparator::new();
+        let options = ReadOptions::default();
+        for (k, v) in kvs.iter() {
+            assert_eq!(table.get(&options, k, &cmp).unwrap().map(|e| e.value).as_ref(), Some(v));
+        }
+        assert!(cache.total_charge() > 0);
+        for i in 0..500 {
+            let missing = format!("key{:04}", i * 2 + 1);
+            assert_eq!(table.get(&options, missing.as_bytes(), &cmp).unwrap(), None);
+        }
+        let keys: Vec<&[u8]> = vec![b"key0000", b"key0001", b"key0998", b"key1000"];
+        let values: Vec<_> = table
+            .multi_get(&options, &keys, &cmp)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.map(|e| e.value))
+            .collect();
+        assert_eq!(values, vec![Some(b"value0".to_vec()), None, Some(b"value499".to_vec()), None]);
+        let entries: Vec<_> = table.entries().unwrap().into_iter().map(|(k, e)| (k, e.value)).collect();
+        assert_eq!(entries, kvs);
+        assert_eq!(table.smallest_key().unwrap().unwrap(), b"key0000");
+        assert_eq!(table.largest_key().unwrap().unwrap(), b"key0998");
+
+        // the table is still readable without the filter policy and the block cache
+        let table = open_table(&env, "/1.sst", &TableOptions::default());
+        assert_eq!(table.get(&options, b"key0500", &cmp).unwrap().unwrap().value, b"value250");
+        assert_eq!(table.get(&options, b"key0501", &cmp).unwrap(), None);
+    }
+
+    #[test]
+    fn test_out_of_order_keys() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        let mut writer = SstFileWriter::new(&new_config(env.clone()), &ColumnFamilyOptions::default());
+        assert!(matches!(writer.put(b"a", b"1"), Err(TinyError::InvalidArgument(_))));
+        writer.open("/1.sst").unwrap();
+        assert!(matches!(writer.open("/2.sst"), Err(TinyError::InvalidArgument(_))));
+        writer.put(b"b", b"1").unwrap();
+        assert!(matches!(writer.put(b"b", b"2"), Err(TinyError::InvalidArgument(_))));
+        assert!(matches!(writer.put(b"a", b"2"), Err(TinyError::InvalidArgument(_))));
+        writer.put(b"c", b"3").unwrap();
+        assert_eq!(writer.finish().unwrap().num_entries, 2);
+
+        writer.open("/2.sst").unwrap();
+        assert!(matches!(writer.finish(), Err(TinyError::InvalidArgument(_))));
+        assert!(!env.file_exists("/2.sst"));
+    }
+}
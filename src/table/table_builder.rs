@@ -0,0 +1,452 @@
+use super::block_builder::BlockBuilder;
+use super::dictionary::{train_dictionary, DictionarySampler, COMPRESSION_DICT_BLOCK_NAME};
+use super::format::{write_block, BlockHandle, Footer, BLOCK_TRAILER_SIZE};
+use super::partitioned_filter::PartitionedFilterBuilder;
+use super::partitioned_index::PartitionedIndexBuilder;
+use super::properties::{
+    EntryType, TableProperties, TablePropertiesBuilder, TablePropertiesCollectorContext,
+    PROPERTIES_BLOCK_NAME,
+};
+use crate::column_family::DEFAULT_COLUMN_FAMILY_ID;
+use crate::config::{Config, IndexType};
+use crate::entry::{Entry, ValueType};
+use crate::util::compression::{CompressionType, CompressorRegistry};
+use crate::util::error::TinyError;
+use crate::util::filter_policy::FilterPolicy;
+
+use std::io::Write;
+use std::sync::Arc;
+
+/// The prefix of the name of the meta block storing the filter of all the keys, which is
+/// followed by the name of the filter policy
+pub const FULL_FILTER_BLOCK_PREFIX: &str = "fullfilter.";
+
+/// The prefix of the name of the meta block storing the top-level index of a partitioned
+/// filter, which is followed by the name of the filter policy
+pub const PARTITIONED_FILTER_BLOCK_PREFIX: &str = "partitionedfilter.";
+
+// The index of the data blocks being built
+enum IndexBuilder {
+    Block(BlockBuilder),
+    Partitioned(PartitionedIndexBuilder),
+}
+
+impl IndexBuilder {
+    fn add(&mut self, last_key: &[u8], handle: &BlockHandle) {
+        match self {
+            IndexBuilder::Block(block) => {
+                let mut encoded_handle = vec![];
+                handle.encode_to(&mut encoded_handle);
+                block.add(last_key, &encoded_handle);
+            }
+            IndexBuilder::Partitioned(builder) => builder.add_index_entry(last_key, handle),
+        }
+    }
+
+    fn num_partitions(&self) -> u64 {
+        match self {
+            IndexBuilder::Block(_) => 0,
+            IndexBuilder::Partitioned(builder) => builder.num_partitions() as u64,
+        }
+    }
+
+    // Writes the index by `write_block` and returns the handle of the (top-level) index
+    fn finish(
+        &mut self,
+        write_block: &mut dyn FnMut(&[u8]) -> Result<BlockHandle, TinyError>,
+    ) -> Result<BlockHandle, TinyError> {
+        match self {
+            IndexBuilder::Block(block) => write_block(block.finish()),
+            IndexBuilder::Partitioned(builder) => builder.finish(write_block),
+        }
+    }
+}
+
+// The filter of the keys being built
+enum FilterBuilder {
+    Full {
+        policy: Arc<dyn FilterPolicy>,
+        keys: Vec<Vec<u8>>,
+    },
+    Partitioned {
+        policy: Arc<dyn FilterPolicy>,
+        builder: PartitionedFilterBuilder,
+    },
+}
+
+impl FilterBuilder {
+    fn new(config: &Config, policy: Arc<dyn FilterPolicy>) -> Self {
+        if config.partition_filters {
+            // the policy doesn't tell the size of a key in the filter, so a partition is
+            // cut by the number of keys assuming the usual 10 bits per key
+            let keys_per_partition = (config.metadata_block_size * 8 / 10).max(1);
+            let builder = PartitionedFilterBuilder::new(policy.clone(), keys_per_partition);
+            FilterBuilder::Partitioned { policy, builder }
+        } else {
+            FilterBuilder::Full { policy, keys: vec![] }
+        }
+    }
+
+    fn policy(&self) -> &Arc<dyn FilterPolicy> {
+        match self {
+            FilterBuilder::Full { policy, .. } | FilterBuilder::Partitioned { policy, .. } => policy,
+        }
+    }
+
+    fn add_key(&mut self, key: &[u8]) {
+        match self {
+            FilterBuilder::Full { keys, .. } => {
+                // the entries of a key are added together
+                if keys.last().map(Vec::as_slice) != Some(key) {
+                    keys.push(key.to_vec());
+                }
+            }
+            FilterBuilder::Partitioned { builder, .. } => builder.add_key(key),
+        }
+    }
+
+    // Writes the filter by `write_block` and returns the name of the meta block of the
+    // filter with its handle
+    fn finish(
+        &mut self,
+        write_block: &mut dyn FnMut(&[u8]) -> Result<BlockHandle, TinyError>,
+    ) -> Result<(String, BlockHandle), TinyError> {
+        match self {
+            FilterBuilder::Full { policy, keys } => {
+                let mut filter = vec![];
+                policy.create_filter(keys, &mut filter);
+                let name = format!("{}{}", FULL_FILTER_BLOCK_PREFIX, policy.name());
+                Ok((name, write_block(&filter)?))
+            }
+            FilterBuilder::Partitioned { policy, builder } => {
+                let name = format!("{}{}", PARTITIONED_FILTER_BLOCK_PREFIX, policy.name());
+                Ok((name, builder.finish(write_block)?))
+            }
+        }
+    }
+}
+
+/// TableBuilder writes the key/value pairs added in the order of the comparator into a
+/// table file of the layout:
+///
+/// ```text
+/// [data block 1]
+/// ...
+/// [data block N]
+/// [filter block or partitions] (optional)
+/// [index block or partitions]
+/// [compression dictionary block] (optional)
+/// [properties block]
+/// [metaindex block]
+/// [footer]
+/// ```
+///
+/// Every block is followed by its trailer as written by `format::write_block`. The data
+/// blocks map the keys to their entries encoded by `Entry::encode_to`. The index block
+/// maps the last key of every data block to the handle of the block, and the metaindex
+/// block maps the names of the meta blocks to their handles.
+///
+/// With `IndexType::TwoLevelIndexSearch`, the index is split into partitions of about
+/// `Config::metadata_block_size` bytes and the footer points to their top-level index.
+/// With a `Config::filter_policy`, a filter of all the keys is written, or a partitioned
+/// one with `Config::partition_filters`.
+///
+/// If the data blocks are compressed and `Config::compression_dict_max_bytes` isn't 0,
+/// the finished data blocks are buffered while the key/value pairs are sampled. Once
+/// `Config::compression_dict_sample_bytes` are sampled or the file is finished, the
+/// dictionary is trained from the samples and all the data blocks are compressed with it.
+pub struct TableBuilder<W: Write> {
+    dest: W,
+    // the number of bytes written to `dest`
+    offset: u64,
+    block_size: usize,
+    compression: CompressionType,
+    compressors: CompressorRegistry,
+    data_block: BlockBuilder,
+    index: IndexBuilder,
+    filter: Option<FilterBuilder>,
+    // the last key added, which is the index key of the pending data block
+    last_key: Vec<u8>,
+    // the total size of the stored data blocks
+    data_size: u64,
+    properties: TablePropertiesBuilder,
+    // the buffer of the stored block being written
+    buf: Vec<u8>,
+    // the buffer of the entry being added
+    entry: Vec<u8>,
+    // the samples for the dictionary, which is `None` once the dictionary is trained or
+    // if the dictionary compression is disabled
+    sampler: Option<DictionarySampler>,
+    max_dict_bytes: usize,
+    // the data blocks finished before the dictionary is trained with their index keys
+    buffered_blocks: Vec<(Vec<u8>, Vec<u8>)>,
+    buffered_size: u64,
+    dict: Vec<u8>,
+}
+
+impl<W: Write> TableBuilder<W> {
+    /// Create a builder writing a table file of `level` to `dest` with the block options,
+    /// the compression and the property collectors in `config`
+    pub fn new(config: &Config, comparator_name: &str, level: usize, dest: W) -> Self {
+        let compression = config.compression_for_level(level);
+        let context = TablePropertiesCollectorContext {
+            column_family_id: DEFAULT_COLUMN_FAMILY_ID,
+            level,
+        };
+        let collectors = config
+            .table_properties_collector_factories
+            .iter()
+            .map(|f| f.create_table_properties_collector(&context))
+            .collect();
+        let creation_time = config.clock.now_micros() / 1_000_000;
+        let properties =
+            TablePropertiesBuilder::new(comparator_name, compression, creation_time, collectors);
+        let sampler = if compression != CompressionType::NoCompression
+            && config.compression_dict_max_bytes > 0
+        {
+            Some(DictionarySampler::new(config.compression_dict_sample_bytes))
+        } else {
+            None
+        };
+        TableBuilder {
+            dest,
+            offset: 0,
+            block_size: config.block_size,
+            compression,
+            compressors: config.compressors.clone(),
+            data_block: BlockBuilder::new(
+                config.block_restart_interval,
+                config.data_block_hash_util_ratio(),
+            ),
+            index: match config.index_type {
+                IndexType::BinarySearch => IndexBuilder::Block(BlockBuilder::new(1, None)),
+                IndexType::TwoLevelIndexSearch => {
+                    IndexBuilder::Partitioned(PartitionedIndexBuilder::new(config.metadata_block_size))
+                }
+            },
+            filter: config
+                .filter_policy
+                .as_ref()
+                .map(|policy| FilterBuilder::new(config, policy.clone())),
+            last_key: vec![],
+            data_size: 0,
+            properties,
+            buf: vec![],
+            entry: vec![],
+            sampler,
+            max_dict_bytes: config.compression_dict_max_bytes,
+            buffered_blocks: vec![],
+            buffered_size: 0,
+            dict: vec![],
+        }
+    }
+
+    /// Adds the entry of `key` written at the sequence `seq`.
+    /// REQUIRES: `key` is after all the keys added before
+    pub fn add(&mut self, key: &[u8], value_type: ValueType, seq: u64, value: &[u8]) -> Result<(), TinyError> {
+        let entry_type = match value_type {
+            ValueType::Deletion => EntryType::Delete,
+            ValueType::Value | ValueType::ValueWithExpiry => EntryType::Put,
+            ValueType::Merge => EntryType::Merge,
+        };
+        self.properties.add(key, value, entry_type, seq)?;
+        if let Some(sampler) = self.sampler.as_mut() {
+            sampler.add(key, value);
+        }
+        if let Some(filter) = self.filter.as_mut() {
+            filter.add_key(key);
+        }
+        self.entry.clear();
+        Entry::encode_to(value_type, seq, value, &mut self.entry);
+        self.data_block.add(key, &self.entry);
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        if self.data_block.current_size_estimate() >= self.block_size {
+            self.flush_data_block()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of bytes written so far
+    #[inline]
+    pub fn file_size(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the size of the file including the pending and the buffered data blocks
+    /// before compression, which is an estimate of the size of the finished file
+    #[inline]
+    pub fn estimated_file_size(&self) -> u64 {
+        self.offset + self.buffered_size + self.data_block.current_size_estimate() as u64
+    }
+
+    /// Writes the pending data block and the metadata blocks.
+    /// Returns the properties of the table and the destination.
+    pub fn finish(mut self) -> Result<(TableProperties, W), TinyError> {
+        self.flush_data_block()?;
+        self.write_buffered_blocks()?;
+        // the meta blocks by their names, which are added to the metaindex block in order
+        let mut meta_blocks = vec![];
+
+        let filter_start = self.offset;
+        if let Some(filter) = self.filter.as_mut() {
+            let (dest, offset, buf, compressors) = (&mut self.dest, &mut self.offset, &mut self.buf, &self.compressors);
+            meta_blocks.push(filter.finish(&mut |raw| {
+                Self::emit_block(dest, offset, buf, raw, CompressionType::NoCompression, compressors, &[])
+            })?);
+        }
+        let filter_size = self.offset - filter_start;
+
+        let index_partitions = self.index.num_partitions();
+        let index_start = self.offset;
+        let index_handle = {
+            let (dest, offset, buf, compressors) = (&mut self.dest, &mut self.offset, &mut self.buf, &self.compressors);
+            self.index.finish(&mut |raw| {
+                Self::emit_block(dest, offset, buf, raw, CompressionType::NoCompression, compressors, &[])
+            })?
+        };
+        let index_size = self.offset - index_start;
+
+        let dict_handle = if self.dict.is_empty() {
+            None
+        } else {
+            Some(Self::emit_block(
+                &mut self.dest,
+                &mut self.offset,
+                &mut self.buf,
+                &self.dict,
+                CompressionType::NoCompression,
+                &self.compressors,
+                &[],
+            )?)
+        };
+        if let Some(handle) = dict_handle {
+            meta_blocks.push((COMPRESSION_DICT_BLOCK_NAME.to_owned(), handle));
+        }
+        let props = self.properties.properties_mut();
+        props.data_size = self.data_size;
+        props.index_size = index_size;
+        props.index_partitions = index_partitions;
+        props.filter_size = filter_size;
+        if let Some(filter) = self.filter.as_ref() {
+            props.filter_policy_name = filter.policy().name().to_owned();
+        }
+        let properties = self.properties.finish()?;
+        let properties_handle = Self::emit_block(
+            &mut self.dest,
+            &mut self.offset,
+            &mut self.buf,
+            &properties.encode(),
+            CompressionType::NoCompression,
+            &self.compressors,
+            &[],
+        )?;
+
+        meta_blocks.push((PROPERTIES_BLOCK_NAME.to_owned(), properties_handle));
+        meta_blocks.sort_by(|a, b| a.0.cmp(&b.0));
+        let mut metaindex_block = BlockBuilder::new(1, None);
+        for (name, handle) in meta_blocks {
+            let mut encoded_handle = vec![];
+            handle.encode_to(&mut encoded_handle);
+            metaindex_block.add(name.as_bytes(), &encoded_handle);
+        }
+        let metaindex_handle = Self::emit_block(
+            &mut self.dest,
+            &mut self.offset,
+            &mut self.buf,
+            metaindex_block.finish(),
+            CompressionType::NoCompression,
+            &self.compressors,
+            &[],
+        )?;
+
+        self.buf.clear();
+        Footer {
+            metaindex_handle,
+            index_handle,
+        }
+        .encode_to(&mut self.buf);
+        self.dest.write_all(&self.buf)?;
+        self.offset += self.buf.len() as u64;
+        self.dest.flush()?;
+        Ok((properties, self.dest))
+    }
+
+    fn flush_data_block(&mut self) -> Result<(), TinyError> {
+        if self.data_block.is_empty() {
+            return Ok(());
+        }
+        match self.sampler.as_ref() {
+            Some(sampler) => {
+                let raw = self.data_block.finish().to_vec();
+                self.buffered_size += raw.len() as u64;
+                self.buffered_blocks.push((raw, self.last_key.clone()));
+                if sampler.is_full() {
+                    self.write_buffered_blocks()?;
+                }
+            }
+            None => {
+                let handle = Self::emit_block(
+                    &mut self.dest,
+                    &mut self.offset,
+                    &mut self.buf,
+                    self.data_block.finish(),
+                    self.compression,
+                    &self.compressors,
+                    &self.dict,
+                )?;
+                Self::add_index_entry(&mut self.index, &mut self.data_size, &self.last_key, &handle);
+            }
+        }
+        self.data_block.reset();
+        Ok(())
+    }
+
+    // Trains the dictionary if it's still sampling and writes the buffered data blocks
+    // compressed with it
+    fn write_buffered_blocks(&mut self) -> Result<(), TinyError> {
+        let sampler = match self.sampler.take() {
+            Some(sampler) => sampler,
+            None => return Ok(()),
+        };
+        self.dict = train_dictionary(sampler.samples(), self.max_dict_bytes);
+        for (raw, last_key) in std::mem::take(&mut self.buffered_blocks) {
+            let handle = Self::emit_block(
+                &mut self.dest,
+                &mut self.offset,
+                &mut self.buf,
+                &raw,
+                self.compression,
+                &self.compressors,
+                &self.dict,
+            )?;
+            Self::add_index_entry(&mut self.index, &mut self.data_size, &last_key, &handle);
+        }
+        self.buffered_size = 0;
+        Ok(())
+    }
+
+    // Adds the handle of the data block written to the index
+    fn add_index_entry(index: &mut IndexBuilder, data_size: &mut u64, last_key: &[u8], handle: &BlockHandle) {
+        *data_size += handle.size + BLOCK_TRAILER_SIZE as u64;
+        index.add(last_key, handle);
+    }
+
+    // Writes the block with its trailer and returns the handle of the block
+    fn emit_block(
+        dest: &mut W,
+        offset: &mut u64,
+        buf: &mut Vec<u8>,
+        raw: &[u8],
+        compression: CompressionType,
+        compressors: &CompressorRegistry,
+        dict: &[u8],
+    ) -> Result<BlockHandle, TinyError> {
+        buf.clear();
+        write_block(raw, compression, compressors, dict, buf);
+        dest.write_all(buf)?;
+        let handle = BlockHandle::new(*offset, (buf.len() - BLOCK_TRAILER_SIZE) as u64);
+        *offset += buf.len() as u64;
+        Ok(handle)
+    }
+}
@@ -0,0 +1,388 @@
+use super::block_cache::BlockCache;
+use super::format::{BlockContents, BlockHandle, BLOCK_TRAILER_SIZE};
+use super::table_reader::{Table, TableOptions};
+use crate::cache::lru::LRUCache;
+use crate::env::{Env, RandomAccessFile, WritableFile};
+use crate::util::compression::CompressorRegistry;
+use crate::util::direct_io::{self, DirectWriter, ReadaheadReader};
+use crate::util::error::TinyError;
+use crate::util::filter_policy::FilterPolicy;
+use crate::util::mmap::{MmapFile, MmapSlice};
+
+use std::io::{self, Write};
+use std::sync::Arc;
+
+pub use crate::filename::table_file_name;
+
+/// How the table files are read
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileReadMode {
+    /// Read by the `RandomAccessFile` of the `Env`
+    Buffered,
+    /// Memory mapped by `Env::new_mmap_file`. Only supported by the Envs backed by the
+    /// local file system.
+    Mmap,
+    /// `pread` bypassing the page cache with O_DIRECT, opened by
+    /// `Env::new_direct_random_access_file`. Only supported by the Envs backed by the
+    /// local file system.
+    Direct,
+}
+
+impl FileReadMode {
+    /// Returns the mode of the given options, which are mutually exclusive
+    pub fn new(use_mmap_reads: bool, use_direct_reads: bool) -> Result<Self, TinyError> {
+        match (use_mmap_reads, use_direct_reads) {
+            (false, false) => Ok(FileReadMode::Buffered),
+            (true, false) => Ok(FileReadMode::Mmap),
+            (false, true) => Ok(FileReadMode::Direct),
+            (true, true) => Err(TinyError::InvalidArgument(
+                "[table] use_mmap_reads and use_direct_reads can't be both set".to_owned(),
+            )),
+        }
+    }
+}
+
+/// TableFile is an opened table file, which is either read by `pread` or memory mapped.
+pub enum TableFile {
+    File(Box<dyn RandomAccessFile>),
+    Mmap(Arc<MmapFile>),
+    Direct(Box<dyn RandomAccessFile>),
+}
+
+impl TableFile {
+    /// Opens the table file at `path` in the given mode.
+    /// Returns `NotSupported` if the `Env` doesn't support the mode.
+    pub fn open(env: &dyn Env, path: &str, mode: FileReadMode) -> Result<Self, TinyError> {
+        match mode {
+            FileReadMode::Buffered => Ok(TableFile::File(env.new_random_access_file(path)?)),
+            FileReadMode::Mmap => Ok(TableFile::Mmap(Arc::new(env.new_mmap_file(path)?))),
+            FileReadMode::Direct => Ok(TableFile::Direct(env.new_direct_random_access_file(path)?)),
+        }
+    }
+
+    /// Reads `len` bytes at `offset`. The returned contents of a memory mapped
+    /// file are a view into the mapping instead of a copy.
+    pub fn read(&self, offset: u64, len: usize) -> Result<BlockContents, TinyError> {
+        match self {
+            TableFile::File(f) => {
+                let mut buf = vec![0; len];
+                f.read_exact_at(offset, &mut buf)?;
+                Ok(BlockContents::Owned(buf))
+            }
+            TableFile::Direct(f) => Ok(BlockContents::Owned(direct_io::read_aligned(f.as_ref(), offset, len)?)),
+            TableFile::Mmap(m) => Ok(BlockContents::Mapped(MmapSlice::new(
+                m.clone(),
+                offset as usize,
+                len,
+            )?)),
+        }
+    }
+
+    /// Reads the stored block at `handle` including its trailer
+    #[inline]
+    pub fn read_block(&self, handle: &BlockHandle) -> Result<BlockContents, TinyError> {
+        self.read(handle.offset, handle.size as usize + BLOCK_TRAILER_SIZE)
+    }
+
+    /// Returns whether the file is memory mapped
+    #[inline]
+    pub fn is_mmap(&self) -> bool {
+        matches!(self, TableFile::Mmap(_))
+    }
+
+    /// Returns a reader with a readahead buffer of `readahead_size` for reading the file
+    /// sequentially, e.g. as a compaction input. Returns `None` for a memory mapped file
+    /// which doesn't benefit from readahead.
+    pub fn readahead_reader(&self, readahead_size: usize) -> Option<ReadaheadReader<'_>> {
+        match self {
+            TableFile::File(f) => Some(ReadaheadReader::new(f.as_ref(), readahead_size)),
+            TableFile::Direct(f) => Some(ReadaheadReader::new(f.as_ref(), readahead_size)),
+            TableFile::Mmap(_) => None,
+        }
+    }
+}
+
+/// TableFileWriter writes a new table file by flush or compaction, either through
+/// the page cache or with the direct I/O.
+pub enum TableFileWriter {
+    /// Written by the `WritableFile` of the `Env`
+    Buffered {
+        file: Box<dyn WritableFile>,
+        size: u64,
+    },
+    /// Written with O_DIRECT by the writer of `Env::new_direct_writable_file`. Only
+    /// supported by the Envs backed by the local file system.
+    Direct(DirectWriter),
+}
+
+impl TableFileWriter {
+    /// Creates the table file at `path`.
+    /// Returns `NotSupported` if `use_direct_io` isn't supported by the `Env`.
+    pub fn create(env: &dyn Env, path: &str, use_direct_io: bool) -> Result<Self, TinyError> {
+        if use_direct_io {
+            Ok(TableFileWriter::Direct(env.new_direct_writable_file(path, 1 << 20)?))
+        } else {
+            Ok(TableFileWriter::Buffered {
+                file: env.new_writable_file(path)?,
+                size: 0,
+            })
+        }
+    }
+
+    /// Flushes and syncs the file. Returns the size of the file.
+    pub fn finish(self) -> Result<u64, TinyError> {
+        match self {
+            TableFileWriter::Buffered { mut file, size } => {
+                file.sync()?;
+                Ok(size)
+            }
+            TableFileWriter::Direct(w) => w.finish(),
+        }
+    }
+}
+
+impl Write for TableFileWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        match self {
+            TableFileWriter::Buffered { file, size } => {
+                let n = file.write(data)?;
+                *size += n as u64;
+                Ok(n)
+            }
+            TableFileWriter::Direct(w) => w.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            TableFileWriter::Buffered { file, .. } => file.flush(),
+            TableFileWriter::Direct(w) => w.flush(),
+        }
+    }
+}
+
+/// TableCache keeps the recently used table files open. With `use_mmap_reads`, a file is
+/// unmapped once it's evicted and no block read from it is alive.
+pub struct TableCache {
+    env: Arc<dyn Env>,
+    dir: String,
+    mode: FileReadMode,
+    cache: LRUCache<u64, TableFile>,
+    // the opened tables with their index blocks and properties
+    tables: LRUCache<u64, Table>,
+    block_cache: Option<Arc<BlockCache>>,
+    filter_policy: Option<Arc<dyn FilterPolicy>>,
+    compressors: CompressorRegistry,
+}
+
+impl TableCache {
+    /// Create a cache keeping at most `capacity` table files in `dir` open
+    pub fn new(env: Arc<dyn Env>, dir: &str, capacity: usize, mode: FileReadMode) -> Self {
+        TableCache {
+            env,
+            dir: dir.to_owned(),
+            mode,
+            cache: LRUCache::new(capacity),
+            tables: LRUCache::new(capacity),
+            block_cache: None,
+            filter_policy: None,
+            compressors: CompressorRegistry::default(),
+        }
+    }
+
+    /// Opens the tables with the blocks cached in `block_cache`
+    pub fn with_block_cache(mut self, block_cache: Arc<BlockCache>) -> Self {
+        self.block_cache = Some(block_cache);
+        self
+    }
+
+    /// Opens the tables with their filters created by `filter_policy`
+    pub fn with_filter_policy(mut self, filter_policy: Option<Arc<dyn FilterPolicy>>) -> Self {
+        self.filter_policy = filter_policy;
+        self
+    }
+
+    /// Opens the tables with their blocks decompressed by the codecs in `compressors`
+    pub fn with_compressors(mut self, compressors: CompressorRegistry) -> Self {
+        self.compressors = compressors;
+        self
+    }
+
+    /// Returns the opened table file of the given number
+    pub fn find_file(&self, number: u64) -> Result<Arc<TableFile>, TinyError> {
+        if let Some(file) = self.cache.get(&number) {
+            return Ok(file);
+        }
+        let file = TableFile::open(self.env.as_ref(), &table_file_name(&self.dir, number), self.mode)?;
+        Ok(self.cache.insert(number, file, 1))
+    }
+
+    /// Opens the table file of the given number to be read by a compaction. With
+    /// `use_direct_io` the file is opened for the direct reads apart from the table cache,
+    /// otherwise the file in the cache is shared.
+    pub fn open_compaction_input(&self, number: u64, use_direct_io: bool) -> Result<Arc<TableFile>, TinyError> {
+        if use_direct_io {
+            let path = table_file_name(&self.dir, number);
+            return Ok(Arc::new(TableFile::open(self.env.as_ref(), &path, FileReadMode::Direct)?));
+        }
+        self.find_file(number)
+    }
+
+    /// Returns the opened table of the given number and `file_size` bytes
+    pub fn find_table(
+        &self,
+        number: u64,
+        file_size: u64,
+        verify_checksums: bool,
+    ) -> Result<Arc<Table>, TinyError> {
+        if let Some(table) = self.tables.get(&number) {
+            return Ok(table);
+        }
+        let options = TableOptions {
+            table_id: number,
+            verify_checksums,
+            block_cache: self.block_cache.clone(),
+            filter_policy: self.filter_policy.clone(),
+            compressors: self.compressors.clone(),
+        };
+        let table = Table::open(self.find_file(number)?, file_size, &options)?;
+        Ok(self.tables.insert(number, table, 1))
+    }
+
+    /// Evicts the table file of the given number, which should be called once the file
+    /// is deleted
+    pub fn evict(&self, number: u64) {
+        self.tables.erase(&number);
+        self.cache.erase(&number);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::block::Block;
+    use super::super::block_builder::BlockBuilder;
+    use super::super::format::{decode_block, write_block};
+    use super::*;
+    use crate::env::mem::MemEnv;
+    use crate::env::posix::PosixEnv;
+    use crate::util::comparator::BytewiseComparator;
+    use crate::util::compression::{CompressionType, CompressorRegistry};
+
+    use std::fs;
+
+    #[test]
+    fn test_read_blocks() {
+        let dir = std::env::temp_dir().join(format!("tinydb_table_cache_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap().to_owned();
+
+        let mut raw = BlockBuilder::new(16, None);
+        for i in 0..100 {
+            raw.add(format!("key{:03}", i).as_bytes(), format!("value{}", i).as_bytes());
+        }
+        let raw = raw.finish().to_vec();
+        let compressors = CompressorRegistry::default();
+        let mut file = vec![];
+        let mut handles = vec![];
+        for compression in [CompressionType::NoCompression, CompressionType::LZ4].iter() {
+            let offset = file.len() as u64;
+            write_block(&raw, *compression, &compressors, &[], &mut file);
+            handles.push(BlockHandle::new(offset, (file.len() - BLOCK_TRAILER_SIZE) as u64 - offset));
+        }
+        let env: Arc<dyn Env> = Arc::new(PosixEnv::new());
+        let mut writer = TableFileWriter::create(env.as_ref(), &table_file_name(&dir, 1), false).unwrap();
+        writer.write_all(&file).unwrap();
+        assert_eq!(writer.finish().unwrap(), file.len() as u64);
+
+        let cmp = BytewiseComparator::new();
+        for mode in [FileReadMode::Buffered, FileReadMode::Mmap, FileReadMode::Direct].iter() {
+            let cache = TableCache::new(env.clone(), &dir, 10, *mode);
+            let table = cache.find_file(1).unwrap();
+            assert_eq!(table.is_mmap(), *mode == FileReadMode::Mmap);
+            assert!(Arc::ptr_eq(&table, &cache.find_file(1).unwrap()));
+            let mut blocks = vec![];
+            for handle in handles.iter() {
+                let contents = decode_block(table.read_block(handle).unwrap(), &compressors, &[], true).unwrap();
+                blocks.push(Block::from_contents(contents).unwrap());
+            }
+            // the uncompressed block is a view into the mapping
+            match table.read_block(&handles[0]).unwrap() {
+                BlockContents::Mapped(_) => assert_eq!(*mode, FileReadMode::Mmap),
+                BlockContents::Owned(_) => assert_ne!(*mode, FileReadMode::Mmap),
+            }
+            assert!(table.read(file.len() as u64 - 1, 2).is_err());
+
+            // the blocks are still readable after the file is evicted
+            drop(table);
+            cache.evict(1);
+            for block in blocks.iter() {
+                assert_eq!(block.get(b"key042", &cmp).unwrap(), Some(&b"value42"[..]));
+            }
+        }
+        assert!(TableCache::new(env, &dir, 10, FileReadMode::Mmap).find_file(2).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_compaction_io() {
+        let path = std::env::temp_dir()
+            .join(format!("tinydb_compaction_io_{}", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 253) as u8).collect();
+        let env = PosixEnv::new();
+        let mut writer = TableFileWriter::create(&env, &path, true).unwrap();
+        for chunk in data.chunks(3000) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), data.len() as u64);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let table = TableFile::open(&env, &path, FileReadMode::Direct).unwrap();
+        let mut reader = table.readahead_reader(16 << 10).unwrap();
+        for offset in (0..data.len()).step_by(1000) {
+            assert_eq!(reader.read(offset as u64, 1000).unwrap(), &data[offset..offset + 1000]);
+        }
+        assert!(TableFile::open(&env, &path, FileReadMode::Mmap)
+            .unwrap()
+            .readahead_reader(16 << 10)
+            .is_none());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_table_file_on_mem_env() {
+        let env: Arc<dyn Env> = Arc::new(MemEnv::new());
+        env.create_dir("/db").unwrap();
+        let mut writer = TableFileWriter::create(env.as_ref(), &table_file_name("/db", 7), false).unwrap();
+        writer.write_all(b"table contents").unwrap();
+        assert_eq!(writer.finish().unwrap(), 14);
+        let cache = TableCache::new(env.clone(), "/db", 10, FileReadMode::Buffered);
+        let table = cache.find_file(7).unwrap();
+        assert_eq!(&*table.read(6, 8).unwrap(), b"contents");
+        assert_eq!(table.readahead_reader(4096).unwrap().read(0, 5).unwrap(), b"table");
+        assert!(cache.find_file(8).is_err());
+
+        // the memory mapped and the direct I/O modes are only supported on the local file system
+        let path = table_file_name("/db", 7);
+        for mode in [FileReadMode::Mmap, FileReadMode::Direct].iter() {
+            assert!(matches!(
+                TableFile::open(env.as_ref(), &path, *mode),
+                Err(TinyError::NotSupported(_))
+            ));
+        }
+        assert!(matches!(
+            TableFileWriter::create(env.as_ref(), &table_file_name("/db", 8), true),
+            Err(TinyError::NotSupported(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_mode() {
+        assert_eq!(FileReadMode::new(false, false).unwrap(), FileReadMode::Buffered);
+        assert_eq!(FileReadMode::new(true, false).unwrap(), FileReadMode::Mmap);
+        assert_eq!(FileReadMode::new(false, true).unwrap(), FileReadMode::Direct);
+        assert!(FileReadMode::new(true, true).is_err());
+    }
+}
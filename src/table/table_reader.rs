@@ -0,0 +1,409 @@
+use super::block::Block;
+use super::block_cache::{lookup_or_load, BlockCache, CachedBlock};
+use super::dictionary::COMPRESSION_DICT_BLOCK_NAME;
+use super::format::{decode_block, BlockContents, BlockHandle, Footer, BLOCK_TRAILER_SIZE, FOOTER_ENCODED_LENGTH};
+use super::partitioned_filter::PartitionedFilterReader;
+use super::partitioned_index::PartitionedIndexReader;
+use super::properties::{TableProperties, PROPERTIES_BLOCK_NAME};
+use super::table_builder::{FULL_FILTER_BLOCK_PREFIX, PARTITIONED_FILTER_BLOCK_PREFIX};
+use super::table_cache::TableFile;
+use crate::config::ReadOptions;
+use crate::entry::Entry;
+use crate::util::comparator::{BytewiseComparator, Comparator};
+use crate::util::compression::CompressorRegistry;
+use crate::util::direct_io::ReadaheadReader;
+use crate::util::error::TinyError;
+use crate::util::filter_policy::FilterPolicy;
+use crate::util::slice::Slice;
+
+use std::sync::Arc;
+
+/// A key and its entry copied out of the table
+pub type KeyEntry = (Vec<u8>, Entry);
+
+/// The options to open a `Table` with
+#[derive(Clone, Default)]
+pub struct TableOptions {
+    /// Identifies the blocks of the table in the block cache, e.g. the file number, which
+    /// must be unique among the tables sharing the cache
+    pub table_id: u64,
+    /// Verifies the checksums of all the blocks read
+    pub verify_checksums: bool,
+    /// The cache of the data blocks and the index and filter partitions
+    pub block_cache: Option<Arc<BlockCache>>,
+    /// The policy to read the filter with. A filter created by another policy is ignored.
+    pub filter_policy: Option<Arc<dyn FilterPolicy>>,
+    /// The codecs to decompress the blocks with
+    pub compressors: CompressorRegistry,
+}
+
+// The index of the data blocks
+enum Index {
+    Block(Block),
+    Partitioned(PartitionedIndexReader),
+}
+
+// The filter of the keys in the table
+enum Filter {
+    Full {
+        policy: Arc<dyn FilterPolicy>,
+        filter: Vec<u8>,
+    },
+    Partitioned(PartitionedFilterReader),
+}
+
+/// Table is an opened table file written by `TableBuilder`. The index block (or the
+/// top-level index of the partitions), the filter and the properties are read on open
+/// while the data blocks and the partitions are read on demand through the block cache.
+///
+/// The checksums of the data blocks are verified if the table is opened with
+/// `verify_checksums` or the read asks for it by `ReadOptions::verify_checksums`.
+pub struct Table {
+    file: Arc<TableFile>,
+    table_id: u64,
+    block_cache: Option<Arc<BlockCache>>,
+    index: Index,
+    filter: Option<Filter>,
+    properties: Arc<TableProperties>,
+    compressors: CompressorRegistry,
+    // the compression dictionary of the data blocks, empty if the table has none
+    dict: Vec<u8>,
+    verify_checksums: bool,
+}
+
+impl Table {
+    /// Opens the table of `file_size` bytes in `file`.
+    /// Returns `TinyError::Corruption` if the file isn't a table file.
+    pub fn open(file: Arc<TableFile>, file_size: u64, options: &TableOptions) -> Result<Self, TinyError> {
+        if file_size < FOOTER_ENCODED_LENGTH as u64 {
+            return Err(TinyError::Corruption(
+                "[table] file is too short to be a table file".to_owned(),
+            ));
+        }
+        let verify_checksums = options.verify_checksums;
+        let read_meta_block = |handle: &BlockHandle| -> Result<Vec<u8>, TinyError> {
+            Ok(decode_block(file.read_block(handle)?, &options.compressors, &[], verify_checksums)?.to_vec())
+        };
+        let footer = Footer::decode_from(
+            &file.read(file_size - FOOTER_ENCODED_LENGTH as u64, FOOTER_ENCODED_LENGTH)?,
+        )?;
+        // the metaindex block maps the names to the handles and is always bytewise ordered
+        let metaindex = Block::new(read_meta_block(&footer.metaindex_handle)?)?;
+        let meta_block_handle = |name: &str| -> Result<Option<BlockHandle>, TinyError> {
+            match metaindex.get(name.as_bytes(), &BytewiseComparator::new())? {
+                Some(handle) => Ok(Some(BlockHandle::decode_from(handle)?.0)),
+                None => Ok(None),
+            }
+        };
+        let properties_handle = meta_block_handle(PROPERTIES_BLOCK_NAME)?
+            .ok_or_else(|| TinyError::Corruption("[table] the properties block is missing".to_owned()))?;
+        let properties = Arc::new(TableProperties::decode(read_meta_block(&properties_handle)?)?);
+        let dict = match meta_block_handle(COMPRESSION_DICT_BLOCK_NAME)? {
+            Some(handle) => read_meta_block(&handle)?,
+            None => vec![],
+        };
+
+        // the partitions are cached even without a block cache, which only keeps them
+        // while they are in use
+        let partition_cache = || {
+            options
+                .block_cache
+                .clone()
+                .unwrap_or_else(|| Arc::new(BlockCache::new(0)))
+        };
+        let index_contents = read_meta_block(&footer.index_handle)?;
+        let index = if properties.index_partitions > 0 {
+            Index::Partitioned(PartitionedIndexReader::new(
+                options.table_id,
+                index_contents,
+                partition_cache(),
+            )?)
+        } else {
+            Index::Block(Block::new(index_contents)?)
+        };
+        let filter = match options.filter_policy.as_ref() {
+            Some(policy) => {
+                let full = meta_block_handle(&format!("{}{}", FULL_FILTER_BLOCK_PREFIX, policy.name()))?;
+                let partitioned =
+                    meta_block_handle(&format!("{}{}", PARTITIONED_FILTER_BLOCK_PREFIX, policy.name()))?;
+                match (full, partitioned) {
+                    (Some(handle), _) => Some(Filter::Full {
+                        policy: policy.clone(),
+                        filter: read_meta_block(&handle)?,
+                    }),
+                    (None, Some(handle)) => Some(Filter::Partitioned(PartitionedFilterReader::new(
+                        options.table_id,
+                        policy.clone(),
+                        read_meta_block(&handle)?,
+                        partition_cache(),
+                    )?)),
+                    (None, None) => None,
+                }
+            }
+            None => None,
+        };
+        Ok(Table {
+            file,
+            table_id: options.table_id,
+            block_cache: options.block_cache.clone(),
+            index,
+            filter,
+            properties,
+            compressors: options.compressors.clone(),
+            dict,
+            verify_checksums,
+        })
+    }
+
+    #[inline]
+    pub fn properties(&self) -> &Arc<TableProperties> {
+        &self.properties
+    }
+
+    /// Returns the entry of `key`
+    pub fn get(
+        &self,
+        options: &ReadOptions,
+        key: &[u8],
+        cmp: &dyn Comparator<Slice>,
+    ) -> Result<Option<Entry>, TinyError> {
+        if !self.key_may_match(options, key, cmp)? {
+            return Ok(None);
+        }
+        let handle = match self.find_data_block(options, key, cmp)? {
+            Some(handle) => handle,
+            None => return Ok(None),
+        };
+        let block = self.read_data_block(options, &handle)?;
+        data_block(&block)?.get(key, cmp)?.map(Entry::decode).transpose()
+    }
+
+    /// Returns the entries of `keys`, which are sorted by `cmp`. A data block or a filter
+    /// partition holding several of the keys is read only once.
+    pub fn multi_get(
+        &self,
+        options: &ReadOptions,
+        keys: &[&[u8]],
+        cmp: &dyn Comparator<Slice>,
+    ) -> Result<Vec<Option<Entry>>, TinyError> {
+        let may_match = match &self.filter {
+            Some(Filter::Partitioned(filter)) => {
+                filter.keys_may_match(keys, cmp, &mut |h| self.read_partition(options, h))?
+            }
+            _ => keys
+                .iter()
+                .map(|key| self.key_may_match(options, key, cmp))
+                .collect::<Result<_, _>>()?,
+        };
+        let mut entries = Vec::with_capacity(keys.len());
+        // the block read last with its offset
+        let mut last: Option<(u64, Arc<CachedBlock>)> = None;
+        for (key, may_match) in keys.iter().zip(may_match) {
+            let handle = match may_match {
+                true => self.find_data_block(options, key, cmp)?,
+                false => None,
+            };
+            let handle = match handle {
+                Some(handle) => handle,
+                None => {
+                    entries.push(None);
+                    continue;
+                }
+            };
+            if last.as_ref().is_none_or(|(offset, _)| *offset != handle.offset) {
+                let block = self.read_data_block(options, &handle)?;
+                last = Some((handle.offset, block));
+            }
+            let block = data_block(&last.as_ref().unwrap().1)?;
+            entries.push(block.get(key, cmp)?.map(Entry::decode).transpose()?);
+        }
+        Ok(entries)
+    }
+
+    /// Returns all the keys and their entries in the table in order
+    pub fn entries(self: &Arc<Self>) -> Result<Vec<KeyEntry>, TinyError> {
+        self.iter(&ReadOptions::default())?.collect()
+    }
+
+    /// Returns an iterator over the keys and their entries in the table in order, which
+    /// reads one data block at a time and keeps the table open until it's dropped
+    pub fn iter(self: &Arc<Self>, options: &ReadOptions) -> Result<TableIterator<'static>, TinyError> {
+        self.new_iter(options, None)
+    }
+
+    /// Like `iter` but reads the data blocks by `reader` over the file of the table, which
+    /// bypasses the block cache. Used by the compactions reading the whole table at once.
+    pub fn iter_with_readahead<'a>(
+        self: &Arc<Self>,
+        options: &ReadOptions,
+        reader: ReadaheadReader<'a>,
+    ) -> Result<TableIterator<'a>, TinyError> {
+        self.new_iter(options, Some(reader))
+    }
+
+    fn new_iter<'a>(
+        self: &Arc<Self>,
+        options: &ReadOptions,
+        readahead: Option<ReadaheadReader<'a>>,
+    ) -> Result<TableIterator<'a>, TinyError> {
+        let handles = self.data_block_handles(options)?;
+        Ok(TableIterator {
+            table: self.clone(),
+            options: *options,
+            readahead,
+            handles: handles.into_iter(),
+            block_entries: vec![].into_iter(),
+        })
+    }
+
+    /// Returns the smallest key in the table, or `None` if the table is empty
+    pub fn smallest_key(&self) -> Result<Option<Vec<u8>>, TinyError> {
+        let options = ReadOptions::default();
+        let handle = match self.data_block_handles(&options)?.first() {
+            Some(handle) => *handle,
+            None => return Ok(None),
+        };
+        let block = self.read_data_block(&options, &handle)?;
+        Ok(data_block(&block)?.entries()?.into_iter().next().map(|(k, _)| k))
+    }
+
+    /// Returns the largest key in the table, or `None` if the table is empty
+    pub fn largest_key(&self) -> Result<Option<Vec<u8>>, TinyError> {
+        // the index key of a block is the last key in it
+        match &self.index {
+            Index::Block(block) => Ok(block.entries()?.pop().map(|(k, _)| k)),
+            Index::Partitioned(index) => index.last_key(),
+        }
+    }
+
+    // Returns false if the filter tells `key` isn't in the table
+    fn key_may_match(&self, options: &ReadOptions, key: &[u8], cmp: &dyn Comparator<Slice>) -> Result<bool, TinyError> {
+        match &self.filter {
+            Some(Filter::Full { policy, filter }) => Ok(policy.key_may_match(key, filter)),
+            Some(Filter::Partitioned(filter)) => {
+                filter.key_may_match(key, cmp, &mut |h| self.read_partition(options, h))
+            }
+            None => Ok(true),
+        }
+    }
+
+    // Returns the handle of the data block that may contain `key`
+    fn find_data_block(
+        &self,
+        options: &ReadOptions,
+        key: &[u8],
+        cmp: &dyn Comparator<Slice>,
+    ) -> Result<Option<BlockHandle>, TinyError> {
+        match &self.index {
+            // the index key of a block is the last key in it
+            Index::Block(block) => match block.seek(key, cmp)? {
+                Some((_, handle)) => Ok(Some(BlockHandle::decode_from(handle)?.0)),
+                None => Ok(None),
+            },
+            Index::Partitioned(index) => index.find(key, cmp, &mut |h| self.read_partition(options, h)),
+        }
+    }
+
+    fn data_block_handles(&self, options: &ReadOptions) -> Result<Vec<BlockHandle>, TinyError> {
+        match &self.index {
+            Index::Block(block) => block
+                .entries()?
+                .into_iter()
+                .map(|(_, handle)| Ok(BlockHandle::decode_from(handle)?.0))
+                .collect(),
+            Index::Partitioned(index) => index.data_block_handles(&mut |h| self.read_partition(options, h)),
+        }
+    }
+
+    // Reads the uncompressed contents of an index or filter partition
+    fn read_partition(&self, options: &ReadOptions, handle: &BlockHandle) -> Result<Vec<u8>, TinyError> {
+        let verify_checksums = self.verify_checksums || options.verify_checksums;
+        Ok(decode_block(self.file.read_block(handle)?, &self.compressors, &[], verify_checksums)?.to_vec())
+    }
+
+    fn read_data_block(&self, options: &ReadOptions, handle: &BlockHandle) -> Result<Arc<CachedBlock>, TinyError> {
+        let mut load = |h: &BlockHandle| {
+            Ok(CachedBlock::Block(self.decode_data_block(options, self.file.read_block(h)?)?))
+        };
+        match self.block_cache.as_ref() {
+            // the uncompressed blocks of a memory mapped file are views into the mapping
+            Some(cache) if !self.file.is_mmap() => lookup_or_load(cache, self.table_id, handle, &mut load),
+            _ => load(handle).map(Arc::new),
+        }
+    }
+
+    // Decodes the stored data block with its trailer
+    fn decode_data_block(&self, options: &ReadOptions, contents: BlockContents) -> Result<Block, TinyError> {
+        let verify_checksums = self.verify_checksums || options.verify_checksums;
+        Block::from_contents(decode_block(contents, &self.compressors, &self.dict, verify_checksums)?)
+    }
+}
+
+// Returns the data block read by `Table::read_data_block`
+fn data_block(block: &CachedBlock) -> Result<&Block, TinyError> {
+    match block {
+        CachedBlock::Block(block) => Ok(block),
+        CachedBlock::Filter(_) => Err(TinyError::Corruption(
+            "[table] block in the cache is not a data block".to_owned(),
+        )),
+    }
+}
+
+/// TableIterator yields the keys and their entries of a `Table` in order. An error
+/// reading a block is yielded once and ends the iteration.
+pub struct TableIterator<'a> {
+    table: Arc<Table>,
+    options: ReadOptions,
+    // reads the data blocks instead of the table if set
+    readahead: Option<ReadaheadReader<'a>>,
+    // the handles of the data blocks not read yet
+    handles: std::vec::IntoIter<BlockHandle>,
+    // the remaining entries of the current data block
+    block_entries: std::vec::IntoIter<KeyEntry>,
+}
+
+impl TableIterator<'_> {
+    // Reads the entries of the next data block
+    fn read_next_block(&mut self) -> Result<bool, TinyError> {
+        let handle = match self.handles.next() {
+            Some(handle) => handle,
+            None => return Ok(false),
+        };
+        let block = match self.readahead.as_mut() {
+            Some(reader) => {
+                let contents = reader.read(handle.offset, handle.size as usize + BLOCK_TRAILER_SIZE)?;
+                let block = self.table.decode_data_block(&self.options, BlockContents::Owned(contents))?;
+                Arc::new(CachedBlock::Block(block))
+            }
+            None => self.table.read_data_block(&self.options, &handle)?,
+        };
+        self.block_entries = data_block(&block)?
+            .entries()?
+            .into_iter()
+            .map(|(k, v)| Ok((k, Entry::decode(v)?)))
+            .collect::<Result<Vec<_>, TinyError>>()?
+            .into_iter();
+        Ok(true)
+    }
+}
+
+impl Iterator for TableIterator<'_> {
+    type Item = Result<KeyEntry, TinyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.block_entries.next() {
+                return Some(Ok(entry));
+            }
+            match self.read_next_block() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => {
+                    // stop after the error
+                    self.handles = vec![].into_iter();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
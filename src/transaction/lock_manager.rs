@@ -0,0 +1,188 @@
+use crate::util::error::TinyError;
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// The maximum length of the wait-for chain followed by the deadlock detector.
+/// A longer chain is regarded as a deadlock.
+const MAX_DEADLOCK_DETECT_DEPTH: usize = 50;
+
+/// A stripe of the lock table. Keys are spread over the stripes by hash so that
+/// transactions locking different keys rarely contend on the same mutex.
+struct LockStripe {
+    // key -> the id of the transaction holding the lock
+    locks: Mutex<HashMap<Vec<u8>, u64>>,
+    // notified whenever a lock in this stripe is released
+    released: Condvar,
+}
+
+/// LockManager grants exclusive per-key locks to transactions.
+///
+/// A transaction waiting for a lock adds an edge to the wait-for graph and the graph is
+/// checked for a cycle before waiting. If one is found, the requesting transaction gets
+/// `TinyError::Deadlock` instead of waiting forever, and is expected to roll back to
+/// release its locks.
+pub struct LockManager {
+    stripes: Vec<LockStripe>,
+    // the wait-for graph: txn id -> the id of the txn holding the lock it waits for
+    wait_for: Mutex<HashMap<u64, u64>>,
+}
+
+impl LockManager {
+    pub fn new(num_stripes: usize) -> Self {
+        invarint!(num_stripes > 0, "[lock manager] the number of stripes must be positive");
+        LockManager {
+            stripes: (0..num_stripes)
+                .map(|_| LockStripe {
+                    locks: Mutex::new(HashMap::new()),
+                    released: Condvar::new(),
+                })
+                .collect(),
+            wait_for: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Acquires the lock of `key` for the transaction `txn_id`, waiting at most `timeout`.
+    /// Locking a key the transaction already holds is a no-op.
+    ///
+    /// Returns `TinyError::TimedOut` if the lock can't be acquired in time, or
+    /// `TinyError::Deadlock` if waiting would deadlock.
+    pub fn lock(&self, txn_id: u64, key: &[u8], timeout: Duration) -> Result<(), TinyError> {
+        let stripe = self.stripe(key);
+        let deadline = Instant::now() + timeout;
+        let mut locks = stripe.locks.lock().unwrap();
+        loop {
+            let holder = match locks.get(key) {
+                None => {
+                    locks.insert(key.to_vec(), txn_id);
+                    self.stop_waiting(txn_id);
+                    return Ok(());
+                }
+                Some(holder) if *holder == txn_id => {
+                    self.stop_waiting(txn_id);
+                    return Ok(());
+                }
+                Some(holder) => *holder,
+            };
+            if let Some(cycle) = self.start_waiting(txn_id, holder) {
+                return Err(TinyError::Deadlock(format!(
+                    "[lock manager] transaction {} waiting for key {:?} would deadlock: {}",
+                    txn_id,
+                    key,
+                    cycle
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                )));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                self.stop_waiting(txn_id);
+                return Err(TinyError::TimedOut(format!(
+                    "[lock manager] transaction {} waiting for key {:?} held by transaction {}",
+                    txn_id, key, holder
+                )));
+            }
+            locks = stripe.released.wait_timeout(locks, deadline - now).unwrap().0;
+        }
+    }
+
+    /// Releases the lock of `key` if it's held by the transaction `txn_id`.
+    pub fn unlock(&self, txn_id: u64, key: &[u8]) {
+        let stripe = self.stripe(key);
+        let mut locks = stripe.locks.lock().unwrap();
+        if locks.get(key) == Some(&txn_id) {
+            locks.remove(key);
+            stripe.released.notify_all();
+        }
+    }
+
+    fn stripe(&self, key: &[u8]) -> &LockStripe {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.stripes[(hasher.finish() % self.stripes.len() as u64) as usize]
+    }
+
+    // Records that `txn_id` waits for `holder`. Returns the cycle in the wait-for graph
+    // if this leads to a deadlock, in which case the edge is not recorded.
+    fn start_waiting(&self, txn_id: u64, holder: u64) -> Option<Vec<u64>> {
+        let mut wait_for = self.wait_for.lock().unwrap();
+        let mut path = vec![txn_id, holder];
+        let mut current = holder;
+        while let Some(next) = wait_for.get(&current) {
+            path.push(*next);
+            if *next == txn_id || path.len() > MAX_DEADLOCK_DETECT_DEPTH {
+                wait_for.remove(&txn_id);
+                return Some(path);
+            }
+            current = *next;
+        }
+        wait_for.insert(txn_id, holder);
+        None
+    }
+
+    fn stop_waiting(&self, txn_id: u64) {
+        self.wait_for.lock().unwrap().remove(&txn_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_lock_and_unlock() {
+        let lm = LockManager::new(4);
+        let timeout = Duration::from_millis(10);
+        lm.lock(1, b"a", timeout).unwrap();
+        // reentrant
+        lm.lock(1, b"a", timeout).unwrap();
+        lm.lock(2, b"b", timeout).unwrap();
+        match lm.lock(2, b"a", timeout) {
+            Err(TinyError::TimedOut(_)) => {}
+            r => panic!("expect timed out but got {:?}", r),
+        }
+        // unlocking a lock held by others is ignored
+        lm.unlock(2, b"a");
+        assert!(lm.lock(2, b"a", timeout).is_err());
+        lm.unlock(1, b"a");
+        lm.lock(2, b"a", timeout).unwrap();
+        assert!(lm.wait_for.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_wait_for_release() {
+        let lm = Arc::new(LockManager::new(1));
+        lm.lock(1, b"k", Duration::from_secs(1)).unwrap();
+        let cloned = lm.clone();
+        let t = thread::spawn(move || cloned.lock(2, b"k", Duration::from_secs(10)));
+        thread::sleep(Duration::from_millis(50));
+        lm.unlock(1, b"k");
+        t.join().unwrap().unwrap();
+        assert!(lm.lock(1, b"k", Duration::from_millis(1)).is_err());
+    }
+
+    #[test]
+    fn test_deadlock_detection() {
+        let lm = LockManager::new(16);
+        let timeout = Duration::from_secs(10);
+        lm.lock(1, b"a", timeout).unwrap();
+        lm.lock(2, b"b", timeout).unwrap();
+        lm.lock(3, b"c", timeout).unwrap();
+        // simulate 1 -> 2 -> 3 waiting
+        assert!(lm.start_waiting(1, 2).is_none());
+        assert!(lm.start_waiting(2, 3).is_none());
+        match lm.lock(3, b"a", timeout) {
+            Err(TinyError::Deadlock(msg)) => assert!(msg.contains("3 -> 1 -> 2 -> 3"), "{}", msg),
+            r => panic!("expect deadlock but got {:?}", r),
+        }
+        // the aborted transaction doesn't wait for anyone
+        assert!(lm.wait_for.lock().unwrap().get(&3).is_none());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod lock_manager;
+pub mod optimistic;
+pub mod pessimistic;
@@ -0,0 +1,326 @@
+use crate::column_family::ColumnFamilyHandle;
+use crate::config::WriteOptions;
+use crate::db::DB;
+use crate::entry::ValueType;
+use crate::util::error::TinyError;
+use crate::util::slice::Slice;
+use crate::write_batch::WriteBatch;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// OptimisticTransactionDB wraps a `DB` to provide transactions which don't take any lock
+/// until committing. A transaction buffers its writes and remembers the sequence of the DB
+/// when it first reads or writes every key. When committing, the transaction fails with
+/// `TinyError::Busy` if any of the tracked keys has an entry of a later sequence in its
+/// column family, i.e. it has been written by another writer since it was tracked,
+/// including the writes issued on the wrapped DB directly.
+///
+/// A key whose entries have all been dropped by a compaction, e.g. a deletion reaching the
+/// bottommost level, is regarded as written at the largest sequence the compactions have
+/// dropped, so such a key may fail the commit even if it hasn't been written since tracked.
+pub struct OptimisticTransactionDB {
+    db: DB,
+}
+
+impl OptimisticTransactionDB {
+    pub fn new(db: DB) -> Self {
+        OptimisticTransactionDB { db }
+    }
+
+    /// Returns the wrapped DB. The writes issued on it directly are seen by the conflict
+    /// detection too.
+    #[inline]
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn begin_transaction(&self) -> OptimisticTransaction<'_> {
+        OptimisticTransaction {
+            txn_db: self,
+            default_handle: self.db.default_column_family(),
+            tracked_keys: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Writes the key/value pair outside of any transaction. The running
+    /// transactions which have tracked the key will fail to commit.
+    pub fn write(&self, key: Slice, value: Slice) -> Result<(), TinyError> {
+        self.db.write(key, value)
+    }
+
+    /// Deletes the key outside of any transaction. The running transactions
+    /// which have tracked the key will fail to commit.
+    pub fn delete(&self, key: Slice) -> Result<(), TinyError> {
+        self.db.delete(key)
+    }
+}
+
+/// A transaction begun by `OptimisticTransactionDB::begin_transaction`.
+/// Dropping a transaction without committing it rolls it back.
+pub struct OptimisticTransaction<'a> {
+    txn_db: &'a OptimisticTransactionDB,
+    default_handle: ColumnFamilyHandle,
+    // (column family, key) -> the last sequence of the DB when the key is tracked
+    tracked_keys: HashMap<(ColumnFamilyHandle, Vec<u8>), u64>,
+    // the buffered writes by (column family id, key), None represents a deletion
+    writes: BTreeMap<(u32, Vec<u8>), Option<Vec<u8>>>,
+}
+
+impl<'a> OptimisticTransaction<'a> {
+    /// Reads the key from the writes buffered by the transaction first and then from the DB.
+    pub fn get(&mut self, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        let handle = self.default_handle.clone();
+        self.get_cf(&handle, key)
+    }
+
+    pub fn put(&mut self, key: Slice, value: Slice) {
+        let handle = self.default_handle.clone();
+        self.put_cf(&handle, key, value)
+    }
+
+    pub fn delete(&mut self, key: Slice) {
+        let handle = self.default_handle.clone();
+        self.delete_cf(&handle, key)
+    }
+
+    /// Reads the key in the column family from the writes buffered by the transaction
+    /// first and then from the DB.
+    pub fn get_cf(&mut self, handle: &ColumnFamilyHandle, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        let k = key.to_slice();
+        if let Some(buffered) = self.writes.get(&(handle.id(), k.to_vec())) {
+            return Ok(buffered.clone());
+        }
+        self.track_key(handle, k);
+        self.txn_db.db.get_cf(handle, key)
+    }
+
+    pub fn put_cf(&mut self, handle: &ColumnFamilyHandle, key: Slice, value: Slice) {
+        let k = key.to_slice();
+        self.track_key(handle, k);
+        self.writes.insert((handle.id(), k.to_vec()), Some(value.to_slice().to_vec()));
+    }
+
+    pub fn delete_cf(&mut self, handle: &ColumnFamilyHandle, key: Slice) {
+        let k = key.to_slice();
+        self.track_key(handle, k);
+        self.writes.insert((handle.id(), k.to_vec()), None);
+    }
+
+    /// Validates that none of the tracked keys has been written by others since it was
+    /// tracked and applies the buffered writes to the DB as one atomic batch. The other
+    /// writes are blocked from the validation to the end of the commit.
+    /// Returns `TinyError::Busy` if a conflict is detected, in which case nothing is written
+    /// and the transaction can be retried from the beginning.
+    pub fn commit(self) -> Result<(), TinyError> {
+        let db = &self.txn_db.db;
+        let mut batch = WriteBatch::new();
+        for ((column_family, key), value) in self.writes.iter() {
+            match value {
+                Some(v) => batch.add(*column_family, ValueType::Value, key, v),
+                None => batch.add(*column_family, ValueType::Deletion, key, &[]),
+            }
+        }
+        db.write_batch_if(&WriteOptions::default(), batch, || {
+            for ((handle, key), seq) in self.tracked_keys.iter() {
+                let current = db.latest_sequence_of(handle, key)?;
+                if current > *seq {
+                    return Err(TinyError::Busy(format!(
+                        "[transaction] key {:?} of column family {} has been written at sequence {} after it was read at sequence {}",
+                        key,
+                        handle.name(),
+                        current,
+                        seq
+                    )));
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Discards all the buffered writes
+    pub fn rollback(self) {}
+
+    fn track_key(&mut self, handle: &ColumnFamilyHandle, key: &[u8]) {
+        let last_sequence = self.txn_db.db.last_sequence();
+        self.tracked_keys
+            .entry((handle.clone(), key.to_vec()))
+            .or_insert(last_sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::column_family::ColumnFamilyOptions;
+    use crate::compaction::job::CompactRangeOptions;
+    use crate::config::Config;
+    use crate::db::open_db;
+    use crate::env::mem::MemEnv;
+    use crate::record::reader::Reader;
+    use std::sync::Arc;
+
+    fn new_test_txn_db() -> OptimisticTransactionDB {
+        OptimisticTransactionDB::new(open_db(Config {
+            dir: "/db".to_owned(),
+            env: Arc::new(MemEnv::new()),
+            ..Config::default()
+        })
+        .unwrap())
+    }
+
+    fn is_busy(r: Result<(), TinyError>) -> bool {
+        matches!(r, Err(TinyError::Busy(_)))
+    }
+
+    #[test]
+    fn test_read_your_own_writes() {
+        let txn_db = new_test_txn_db();
+        let mut txn = txn_db.begin_transaction();
+        assert_eq!(txn.get(Slice::from("k")).unwrap(), None);
+        txn.put(Slice::from("k"), Slice::from("v"));
+        assert_eq!(txn.get(Slice::from("k")).unwrap(), Some(b"v".to_vec()));
+        txn.delete(Slice::from("k"));
+        assert_eq!(txn.get(Slice::from("k")).unwrap(), None);
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_read_conflict() {
+        let txn_db = new_test_txn_db();
+        let mut txn1 = txn_db.begin_transaction();
+        let mut txn2 = txn_db.begin_transaction();
+        txn1.get(Slice::from("counter")).unwrap();
+        txn1.put(Slice::from("counter"), Slice::from("1"));
+        txn2.get(Slice::from("counter")).unwrap();
+        txn2.put(Slice::from("counter"), Slice::from("1"));
+        txn1.commit().unwrap();
+        assert!(is_busy(txn2.commit()));
+
+        // the retry succeeds
+        let mut txn3 = txn_db.begin_transaction();
+        txn3.get(Slice::from("counter")).unwrap();
+        txn3.put(Slice::from("counter"), Slice::from("2"));
+        txn3.commit().unwrap();
+    }
+
+    #[test]
+    fn test_conflict_with_non_transactional_write() {
+        let txn_db = new_test_txn_db();
+        let mut txn = txn_db.begin_transaction();
+        txn.get(Slice::from("a")).unwrap();
+        txn_db.write(Slice::from("a"), Slice::from("x")).unwrap();
+        assert!(is_busy(txn.commit()));
+
+        let mut txn = txn_db.begin_transaction();
+        txn.put(Slice::from("b"), Slice::from("y"));
+        txn_db.delete(Slice::from("b")).unwrap();
+        assert!(is_busy(txn.commit()));
+    }
+
+    #[test]
+    fn test_no_conflict_on_disjoint_keys() {
+        let txn_db = new_test_txn_db();
+        let mut txn1 = txn_db.begin_transaction();
+        let mut txn2 = txn_db.begin_transaction();
+        txn1.get(Slice::from("a")).unwrap();
+        txn1.put(Slice::from("a"), Slice::from("1"));
+        txn2.get(Slice::from("b")).unwrap();
+        txn2.put(Slice::from("b"), Slice::from("1"));
+        txn2.commit().unwrap();
+        txn1.commit().unwrap();
+        // a write tracked after the other commit doesn't conflict
+        let mut txn3 = txn_db.begin_transaction();
+        txn_db.write(Slice::from("a"), Slice::from("2")).unwrap();
+        txn3.get(Slice::from("a")).unwrap();
+        txn3.commit().unwrap();
+    }
+
+    #[test]
+    fn test_conflict_with_db_write() {
+        let txn_db = new_test_txn_db();
+        let mut txn = txn_db.begin_transaction();
+        txn.get(Slice::from("a")).unwrap();
+        txn_db.db().write(Slice::from("a"), Slice::from("x")).unwrap();
+        // found in the table file after the memtable is flushed
+        txn_db
+            .db()
+            .compact_range(None, None, &CompactRangeOptions::default())
+            .unwrap();
+        assert!(is_busy(txn.commit()));
+
+        // the writes of a failed commit are discarded
+        let mut txn = txn_db.begin_transaction();
+        txn.put(Slice::from("b"), Slice::from("1"));
+        txn.put(Slice::from("c"), Slice::from("1"));
+        txn_db.db().delete(Slice::from("c")).unwrap();
+        assert!(is_busy(txn.commit()));
+        assert_eq!(txn_db.db().get(Slice::from("b")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_conflict_in_column_family() {
+        let txn_db = new_test_txn_db();
+        let cf = txn_db.db().create_column_family("cf", ColumnFamilyOptions::default()).unwrap();
+        let mut txn = txn_db.begin_transaction();
+        assert_eq!(txn.get_cf(&cf, Slice::from("a")).unwrap(), None);
+        txn.put_cf(&cf, Slice::from("a"), Slice::from("1"));
+        assert_eq!(txn.get_cf(&cf, Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        // the same key in another column family doesn't conflict
+        txn_db.write(Slice::from("a"), Slice::from("x")).unwrap();
+        txn.commit().unwrap();
+        assert_eq!(txn_db.db().get_cf(&cf, Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn_db.db().get(Slice::from("a")).unwrap(), Some(b"x".to_vec()));
+
+        let mut txn = txn_db.begin_transaction();
+        txn.get_cf(&cf, Slice::from("b")).unwrap();
+        txn_db.db().write_cf(&cf, Slice::from("b"), Slice::from("y")).unwrap();
+        txn_db
+            .db()
+            .compact_range_cf(&cf, None, None, &CompactRangeOptions::default())
+            .unwrap();
+        assert!(is_busy(txn.commit()));
+    }
+
+    #[test]
+    fn test_conflict_with_compacted_away_write() {
+        let txn_db = new_test_txn_db();
+        let mut txn = txn_db.begin_transaction();
+        assert_eq!(txn.get(Slice::from("a")).unwrap(), None);
+        txn.put(Slice::from("a"), Slice::from("1"));
+        // the deletion is dropped in the bottommost level and leaves no entry of the key
+        txn_db.write(Slice::from("a"), Slice::from("x")).unwrap();
+        txn_db.delete(Slice::from("a")).unwrap();
+        txn_db
+            .db()
+            .compact_range(None, None, &CompactRangeOptions::default())
+            .unwrap();
+        assert!(is_busy(txn.commit()));
+
+        // the keys tracked after the compaction aren't affected
+        let mut txn = txn_db.begin_transaction();
+        assert_eq!(txn.get(Slice::from("a")).unwrap(), None);
+        txn.put(Slice::from("a"), Slice::from("2"));
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_commit_as_one_batch() {
+        let txn_db = new_test_txn_db();
+        let mut txn = txn_db.begin_transaction();
+        txn.put(Slice::from("a"), Slice::from("1"));
+        txn.put(Slice::from("b"), Slice::from("2"));
+        txn.delete(Slice::from("c"));
+        let last_sequence = txn_db.db().last_sequence();
+        txn.commit().unwrap();
+        assert_eq!(txn_db.db().last_sequence(), last_sequence + 3);
+
+        // a single record of the WAL
+        let env = txn_db.db().config().env.clone();
+        let wal = txn_db.db().get_sorted_wal_files().unwrap().pop().unwrap();
+        let mut reader = Reader::new(env.new_sequential_file(&wal).unwrap(), true);
+        let batch = WriteBatch::from_data(reader.read_record().unwrap().unwrap()).unwrap();
+        assert_eq!(batch.count(), 3);
+        assert!(reader.read_record().unwrap().is_none());
+    }
+}
@@ -0,0 +1,329 @@
+use super::lock_manager::LockManager;
+use crate::config::WriteOptions;
+use crate::db::DB;
+use crate::util::error::TinyError;
+use crate::util::slice::Slice;
+use crate::write_batch::WriteBatch;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub struct TransactionDBOptions {
+    /// The number of stripes of the lock table.
+    pub num_stripes: usize,
+    /// How long a transaction waits for a lock before giving up with `TimedOut`.
+    pub lock_timeout: Duration,
+}
+
+impl Default for TransactionDBOptions {
+    fn default() -> Self {
+        TransactionDBOptions {
+            num_stripes: 16,
+            lock_timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+/// TransactionDB wraps a `DB` to provide pessimistic transactions. Every key written or
+/// read by `get_for_update` is locked until the transaction finishes, so a committing
+/// transaction never conflicts with others. Waiting transactions are checked for
+/// deadlocks and one of the participants is aborted with `TinyError::Deadlock`.
+pub struct TransactionDB {
+    db: DB,
+    lock_manager: LockManager,
+    lock_timeout: Duration,
+    next_txn_id: AtomicU64,
+}
+
+impl TransactionDB {
+    pub fn new(db: DB, options: TransactionDBOptions) -> Self {
+        TransactionDB {
+            db,
+            lock_manager: LockManager::new(options.num_stripes),
+            lock_timeout: options.lock_timeout,
+            next_txn_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Returns the wrapped DB. Writes issued on it directly don't respect the locks.
+    #[inline]
+    pub fn db(&self) -> &DB {
+        &self.db
+    }
+
+    pub fn begin_transaction(&self) -> Transaction<'_> {
+        Transaction {
+            txn_db: self,
+            id: self.next_txn_id.fetch_add(1, Ordering::SeqCst),
+            writes: vec![],
+            locked_keys: vec![],
+            savepoints: vec![],
+            finished: false,
+        }
+    }
+
+    /// Writes the key/value pair outside of any transaction, waiting for the
+    /// transaction holding the lock of the key to finish.
+    pub fn write(&self, key: Slice, value: Slice) -> Result<(), TinyError> {
+        let mut txn = self.begin_transaction();
+        txn.put(key, value)?;
+        txn.commit()
+    }
+}
+
+struct SavePoint {
+    num_writes: usize,
+    num_locked_keys: usize,
+}
+
+/// A transaction begun by `TransactionDB::begin_transaction`.
+/// Dropping a transaction without committing it rolls it back.
+pub struct Transaction<'a> {
+    txn_db: &'a TransactionDB,
+    id: u64,
+    // the buffered writes in the order they are issued, None represents a deletion
+    writes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    // the keys locked by the transaction in the order they are locked
+    locked_keys: Vec<Vec<u8>>,
+    savepoints: Vec<SavePoint>,
+    finished: bool,
+}
+
+impl<'a> Transaction<'a> {
+    #[inline]
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Reads the key from the writes buffered by the transaction first and then from the DB
+    /// without locking it.
+    pub fn get(&self, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        let k = key.to_slice();
+        if let Some((_, buffered)) = self.writes.iter().rev().find(|(wk, _)| wk.as_slice() == k) {
+            return Ok(buffered.clone());
+        }
+        self.txn_db.db.get(key)
+    }
+
+    /// Locks the key and reads it, so that no other transaction can write the key
+    /// until this one finishes.
+    pub fn get_for_update(&mut self, key: Slice) -> Result<Option<Vec<u8>>, TinyError> {
+        self.lock(key.to_slice())?;
+        self.get(key)
+    }
+
+    pub fn put(&mut self, key: Slice, value: Slice) -> Result<(), TinyError> {
+        let k = key.to_slice();
+        self.lock(k)?;
+        self.writes.push((k.to_vec(), Some(value.to_slice().to_vec())));
+        Ok(())
+    }
+
+    pub fn delete(&mut self, key: Slice) -> Result<(), TinyError> {
+        let k = key.to_slice();
+        self.lock(k)?;
+        self.writes.push((k.to_vec(), None));
+        Ok(())
+    }
+
+    /// Records the current state of the transaction which can be restored by
+    /// `rollback_to_savepoint`. Savepoints can be nested.
+    pub fn set_savepoint(&mut self) {
+        self.savepoints.push(SavePoint {
+            num_writes: self.writes.len(),
+            num_locked_keys: self.locked_keys.len(),
+        });
+    }
+
+    /// Discards the writes issued since the latest savepoint and releases the locks
+    /// acquired since then. Returns `NotSupported` if there is no savepoint.
+    pub fn rollback_to_savepoint(&mut self) -> Result<(), TinyError> {
+        match self.savepoints.pop() {
+            Some(sp) => {
+                self.writes.truncate(sp.num_writes);
+                for key in self.locked_keys.drain(sp.num_locked_keys..) {
+                    self.txn_db.lock_manager.unlock(self.id, &key);
+                }
+                Ok(())
+            }
+            None => Err(TinyError::NotSupported(
+                "[transaction] no savepoint to roll back to".to_owned(),
+            )),
+        }
+    }
+
+    /// Applies the buffered writes to the DB as one atomic batch and releases all the
+    /// locks. Nothing is written if the commit fails.
+    pub fn commit(mut self) -> Result<(), TinyError> {
+        let mut batch = WriteBatch::new();
+        for (key, value) in self.writes.iter() {
+            match value {
+                Some(v) => batch.put(key, v),
+                None => batch.delete(key),
+            }
+        }
+        self.txn_db.db.write_batch(&WriteOptions::default(), batch)?;
+        self.release();
+        Ok(())
+    }
+
+    /// Discards all the buffered writes and releases all the locks.
+    pub fn rollback(mut self) {
+        self.release();
+    }
+
+    fn lock(&mut self, key: &[u8]) -> Result<(), TinyError> {
+        if self.locked_keys.iter().any(|k| k.as_slice() == key) {
+            return Ok(());
+        }
+        self.txn_db
+            .lock_manager
+            .lock(self.id, key, self.txn_db.lock_timeout)?;
+        self.locked_keys.push(key.to_vec());
+        Ok(())
+    }
+
+    fn release(&mut self) {
+        for key in self.locked_keys.drain(..) {
+            self.txn_db.lock_manager.unlock(self.id, &key);
+        }
+        self.writes.clear();
+        self.savepoints.clear();
+        self.finished = true;
+    }
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.release();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::db::open_db;
+    use crate::env::mem::MemEnv;
+    use crate::record::reader::Reader;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    fn new_test_txn_db(lock_timeout: Duration) -> TransactionDB {
+        TransactionDB::new(
+            open_db(Config {
+                dir: "/db".to_owned(),
+                env: Arc::new(MemEnv::new()),
+                ..Config::default()
+            })
+            .unwrap(),
+            TransactionDBOptions {
+                lock_timeout,
+                ..TransactionDBOptions::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_lock_timeout() {
+        let txn_db = new_test_txn_db(Duration::from_millis(10));
+        let mut txn1 = txn_db.begin_transaction();
+        let mut txn2 = txn_db.begin_transaction();
+        txn1.get_for_update(Slice::from("k")).unwrap();
+        match txn2.put(Slice::from("k"), Slice::from("v")) {
+            Err(TinyError::TimedOut(_)) => {}
+            r => panic!("expect timed out but got {:?}", r),
+        }
+        txn1.commit().unwrap();
+        txn2.put(Slice::from("k"), Slice::from("v")).unwrap();
+        assert!(txn_db.write(Slice::from("k"), Slice::from("v")).is_err());
+        txn2.rollback();
+        txn_db.write(Slice::from("k"), Slice::from("v")).unwrap();
+    }
+
+    #[test]
+    fn test_savepoint() {
+        let txn_db = new_test_txn_db(Duration::from_millis(10));
+        let mut txn = txn_db.begin_transaction();
+        assert!(txn.rollback_to_savepoint().is_err());
+        txn.put(Slice::from("a"), Slice::from("1")).unwrap();
+        txn.set_savepoint();
+        txn.put(Slice::from("a"), Slice::from("2")).unwrap();
+        txn.put(Slice::from("b"), Slice::from("1")).unwrap();
+        txn.set_savepoint();
+        txn.delete(Slice::from("c")).unwrap();
+        assert_eq!(txn.get(Slice::from("c")).unwrap(), None);
+
+        txn.rollback_to_savepoint().unwrap();
+        // the lock of c acquired after the savepoint is released
+        let mut other = txn_db.begin_transaction();
+        other.put(Slice::from("c"), Slice::from("x")).unwrap();
+        other.commit().unwrap();
+        assert_eq!(txn.get(Slice::from("b")).unwrap(), Some(b"1".to_vec()));
+
+        txn.rollback_to_savepoint().unwrap();
+        assert_eq!(txn.get(Slice::from("a")).unwrap(), Some(b"1".to_vec()));
+        assert_eq!(txn.get(Slice::from("b")).unwrap(), None);
+        // a is still locked since it was locked before the savepoint
+        assert!(txn_db.write(Slice::from("a"), Slice::from("x")).is_err());
+        txn_db.write(Slice::from("b"), Slice::from("x")).unwrap();
+        txn.commit().unwrap();
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let txn_db = Arc::new(new_test_txn_db(Duration::from_secs(10)));
+        let barrier = Arc::new(Barrier::new(2));
+        let handles = vec![("a", "b"), ("b", "a")]
+            .into_iter()
+            .map(|(first, second)| {
+                let txn_db = txn_db.clone();
+                let barrier = barrier.clone();
+                thread::spawn(move || {
+                    let mut txn = txn_db.begin_transaction();
+                    txn.put(Slice::from(first), Slice::from("1")).unwrap();
+                    barrier.wait();
+                    match txn.put(Slice::from(second), Slice::from("2")) {
+                        Ok(()) => txn.commit().map(|_| false),
+                        Err(TinyError::Deadlock(_)) => {
+                            txn.rollback();
+                            Ok(true)
+                        }
+                        Err(e) => Err(e),
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        let aborted = handles
+            .into_iter()
+            .map(|h| h.join().unwrap().unwrap())
+            .filter(|aborted| *aborted)
+            .count();
+        assert_eq!(aborted, 1);
+    }
+
+    #[test]
+    fn test_commit_as_one_batch() {
+        let txn_db = new_test_txn_db(Duration::from_secs(1));
+        let mut txn = txn_db.begin_transaction();
+        txn.put(Slice::from("a"), Slice::from("1")).unwrap();
+        txn.put(Slice::from("b"), Slice::from("2")).unwrap();
+        txn.delete(Slice::from("a")).unwrap();
+        let last_sequence = txn_db.db().last_sequence();
+        txn.commit().unwrap();
+        assert_eq!(txn_db.db().last_sequence(), last_sequence + 3);
+        // applied in the order they are issued
+        assert_eq!(txn_db.db().get(Slice::from("a")).unwrap(), None);
+        assert_eq!(txn_db.db().get(Slice::from("b")).unwrap(), Some(b"2".to_vec()));
+
+        // a single record of the WAL
+        let env = txn_db.db().config().env.clone();
+        let wal = txn_db.db().get_sorted_wal_files().unwrap().pop().unwrap();
+        let mut reader = Reader::new(env.new_sequential_file(&wal).unwrap(), true);
+        let batch = WriteBatch::from_data(reader.read_record().unwrap().unwrap()).unwrap();
+        assert_eq!(batch.count(), 3);
+        assert!(reader.read_record().unwrap().is_none());
+    }
+}
@@ -0,0 +1,85 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A Clock provides the current time to the components depending on the wall clock
+/// such as the TTL expiration. It can be replaced in `Config` to control the time in tests.
+pub trait Clock: Send + Sync {
+    /// Returns the number of micro-seconds since the Unix epoch.
+    fn now_micros(&self) -> u64;
+}
+
+/// SystemClock reads the time from the operating system.
+pub struct SystemClock {}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        SystemClock {}
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now_micros(&self) -> u64 {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_micros() as u64,
+            Err(_) => 0,
+        }
+    }
+}
+
+/// ManualClock only moves when being told to. Useful for testing time based behaviors.
+pub struct ManualClock {
+    micros: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn new(micros: u64) -> ManualClock {
+        ManualClock {
+            micros: AtomicU64::new(micros),
+        }
+    }
+
+    pub fn set_micros(&self, micros: u64) {
+        self.micros.store(micros, Ordering::Release);
+    }
+
+    pub fn advance_micros(&self, delta: u64) {
+        self.micros.fetch_add(delta, Ordering::AcqRel);
+    }
+}
+
+impl Clock for ManualClock {
+    #[inline]
+    fn now_micros(&self) -> u64 {
+        self.micros.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manual_clock() {
+        let clock = ManualClock::new(10);
+        assert_eq!(clock.now_micros(), 10);
+        clock.advance_micros(5);
+        assert_eq!(clock.now_micros(), 15);
+        clock.set_micros(3);
+        assert_eq!(clock.now_micros(), 3);
+    }
+
+    #[test]
+    fn test_system_clock() {
+        let clock = SystemClock::new();
+        let t1 = clock.now_micros();
+        let t2 = clock.now_micros();
+        assert!(t1 > 0);
+        assert!(t2 >= t1);
+    }
+}
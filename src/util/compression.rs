@@ -0,0 +1,366 @@
+use super::error::TinyError;
+use super::varint::{read_u64, write_u64, MAX_VARINT_LEN_U64};
+
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The compression applied to a block. The value is stored in the block trailer
+/// so it must never change once it's assigned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CompressionType {
+    NoCompression,
+    LZ4,
+    /// A user-defined codec registered in `CompressorRegistry` under the given value,
+    /// which shouldn't collide with the built-in types
+    Custom(u8),
+}
+
+impl CompressionType {
+    /// Returns the compression type of the byte stored in a block trailer
+    pub fn from_u8(b: u8) -> CompressionType {
+        match b {
+            0 => CompressionType::NoCompression,
+            1 => CompressionType::LZ4,
+            b => CompressionType::Custom(b),
+        }
+    }
+
+    /// Returns the byte stored in the block trailer
+    pub fn to_u8(self) -> u8 {
+        match self {
+            CompressionType::NoCompression => 0,
+            CompressionType::LZ4 => 1,
+            CompressionType::Custom(b) => b,
+        }
+    }
+}
+
+/// CompressorRegistry maps the compression types to the codecs implementing them, by
+/// which the blocks are compressed and decompressed. LZ4 is registered by default.
+#[derive(Clone)]
+pub struct CompressorRegistry {
+    codecs: HashMap<u8, Arc<dyn Compressor>>,
+}
+
+impl CompressorRegistry {
+    /// Registers `codec` for `compression`, replacing the codec registered before.
+    /// Returns `InvalidArgument` for `NoCompression`.
+    pub fn register(&mut self, compression: CompressionType, codec: Arc<dyn Compressor>) -> Result<(), TinyError> {
+        if compression.to_u8() == CompressionType::NoCompression.to_u8() {
+            return Err(TinyError::InvalidArgument(
+                "[compression] no codec can be registered for NoCompression".to_owned(),
+            ));
+        }
+        self.codecs.insert(compression.to_u8(), codec);
+        Ok(())
+    }
+
+    /// Returns the codec of `compression`, `None` for `NoCompression` or an unregistered type
+    pub fn get(&self, compression: CompressionType) -> Option<&dyn Compressor> {
+        self.codecs.get(&compression.to_u8()).map(|c| c.as_ref())
+    }
+}
+
+impl Default for CompressorRegistry {
+    fn default() -> Self {
+        let mut codecs: HashMap<u8, Arc<dyn Compressor>> = HashMap::new();
+        codecs.insert(CompressionType::LZ4.to_u8(), Arc::new(Lz4Compressor {}));
+        CompressorRegistry { codecs }
+    }
+}
+
+/// A Compressor is a codec used to compress the blocks of the table files.
+/// A Compressor implementation must be thread-safe.
+pub trait Compressor: Send + Sync {
+    /// Compresses `src` and appends the result to `dst`
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>);
+
+    /// Decompresses the data produced by `compress`.
+    /// Returns `TinyError::Corruption` if `src` is malformed.
+    fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, TinyError>;
+
+    /// Compresses `src` with the help of a dictionary shared by the blocks of a table file
+    /// and appends the result to `dst`. Codecs without dictionary support ignore `dict`.
+    fn compress_with_dict(&self, src: &[u8], dict: &[u8], dst: &mut Vec<u8>) {
+        let _ = dict;
+        self.compress(src, dst)
+    }
+
+    /// Decompresses the data produced by `compress_with_dict` with the same dictionary.
+    fn decompress_with_dict(&self, src: &[u8], dict: &[u8]) -> Result<Vec<u8>, TinyError> {
+        let _ = dict;
+        self.decompress(src)
+    }
+}
+
+const MIN_MATCH: usize = 4;
+// the last 5 bytes are always literals
+const LAST_LITERALS: usize = 5;
+// a match must start at least 12 bytes before the end of input
+const MF_LIMIT: usize = 12;
+const MAX_DISTANCE: usize = 65535;
+const HASH_LOG: u32 = 12;
+
+/// Lz4Compressor implements the LZ4 block format. The compressed data is
+/// prefixed with the varint encoded length of the uncompressed data.
+pub struct Lz4Compressor {}
+
+impl Lz4Compressor {
+    #[inline]
+    fn hash(seq: u32) -> usize {
+        (seq.wrapping_mul(2_654_435_761) >> (32 - HASH_LOG)) as usize
+    }
+
+    fn write_length(dst: &mut Vec<u8>, mut len: usize) {
+        while len >= 255 {
+            dst.push(255);
+            len -= 255;
+        }
+        dst.push(len as u8);
+    }
+
+    fn write_sequence(dst: &mut Vec<u8>, literals: &[u8], m: Option<(usize, usize)>) {
+        let lit_len = literals.len();
+        let mut token = (lit_len.min(15) as u8) << 4;
+        if let Some((_, match_len)) = m {
+            token |= (match_len - MIN_MATCH).min(15) as u8;
+        }
+        dst.push(token);
+        if lit_len >= 15 {
+            Self::write_length(dst, lit_len - 15);
+        }
+        dst.extend_from_slice(literals);
+        if let Some((offset, match_len)) = m {
+            let mut buf = [0u8; 2];
+            LittleEndian::write_u16(&mut buf, offset as u16);
+            dst.extend_from_slice(&buf);
+            if match_len - MIN_MATCH >= 15 {
+                Self::write_length(dst, match_len - MIN_MATCH - 15);
+            }
+        }
+    }
+
+    fn read_length(src: &[u8], pos: &mut usize, mut len: usize) -> Result<usize, TinyError> {
+        loop {
+            let b = *src
+                .get(*pos)
+                .ok_or_else(|| corruption("truncated length"))? as usize;
+            *pos += 1;
+            len += b;
+            if b != 255 {
+                return Ok(len);
+            }
+        }
+    }
+}
+
+fn corruption(msg: &str) -> TinyError {
+    TinyError::Corruption(format!("[lz4] {}", msg))
+}
+
+impl Compressor for Lz4Compressor {
+    fn compress(&self, src: &[u8], dst: &mut Vec<u8>) {
+        self.compress_with_dict(src, &[], dst)
+    }
+
+    fn decompress(&self, src: &[u8]) -> Result<Vec<u8>, TinyError> {
+        self.decompress_with_dict(src, &[])
+    }
+
+    /// The dictionary is regarded as the data preceding `src` so that the matches can
+    /// refer to it. Only the last 64KB of the dictionary is reachable.
+    fn compress_with_dict(&self, src: &[u8], dict: &[u8], dst: &mut Vec<u8>) {
+        let mut buf = [0u8; MAX_VARINT_LEN_U64];
+        let n = write_u64(&mut buf, src.len() as u64);
+        dst.extend_from_slice(&buf[..n]);
+
+        let dict = &dict[dict.len().saturating_sub(MAX_DISTANCE)..];
+        let input = if dict.is_empty() {
+            src.to_vec()
+        } else {
+            [dict, src].concat()
+        };
+        let mut anchor = dict.len();
+        if src.len() > MF_LIMIT {
+            let match_limit = input.len() - MF_LIMIT;
+            let match_end_limit = input.len() - LAST_LITERALS;
+            let mut table = vec![usize::MAX; 1 << HASH_LOG];
+            for p in 0..dict.len().saturating_sub(MIN_MATCH - 1) {
+                table[Self::hash(LittleEndian::read_u32(&input[p..]))] = p;
+            }
+            let mut i = dict.len();
+            while i < match_limit {
+                let seq = LittleEndian::read_u32(&input[i..]);
+                let h = Self::hash(seq);
+                let candidate = table[h];
+                table[h] = i;
+                if candidate != usize::MAX
+                    && i - candidate <= MAX_DISTANCE
+                    && LittleEndian::read_u32(&input[candidate..]) == seq
+                {
+                    let mut len = MIN_MATCH;
+                    while i + len < match_end_limit && input[candidate + len] == input[i + len] {
+                        len += 1;
+                    }
+                    Self::write_sequence(dst, &input[anchor..i], Some((i - candidate, len)));
+                    i += len;
+                    anchor = i;
+                } else {
+                    i += 1;
+                }
+            }
+        }
+        Self::write_sequence(dst, &input[anchor..], None);
+    }
+
+    fn decompress_with_dict(&self, src: &[u8], dict: &[u8]) -> Result<Vec<u8>, TinyError> {
+        let (size, n) = read_u64(src);
+        if n <= 0 {
+            return Err(corruption("invalid uncompressed length"));
+        }
+        let dict = &dict[dict.len().saturating_sub(MAX_DISTANCE)..];
+        let size = (size as usize)
+            .checked_add(dict.len())
+            .ok_or_else(|| corruption("invalid uncompressed length"))?;
+        let mut pos = n as usize;
+        // the length might be broken so don't trust it for allocation
+        let mut out = Vec::with_capacity(dict.len() + src.len());
+        out.extend_from_slice(dict);
+        loop {
+            let token = *src.get(pos).ok_or_else(|| corruption("truncated token"))?;
+            pos += 1;
+            let mut lit_len = (token >> 4) as usize;
+            if lit_len == 15 {
+                lit_len = Self::read_length(src, &mut pos, lit_len)?;
+            }
+            if pos + lit_len > src.len() || out.len() + lit_len > size {
+                return Err(corruption("literals out of range"));
+            }
+            out.extend_from_slice(&src[pos..pos + lit_len]);
+            pos += lit_len;
+            if pos == src.len() {
+                break;
+            }
+            if pos + 2 > src.len() {
+                return Err(corruption("truncated offset"));
+            }
+            let offset = LittleEndian::read_u16(&src[pos..]) as usize;
+            pos += 2;
+            if offset == 0 || offset > out.len() {
+                return Err(corruption("invalid offset"));
+            }
+            let mut match_len = (token & 0x0f) as usize;
+            if match_len == 15 {
+                match_len = Self::read_length(src, &mut pos, match_len)?;
+            }
+            match_len += MIN_MATCH;
+            if out.len() + match_len > size {
+                return Err(corruption("match out of range"));
+            }
+            // the match may overlap with the bytes being copied
+            let start = out.len() - offset;
+            for i in 0..match_len {
+                let b = out[start + i];
+                out.push(b);
+            }
+        }
+        if out.len() != size {
+            return Err(corruption("uncompressed length mismatch"));
+        }
+        Ok(out.split_off(dict.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    fn round_trip(input: &[u8]) -> usize {
+        let c = Lz4Compressor {};
+        let mut compressed = vec![];
+        c.compress(input, &mut compressed);
+        assert_eq!(c.decompress(&compressed).unwrap(), input);
+        compressed.len()
+    }
+
+    #[test]
+    fn test_compression_type() {
+        for t in [CompressionType::NoCompression, CompressionType::LZ4, CompressionType::Custom(100)].iter() {
+            assert_eq!(CompressionType::from_u8(t.to_u8()), *t);
+        }
+        let mut registry = CompressorRegistry::default();
+        assert!(registry.get(CompressionType::NoCompression).is_none());
+        assert!(registry.get(CompressionType::LZ4).is_some());
+        assert!(registry.get(CompressionType::Custom(100)).is_none());
+        registry.register(CompressionType::Custom(100), Arc::new(Lz4Compressor {})).unwrap();
+        assert!(registry.get(CompressionType::Custom(100)).is_some());
+        assert!(matches!(
+            registry.register(CompressionType::Custom(0), Arc::new(Lz4Compressor {})),
+            Err(TinyError::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_lz4_round_trip() {
+        let mut rng = rand::thread_rng();
+        let random: Vec<u8> = (0..10000).map(|_| rng.gen()).collect();
+        let tests: Vec<Vec<u8>> = vec![
+            vec![],
+            b"a".to_vec(),
+            b"abcdabcdabcd".to_vec(),
+            b"abcdabcdabcdabcdabcdabcd".to_vec(),
+            vec![7u8; 1000],
+            b"{\"id\":1,\"name\":\"tiny\"}".repeat(100),
+            random,
+        ];
+        for t in tests.iter() {
+            round_trip(t);
+        }
+    }
+
+    #[test]
+    fn test_lz4_compress_ratio() {
+        let input = b"{\"id\":1,\"name\":\"tiny\"}".repeat(100);
+        assert!(round_trip(&input) < input.len() / 10);
+        // long runs exercise the extended match length
+        let input = vec![0u8; 100_000];
+        assert!(round_trip(&input) < 1000);
+    }
+
+    #[test]
+    fn test_lz4_with_dict() {
+        let c = Lz4Compressor {};
+        let dict = b"{\"user_id\":,\"status\":\"active\",\"created_at\":\"2020-01-01\"}".to_vec();
+        let input = b"{\"user_id\":42,\"status\":\"active\",\"created_at\":\"2020-01-01\"}".to_vec();
+        let mut plain = vec![];
+        c.compress(&input, &mut plain);
+        let mut with_dict = vec![];
+        c.compress_with_dict(&input, &dict, &mut with_dict);
+        assert!(with_dict.len() < plain.len());
+        assert_eq!(c.decompress_with_dict(&with_dict, &dict).unwrap(), input);
+        // decompressing with a different dictionary never reads out of the output
+        let _ = c.decompress_with_dict(&with_dict, b"short");
+        assert!(c.decompress(&with_dict).is_err());
+
+        // short inputs and empty dictionaries
+        for (input, dict) in [(&b""[..], &dict[..]), (b"abc", &dict[..]), (&input[..], b"")] {
+            let mut compressed = vec![];
+            c.compress_with_dict(input, dict, &mut compressed);
+            assert_eq!(c.decompress_with_dict(&compressed, dict).unwrap(), input);
+        }
+    }
+
+    #[test]
+    fn test_lz4_decompress_corruption() {
+        let c = Lz4Compressor {};
+        let mut compressed = vec![];
+        c.compress(&b"hello hello hello hello hello".repeat(3), &mut compressed);
+        for len in 0..compressed.len() {
+            assert!(c.decompress(&compressed[..len]).is_err());
+        }
+        // offset pointing before the beginning
+        assert!(c.decompress(&[8, 0x14, b'a', 9, 0]).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+const POLY: u32 = 0x82f6_3b78;
+const MASK_DELTA: u32 = 0xa282_ead8;
+
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the crc32c of `concat(A, data)` where `init_crc` is the crc32c of some string A.
+/// `extend` is often used to maintain the crc32c of a stream of data.
+pub fn extend(init_crc: u32, data: &[u8]) -> u32 {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse4.2") {
+            return unsafe { extend_sse42(init_crc, data) };
+        }
+    }
+    extend_software(init_crc, data)
+}
+
+/// Returns the crc32c of `data`
+#[inline]
+pub fn value(data: &[u8]) -> u32 {
+    extend(0, data)
+}
+
+/// Returns a masked representation of `crc`.
+///
+/// It's problematic to compute the CRC of a string that contains embedded CRCs.
+/// Therefore we recommend that CRCs stored somewhere (e.g., in files) should be masked
+/// before being stored.
+#[inline]
+pub fn mask(crc: u32) -> u32 {
+    // rotate right by 15 bits and add a constant
+    crc.rotate_right(15).wrapping_add(MASK_DELTA)
+}
+
+/// Returns the crc whose masked representation is `masked`
+#[inline]
+pub fn unmask(masked: u32) -> u32 {
+    masked.wrapping_sub(MASK_DELTA).rotate_left(15)
+}
+
+fn extend_software(init_crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !init_crc;
+    for b in data {
+        crc = TABLE[((crc as u8) ^ *b) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse4.2")]
+unsafe fn extend_sse42(init_crc: u32, data: &[u8]) -> u32 {
+    use byteorder::{ByteOrder, LittleEndian};
+    use std::arch::x86_64::{_mm_crc32_u64, _mm_crc32_u8};
+
+    let mut crc = u64::from(!init_crc);
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        crc = _mm_crc32_u64(crc, LittleEndian::read_u64(chunk));
+    }
+    let mut crc = crc as u32;
+    for b in chunks.remainder() {
+        crc = _mm_crc32_u8(crc, *b);
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_results() {
+        // From rfc3720 section B.4.
+        assert_eq!(value(&[0u8; 32]), 0x8a91_36aa);
+        assert_eq!(value(&[0xffu8; 32]), 0x62a8_ab43);
+        let buf: Vec<u8> = (0..32u8).collect();
+        assert_eq!(value(&buf), 0x46dd_794e);
+        let buf: Vec<u8> = (0..32u8).rev().collect();
+        assert_eq!(value(&buf), 0x113f_db5c);
+        #[rustfmt::skip]
+        let data = [
+            0x01u8, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00,
+            0x00, 0x00, 0x00, 0x14, 0x00, 0x00, 0x00, 0x18,
+            0x28, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(value(&data), 0xd996_3a56);
+    }
+
+    #[test]
+    fn test_software_matches_accelerated() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i * 7 + 3) as u8).collect();
+        for len in [0usize, 1, 7, 8, 9, 63, 64, 65, 1000].iter() {
+            assert_eq!(value(&data[..*len]), extend_software(0, &data[..*len]));
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        assert_ne!(value(b"a"), value(b"foo"));
+    }
+
+    #[test]
+    fn test_extend() {
+        assert_eq!(value(b"hello world"), extend(value(b"hello "), b"world"));
+    }
+
+    #[test]
+    fn test_mask() {
+        let crc = value(b"foo");
+        assert_ne!(crc, mask(crc));
+        assert_ne!(crc, mask(mask(crc)));
+        assert_eq!(crc, unmask(mask(crc)));
+        assert_eq!(crc, unmask(unmask(mask(mask(crc)))));
+    }
+}
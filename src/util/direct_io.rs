@@ -0,0 +1,346 @@
+use super::error::TinyError;
+use crate::env::RandomAccessFile;
+
+use std::alloc::{self, Layout};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::slice;
+
+/// The alignment of the offsets, sizes and memory addresses of the direct I/O.
+/// 4KB is a multiple of the logical block size of almost all devices.
+pub const DIRECT_IO_ALIGNMENT: usize = 4096;
+
+/// The default size of the readahead buffer of the compaction inputs
+pub const DEFAULT_COMPACTION_READAHEAD_SIZE: usize = 2 << 20;
+
+#[inline]
+fn align_down(n: usize, alignment: usize) -> usize {
+    n - n % alignment
+}
+
+#[inline]
+fn align_up(n: usize, alignment: usize) -> usize {
+    align_down(n + alignment - 1, alignment)
+}
+
+/// AlignedBuffer is a fixed capacity buffer whose address is aligned, as required
+/// by the direct I/O.
+pub struct AlignedBuffer {
+    ptr: *mut u8,
+    layout: Layout,
+    len: usize,
+}
+
+unsafe impl Send for AlignedBuffer {}
+unsafe impl Sync for AlignedBuffer {}
+
+impl AlignedBuffer {
+    /// Create a zeroed buffer. `capacity` is rounded up to a multiple of `alignment`,
+    /// which must be a power of two.
+    pub fn new(alignment: usize, capacity: usize) -> Self {
+        let capacity = align_up(capacity.max(1), alignment);
+        let layout = match Layout::from_size_align(capacity, alignment) {
+            Ok(layout) => layout,
+            Err(e) => panic!("[aligned buffer] bad alignment {}: {}", alignment, e),
+        };
+        let ptr = unsafe { alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            alloc::handle_alloc_error(layout);
+        }
+        AlignedBuffer { ptr, layout, len: 0 }
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    #[inline]
+    pub fn alignment(&self) -> usize {
+        self.layout.align()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sets the length of the valid data.
+    /// REQUIRES: len <= capacity
+    #[inline]
+    pub fn set_len(&mut self, len: usize) {
+        invarint!(
+            len <= self.capacity(),
+            "[aligned buffer] length {} exceeds capacity {}",
+            len,
+            self.capacity()
+        );
+        self.len = len;
+    }
+
+    /// Appends as much of `data` as the remaining capacity allows and returns the
+    /// number of bytes appended
+    pub fn append(&mut self, data: &[u8]) -> usize {
+        let n = data.len().min(self.capacity() - self.len);
+        let len = self.len;
+        self.as_mut_capacity()[len..len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        n
+    }
+
+    /// Moves the range [start, len) to the beginning of the buffer
+    pub fn discard_front(&mut self, start: usize) {
+        let len = self.len;
+        self.as_mut_capacity().copy_within(start..len, 0);
+        self.len = len - start;
+    }
+
+    /// Returns the valid data
+    #[inline]
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    /// Returns the whole capacity of the buffer
+    #[inline]
+    pub fn as_mut_capacity(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for AlignedBuffer {
+    fn drop(&mut self) {
+        unsafe { alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn direct_open_options(options: &mut OpenOptions) -> Result<(), TinyError> {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.custom_flags(libc::O_DIRECT);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn direct_open_options(_: &mut OpenOptions) -> Result<(), TinyError> {
+    Err(TinyError::NotSupported(
+        "[direct io] direct I/O is only supported on Linux".to_owned(),
+    ))
+}
+
+/// Opens the file at `path` for direct reads bypassing the page cache
+pub fn open_for_direct_read(path: &str) -> Result<File, TinyError> {
+    let mut options = OpenOptions::new();
+    options.read(true);
+    direct_open_options(&mut options)?;
+    Ok(options.open(path)?)
+}
+
+/// Creates (or truncates) the file at `path` for direct writes bypassing the page cache
+pub fn create_for_direct_write(path: &str) -> Result<File, TinyError> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    direct_open_options(&mut options)?;
+    Ok(options.open(path)?)
+}
+
+fn unexpected_eof(offset: u64, len: usize) -> TinyError {
+    TinyError::Corruption(format!(
+        "[direct io] read [{}, {}) beyond the end of file",
+        offset,
+        offset + len as u64
+    ))
+}
+
+/// Reads `len` bytes at `offset` from a file opened for direct reads. The read is
+/// expanded to the aligned range and the requested bytes are copied out.
+pub fn read_aligned(file: &dyn RandomAccessFile, offset: u64, len: usize) -> Result<Vec<u8>, TinyError> {
+    let start = align_down(offset as usize, DIRECT_IO_ALIGNMENT);
+    let end = align_up(offset as usize + len, DIRECT_IO_ALIGNMENT);
+    let mut buf = AlignedBuffer::new(DIRECT_IO_ALIGNMENT, end - start);
+    let read = file.read_at(start as u64, buf.as_mut_capacity())?;
+    let skip = offset as usize - start;
+    if read < skip + len {
+        return Err(unexpected_eof(offset, len));
+    }
+    buf.set_len(read);
+    Ok(buf.as_slice()[skip..skip + len].to_vec())
+}
+
+/// ReadaheadReader reads a file in large aligned chunks and serves the following
+/// reads from the buffer. It's used by the compactions, which read the input
+/// files sequentially, to cut the number of the (direct) reads.
+pub struct ReadaheadReader<'a> {
+    file: &'a dyn RandomAccessFile,
+    buffer: AlignedBuffer,
+    // the offset in file of the buffered data
+    buffer_offset: u64,
+}
+
+impl<'a> ReadaheadReader<'a> {
+    pub fn new(file: &'a dyn RandomAccessFile, readahead_size: usize) -> Self {
+        ReadaheadReader {
+            file,
+            buffer: AlignedBuffer::new(DIRECT_IO_ALIGNMENT, readahead_size.max(DIRECT_IO_ALIGNMENT)),
+            buffer_offset: 0,
+        }
+    }
+
+    /// Reads `len` bytes at `offset`
+    pub fn read(&mut self, offset: u64, len: usize) -> Result<Vec<u8>, TinyError> {
+        let buffered_end = self.buffer_offset + self.buffer.len() as u64;
+        if offset < self.buffer_offset || offset + len as u64 > buffered_end {
+            if len > self.buffer.capacity() - DIRECT_IO_ALIGNMENT {
+                // too large to be buffered
+                return read_aligned(self.file, offset, len);
+            }
+            let start = align_down(offset as usize, DIRECT_IO_ALIGNMENT);
+            let read = self.file.read_at(start as u64, self.buffer.as_mut_capacity())?;
+            self.buffer.set_len(read);
+            self.buffer_offset = start as u64;
+        }
+        let skip = (offset - self.buffer_offset) as usize;
+        if skip + len > self.buffer.len() {
+            return Err(unexpected_eof(offset, len));
+        }
+        Ok(self.buffer.as_slice()[skip..skip + len].to_vec())
+    }
+}
+
+/// DirectWriter writes a file opened by `create_for_direct_write` through an aligned buffer.
+///
+/// Only whole aligned chunks can be written with the direct I/O, so the partial chunk at
+/// the tail is padded when flushed and kept in the buffer to be written again at the same
+/// offset by the next flush. The padding is truncated by `finish`.
+pub struct DirectWriter {
+    file: File,
+    buffer: AlignedBuffer,
+    // the aligned offset in file of the buffered data
+    file_offset: u64,
+}
+
+impl DirectWriter {
+    pub fn new(file: File, buffer_size: usize) -> Self {
+        DirectWriter {
+            file,
+            buffer: AlignedBuffer::new(DIRECT_IO_ALIGNMENT, buffer_size.max(DIRECT_IO_ALIGNMENT)),
+            file_offset: 0,
+        }
+    }
+
+    /// Returns the size of the data written so far
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.file_offset + self.buffer.len() as u64
+    }
+
+    // Writes the buffer padded to the alignment and keeps the partial tail chunk
+    fn write_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let len = self.buffer.len();
+        let padded = align_up(len, DIRECT_IO_ALIGNMENT);
+        let file_offset = self.file_offset;
+        let capacity = self.buffer.as_mut_capacity();
+        for b in capacity[len..padded].iter_mut() {
+            *b = 0;
+        }
+        self.file.write_all_at(&capacity[..padded], file_offset)?;
+        let tail_start = align_down(len, DIRECT_IO_ALIGNMENT);
+        self.buffer.discard_front(tail_start);
+        self.file_offset += tail_start as u64;
+        Ok(())
+    }
+
+    /// Flushes the buffered data, truncates the padding and syncs the file.
+    /// Returns the size of the file.
+    pub fn finish(mut self) -> Result<u64, TinyError> {
+        let size = self.size();
+        self.write_buffer()?;
+        self.file.set_len(size)?;
+        self.file.sync_all()?;
+        Ok(size)
+    }
+}
+
+impl Write for DirectWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < data.len() {
+            written += self.buffer.append(&data[written..]);
+            if self.buffer.len() == self.buffer.capacity() {
+                self.write_buffer()?;
+            }
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_buffer()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_file(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("tinydb_direct_io_{}_{}", name, std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[test]
+    fn test_aligned_buffer() {
+        let mut buf = AlignedBuffer::new(512, 1000);
+        assert_eq!(buf.capacity(), 1024);
+        assert_eq!(buf.as_mut_capacity().as_ptr() as usize % 512, 0);
+        assert_eq!(buf.append(&[1; 1000]), 1000);
+        assert_eq!(buf.append(&[2; 100]), 24);
+        assert_eq!(buf.len(), 1024);
+        buf.discard_front(1000);
+        assert_eq!(buf.as_slice(), &[2; 24][..]);
+    }
+
+    #[test]
+    fn test_direct_write_and_read() {
+        let path = test_file("rw");
+        let data: Vec<u8> = (0..20000u32).map(|i| (i % 251) as u8).collect();
+        let mut writer = DirectWriter::new(create_for_direct_write(&path).unwrap(), 8192);
+        // flush the partial tail in the middle and overwrite it later
+        writer.write_all(&data[..5000]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&data[5000..]).unwrap();
+        assert_eq!(writer.size(), data.len() as u64);
+        assert_eq!(writer.finish().unwrap(), data.len() as u64);
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let file = open_for_direct_read(&path).unwrap();
+        assert_eq!(read_aligned(&file, 4095, 3).unwrap(), &data[4095..4098]);
+        assert_eq!(read_aligned(&file, 0, data.len()).unwrap(), data);
+        assert!(read_aligned(&file, 19999, 2).is_err());
+
+        let mut reader = ReadaheadReader::new(&file, 8192);
+        let mut offset = 0;
+        while offset < data.len() {
+            let len = (data.len() - offset).min(1000);
+            assert_eq!(reader.read(offset as u64, len).unwrap(), &data[offset..offset + len]);
+            offset += len;
+        }
+        // backward and large reads
+        assert_eq!(reader.read(10, 10).unwrap(), &data[10..20]);
+        assert_eq!(reader.read(100, 10000).unwrap(), &data[100..10100]);
+        assert!(reader.read(19990, 20).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,3 +1,43 @@
-pub enum TinyError{
+use std::error::Error;
+use std::fmt;
+use std::io;
 
+#[derive(Debug)]
+pub enum TinyError {
+    /// The requested operation is not supported by the current configuration.
+    NotSupported(String),
+    /// The data read is broken.
+    Corruption(String),
+    /// The caller passed an argument that can not be handled.
+    InvalidArgument(String),
+    /// The operation conflicts with a concurrent one and can be retried.
+    Busy(String),
+    /// The operation could not be finished in the given time.
+    TimedOut(String),
+    /// The transaction is aborted to resolve a deadlock.
+    Deadlock(String),
+    /// An error returned by the file system.
+    IOError(io::Error),
+}
+
+impl fmt::Display for TinyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TinyError::NotSupported(msg) => write!(f, "not supported: {}", msg),
+            TinyError::Corruption(msg) => write!(f, "corruption: {}", msg),
+            TinyError::InvalidArgument(msg) => write!(f, "invalid argument: {}", msg),
+            TinyError::Busy(msg) => write!(f, "busy: {}", msg),
+            TinyError::TimedOut(msg) => write!(f, "timed out: {}", msg),
+            TinyError::Deadlock(msg) => write!(f, "deadlock: {}", msg),
+            TinyError::IOError(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl Error for TinyError {}
+
+impl From<io::Error> for TinyError {
+    fn from(e: io::Error) -> Self {
+        TinyError::IOError(e)
+    }
 }
@@ -0,0 +1,153 @@
+use super::hash::hash;
+
+/// A FilterPolicy creates a small filter from a set of keys, which is stored
+/// in the table files and consulted to skip the disk reads for the keys
+/// that are definitely not in the table.
+pub trait FilterPolicy: Send + Sync {
+    /// The name of the policy. If the filter encoding changes in an incompatible way,
+    /// the name returned by this method must be changed. Otherwise, old incompatible
+    /// filters may be passed to methods of this type.
+    fn name(&self) -> &str;
+
+    /// Appends a filter that summarizes `keys` to `dst`.
+    fn create_filter(&self, keys: &[Vec<u8>], dst: &mut Vec<u8>);
+
+    /// Returns false if `key` is definitely not in the set of keys the `filter` was
+    /// created from. Returns true if the key may be in the set.
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool;
+}
+
+/// BloomFilterPolicy is the bloom filter of LevelDB, which uses double hashing
+/// to generate the sequence of hash values.
+pub struct BloomFilterPolicy {
+    bits_per_key: usize,
+    // number of probes
+    k: usize,
+}
+
+impl BloomFilterPolicy {
+    /// Create a bloom filter with approximately the specified number of bits per key.
+    /// A good value for `bits_per_key` is 10, which yields a filter with ~1% false positive rate.
+    pub fn new(bits_per_key: usize) -> Self {
+        // We intentionally round down to reduce probing cost a little bit
+        // 0.69 =~ ln(2)
+        let k = ((bits_per_key as f64 * 0.69) as usize).clamp(1, 30);
+        BloomFilterPolicy { bits_per_key, k }
+    }
+
+    /// The number of bits per key
+    #[inline]
+    pub fn bits_per_key(&self) -> usize {
+        self.bits_per_key
+    }
+}
+
+#[inline]
+fn bloom_hash(key: &[u8]) -> u32 {
+    hash(key, 0xbc9f_1d34)
+}
+
+impl FilterPolicy for BloomFilterPolicy {
+    fn name(&self) -> &str {
+        "tinydb.BuiltinBloomFilter"
+    }
+
+    fn create_filter(&self, keys: &[Vec<u8>], dst: &mut Vec<u8>) {
+        // For small n, we can see a very high false positive rate.
+        // Fix it by enforcing a minimum bloom filter length.
+        let bits = (keys.len() * self.bits_per_key).max(64);
+        let bytes = bits.div_ceil(8);
+        let bits = bytes * 8;
+
+        let start = dst.len();
+        dst.resize(start + bytes, 0);
+        // Remember # of probes in filter
+        dst.push(self.k as u8);
+        let array = &mut dst[start..start + bytes];
+        for key in keys {
+            // Use double-hashing to generate a sequence of hash values.
+            let mut h = bloom_hash(key);
+            // Rotate right 17 bits
+            let delta = h.rotate_right(17);
+            for _ in 0..self.k {
+                let bit_pos = h as usize % bits;
+                array[bit_pos / 8] |= 1 << (bit_pos % 8);
+                h = h.wrapping_add(delta);
+            }
+        }
+    }
+
+    fn key_may_match(&self, key: &[u8], filter: &[u8]) -> bool {
+        if filter.len() < 2 {
+            return false;
+        }
+        let (array, k) = filter.split_at(filter.len() - 1);
+        let bits = array.len() * 8;
+        // Use the encoded k so that we can read filters generated by
+        // bloom filters created using different parameters.
+        let k = k[0];
+        if k > 30 {
+            // Reserved for potentially new encodings for short bloom filters.
+            // Consider it a match.
+            return true;
+        }
+        let mut h = bloom_hash(key);
+        let delta = h.rotate_right(17);
+        for _ in 0..k {
+            let bit_pos = h as usize % bits;
+            if array[bit_pos / 8] & (1 << (bit_pos % 8)) == 0 {
+                return false;
+            }
+            h = h.wrapping_add(delta);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(i: usize) -> Vec<u8> {
+        (i as u32).to_le_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_empty_filter() {
+        let policy = BloomFilterPolicy::new(10);
+        let mut filter = vec![];
+        policy.create_filter(&[], &mut filter);
+        assert!(!policy.key_may_match(b"hello", &filter));
+        assert!(!policy.key_may_match(b"world", &filter));
+    }
+
+    #[test]
+    fn test_small_filter() {
+        let policy = BloomFilterPolicy::new(10);
+        let mut filter = vec![];
+        policy.create_filter(&[b"hello".to_vec(), b"world".to_vec()], &mut filter);
+        assert!(policy.key_may_match(b"hello", &filter));
+        assert!(policy.key_may_match(b"world", &filter));
+        assert!(!policy.key_may_match(b"x", &filter));
+        assert!(!policy.key_may_match(b"foo", &filter));
+    }
+
+    #[test]
+    fn test_varying_lengths() {
+        let policy = BloomFilterPolicy::new(10);
+        for n in [1, 10, 100, 1000, 10000].iter() {
+            let keys: Vec<Vec<u8>> = (0..*n).map(key).collect();
+            let mut filter = vec![];
+            policy.create_filter(&keys, &mut filter);
+            assert!(filter.len() <= (n * 10 / 8) + 40, "filter of {} keys is too large", n);
+            for k in keys.iter() {
+                assert!(policy.key_may_match(k, &filter));
+            }
+            // check false positive rate
+            let false_positives = (0..10000)
+                .filter(|i| policy.key_may_match(&key(i + 1_000_000_000), &filter))
+                .count();
+            assert!(false_positives <= 200, "{} false positives for {} keys", false_positives, n);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+/// Computes a 32-bit hash of `data` in the same way as LevelDB, which is similar
+/// to murmur hash.
+pub fn hash(data: &[u8], seed: u32) -> u32 {
+    const M: u32 = 0xc6a4_a793;
+    const R: u32 = 24;
+    let mut h = seed ^ (data.len() as u32).wrapping_mul(M);
+
+    // Pick up four bytes at a time
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        h = h.wrapping_add(LittleEndian::read_u32(chunk));
+        h = h.wrapping_mul(M);
+        h ^= h >> 16;
+    }
+
+    // Pick up remaining bytes
+    let rest = chunks.remainder();
+    if rest.len() == 3 {
+        h = h.wrapping_add(u32::from(rest[2]) << 16);
+    }
+    if rest.len() >= 2 {
+        h = h.wrapping_add(u32::from(rest[1]) << 8);
+    }
+    if !rest.is_empty() {
+        h = h.wrapping_add(u32::from(rest[0]));
+        h = h.wrapping_mul(M);
+        h ^= h >> R;
+    }
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash;
+
+    #[test]
+    fn test_signed_unsigned_issue() {
+        let tests: Vec<(&[u8], u32)> = vec![
+            (&[], 0xbc9f_1d34),
+            (&[0x62], 0xef13_45c4),
+            (&[0xc3, 0x97], 0x5b66_3814),
+            (&[0xe2, 0x99, 0xa5], 0x323c_078f),
+            (&[0xe1, 0x80, 0xb9, 0x32], 0xed21_633a),
+        ];
+        for (data, expect) in tests {
+            assert_eq!(hash(data, 0xbc9f_1d34), expect);
+        }
+    }
+}
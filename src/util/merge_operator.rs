@@ -0,0 +1,163 @@
+use super::slice::Slice;
+use super::varint::{read_u64, write_u64, MAX_VARINT_LEN_U64};
+
+/// A MergeOperator specifies the semantics of a read-modify-write
+/// operation. The client only needs to write the merge operand with
+/// `DB::merge` and the database combines the operands with the existing
+/// value lazily, when the key is read by `get` or iterators, or when the
+/// entries are rewritten by a flush or a compaction.
+///
+/// A MergeOperator implementation must be thread-safe since we may invoke
+/// its methods concurrently from multiple threads.
+pub trait MergeOperator: Send + Sync {
+    /// Applies a stack of merge operands onto the existing value of `key`.
+    /// `existing_value` is `None` if the key does not exist or has been
+    /// deleted, and `operands` are ordered from the oldest to the newest.
+    ///
+    /// Returns `None` if the operands can not be merged, which will be
+    /// reported to the client as a corruption.
+    fn full_merge(
+        &self,
+        key: &Slice,
+        existing_value: Option<&Slice>,
+        operands: &[Slice],
+    ) -> Option<Vec<u8>>;
+
+    /// Combines two adjacent operands of `key` into a single operand, where
+    /// `left` is older than `right`. This allows flush and compaction to
+    /// shrink the operand stack before the base value is known.
+    ///
+    /// Returns `None` if the operands can not be combined and both of them
+    /// must be kept.
+    fn partial_merge(&self, _key: &Slice, _left: &Slice, _right: &Slice) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// The name of the merge operator. Used to check for merge operator
+    /// mismatches (i.e., a DB created with one merge operator is
+    /// accessed using a different merge operator).
+    ///
+    /// Names starting with "tinydb." are reserved and should not be used
+    /// by any clients of this package.
+    fn name(&self) -> &str;
+}
+
+/// U64AddOperator treats both the values and the operands as varint encoded
+/// u64 and sums them up. A missing value is regarded as 0 and the addition
+/// wraps around on overflow.
+pub struct U64AddOperator {}
+
+impl U64AddOperator {
+    pub fn new() -> U64AddOperator {
+        U64AddOperator {}
+    }
+
+    /// Encodes `n` into a value or an operand that `U64AddOperator` accepts.
+    pub fn encode(n: u64) -> Vec<u8> {
+        let mut buf = vec![0u8; MAX_VARINT_LEN_U64];
+        let written = write_u64(&mut buf, n);
+        buf.truncate(written);
+        buf
+    }
+
+    /// Decodes a value or an operand encoded by `U64AddOperator::encode`.
+    /// Returns `None` if `data` is not a single valid varint.
+    pub fn decode(data: &[u8]) -> Option<u64> {
+        match read_u64(data) {
+            (n, size) if size > 0 && size as usize == data.len() => Some(n),
+            _ => None,
+        }
+    }
+}
+
+impl Default for U64AddOperator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MergeOperator for U64AddOperator {
+    fn full_merge(
+        &self,
+        _key: &Slice,
+        existing_value: Option<&Slice>,
+        operands: &[Slice],
+    ) -> Option<Vec<u8>> {
+        let mut sum = match existing_value {
+            Some(v) => Self::decode(v.to_slice())?,
+            None => 0,
+        };
+        for operand in operands {
+            sum = sum.wrapping_add(Self::decode(operand.to_slice())?);
+        }
+        Some(Self::encode(sum))
+    }
+
+    fn partial_merge(&self, _key: &Slice, left: &Slice, right: &Slice) -> Option<Vec<u8>> {
+        let l = Self::decode(left.to_slice())?;
+        let r = Self::decode(right.to_slice())?;
+        Some(Self::encode(l.wrapping_add(r)))
+    }
+
+    #[inline]
+    fn name(&self) -> &str {
+        "tinydb.U64AddOperator"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u64_add_encode_decode() {
+        for n in [0u64, 1, 127, 128, 300, 58962304, u64::MAX] {
+            let encoded = U64AddOperator::encode(n);
+            assert_eq!(U64AddOperator::decode(&encoded), Some(n));
+        }
+        assert_eq!(U64AddOperator::decode(&[]), None);
+        // trailing bytes are not allowed
+        assert_eq!(U64AddOperator::decode(&[1u8, 2u8]), None);
+        // truncated varint
+        assert_eq!(U64AddOperator::decode(&[0b1000_0001]), None);
+    }
+
+    #[test]
+    fn test_u64_add_full_merge() {
+        let op = U64AddOperator::new();
+        let key = Slice::from("counter");
+        let (one, two, three) = (
+            U64AddOperator::encode(1),
+            U64AddOperator::encode(2),
+            U64AddOperator::encode(300),
+        );
+        let operands = vec![Slice::from(&one), Slice::from(&two)];
+
+        let merged = op.full_merge(&key, None, &operands).unwrap();
+        assert_eq!(U64AddOperator::decode(&merged), Some(3));
+
+        let existing = Slice::from(&three);
+        let merged = op.full_merge(&key, Some(&existing), &operands).unwrap();
+        assert_eq!(U64AddOperator::decode(&merged), Some(303));
+
+        let max = U64AddOperator::encode(u64::MAX);
+        let merged = op
+            .full_merge(&key, Some(&Slice::from(&max)), &operands[1..])
+            .unwrap();
+        assert_eq!(U64AddOperator::decode(&merged), Some(1));
+
+        let bad = vec![0b1000_0001u8];
+        assert!(op.full_merge(&key, Some(&Slice::from(&bad)), &operands).is_none());
+    }
+
+    #[test]
+    fn test_u64_add_partial_merge() {
+        let op = U64AddOperator::new();
+        let key = Slice::from("counter");
+        let (left, right) = (U64AddOperator::encode(5), U64AddOperator::encode(7));
+        let merged = op
+            .partial_merge(&key, &Slice::from(&left), &Slice::from(&right))
+            .unwrap();
+        assert_eq!(U64AddOperator::decode(&merged), Some(12));
+    }
+}
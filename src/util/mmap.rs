@@ -0,0 +1,180 @@
+use super::error::TinyError;
+use super::slice::Slice;
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::os::unix::io::AsRawFd;
+use std::ptr;
+use std::slice;
+use std::sync::Arc;
+
+/// MmapFile is a read-only memory mapping of a whole file, which is unmapped on drop.
+pub struct MmapFile {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// The mapping is read-only so it can be shared between threads
+unsafe impl Send for MmapFile {}
+unsafe impl Sync for MmapFile {}
+
+impl MmapFile {
+    /// Maps the whole `file` into memory
+    pub fn new(file: &File) -> Result<Self, TinyError> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // mmap rejects an empty mapping
+            return Ok(MmapFile {
+                ptr: ptr::null_mut(),
+                len: 0,
+            });
+        }
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(TinyError::IOError(io::Error::last_os_error()));
+        }
+        Ok(MmapFile { ptr, len })
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the mapped contents of the file
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for MmapFile {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe {
+                libc::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
+
+/// MmapSlice is a range of a `MmapFile`, which keeps the mapping alive until all
+/// the slices referring to it are dropped.
+#[derive(Clone)]
+pub struct MmapSlice {
+    file: Arc<MmapFile>,
+    offset: usize,
+    len: usize,
+}
+
+impl MmapSlice {
+    /// Returns the range `[offset, offset + len)` of `file`.
+    /// Returns `Corruption` if the range exceeds the end of the file.
+    pub fn new(file: Arc<MmapFile>, offset: usize, len: usize) -> Result<Self, TinyError> {
+        if offset > file.len() || file.len() - offset < len {
+            return Err(TinyError::Corruption(format!(
+                "[mmap] read [{}, {}) beyond the end of file {}",
+                offset,
+                offset + len,
+                file.len()
+            )));
+        }
+        Ok(MmapSlice { file, offset, len })
+    }
+
+    /// Returns the sub-range `[offset, offset + len)` of this slice
+    pub fn sub(&self, offset: usize, len: usize) -> Result<Self, TinyError> {
+        if offset > self.len || self.len - offset < len {
+            return Err(TinyError::Corruption(format!(
+                "[mmap] sub-range [{}, {}) beyond the slice of {} bytes",
+                offset,
+                offset + len,
+                self.len
+            )));
+        }
+        Ok(MmapSlice {
+            file: self.file.clone(),
+            offset: self.offset + offset,
+            len,
+        })
+    }
+
+    /// Returns a `Slice` view into the mapping, which must not outlive this `MmapSlice`
+    #[inline]
+    pub fn to_slice(&self) -> Slice {
+        Slice::from(self.deref())
+    }
+}
+
+impl Deref for MmapSlice {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        &self.file.as_bytes()[self.offset..self.offset + self.len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+
+    #[test]
+    fn test_mmap_file() {
+        let path = std::env::temp_dir().join(format!("tinydb_mmap_test_{}", std::process::id()));
+        let mut f = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        f.write_all(b"hello mmap world").unwrap();
+
+        let mmap = Arc::new(MmapFile::new(&f).unwrap());
+        assert_eq!(mmap.as_bytes(), b"hello mmap world");
+        let s = MmapSlice::new(mmap.clone(), 6, 4).unwrap();
+        assert_eq!(&*s, b"mmap");
+        assert_eq!(s.to_slice().to_slice(), b"mmap");
+        assert_eq!(&*s.sub(1, 3).unwrap(), b"map");
+        assert!(s.sub(1, 4).is_err());
+        assert!(MmapSlice::new(mmap.clone(), 10, 7).is_err());
+        assert!(MmapSlice::new(mmap.clone(), 17, 0).is_err());
+
+        // the slice keeps the mapping alive
+        drop(mmap);
+        drop(f);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(&*s, b"mmap");
+    }
+
+    #[test]
+    fn test_mmap_empty_file() {
+        let path = std::env::temp_dir().join(format!("tinydb_mmap_empty_{}", std::process::id()));
+        let f = File::create(&path).unwrap();
+        let mmap = MmapFile::new(&f).unwrap();
+        assert!(mmap.is_empty());
+        assert_eq!(mmap.as_bytes(), b"");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -5,3 +5,12 @@ pub mod varint;
 pub mod byte;
 pub mod comparator;
 pub mod slice;
+pub mod merge_operator;
+pub mod clock;
+pub mod ttl;
+pub mod compression;
+pub mod crc32;
+pub mod hash;
+pub mod filter_policy;
+pub mod mmap;
+pub mod direct_io;
@@ -72,7 +72,7 @@ impl Index<usize> for Slice {
                 self.size, index
             );
         }
-        unsafe { &*self.data.offset(index as isize) }
+        unsafe { &*self.data.add(index) }
     }
 }
 
@@ -94,3 +94,10 @@ impl<'a> From<&'a Vec<u8>> for Slice {
         Slice::new(v.as_ptr(), v.len())
     }
 }
+
+impl<'a> From<&'a str> for Slice {
+    #[inline]
+    fn from(s: &'a str) -> Self {
+        Slice::new(s.as_ptr(), s.len())
+    }
+}
@@ -0,0 +1,91 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::time::Duration;
+
+/// The size of the expiration timestamp appended to a value written with a TTL.
+pub const EXPIRY_SIZE: usize = 8;
+
+/// Returns the expiration timestamp in micro-seconds for an entry written at `now_micros`
+/// with the given `ttl`. The timestamp saturates at `u64::MAX` which means "never expire".
+pub fn expire_at(now_micros: u64, ttl: Duration) -> u64 {
+    let ttl_micros = ttl.as_micros();
+    if ttl_micros >= u128::from(u64::MAX) {
+        return u64::MAX;
+    }
+    now_micros.saturating_add(ttl_micros as u64)
+}
+
+/// Encodes a value written with a TTL as:
+///
+/// ```text
+/// +---------------+---------------------------+
+/// | value         | expire_at (fixed64 LE)    |
+/// +---------------+---------------------------+
+/// ```
+pub fn encode_value_with_expiry(value: &[u8], expire_at: u64) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(value.len() + EXPIRY_SIZE);
+    buf.extend_from_slice(value);
+    buf.resize(value.len() + EXPIRY_SIZE, 0);
+    LittleEndian::write_u64(&mut buf[value.len()..], expire_at);
+    buf
+}
+
+/// Splits the data encoded by `encode_value_with_expiry` into the user value and the
+/// expiration timestamp. Returns `None` if `data` is too short to contain a timestamp.
+pub fn decode_value_with_expiry(data: &[u8]) -> Option<(&[u8], u64)> {
+    if data.len() < EXPIRY_SIZE {
+        return None;
+    }
+    let (value, expiry) = data.split_at(data.len() - EXPIRY_SIZE);
+    Some((value, LittleEndian::read_u64(expiry)))
+}
+
+/// Returns whether an entry expiring at `expire_at` is expired at `now_micros`.
+#[inline]
+pub fn is_expired(expire_at: u64, now_micros: u64) -> bool {
+    expire_at <= now_micros
+}
+
+/// Returns the user value of the data encoded by `encode_value_with_expiry` if it's still
+/// alive at `now_micros`. Both the read path and compaction use this to treat expired
+/// entries as absent.
+pub fn live_value(data: &[u8], now_micros: u64) -> Option<&[u8]> {
+    match decode_value_with_expiry(data) {
+        Some((value, expire_at)) if !is_expired(expire_at, now_micros) => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expire_at() {
+        assert_eq!(expire_at(100, Duration::from_micros(20)), 120);
+        assert_eq!(expire_at(100, Duration::from_secs(1)), 1_000_100);
+        assert_eq!(expire_at(u64::MAX - 1, Duration::from_secs(1)), u64::MAX);
+        assert_eq!(expire_at(0, Duration::from_secs(u64::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn test_encode_decode_value_with_expiry() {
+        let tests = vec![(vec![], 0u64), (vec![1u8, 2, 3], 42), (vec![9u8; 100], u64::MAX)];
+        for (value, expiry) in tests {
+            let encoded = encode_value_with_expiry(&value, expiry);
+            assert_eq!(encoded.len(), value.len() + EXPIRY_SIZE);
+            let (v, e) = decode_value_with_expiry(&encoded).unwrap();
+            assert_eq!(v, value.as_slice());
+            assert_eq!(e, expiry);
+        }
+        assert!(decode_value_with_expiry(&[1u8, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn test_live_value() {
+        let encoded = encode_value_with_expiry(b"session", 1000);
+        assert_eq!(live_value(&encoded, 999), Some(&b"session"[..]));
+        assert_eq!(live_value(&encoded, 1000), None);
+        assert_eq!(live_value(&encoded, 2000), None);
+        assert_eq!(live_value(&[0u8; 3], 0), None);
+    }
+}
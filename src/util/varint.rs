@@ -1,12 +1,12 @@
-const MAX_VARINT_LEN_U64: usize = 10;
+pub const MAX_VARINT_LEN_U64: usize = 10;
 
 /// Encodes a u64 into given vec and returns the number of bytes written.
 /// Using little endian style.
 /// https://developers.google.com/protocol-buffers/docs/encoding#varints
 pub fn write_u64(data: &mut [u8], mut n: u64) -> usize {
     let mut i = 0;
-    // n>128
-    while n > 0b1000_0000 {
+    // n>=128
+    while n >= 0b1000_0000 {
         // 取低7位
         data[i] = (n as u8) | 0b1000_0000;
         n >>= 7;
@@ -14,7 +14,14 @@ pub fn write_u64(data: &mut [u8], mut n: u64) -> usize {
         i += 1;
     }
     data[i] = n as u8;
-    return i + 1;
+    i + 1
+}
+
+/// Appends the varint encoded u64 to the end of `dst`.
+pub fn put_u64(dst: &mut Vec<u8>, n: u64) {
+    let mut buf = [0u8; MAX_VARINT_LEN_U64];
+    let written = write_u64(&mut buf, n);
+    dst.extend_from_slice(&buf[..written]);
 }
 
 pub fn read_u64(data: &[u8]) -> (u64, isize) {
@@ -39,9 +46,41 @@ pub fn read_u64(data: &[u8]) -> (u64, isize) {
     (0, 0)
 }
 
+/// Decodes a u64 from the front of `src` and advances `src` past it.
+/// Returns `None` if `src` doesn't start with a valid varint.
+pub fn get_u64(src: &mut &[u8]) -> Option<u64> {
+    match read_u64(src) {
+        (n, size) if size > 0 => {
+            *src = &src[size as usize..];
+            Some(n)
+        }
+        _ => None,
+    }
+}
+
+/// Appends `data` prefixed by its varint encoded length to the end of `dst`.
+pub fn put_length_prefixed(dst: &mut Vec<u8>, data: &[u8]) {
+    put_u64(dst, data.len() as u64);
+    dst.extend_from_slice(data);
+}
+
+/// Decodes the bytes written by `put_length_prefixed` from the front of `src` and
+/// advances `src` past them.
+pub fn get_length_prefixed<'a>(src: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = get_u64(src)? as usize;
+    if len > src.len() {
+        return None;
+    }
+    let (data, rest) = src.split_at(len);
+    *src = rest;
+    Some(data)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{read_u64, write_u64, MAX_VARINT_LEN_U64};
+    use super::{
+        get_length_prefixed, get_u64, put_length_prefixed, put_u64, read_u64, write_u64,
+    };
 
     #[test]
     fn test_write_u64() {
@@ -49,6 +88,7 @@ mod tests {
         let tests = vec![
             (0u64, vec![0]),
             (100u64, vec![0b0110_0100]),
+            (128u64, vec![0b1000_0000, 0b1]),
             (129u64, vec![0b1000_0001, 0b1]),
             (258u64, vec![0b1000_0010, 0b10]),
             (
@@ -58,10 +98,7 @@ mod tests {
         ];
 
         for (input, results) in tests {
-            let mut bytes = Vec::with_capacity(MAX_VARINT_LEN_U64);
-            for _ in 0..results.len() {
-                bytes.push(0);
-            }
+            let mut bytes = vec![0; results.len()];
             let written = write_u64(&mut bytes, input);
             assert_eq!(written,results.len());
             for (i, b) in bytes.iter().enumerate() {
@@ -71,6 +108,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_put_u64() {
+        let mut dst = vec![];
+        for n in [0u64, 128, 58962304, u64::MAX].iter() {
+            put_u64(&mut dst, *n);
+        }
+        let mut data = dst.as_slice();
+        for n in [0u64, 128, 58962304, u64::MAX].iter() {
+            let (v, size) = read_u64(data);
+            assert_eq!(v, *n);
+            data = &data[size as usize..];
+        }
+        assert!(data.is_empty());
+    }
+
     #[test]
     fn test_read_u64() {
         #[rustfmt::skip]
@@ -82,7 +134,7 @@ mod tests {
             0b1000_0000, 0b1110_0011, 0b1000_1110, 0b1_1100,
             0b1100_1110, 0b1000_0001, 0b1011_0101, 0b1101_1001, 0b1111_0110, 0b1010_1100, 0b1100_1110, 0b1000_0001, 0b1011_0101, 0b1101_1001, 0b1111_0110, 0b1010_1100,
         ];
-        let expects = vec![
+        let expects = [
             (0u64, 1),
             (100u64, 1),
             (129u64, 2),
@@ -105,4 +157,21 @@ mod tests {
             idx += 1;
         }
     }
+
+    #[test]
+    fn test_length_prefixed() {
+        let mut buf = vec![];
+        put_length_prefixed(&mut buf, b"");
+        put_length_prefixed(&mut buf, b"foo");
+        put_u64(&mut buf, 300);
+        let mut src = &buf[..];
+        assert_eq!(get_length_prefixed(&mut src), Some(&b""[..]));
+        assert_eq!(get_length_prefixed(&mut src), Some(&b"foo"[..]));
+        assert_eq!(get_u64(&mut src), Some(300));
+        assert!(src.is_empty());
+        assert_eq!(get_u64(&mut src), None);
+        // the length exceeds the data
+        let mut src = &[5u8, 1, 2][..];
+        assert_eq!(get_length_prefixed(&mut src), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,254 @@
+use crate::column_family::{ColumnFamilyHandle, DEFAULT_COLUMN_FAMILY_ID};
+use crate::entry::ValueType;
+use crate::util::error::TinyError;
+use crate::util::varint::{get_length_prefixed, get_u64, put_length_prefixed, put_u64};
+
+use byteorder::{ByteOrder, LittleEndian};
+
+// A WriteBatch is encoded as a record of the WAL:
+//
+//   sequence number (fixed64 LE) | count (fixed32 LE) | change * count
+//
+// where every change is
+//
+//   type (u8) | column family id (varint) | key (length prefixed) | value (length prefixed)
+//
+// and a deletion has no value.
+const HEADER_SIZE: usize = 8 + 4;
+
+/// WriteBatch holds a sequence of changes applied to the DB atomically by `DB::write_batch`.
+/// The changes are applied in the order they are added and get consecutive sequence
+/// numbers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteBatch {
+    rep: Vec<u8>,
+}
+
+/// A change in a `WriteBatch`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WriteBatchEntry<'a> {
+    pub column_family: u32,
+    pub value_type: ValueType,
+    pub key: &'a [u8],
+    /// Empty for a deletion
+    pub value: &'a [u8],
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        WriteBatch {
+            rep: vec![0; HEADER_SIZE],
+        }
+    }
+
+    /// Sets `key` to `value`
+    pub fn put(&mut self, key: &[u8], value: &[u8]) {
+        self.add(DEFAULT_COLUMN_FAMILY_ID, ValueType::Value, key, value);
+    }
+
+    /// Appends the merge operand of `key`, which is applied by the merge operator
+    pub fn merge(&mut self, key: &[u8], operand: &[u8]) {
+        self.add(DEFAULT_COLUMN_FAMILY_ID, ValueType::Merge, key, operand);
+    }
+
+    /// Deletes `key`
+    pub fn delete(&mut self, key: &[u8]) {
+        self.add(DEFAULT_COLUMN_FAMILY_ID, ValueType::Deletion, key, &[]);
+    }
+
+    /// Sets `key` to `value` in the column family
+    pub fn put_cf(&mut self, handle: &ColumnFamilyHandle, key: &[u8], value: &[u8]) {
+        self.add(handle.id(), ValueType::Value, key, value);
+    }
+
+    /// Appends the merge operand of `key` in the column family
+    pub fn merge_cf(&mut self, handle: &ColumnFamilyHandle, key: &[u8], operand: &[u8]) {
+        self.add(handle.id(), ValueType::Merge, key, operand);
+    }
+
+    /// Deletes `key` in the column family
+    pub fn delete_cf(&mut self, handle: &ColumnFamilyHandle, key: &[u8]) {
+        self.add(handle.id(), ValueType::Deletion, key, &[]);
+    }
+
+    /// Returns the number of the changes
+    #[inline]
+    pub fn count(&self) -> u32 {
+        LittleEndian::read_u32(&self.rep[8..HEADER_SIZE])
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.count() == 0
+    }
+
+    /// Removes all the changes
+    pub fn clear(&mut self) {
+        self.rep.clear();
+        self.rep.resize(HEADER_SIZE, 0);
+    }
+
+    /// Returns the sequence number of the first change
+    #[inline]
+    pub fn sequence(&self) -> u64 {
+        LittleEndian::read_u64(&self.rep[..8])
+    }
+
+    pub(crate) fn set_sequence(&mut self, seq: u64) {
+        LittleEndian::write_u64(&mut self.rep[..8], seq);
+    }
+
+    /// Returns the encoded batch written to the WAL
+    #[inline]
+    pub(crate) fn data(&self) -> &[u8] {
+        &self.rep
+    }
+
+    /// Creates the batch from a record of the WAL.
+    /// Returns `TinyError::Corruption` if the record is malformed.
+    pub(crate) fn from_data(data: Vec<u8>) -> Result<Self, TinyError> {
+        if data.len() < HEADER_SIZE {
+            return Err(TinyError::Corruption(
+                "[write batch] the record is too short".to_owned(),
+            ));
+        }
+        let batch = WriteBatch { rep: data };
+        let mut count = 0;
+        for entry in batch.iter() {
+            entry?;
+            count += 1;
+        }
+        if count != batch.count() {
+            return Err(TinyError::Corruption(format!(
+                "[write batch] {} changes are found but the count is {}",
+                count,
+                batch.count()
+            )));
+        }
+        Ok(batch)
+    }
+
+    /// Returns an iterator over the changes in the order they are added
+    pub fn iter(&self) -> WriteBatchIter<'_> {
+        WriteBatchIter {
+            data: &self.rep[HEADER_SIZE..],
+        }
+    }
+
+    pub(crate) fn add(&mut self, column_family: u32, value_type: ValueType, key: &[u8], value: &[u8]) {
+        let count = self.count() + 1;
+        LittleEndian::write_u32(&mut self.rep[8..HEADER_SIZE], count);
+        self.rep.push(value_type as u8);
+        put_u64(&mut self.rep, u64::from(column_family));
+        put_length_prefixed(&mut self.rep, key);
+        if value_type != ValueType::Deletion {
+            put_length_prefixed(&mut self.rep, value);
+        }
+    }
+}
+
+impl Default for WriteBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// WriteBatchIter yields the changes of a `WriteBatch`. A malformed change is yielded as
+/// an error and ends the iteration.
+pub struct WriteBatchIter<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> WriteBatchIter<'a> {
+    fn decode_entry(&mut self) -> Result<WriteBatchEntry<'a>, TinyError> {
+        let corruption = |what: &str| TinyError::Corruption(format!("[write batch] bad {}", what));
+        let value_type = ValueType::from_u8(self.data[0]).ok_or_else(|| corruption("type"))?;
+        self.data = &self.data[1..];
+        let column_family = get_u64(&mut self.data).ok_or_else(|| corruption("column family"))?;
+        let key = get_length_prefixed(&mut self.data).ok_or_else(|| corruption("key"))?;
+        let value = match value_type {
+            ValueType::Deletion => &[],
+            _ => get_length_prefixed(&mut self.data).ok_or_else(|| corruption("value"))?,
+        };
+        Ok(WriteBatchEntry {
+            column_family: column_family as u32,
+            value_type,
+            key,
+            value,
+        })
+    }
+}
+
+impl<'a> Iterator for WriteBatchIter<'a> {
+    type Item = Result<WriteBatchEntry<'a>, TinyError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        let entry = self.decode_entry();
+        if entry.is_err() {
+            // stop after the error
+            self.data = &[];
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_batch() {
+        let mut batch = WriteBatch::new();
+        assert!(batch.is_empty());
+        batch.put(b"k1", b"v1");
+        batch.delete(b"k2");
+        batch.put(b"k3", b"");
+        batch.merge(b"k1", b"1");
+        batch.add(7, ValueType::Deletion, b"k4", &[]);
+        batch.set_sequence(100);
+        assert_eq!(batch.count(), 5);
+
+        let decoded = WriteBatch::from_data(batch.data().to_vec()).unwrap();
+        assert_eq!(decoded.sequence(), 100);
+        let entries: Vec<_> = decoded
+            .iter()
+            .map(|e| {
+                let e = e.unwrap();
+                (e.column_family, e.value_type, e.key.to_vec(), e.value.to_vec())
+            })
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                (0, ValueType::Value, b"k1".to_vec(), b"v1".to_vec()),
+                (0, ValueType::Deletion, b"k2".to_vec(), vec![]),
+                (0, ValueType::Value, b"k3".to_vec(), vec![]),
+                (0, ValueType::Merge, b"k1".to_vec(), b"1".to_vec()),
+                (7, ValueType::Deletion, b"k4".to_vec(), vec![]),
+            ]
+        );
+
+        batch.clear();
+        assert!(batch.is_empty());
+        assert_eq!(batch.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_corrupted_batch() {
+        let mut batch = WriteBatch::new();
+        batch.put(b"key", b"value");
+        let data = batch.data().to_vec();
+        assert!(WriteBatch::from_data(data[..5].to_vec()).is_err());
+        assert!(WriteBatch::from_data(data[..data.len() - 1].to_vec()).is_err());
+        // the count doesn't match
+        let mut bad = data.clone();
+        bad[8] = 2;
+        assert!(WriteBatch::from_data(bad).is_err());
+        let mut bad = data;
+        bad[HEADER_SIZE] = 100;
+        assert!(WriteBatch::from_data(bad).is_err());
+    }
+}